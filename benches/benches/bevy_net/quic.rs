@@ -0,0 +1,177 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy_net::crypto_utils::SkipServerVerification;
+use bevy_net::quic::crypto::rustls::QuicClientConfig;
+use bevy_net::quic::{ClientConfig, Connection, EndPoint, ServerConfig};
+use bevy_net::rustls;
+use bevy_tasks::{block_on, IoTaskPool, TaskPool};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rustls_pemfile::{read_all, Item};
+
+criterion_group!(benches, loopback_echo, idle_cpu);
+criterion_main!(benches);
+
+const PAYLOAD_SIZES: [usize; 3] = [64, 1024, 64 * 1024];
+
+/// Connects a client and a server [`EndPoint`] over the loopback interface.
+///
+/// The server echoes back every datagram and bidirectional stream it receives.
+fn connected_pair() -> (EndPoint, EndPoint, Connection) {
+    IoTaskPool::get_or_init(TaskPool::new);
+
+    let pem = include_bytes!("../../../assets/cypto/bevy_ping_pong_example.pem");
+    let (mut cert, mut key) = (None, None);
+    for item in read_all(&mut &pem[..]) {
+        match item.unwrap() {
+            Item::X509Certificate(c) => cert = Some(c),
+            Item::Pkcs1Key(k) => key = Some(k),
+            _ => {}
+        }
+    }
+
+    let server = EndPoint::server(
+        ServerConfig::with_single_cert(vec![cert.unwrap()], key.unwrap().into()).unwrap(),
+        SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0),
+    )
+    .unwrap();
+
+    let mut client = EndPoint::client(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).unwrap();
+    client.set_default_client_config(ClientConfig::new(Arc::new(
+        QuicClientConfig::try_from(
+            rustls::ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(SkipServerVerification::new())
+                .with_no_client_auth(),
+        )
+        .unwrap(),
+    )));
+
+    let accepting = server.clone();
+    let echo = IoTaskPool::get().spawn(async move {
+        let connection = accepting.accept().await.unwrap().await.unwrap();
+
+        let datagrams = connection.clone();
+        IoTaskPool::get()
+            .spawn(async move {
+                while let Ok(data) = datagrams.read_datagram().await {
+                    let _ = datagrams.send_datagram(data);
+                }
+            })
+            .detach();
+
+        IoTaskPool::get()
+            .spawn(async move {
+                while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+                    IoTaskPool::get()
+                        .spawn(async move {
+                            let data = recv.read_to_end(usize::MAX).await.unwrap();
+                            send.write_all(&data).await.unwrap();
+                            send.finish().unwrap();
+                        })
+                        .detach();
+                }
+            })
+            .detach();
+    });
+
+    let connection = block_on(async {
+        client
+            .connect(server.local_addr().unwrap(), "localhost")
+            .unwrap()
+            .await
+            .unwrap()
+    });
+    block_on(echo);
+
+    (client, server, connection)
+}
+
+fn loopback_echo(c: &mut Criterion) {
+    let (_client, _server, connection) = connected_pair();
+
+    let mut group = c.benchmark_group("quic_loopback_echo");
+    group.warm_up_time(Duration::from_millis(500));
+    group.measurement_time(Duration::from_secs(4));
+
+    let datagram_size = connection.max_datagram_size().unwrap();
+    for size in PAYLOAD_SIZES
+        .into_iter()
+        .filter(|size| *size <= datagram_size)
+    {
+        let payload = vec![0u8; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(
+            BenchmarkId::new("datagram", size),
+            &payload,
+            |b, payload| {
+                b.iter(|| {
+                    block_on(async {
+                        connection.send_datagram(payload.clone().into()).unwrap();
+                        connection.read_datagram().await.unwrap();
+                    });
+                });
+            },
+        );
+    }
+
+    for size in PAYLOAD_SIZES {
+        let payload = vec![0u8; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("stream", size), &payload, |b, payload| {
+            b.iter(|| {
+                block_on(async {
+                    let (mut send, mut recv) = connection.open_bi().await.unwrap();
+                    send.write_all(payload).await.unwrap();
+                    send.finish().unwrap();
+                    recv.read_to_end(usize::MAX).await.unwrap();
+                });
+            });
+        });
+    }
+
+    group.finish();
+}
+
+/// CPU time consumed by the whole process so far.
+#[cfg(unix)]
+fn process_cpu_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `time` is a valid, writable `timespec` for the duration of the call.
+    let result = unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut time) };
+    assert_eq!(result, 0, "failed to read the process cpu time");
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+/// Measures the CPU time burned by an open but silent connection.
+///
+/// Each iteration sleeps for [`IDLE_WINDOW`] and reports the CPU time the process used meanwhile,
+/// so a runtime that busy-polls its sockets or timers shows up as a large number here.
+#[cfg(unix)]
+fn idle_cpu(c: &mut Criterion) {
+    const IDLE_WINDOW: Duration = Duration::from_millis(100);
+
+    let (_client, _server, _connection) = connected_pair();
+
+    let mut group = c.benchmark_group("quic_idle");
+    group.sample_size(10);
+    group.bench_function("cpu_time_per_100ms", |b| {
+        b.iter_custom(|iters| {
+            let mut total = Duration::ZERO;
+            for _ in 0..iters {
+                let cpu_start = process_cpu_time();
+                std::thread::sleep(IDLE_WINDOW);
+                total += process_cpu_time() - cpu_start;
+            }
+            total
+        });
+    });
+    group.finish();
+}
+
+#[cfg(not(unix))]
+fn idle_cpu(_c: &mut Criterion) {}
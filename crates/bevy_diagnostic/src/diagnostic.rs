@@ -306,6 +306,11 @@ impl DiagnosticsStore {
         self.diagnostics.insert(diagnostic.path.clone(), diagnostic);
     }
 
+    /// Remove a [`Diagnostic`], returning it if it was registered.
+    pub fn remove(&mut self, path: &DiagnosticPath) -> Option<Diagnostic> {
+        self.diagnostics.remove(path)
+    }
+
     pub fn get(&self, path: &DiagnosticPath) -> Option<&Diagnostic> {
         self.diagnostics.get(path)
     }
@@ -0,0 +1,852 @@
+//! Loading assets from another machine over QUIC.
+//!
+//! An [`AssetSourceServer`] serves the assets of any [`AssetReader`], such as the one of the
+//! default `assets` folder, over its own [`EndPoint`]. A [`RemoteAssetReader`] fetches them from
+//! it, and is registered as a named asset source like any other reader:
+//!
+//! ```no_run
+//! # use std::net::SocketAddr;
+//! # use bevy_app::App;
+//! # use bevy_asset::{AssetApp, AssetServer};
+//! # use bevy_net::asset::RemoteAssetReader;
+//! # use bevy_net::quic::EndPoint;
+//! # let mut app = App::new();
+//! # let server_addr: SocketAddr = "192.168.1.2:5000".parse().unwrap();
+//! let endpoint = EndPoint::client("0.0.0.0:0".parse().unwrap()).unwrap();
+//! app.register_asset_source(
+//!     "remote",
+//!     RemoteAssetReader::new(endpoint, server_addr, "devbox")
+//!         .with_cache("remote_cache")
+//!         .source_builder(),
+//! );
+//! // Once the `AssetPlugin` is added:
+//! // asset_server.load::<Image>("remote://textures/grass.png");
+//! ```
+//!
+//! Every request is sent on a bidirectional stream of its own, over a single connection that is
+//! established on the first request and again after it is lost. With a cache, fetched assets are
+//! stored on disk, and the server only sends them again if their contents changed.
+//!
+//! # Hot reloading
+//!
+//! A server given a watcher with [`AssetSourceServer::with_watcher`], such as the
+//! [`file_watcher`] of its asset folder, pushes the [`AssetSourceEvent`]s it reports to the
+//! clients. If their [`AssetServer`] watches for changes, the sources built with
+//! [`RemoteAssetReader::source_builder`] forward those events to it, which reloads the assets as if
+//! they had changed locally. Changes made while a client is disconnected from the server are
+//! missed.
+//!
+//! [`AssetReader`]: bevy_asset::io::AssetReader
+//! [`file_watcher`]: bevy_asset::io::AssetSource::get_default_watcher
+//! [`AssetServer`]: bevy_asset::AssetServer
+
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_io::Timer;
+use async_lock::Mutex as AsyncMutex;
+use bevy_asset::io::{
+    AssetReader, AssetReaderError, AssetSourceBuilder, AssetSourceEvent, AssetWatcher,
+    ErasedAssetReader, PathStream, Reader, VecReader,
+};
+use bevy_tasks::{IoTaskPool, Task};
+use bevy_utils::tracing::warn;
+use crossbeam_channel::Sender;
+use futures_lite::StreamExt;
+use ring::digest::{digest, SHA256, SHA256_OUTPUT_LEN};
+
+use crate::channel::read_stream_varint;
+use crate::quic::{Connection, EndPoint, RecvStream, SendStream};
+use crate::varint::{read_varint, write_varint};
+
+/// Requests the contents of an asset.
+const READ: u64 = 0;
+/// Requests the contents of an asset's meta file.
+const READ_META: u64 = 1;
+/// Requests the paths in a directory.
+const READ_DIRECTORY: u64 = 2;
+/// Requests whether a path is a directory.
+const IS_DIRECTORY: u64 = 3;
+/// Requests the changes to the served assets, sent as events prefixed with their varint length
+/// until the stream is closed.
+const WATCH: u64 = 4;
+
+/// The request succeeded, and its result follows.
+const FOUND: u64 = 0;
+/// The contents match the digest sent by the client.
+const UNCHANGED: u64 = 1;
+/// The path was not found.
+const NOT_FOUND: u64 = 2;
+/// Reading failed, and the error message follows.
+const IO_ERROR: u64 = 3;
+/// The server's reader got an HTTP status, which follows.
+const HTTP_ERROR: u64 = 4;
+
+/// The largest request the server accepts, and the largest change event the client accepts.
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// How long a client waits before watching for changes again after losing the server.
+const WATCH_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// An [`AssetReader`] fetching assets from an [`AssetSourceServer`].
+///
+/// Clones share their connection to the server.
+#[derive(Clone)]
+pub struct RemoteAssetReader {
+    endpoint: EndPoint,
+    server_addr: SocketAddr,
+    server_name: Arc<str>,
+    connection: Arc<AsyncMutex<Option<Connection>>>,
+    cache: Option<PathBuf>,
+    max_asset_size: usize,
+}
+
+impl RemoteAssetReader {
+    /// The default value of [`with_max_asset_size`](Self::with_max_asset_size).
+    pub const DEFAULT_MAX_ASSET_SIZE: usize = 256 * 1024 * 1024;
+
+    /// Creates a reader fetching assets from the server at `server_addr`, connecting with the
+    /// default client configuration of `endpoint`.
+    pub fn new(
+        endpoint: EndPoint,
+        server_addr: SocketAddr,
+        server_name: impl Into<Arc<str>>,
+    ) -> Self {
+        Self {
+            endpoint,
+            server_addr,
+            server_name: server_name.into(),
+            connection: Arc::default(),
+            cache: None,
+            max_asset_size: Self::DEFAULT_MAX_ASSET_SIZE,
+        }
+    }
+
+    /// Stores the fetched assets and meta files in `directory`, so they are only sent again when
+    /// their contents change on the server.
+    pub fn with_cache(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.cache = Some(directory.into());
+        self
+    }
+
+    /// Limits the size of the assets the reader accepts, in bytes.
+    pub fn with_max_asset_size(mut self, max_asset_size: usize) -> Self {
+        self.max_asset_size = max_asset_size;
+        self
+    }
+
+    /// Builds an asset source reading its assets with this reader, and watching for the changes
+    /// the server pushes if the [`AssetServer`](bevy_asset::AssetServer) watches for changes.
+    pub fn source_builder(self) -> AssetSourceBuilder {
+        let watcher = self.clone();
+        AssetSourceBuilder::default()
+            .with_reader(move || Box::new(self.clone()))
+            .with_watcher(move |sender| {
+                let reader = watcher.clone();
+                let task = IoTaskPool::get().spawn(async move { reader.watch(sender).await });
+                Some(Box::new(RemoteAssetWatcher { _task: task }))
+            })
+    }
+
+    /// Gets the connection to the server, connecting if there is none.
+    ///
+    /// The lock is held while connecting, so concurrent requests share a single new connection.
+    async fn connection(&self) -> Result<Connection, AssetReaderError> {
+        let mut current = self.connection.lock().await;
+        if let Some(connection) = current.as_ref().filter(|c| c.close_reason().is_none()) {
+            return Ok(connection.clone());
+        }
+        let connection = self
+            .endpoint
+            .connect(self.server_addr, &self.server_name)
+            .map_err(io::Error::other)?
+            .await
+            .map_err(io::Error::other)?;
+        *current = Some(connection.clone());
+        Ok(connection)
+    }
+
+    /// Sends `request`, returning the response once the server finished it.
+    async fn request(&self, request: &[u8]) -> Result<Vec<u8>, AssetReaderError> {
+        let (mut send, mut recv) = self
+            .connection()
+            .await?
+            .open_bi()
+            .await
+            .map_err(io::Error::other)?;
+        send.write_all(request).await.map_err(io::Error::other)?;
+        send.finish().map_err(io::Error::other)?;
+        let response = recv
+            .read_to_end(self.max_asset_size.saturating_add(MAX_REQUEST_SIZE))
+            .await
+            .map_err(io::Error::other)?;
+        Ok(response)
+    }
+
+    /// Fetches the contents of an asset or of its meta file, through the cache if there is one.
+    async fn fetch(&self, kind: u64, path: &Path) -> Result<VecReader, AssetReaderError> {
+        let cache_path = self
+            .cache
+            .as_ref()
+            .filter(|_| is_relative(path))
+            .map(|cache| {
+                let directory = if kind == READ_META { "meta" } else { "assets" };
+                cache.join(directory).join(path)
+            });
+        let cached = cache_path
+            .as_ref()
+            .and_then(|cache_path| std::fs::read(cache_path).ok());
+
+        let mut request = Vec::new();
+        write_request(&mut request, kind, path)?;
+        match &cached {
+            Some(cached) => {
+                request.push(1);
+                request.extend_from_slice(digest(&SHA256, cached).as_ref());
+            }
+            None => request.push(0),
+        }
+
+        let response = self.request(&request).await?;
+        let mut bytes = &response[..];
+        let status = read_status(&mut bytes, path);
+        if let (Err(AssetReaderError::NotFound(_)), Some(cache_path)) = (&status, &cache_path) {
+            let _ = std::fs::remove_file(cache_path);
+        }
+        let contents = match status? {
+            Some(bytes) => {
+                let contents = bytes.to_vec();
+                if let Some(cache_path) = &cache_path {
+                    if let Err(error) = write_cache(cache_path, &contents) {
+                        warn!("cannot cache {}: {error}", path.display());
+                    }
+                }
+                contents
+            }
+            None => cached.ok_or_else(|| malformed("unexpected unchanged response"))?,
+        };
+        Ok(VecReader::new(contents))
+    }
+
+    /// Forwards the changes pushed by the server to `sender` until it is disconnected, watching
+    /// again whenever the server is lost.
+    async fn watch(&self, sender: Sender<AssetSourceEvent>) {
+        let mut watching = true;
+        loop {
+            match self.forward_changes(&sender, &mut watching).await {
+                Ok(()) => return,
+                // Only the first of consecutive failures is reported.
+                Err(error) if watching => {
+                    warn!("stopped receiving asset changes from the server: {error}");
+                    watching = false;
+                }
+                Err(_) => {}
+            }
+            Timer::after(WATCH_RETRY_DELAY).await;
+        }
+    }
+
+    /// Forwards the changes pushed by the server on a single stream, returning `Ok` once `sender`
+    /// is disconnected.
+    async fn forward_changes(
+        &self,
+        sender: &Sender<AssetSourceEvent>,
+        watching: &mut bool,
+    ) -> Result<(), AssetReaderError> {
+        let mut request = Vec::new();
+        write_request(&mut request, WATCH, Path::new(""))?;
+        let (mut send, mut recv) = self
+            .connection()
+            .await?
+            .open_bi()
+            .await
+            .map_err(io::Error::other)?;
+        send.write_all(&request).await.map_err(io::Error::other)?;
+        send.finish().map_err(io::Error::other)?;
+        *watching = true;
+
+        loop {
+            let len = read_stream_varint(&mut recv)
+                .await
+                .map_err(io::Error::other)?
+                .ok_or_else(|| io::Error::other("the server stopped pushing asset changes"))?;
+            let len = len as usize;
+            if len > MAX_REQUEST_SIZE {
+                return Err(malformed("oversized asset change"));
+            }
+            let mut event = vec![0; len];
+            recv.read_exact(&mut event)
+                .await
+                .map_err(io::Error::other)?;
+            let event =
+                read_event(&mut &event[..]).ok_or_else(|| malformed("invalid asset change"))?;
+            if sender.send(event).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Forwards the changes pushed by an [`AssetSourceServer`] to an asset source, for as long as it
+/// is kept.
+struct RemoteAssetWatcher {
+    _task: Task<()>,
+}
+
+impl AssetWatcher for RemoteAssetWatcher {}
+
+impl AssetReader for RemoteAssetReader {
+    async fn read<'a>(&'a self, path: &'a Path) -> Result<impl Reader + 'a, AssetReaderError> {
+        self.fetch(READ, path).await
+    }
+
+    async fn read_meta<'a>(&'a self, path: &'a Path) -> Result<impl Reader + 'a, AssetReaderError> {
+        self.fetch(READ_META, path).await
+    }
+
+    async fn read_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> Result<Box<PathStream>, AssetReaderError> {
+        let mut request = Vec::new();
+        write_request(&mut request, READ_DIRECTORY, path)?;
+        let response = self.request(&request).await?;
+        let mut bytes = &response[..];
+        let mut bytes = read_status(&mut bytes, path)?.ok_or_else(|| malformed("unchanged"))?;
+
+        let count = read_varint(&mut bytes).ok_or_else(|| malformed("missing path count"))?;
+        let mut paths = Vec::new();
+        for _ in 0..count {
+            let path = read_path(&mut bytes).ok_or_else(|| malformed("invalid path"))?;
+            paths.push(path);
+        }
+        Ok(Box::new(futures_lite::stream::iter(paths)))
+    }
+
+    async fn is_directory<'a>(&'a self, path: &'a Path) -> Result<bool, AssetReaderError> {
+        let mut request = Vec::new();
+        write_request(&mut request, IS_DIRECTORY, path)?;
+        let response = self.request(&request).await?;
+        let mut bytes = &response[..];
+        match read_status(&mut bytes, path)? {
+            Some([is_directory]) => Ok(*is_directory != 0),
+            _ => Err(malformed("invalid directory flag")),
+        }
+    }
+}
+
+/// Serves the assets of an [`AssetReader`] to [`RemoteAssetReader`]s.
+///
+/// Accepts every connection made to its endpoint for as long as it is kept, so the endpoint must
+/// not be used for anything else, such as being spawned for the
+/// [`QuicNetworkPlugin`](crate::quic::QuicNetworkPlugin). Requests for paths leaving the reader's
+/// root, through `..` or absolute paths, are answered as not found.
+///
+/// [`AssetReader`]: bevy_asset::io::AssetReader
+pub struct AssetSourceServer {
+    endpoint: EndPoint,
+    source: Arc<ServedSource>,
+    watcher: Option<Box<dyn AssetWatcher>>,
+    _accept_task: Task<()>,
+}
+
+/// What the tasks serving the clients share.
+struct ServedSource {
+    reader: Box<dyn ErasedAssetReader>,
+    /// The encoded changes to send on each stream watching for them.
+    watchers: Mutex<Vec<async_channel::Sender<Arc<[u8]>>>>,
+}
+
+impl AssetSourceServer {
+    /// Serves the assets of `reader` on `endpoint`, which needs a server configuration.
+    ///
+    /// The reader of an existing source can be built with the functions of
+    /// [`AssetSource`](bevy_asset::io::AssetSource), for example
+    /// `AssetSource::get_default_reader("assets".to_string())()`.
+    pub fn new(endpoint: EndPoint, reader: Box<dyn ErasedAssetReader>) -> Self {
+        let source = Arc::new(ServedSource {
+            reader,
+            watchers: Mutex::default(),
+        });
+        let accept_task = {
+            let endpoint = endpoint.clone();
+            let source = source.clone();
+            IoTaskPool::get().spawn(async move {
+                while let Some(incoming) = endpoint.accept().await {
+                    let source = source.clone();
+                    IoTaskPool::get()
+                        .spawn(async move {
+                            if let Ok(connection) = incoming.await {
+                                serve_connection(connection, source).await;
+                            }
+                        })
+                        .detach();
+                }
+            })
+        };
+        Self {
+            endpoint,
+            source,
+            watcher: None,
+            _accept_task: accept_task,
+        }
+    }
+
+    /// Pushes the changes reported by the watcher `watcher` builds to the clients watching for
+    /// them, for example with
+    /// `AssetSource::get_default_watcher("assets".to_string(), Duration::from_millis(300))`.
+    ///
+    /// The events are forwarded from a thread of their own, which stops along with the watcher.
+    pub fn with_watcher(
+        mut self,
+        mut watcher: impl FnMut(Sender<AssetSourceEvent>) -> Option<Box<dyn AssetWatcher>>,
+    ) -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        self.watcher = watcher(sender);
+        let source = self.source.clone();
+        std::thread::Builder::new()
+            .name("asset change push".into())
+            .spawn(move || {
+                // Ends once the watcher drops its sender.
+                while let Ok(event) = receiver.recv() {
+                    let mut encoded = Vec::new();
+                    if let Err(error) = write_event(&mut encoded, &event) {
+                        warn!("cannot push an asset change: {error}");
+                        continue;
+                    }
+                    let mut frame = Vec::new();
+                    write_varint(&mut frame, encoded.len() as u64);
+                    frame.extend_from_slice(&encoded);
+                    let frame: Arc<[u8]> = frame.into();
+                    source
+                        .watchers
+                        .lock()
+                        .unwrap()
+                        .retain(|watcher| watcher.try_send(frame.clone()).is_ok());
+                }
+            })
+            .expect("failed to spawn the asset change thread");
+        self
+    }
+
+    /// Gets the endpoint the assets are served on.
+    pub fn endpoint(&self) -> &EndPoint {
+        &self.endpoint
+    }
+}
+
+impl Drop for AssetSourceServer {
+    fn drop(&mut self) {
+        // Finishes the streams of the clients watching for changes.
+        self.source.watchers.lock().unwrap().clear();
+    }
+}
+
+async fn serve_connection(connection: Connection, source: Arc<ServedSource>) {
+    while let Ok((send, recv)) = connection.accept_bi().await {
+        let source = source.clone();
+        IoTaskPool::get()
+            .spawn(async move {
+                if let Err(error) = serve_request(send, recv, &source).await {
+                    warn!("failed to serve an asset request: {error}");
+                }
+            })
+            .detach();
+    }
+}
+
+async fn serve_request(
+    mut send: SendStream,
+    mut recv: RecvStream,
+    source: &ServedSource,
+) -> io::Result<()> {
+    let request = recv
+        .read_to_end(MAX_REQUEST_SIZE)
+        .await
+        .map_err(io::Error::other)?;
+    let mut bytes = &request[..];
+    let kind = read_varint(&mut bytes).ok_or_else(|| io::Error::other("missing request kind"))?;
+    let path = read_path(&mut bytes).ok_or_else(|| io::Error::other("invalid request path"))?;
+
+    if kind == WATCH {
+        let (sender, receiver) = async_channel::unbounded();
+        source.watchers.lock().unwrap().push(sender);
+        while let Ok(frame) = receiver.recv().await {
+            send.write_all(&frame).await.map_err(io::Error::other)?;
+        }
+        send.finish().map_err(io::Error::other)?;
+        return Ok(());
+    }
+
+    let reader = &*source.reader;
+
+    let mut response = Vec::new();
+    let result = if !is_relative(&path) {
+        Err(AssetReaderError::NotFound(path))
+    } else {
+        match kind {
+            READ | READ_META => {
+                let known_digest = match bytes {
+                    [0] => None,
+                    [1, digest @ ..] if digest.len() == SHA256_OUTPUT_LEN => Some(digest),
+                    _ => return Err(io::Error::other("invalid request digest")),
+                };
+                let contents = if kind == READ {
+                    reader.read(&path).await
+                } else {
+                    reader.read_meta(&path).await
+                };
+                match contents {
+                    Ok(mut contents) => {
+                        let mut buf = Vec::new();
+                        contents.read_to_end(&mut buf).await?;
+                        if known_digest == Some(digest(&SHA256, &buf).as_ref()) {
+                            write_varint(&mut response, UNCHANGED);
+                        } else {
+                            write_varint(&mut response, FOUND);
+                            response.extend_from_slice(&buf);
+                        }
+                        Ok(())
+                    }
+                    Err(error) => Err(error),
+                }
+            }
+            READ_DIRECTORY => match reader.read_directory(&path).await {
+                Ok(stream) => {
+                    let paths: Vec<_> = stream.collect().await;
+                    write_varint(&mut response, FOUND);
+                    write_varint(&mut response, paths.len() as u64);
+                    for path in paths {
+                        write_path(&mut response, &path)?;
+                    }
+                    Ok(())
+                }
+                Err(error) => Err(error),
+            },
+            IS_DIRECTORY => match reader.is_directory(&path).await {
+                Ok(is_directory) => {
+                    write_varint(&mut response, FOUND);
+                    response.push(is_directory.into());
+                    Ok(())
+                }
+                Err(error) => Err(error),
+            },
+            _ => return Err(io::Error::other(format!("unknown request kind {kind}"))),
+        }
+    };
+
+    if let Err(error) = result {
+        match error {
+            AssetReaderError::NotFound(_) => write_varint(&mut response, NOT_FOUND),
+            AssetReaderError::Io(error) => {
+                write_varint(&mut response, IO_ERROR);
+                response.extend_from_slice(error.to_string().as_bytes());
+            }
+            AssetReaderError::HttpError(status) => {
+                write_varint(&mut response, HTTP_ERROR);
+                write_varint(&mut response, u64::from(status));
+            }
+        }
+    }
+    send.write_all(&response).await.map_err(io::Error::other)?;
+    send.finish().map_err(io::Error::other)?;
+    Ok(())
+}
+
+/// Whether `path` stays within the root it is relative to.
+fn is_relative(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+fn write_event(buf: &mut Vec<u8>, event: &AssetSourceEvent) -> io::Result<()> {
+    use AssetSourceEvent::*;
+    let (tag, path, new) = match event {
+        AddedAsset(path) => (0, path, None),
+        ModifiedAsset(path) => (1, path, None),
+        RemovedAsset(path) => (2, path, None),
+        RenamedAsset { old, new } => (3, old, Some(new)),
+        AddedMeta(path) => (4, path, None),
+        ModifiedMeta(path) => (5, path, None),
+        RemovedMeta(path) => (6, path, None),
+        RenamedMeta { old, new } => (7, old, Some(new)),
+        AddedFolder(path) => (8, path, None),
+        RemovedFolder(path) => (9, path, None),
+        RenamedFolder { old, new } => (10, old, Some(new)),
+        RemovedUnknown { path, is_meta } => (11 + u64::from(*is_meta), path, None),
+    };
+    write_varint(buf, tag);
+    write_path(buf, path)?;
+    if let Some(new) = new {
+        write_path(buf, new)?;
+    }
+    Ok(())
+}
+
+fn read_event(bytes: &mut &[u8]) -> Option<AssetSourceEvent> {
+    use AssetSourceEvent::*;
+    let tag = read_varint(bytes)?;
+    let path = read_path(bytes)?;
+    Some(match tag {
+        0 => AddedAsset(path),
+        1 => ModifiedAsset(path),
+        2 => RemovedAsset(path),
+        3 => RenamedAsset {
+            old: path,
+            new: read_path(bytes)?,
+        },
+        4 => AddedMeta(path),
+        5 => ModifiedMeta(path),
+        6 => RemovedMeta(path),
+        7 => RenamedMeta {
+            old: path,
+            new: read_path(bytes)?,
+        },
+        8 => AddedFolder(path),
+        9 => RemovedFolder(path),
+        10 => RenamedFolder {
+            old: path,
+            new: read_path(bytes)?,
+        },
+        11 | 12 => RemovedUnknown {
+            path,
+            is_meta: tag == 12,
+        },
+        _ => return None,
+    })
+}
+
+fn write_request(buf: &mut Vec<u8>, kind: u64, path: &Path) -> Result<(), AssetReaderError> {
+    write_varint(buf, kind);
+    write_path(buf, path).map_err(|_| AssetReaderError::NotFound(path.to_path_buf()))
+}
+
+fn write_path(buf: &mut Vec<u8>, path: &Path) -> io::Result<()> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| io::Error::other(format!("{} is not valid UTF-8", path.display())))?;
+    write_varint(buf, path.len() as u64);
+    buf.extend_from_slice(path.as_bytes());
+    Ok(())
+}
+
+fn read_path(bytes: &mut &[u8]) -> Option<PathBuf> {
+    let len = usize::try_from(read_varint(bytes)?).ok()?;
+    let path = std::str::from_utf8(bytes.get(..len)?).ok()?.into();
+    *bytes = &bytes[len..];
+    Some(path)
+}
+
+/// Reads the status at the front of a response, returning what follows it, or `None` if the
+/// contents are unchanged.
+fn read_status<'a>(
+    bytes: &mut &'a [u8],
+    path: &Path,
+) -> Result<Option<&'a [u8]>, AssetReaderError> {
+    match read_varint(bytes) {
+        Some(FOUND) => Ok(Some(bytes)),
+        Some(UNCHANGED) => Ok(None),
+        Some(NOT_FOUND) => Err(AssetReaderError::NotFound(path.to_path_buf())),
+        Some(IO_ERROR) => Err(io::Error::other(String::from_utf8_lossy(bytes).into_owned()).into()),
+        Some(HTTP_ERROR) => {
+            let status = read_varint(bytes).and_then(|status| u16::try_from(status).ok());
+            Err(AssetReaderError::HttpError(
+                status.ok_or_else(|| malformed("invalid status"))?,
+            ))
+        }
+        _ => Err(malformed("unknown response status")),
+    }
+}
+
+fn write_cache(cache_path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(parent) = cache_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(cache_path, contents)
+}
+
+fn malformed(message: &str) -> AssetReaderError {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("malformed asset server response: {message}"),
+    )
+    .into()
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod quic_tests {
+    use super::*;
+    use crate::quic::test_utils::{client_config, init_task_pool, server_config};
+    use crate::quic::{EndpointConfig, LoopbackNetwork};
+    use bevy_asset::io::memory::{Dir, MemoryAssetReader};
+    use futures_lite::future::block_on;
+    use std::time::Instant;
+
+    fn read_to_end(mut reader: impl Reader) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        block_on(reader.read_to_end(&mut bytes)).unwrap();
+        bytes
+    }
+
+    /// Creates a server and a client endpoint over a [`LoopbackNetwork`], along with the server's
+    /// address.
+    fn endpoints() -> (EndPoint, EndPoint, SocketAddr) {
+        init_task_pool();
+        let (server_socket, client_socket) = LoopbackNetwork::pair();
+        let server_addr = server_socket.local_addr().unwrap();
+        let server_endpoint = EndPoint::new_with_abstract_socket(
+            EndpointConfig::default(),
+            Some(server_config()),
+            server_socket,
+        )
+        .unwrap();
+        let mut client_endpoint =
+            EndPoint::new_with_abstract_socket(EndpointConfig::default(), None, client_socket)
+                .unwrap();
+        client_endpoint.set_default_client_config(client_config());
+        (server_endpoint, client_endpoint, server_addr)
+    }
+
+    #[test]
+    fn fetches_assets_from_a_server() {
+        let (server_endpoint, client_endpoint, server_addr) = endpoints();
+
+        let root = Dir::default();
+        root.insert_asset_text(Path::new("textures/grass.txt"), "green");
+        root.insert_meta_text(Path::new("textures/grass.txt"), "(meta)");
+        let _server = AssetSourceServer::new(
+            server_endpoint,
+            Box::new(MemoryAssetReader { root: root.clone() }),
+        );
+
+        let cache =
+            std::env::temp_dir().join(format!("bevy_net_asset_cache_{}", std::process::id()));
+        let reader: Box<dyn ErasedAssetReader> = Box::new(
+            RemoteAssetReader::new(client_endpoint, server_addr, "localhost").with_cache(&cache),
+        );
+        let path = Path::new("textures/grass.txt");
+        assert_eq!(read_to_end(block_on(reader.read(path)).unwrap()), b"green");
+        assert_eq!(
+            read_to_end(block_on(reader.read_meta(path)).unwrap()),
+            b"(meta)"
+        );
+        assert_eq!(
+            std::fs::read(cache.join("assets").join(path)).unwrap(),
+            b"green"
+        );
+
+        // Cached contents are used while they match, and replaced once they change.
+        assert_eq!(read_to_end(block_on(reader.read(path)).unwrap()), b"green");
+        root.insert_asset_text(path, "brown");
+        assert_eq!(read_to_end(block_on(reader.read(path)).unwrap()), b"brown");
+
+        assert!(block_on(reader.is_directory(Path::new("textures"))).unwrap());
+        assert!(!block_on(reader.is_directory(path)).unwrap());
+        let paths: Vec<_> = block_on(async {
+            let directory = reader.read_directory(Path::new("textures")).await;
+            directory.unwrap().collect().await
+        });
+        assert_eq!(paths, [PathBuf::from("textures/grass.txt")]);
+
+        assert_eq!(
+            block_on(reader.read(Path::new("missing.txt"))).err(),
+            Some(AssetReaderError::NotFound("missing.txt".into()))
+        );
+        assert_eq!(
+            block_on(reader.read(Path::new("../secret.txt"))).err(),
+            Some(AssetReaderError::NotFound("../secret.txt".into()))
+        );
+        let _ = std::fs::remove_dir_all(cache);
+    }
+
+    #[test]
+    fn shares_one_connection_between_concurrent_requests() {
+        let (server_endpoint, client_endpoint, server_addr) = endpoints();
+
+        let root = Dir::default();
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+            root.insert_asset_text(Path::new(name), name);
+        }
+        let _server = AssetSourceServer::new(server_endpoint, Box::new(MemoryAssetReader { root }));
+
+        let reader = RemoteAssetReader::new(client_endpoint.clone(), server_addr, "localhost");
+        let read = |name: &'static str| {
+            let reader = reader.clone();
+            IoTaskPool::get().spawn(async move {
+                let mut bytes = Vec::new();
+                let mut asset = AssetReader::read(&reader, Path::new(name)).await.unwrap();
+                asset.read_to_end(&mut bytes).await.unwrap();
+                bytes
+            })
+        };
+        let tasks = [read("a.txt"), read("b.txt"), read("c.txt"), read("d.txt")];
+        for (task, name) in tasks.into_iter().zip(["a.txt", "b.txt", "c.txt", "d.txt"]) {
+            assert_eq!(block_on(task), name.as_bytes());
+        }
+        assert_eq!(client_endpoint.open_connections(), 1);
+    }
+
+    struct TestWatcher;
+
+    impl AssetWatcher for TestWatcher {}
+
+    #[test]
+    fn pushes_asset_changes() {
+        let (server_endpoint, client_endpoint, server_addr) = endpoints();
+        let (changes, changes_receiver) = crossbeam_channel::bounded(1);
+        let _server = AssetSourceServer::new(
+            server_endpoint,
+            Box::new(MemoryAssetReader {
+                root: Dir::default(),
+            }),
+        )
+        .with_watcher(move |sender| {
+            changes.send(sender).unwrap();
+            Some(Box::new(TestWatcher))
+        });
+        let changes = changes_receiver.recv().unwrap();
+
+        let mut source =
+            RemoteAssetReader::new(client_endpoint, server_addr, "localhost").source_builder();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let _watcher = source.watcher.as_mut().unwrap()(sender).unwrap();
+
+        // Changes made before the client watches for them are missed.
+        let modified = AssetSourceEvent::ModifiedAsset("textures/grass.png".into());
+        let start = Instant::now();
+        loop {
+            changes.send(modified.clone()).unwrap();
+            if let Ok(event) = receiver.recv_timeout(Duration::from_millis(10)) {
+                assert_eq!(event, modified);
+                break;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "no change received"
+            );
+        }
+
+        let events = [
+            AssetSourceEvent::RenamedMeta {
+                old: "a.ron".into(),
+                new: "b.ron".into(),
+            },
+            AssetSourceEvent::RemovedUnknown {
+                path: "c".into(),
+                is_meta: true,
+            },
+        ];
+        for event in &events {
+            changes.send(event.clone()).unwrap();
+        }
+        let received: Vec<_> =
+            std::iter::from_fn(|| receiver.recv_timeout(Duration::from_secs(5)).ok())
+                .filter(|event| *event != modified)
+                .take(events.len())
+                .collect();
+        assert_eq!(received, events);
+    }
+}
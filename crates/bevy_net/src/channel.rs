@@ -0,0 +1,960 @@
+//! Typed message channels on top of QUIC streams and datagrams.
+//!
+//! Every message type registered with [`MessageApp::add_message`] gets its own channel, configured
+//! with a [`ChannelConfig`] that picks the delivery guarantee. Systems queue outgoing messages with a
+//! [`MessageWriter`] and read the messages received this frame with a [`MessageReader`]. Queued
+//! messages are flushed to the connections in [`NetworkSystem::Send`], and incoming data is drained
+//! once per frame in [`NetworkSystem::Receive`].
+//!
+//! Channels are identified on the wire by the order they were registered in, so both peers must
+//! register the same message types in the same order.
+//!
+//! Connections of a [`TransportEndPoint`] carry the channels through its [`NetTransport`], which
+//! picks its own wire format. The format below is the one of QUIC connections.
+//!
+//! # Wire format
+//!
+//! All integers are variable length encoded, and every message is prefixed with its length.
+//!
+//! - [`ChannelKind::ReliableOrdered`] channels open one unidirectional stream per connection, which
+//!   starts with the channel id and carries the messages back to back. If that stream fails, the
+//!   connection is closed with [`MESSAGE_STREAM_FAILED`] rather than the stream being reopened, as
+//!   the messages of both streams could be read out of order.
+//! - [`ChannelKind::ReliableUnordered`] channels open a new unidirectional stream for every message,
+//!   holding the channel id followed by the message.
+//! - [`ChannelKind::Unreliable`] channels send one datagram per message, holding the channel id
+//!   followed by the message.
+//! - [`ChannelKind::UnreliableSequenced`] channels do the same, but add a sequence number after the
+//!   channel id, so receivers can discard messages older than one they already read.
+
+use std::sync::Arc;
+
+use bevy_app::{App, PostUpdate, PreUpdate};
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemParam;
+use bevy_tasks::{IoTaskPool, Task};
+use bevy_utils::tracing::warn;
+use bevy_utils::HashMap;
+use bytes::Bytes;
+use crossbeam_channel::{Receiver, Sender};
+use thiserror::Error;
+
+use crate::quic::{
+    Connected, Connection, ConnectionEndPoint, ConnectionState, NetworkSystem, QuicConnection,
+    ReadExactError, RecvStream, SendDatagramError, SendStream, VarInt,
+};
+use crate::transport::{NetTransport, TransportConnection, TransportEndPoint};
+use crate::varint::{read_varint, write_varint, MAX_VARINT_LEN};
+
+/// The delivery guarantee of a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelKind {
+    /// Messages always arrive, in the order they were sent.
+    ReliableOrdered,
+    /// Messages always arrive, but a message may overtake the ones sent before it.
+    ReliableUnordered,
+    /// Messages may be lost or arrive out of order.
+    Unreliable,
+    /// Messages may be lost, and any message older than the newest one received is dropped.
+    UnreliableSequenced,
+}
+
+impl ChannelKind {
+    /// Whether the channel is carried by streams rather than datagrams.
+    pub fn is_reliable(self) -> bool {
+        matches!(self, Self::ReliableOrdered | Self::ReliableUnordered)
+    }
+}
+
+/// The configuration of a message channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelConfig {
+    /// The delivery guarantee of the channel.
+    pub kind: ChannelKind,
+    /// The largest encoded message the channel accepts, larger messages are dropped by the sender
+    /// with a warning, and by the receiver if the peer sends them anyway.
+    ///
+    /// Messages on unreliable channels are additionally limited by
+    /// [`Connection::max_datagram_size`].
+    pub max_message_size: usize,
+}
+
+impl ChannelConfig {
+    /// The default value of [`max_message_size`](Self::max_message_size).
+    pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+    /// Creates a configuration for a channel of the given kind.
+    pub const fn new(kind: ChannelKind) -> Self {
+        Self {
+            kind,
+            max_message_size: Self::DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Sets [`max_message_size`](Self::max_message_size).
+    pub const fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+}
+
+/// Identifies a channel on the wire, assigned in registration order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChannelId(u32);
+
+impl ChannelId {
+    /// Creates the id of the channel registered at index `id`, such as one read from the wire by
+    /// a [`NetTransport`].
+    pub const fn new(id: u32) -> Self {
+        Self(id)
+    }
+
+    /// The index of the channel in the [`MessageRegistry`].
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A type that can be sent through a message channel.
+pub trait NetworkMessage: Send + Sync + 'static + Sized {
+    /// Appends the encoded message to `buf`.
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Decodes a message previously encoded with [`encode`](Self::encode).
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError>;
+}
+
+/// An error produced when a received message could not be decoded.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("failed to decode network message: {0}")]
+pub struct DecodeError(pub String);
+
+impl NetworkMessage for Vec<u8> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl NetworkMessage for Bytes {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Bytes::copy_from_slice(bytes))
+    }
+}
+
+impl NetworkMessage for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        String::from_utf8(bytes.to_vec()).map_err(|error| DecodeError(error.to_string()))
+    }
+}
+
+/// Holds the configuration of every registered channel.
+#[derive(Resource, Debug, Default)]
+pub struct MessageRegistry {
+    channels: Vec<ChannelConfig>,
+}
+
+impl MessageRegistry {
+    /// Gets the configuration of a channel.
+    pub fn get(&self, channel: ChannelId) -> Option<&ChannelConfig> {
+        self.channels.get(channel.index())
+    }
+
+    /// Iterates over every registered channel.
+    pub fn iter(&self) -> impl Iterator<Item = (ChannelId, &ChannelConfig)> {
+        self.channels
+            .iter()
+            .enumerate()
+            .map(|(index, config)| (ChannelId(index as u32), config))
+    }
+
+    fn register(&mut self, config: ChannelConfig) -> ChannelId {
+        let id = ChannelId(self.channels.len() as u32);
+        self.channels.push(config);
+        id
+    }
+}
+
+/// Adds message channels to an [`App`].
+pub trait MessageApp {
+    /// Registers a channel for messages of type `T`, enabling [`MessageReader<T>`] and
+    /// [`MessageWriter<T>`].
+    ///
+    /// Both peers must register the same message types in the same order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` was already registered.
+    fn add_message<T: NetworkMessage>(&mut self, config: ChannelConfig) -> &mut Self;
+}
+
+impl MessageApp for App {
+    fn add_message<T: NetworkMessage>(&mut self, config: ChannelConfig) -> &mut Self {
+        assert!(
+            !self.world().contains_resource::<OutgoingMessages<T>>(),
+            "message type {} was already registered",
+            std::any::type_name::<T>()
+        );
+
+        if !self.world().contains_resource::<MessageRegistry>() {
+            self.init_resource::<MessageRegistry>()
+                .init_resource::<ReceivedPayloads>()
+                .observe(start_messaging)
+                .add_systems(
+                    PreUpdate,
+                    receive_payloads
+                        .in_set(NetworkSystem::Receive)
+                        .before(DecodeMessages),
+                );
+        }
+
+        let channel = self
+            .world_mut()
+            .resource_mut::<MessageRegistry>()
+            .register(config);
+
+        self.insert_resource(OutgoingMessages::<T> {
+            channel,
+            messages: Vec::new(),
+        })
+        .insert_resource(ReceivedMessages::<T> {
+            channel,
+            messages: Vec::new(),
+        })
+        .add_systems(
+            PreUpdate,
+            decode_messages::<T>
+                .in_set(NetworkSystem::Receive)
+                .in_set(DecodeMessages),
+        )
+        .add_systems(PostUpdate, send_messages::<T>.in_set(NetworkSystem::Send))
+    }
+}
+
+/// Decodes the payloads drained from the connections into typed messages.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemSet)]
+struct DecodeMessages;
+
+/// Messages of type `T` waiting to be sent.
+#[derive(Resource)]
+struct OutgoingMessages<T> {
+    channel: ChannelId,
+    messages: Vec<(Option<Entity>, T)>,
+}
+
+/// Messages of type `T` received this frame, with the connection entity they arrived on.
+#[derive(Resource)]
+struct ReceivedMessages<T> {
+    channel: ChannelId,
+    messages: Vec<(Entity, T)>,
+}
+
+/// Queues messages of type `T` to be sent at the end of the frame.
+#[derive(SystemParam)]
+pub struct MessageWriter<'w, T: NetworkMessage> {
+    outgoing: ResMut<'w, OutgoingMessages<T>>,
+}
+
+impl<T: NetworkMessage> MessageWriter<'_, T> {
+    /// Sends `message` to the connection entity `connection`.
+    pub fn send(&mut self, connection: Entity, message: T) {
+        self.outgoing.messages.push((Some(connection), message));
+    }
+
+    /// Sends `message` to every connected peer.
+    pub fn broadcast(&mut self, message: T) {
+        self.outgoing.messages.push((None, message));
+    }
+}
+
+/// Reads the messages of type `T` received this frame.
+#[derive(SystemParam)]
+pub struct MessageReader<'w, T: NetworkMessage> {
+    received: Res<'w, ReceivedMessages<T>>,
+}
+
+impl<T: NetworkMessage> MessageReader<'_, T> {
+    /// Iterates over the messages received this frame, along with the connection entity each one
+    /// arrived on.
+    pub fn read(&self) -> impl ExactSizeIterator<Item = (Entity, &T)> {
+        self.received
+            .messages
+            .iter()
+            .map(|(connection, message)| (*connection, message))
+    }
+
+    /// The number of messages received this frame.
+    pub fn len(&self) -> usize {
+        self.received.messages.len()
+    }
+
+    /// Whether no messages were received this frame.
+    pub fn is_empty(&self) -> bool {
+        self.received.messages.is_empty()
+    }
+}
+
+/// A message payload read by one of a connection's tasks.
+struct Payload {
+    channel: ChannelId,
+    sequence: Option<u64>,
+    bytes: Bytes,
+}
+
+/// The error code connections are closed with when the stream of an ordered channel fails, as
+/// the messages queued on it cannot be delivered in order anymore.
+pub const MESSAGE_STREAM_FAILED: VarInt = VarInt::from_u32(0x5354_524d);
+
+/// The message channels of a QUIC connection, read and written by background tasks.
+pub(crate) struct QuicChannels {
+    connection: Connection,
+    payloads: Receiver<Payload>,
+    /// The framed messages to write to the stream of every ordered channel, each written by a
+    /// task of its own so a blocked channel does not hold up the others.
+    ordered: HashMap<ChannelId, async_channel::Sender<Vec<u8>>>,
+    /// The next sequence number to send, for every sequenced channel.
+    next_sequence: HashMap<ChannelId, u64>,
+    /// The newest sequence number received, for every sequenced channel.
+    last_sequence: HashMap<ChannelId, u64>,
+    _tasks: Vec<Task<()>>,
+}
+
+impl QuicChannels {
+    /// Starts reading and writing the message channels of `connection`.
+    pub(crate) fn new(connection: Connection, channels: Arc<[ChannelConfig]>) -> Self {
+        let (payload_sender, payloads) = crossbeam_channel::unbounded();
+
+        let pool = IoTaskPool::get();
+        let mut ordered = HashMap::default();
+        let mut tasks = Vec::new();
+        for (index, config) in channels.iter().enumerate() {
+            if config.kind == ChannelKind::ReliableOrdered {
+                let channel = ChannelId(index as u32);
+                let (frames, frame_receiver) = async_channel::unbounded();
+                ordered.insert(channel, frames);
+                tasks.push(pool.spawn(write_ordered_stream(
+                    connection.clone(),
+                    channel,
+                    frame_receiver,
+                )));
+            }
+        }
+        tasks.push(pool.spawn(read_streams(
+            connection.clone(),
+            channels.clone(),
+            payload_sender.clone(),
+        )));
+        tasks.push(pool.spawn(read_datagrams(connection.clone(), channels, payload_sender)));
+
+        Self {
+            connection,
+            payloads,
+            ordered,
+            next_sequence: HashMap::default(),
+            last_sequence: HashMap::default(),
+            _tasks: tasks,
+        }
+    }
+
+    /// Iterates over the messages received since the last call, skipping stale sequenced messages.
+    pub(crate) fn receive(&mut self) -> impl Iterator<Item = (ChannelId, Bytes)> + '_ {
+        let last_sequence = &mut self.last_sequence;
+        self.payloads.try_iter().filter_map(move |payload| {
+            if let Some(sequence) = payload.sequence {
+                // Stale sequenced messages are superseded by one that was already received.
+                let last = last_sequence.get(&payload.channel);
+                if last.is_some_and(|last| sequence <= *last) {
+                    return None;
+                }
+                last_sequence.insert(payload.channel, sequence);
+            }
+            Some((payload.channel, payload.bytes))
+        })
+    }
+
+    /// Sends `payload` on a channel of the given kind.
+    pub(crate) fn send(
+        &mut self,
+        channel: ChannelId,
+        kind: ChannelKind,
+        payload: &[u8],
+    ) -> Result<(), SendDatagramError> {
+        let mut bytes = Vec::with_capacity(payload.len() + 2 * MAX_VARINT_LEN);
+        match kind {
+            ChannelKind::ReliableOrdered => {
+                write_varint(&mut bytes, payload.len() as u64);
+                bytes.extend_from_slice(payload);
+                // The writer task only stops once the connection is closed, which is reported
+                // separately.
+                if let Some(frames) = self.ordered.get(&channel) {
+                    let _ = frames.try_send(bytes);
+                }
+                Ok(())
+            }
+            ChannelKind::ReliableUnordered => {
+                write_varint(&mut bytes, payload.len() as u64);
+                bytes.extend_from_slice(payload);
+                let connection = self.connection.clone();
+                IoTaskPool::get()
+                    .spawn(async move {
+                        if let Ok(mut stream) = open_channel_stream(&connection, channel).await {
+                            if stream.write_all(&bytes).await.is_ok() {
+                                let _ = stream.finish();
+                            }
+                        }
+                    })
+                    .detach();
+                Ok(())
+            }
+            ChannelKind::Unreliable | ChannelKind::UnreliableSequenced => {
+                write_varint(&mut bytes, u64::from(channel.0));
+                if kind == ChannelKind::UnreliableSequenced {
+                    let sequence = self.next_sequence.entry(channel).or_default();
+                    write_varint(&mut bytes, *sequence);
+                    *sequence += 1;
+                }
+                bytes.extend_from_slice(payload);
+                self.connection.send_datagram(bytes.into())
+            }
+        }
+    }
+}
+
+/// The message channels of a connection entity, inserted once it is connected.
+#[derive(Component)]
+struct MessageConnection(QuicChannels);
+
+/// Payloads drained from the connections this frame, waiting to be decoded, indexed by channel.
+#[derive(Resource, Default)]
+struct ReceivedPayloads(Vec<Vec<(Entity, Bytes)>>);
+
+fn start_messaging(
+    trigger: Trigger<Connected>,
+    connections: Query<&QuicConnection>,
+    registry: Res<MessageRegistry>,
+    mut commands: Commands,
+) {
+    let entity = trigger.event().connection;
+    let Ok(connection) = connections.get(entity) else {
+        return;
+    };
+    let channels = QuicChannels::new(connection.get().clone(), registry.channels.clone().into());
+    commands.entity(entity).insert(MessageConnection(channels));
+}
+
+fn receive_payloads(
+    registry: Res<MessageRegistry>,
+    mut received: ResMut<ReceivedPayloads>,
+    mut connections: Query<(Entity, &mut MessageConnection)>,
+    mut transports: Query<&mut TransportEndPoint>,
+) {
+    received.0.resize_with(registry.channels.len(), Vec::new);
+
+    for (entity, mut connection) in &mut connections {
+        for (channel, bytes) in connection.0.receive() {
+            received.0[channel.index()].push((entity, bytes));
+        }
+    }
+
+    for mut transport in &mut transports {
+        while let Some((entity, message)) = transport.receive() {
+            match received.0.get_mut(message.channel.index()) {
+                Some(payloads) => payloads.push((entity, message.bytes)),
+                None => warn!(
+                    "dropping message from {entity:?}: unknown channel {:?}",
+                    message.channel
+                ),
+            }
+        }
+    }
+}
+
+fn decode_messages<T: NetworkMessage>(
+    mut received: ResMut<ReceivedPayloads>,
+    mut messages: ResMut<ReceivedMessages<T>>,
+) {
+    messages.messages.clear();
+    let channel = messages.channel;
+    let Some(payloads) = received.0.get_mut(channel.index()) else {
+        return;
+    };
+
+    for (connection, bytes) in payloads.drain(..) {
+        match T::decode(&bytes) {
+            Ok(message) => messages.messages.push((connection, message)),
+            Err(error) => warn!(
+                "dropping message of type {} from {connection:?}: {error}",
+                std::any::type_name::<T>()
+            ),
+        }
+    }
+}
+
+fn send_messages<T: NetworkMessage>(
+    registry: Res<MessageRegistry>,
+    mut outgoing: ResMut<OutgoingMessages<T>>,
+    mut connections: Query<(Entity, &mut MessageConnection)>,
+    transport_connections: Query<(
+        Entity,
+        &TransportConnection,
+        &ConnectionEndPoint,
+        &ConnectionState,
+    )>,
+    mut transports: Query<&mut TransportEndPoint>,
+) {
+    if outgoing.messages.is_empty() {
+        return;
+    }
+    let channel = outgoing.channel;
+    let config = registry.channels[channel.index()];
+    let kind = config.kind;
+
+    let mut encoded = Vec::new();
+    for (target, message) in outgoing.messages.drain(..) {
+        encoded.clear();
+        message.encode(&mut encoded);
+        if encoded.len() > config.max_message_size {
+            warn!(
+                "dropping message of type {}: its {} bytes exceed the maximum size of channel {channel:?}",
+                std::any::type_name::<T>(),
+                encoded.len()
+            );
+            continue;
+        }
+
+        match target {
+            Some(target) => {
+                if let Ok((_, mut connection)) = connections.get_mut(target) {
+                    send_quic_payload(&mut connection, channel, kind, &encoded);
+                    continue;
+                }
+                let transport = transport_connections
+                    .get(target)
+                    .ok()
+                    .filter(|(.., state)| **state == ConnectionState::Connected)
+                    .and_then(|(_, connection, endpoint, _)| {
+                        Some((connection, transports.get_mut(endpoint.get()).ok()?))
+                    });
+                let Some((connection, mut transport)) = transport else {
+                    warn!("cannot send a message to {target:?}, it is not connected");
+                    continue;
+                };
+                send_transport_payload(transport.get_mut(), connection, channel, kind, &encoded);
+            }
+            None => {
+                for (_, mut connection) in &mut connections {
+                    send_quic_payload(&mut connection, channel, kind, &encoded);
+                }
+                for (_, connection, endpoint, state) in &transport_connections {
+                    if *state != ConnectionState::Connected {
+                        continue;
+                    }
+                    if let Ok(mut transport) = transports.get_mut(endpoint.get()) {
+                        send_transport_payload(
+                            transport.get_mut(),
+                            connection,
+                            channel,
+                            kind,
+                            &encoded,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn send_quic_payload(
+    connection: &mut MessageConnection,
+    channel: ChannelId,
+    kind: ChannelKind,
+    payload: &[u8],
+) {
+    if let Err(error) = connection.0.send(channel, kind, payload) {
+        warn!("dropping unreliable message on channel {channel:?}: {error}");
+    }
+}
+
+fn send_transport_payload(
+    transport: &mut dyn NetTransport,
+    connection: &TransportConnection,
+    channel: ChannelId,
+    kind: ChannelKind,
+    payload: &[u8],
+) {
+    if let Err(error) = transport.send(connection.get(), channel, kind, payload) {
+        warn!("dropping message on channel {channel:?}: {error}");
+    }
+}
+
+/// Writes the queued frames of an ordered channel to its stream, opening it on the first frame.
+///
+/// Reopening a failed stream would let the peer read the messages of both streams out of order,
+/// so the connection is closed with [`MESSAGE_STREAM_FAILED`] instead.
+async fn write_ordered_stream(
+    connection: Connection,
+    channel: ChannelId,
+    frames: async_channel::Receiver<Vec<u8>>,
+) {
+    let mut stream: Option<SendStream> = None;
+    while let Ok(frame) = frames.recv().await {
+        if stream.is_none() {
+            stream = open_channel_stream(&connection, channel).await.ok();
+        }
+        let result = match &mut stream {
+            Some(stream) => stream
+                .write_all(&frame)
+                .await
+                .map_err(|error| error.to_string()),
+            None => Err("failed to open it".to_string()),
+        };
+        if let Err(error) = result {
+            if connection.close_reason().is_none() {
+                warn!("closing the connection, the stream of channel {channel:?} failed: {error}");
+                connection.close(MESSAGE_STREAM_FAILED, b"message stream failed");
+            }
+            return;
+        }
+    }
+}
+
+async fn open_channel_stream(
+    connection: &Connection,
+    channel: ChannelId,
+) -> Result<SendStream, ()> {
+    let mut stream = connection.open_uni().await.map_err(|_| ())?;
+    let mut header = Vec::with_capacity(MAX_VARINT_LEN);
+    write_varint(&mut header, u64::from(channel.0));
+    stream.write_all(&header).await.map_err(|_| ())?;
+    Ok(stream)
+}
+
+/// Accepts the peer's streams and forwards the messages read from them.
+async fn read_streams(
+    connection: Connection,
+    channels: Arc<[ChannelConfig]>,
+    payloads: Sender<Payload>,
+) {
+    while let Ok(stream) = connection.accept_uni().await {
+        let channels = channels.clone();
+        let payloads = payloads.clone();
+        IoTaskPool::get()
+            .spawn(async move {
+                if let Err(error) = read_stream(stream, &channels, &payloads).await {
+                    warn!("closing message stream: {error}");
+                }
+            })
+            .detach();
+    }
+}
+
+async fn read_stream(
+    mut stream: RecvStream,
+    channels: &[ChannelConfig],
+    payloads: &Sender<Payload>,
+) -> Result<(), ChannelError> {
+    let Some(channel) = read_stream_varint(&mut stream).await? else {
+        return Ok(());
+    };
+    let (channel, config) = channel_config(channels, channel)?;
+
+    while let Some(len) = read_stream_varint(&mut stream).await? {
+        let len = len as usize;
+        if len > config.max_message_size {
+            return Err(ChannelError::TooLarge(channel, len));
+        }
+
+        let mut bytes = vec![0; len];
+        stream
+            .read_exact(&mut bytes)
+            .await
+            .map_err(|_| ChannelError::Truncated)?;
+        let payload = Payload {
+            channel,
+            sequence: None,
+            bytes: bytes.into(),
+        };
+        if payloads.send(payload).is_err() {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Reads a variable length integer from `stream`, or `None` if the stream finished cleanly.
+pub(crate) async fn read_stream_varint(
+    stream: &mut RecvStream,
+) -> Result<Option<u64>, ChannelError> {
+    let mut buf = [0; MAX_VARINT_LEN];
+    for len in 1..=MAX_VARINT_LEN {
+        match stream.read_exact(&mut buf[len - 1..len]).await {
+            Ok(()) => {}
+            Err(ReadExactError::FinishedEarly(_)) if len == 1 => return Ok(None),
+            Err(_) => return Err(ChannelError::Truncated),
+        }
+        if buf[len - 1] & 0x80 == 0 {
+            return read_varint(&mut &buf[..len])
+                .map(Some)
+                .ok_or(ChannelError::Malformed);
+        }
+    }
+    Err(ChannelError::Malformed)
+}
+
+/// Reads the peer's datagrams and forwards the messages they carry.
+async fn read_datagrams(
+    connection: Connection,
+    channels: Arc<[ChannelConfig]>,
+    payloads: Sender<Payload>,
+) {
+    while let Ok(datagram) = connection.read_datagram().await {
+        match parse_datagram(&channels, datagram) {
+            Ok(payload) => {
+                if payloads.send(payload).is_err() {
+                    return;
+                }
+            }
+            Err(error) => warn!("dropping datagram: {error}"),
+        }
+    }
+}
+
+fn parse_datagram(channels: &[ChannelConfig], datagram: Bytes) -> Result<Payload, ChannelError> {
+    let mut bytes = &datagram[..];
+    let channel = read_varint(&mut bytes).ok_or(ChannelError::Malformed)?;
+    let (channel, config) = channel_config(channels, channel)?;
+
+    let sequence = match config.kind {
+        ChannelKind::UnreliableSequenced => {
+            Some(read_varint(&mut bytes).ok_or(ChannelError::Malformed)?)
+        }
+        ChannelKind::Unreliable => None,
+        ChannelKind::ReliableOrdered | ChannelKind::ReliableUnordered => {
+            return Err(ChannelError::WrongTransport(channel));
+        }
+    };
+    if bytes.len() > config.max_message_size {
+        return Err(ChannelError::TooLarge(channel, bytes.len()));
+    }
+
+    Ok(Payload {
+        channel,
+        sequence,
+        bytes: datagram.slice_ref(bytes),
+    })
+}
+
+fn channel_config(
+    channels: &[ChannelConfig],
+    channel: u64,
+) -> Result<(ChannelId, &ChannelConfig), ChannelError> {
+    u32::try_from(channel)
+        .ok()
+        .and_then(|id| Some((ChannelId(id), channels.get(id as usize)?)))
+        .ok_or(ChannelError::UnknownChannel(channel))
+}
+
+/// An error in the data received from a peer.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ChannelError {
+    #[error("unknown channel {0}")]
+    UnknownChannel(u64),
+    #[error("message of {1} bytes exceeds the maximum size of channel {0:?}")]
+    TooLarge(ChannelId, usize),
+    #[error("channel {0:?} received data through the wrong transport")]
+    WrongTransport(ChannelId),
+    #[error("malformed message framing")]
+    Malformed,
+    #[error("the stream ended in the middle of a message")]
+    Truncated,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNELS: [ChannelConfig; 2] = [
+        ChannelConfig::new(ChannelKind::Unreliable),
+        ChannelConfig::new(ChannelKind::UnreliableSequenced).with_max_message_size(4),
+    ];
+
+    fn datagram(channel: u64, sequence: Option<u64>, payload: &[u8]) -> Bytes {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, channel);
+        if let Some(sequence) = sequence {
+            write_varint(&mut bytes, sequence);
+        }
+        bytes.extend_from_slice(payload);
+        bytes.into()
+    }
+
+    #[test]
+    fn parses_datagrams() {
+        let payload = parse_datagram(&CHANNELS, datagram(0, None, b"hello")).unwrap();
+        assert_eq!(payload.channel, ChannelId(0));
+        assert_eq!(payload.sequence, None);
+        assert_eq!(payload.bytes, Bytes::from("hello"));
+
+        let payload = parse_datagram(&CHANNELS, datagram(1, Some(300), b"hi")).unwrap();
+        assert_eq!(payload.channel, ChannelId(1));
+        assert_eq!(payload.sequence, Some(300));
+        assert_eq!(payload.bytes, Bytes::from("hi"));
+    }
+
+    #[test]
+    fn rejects_invalid_datagrams() {
+        assert!(matches!(
+            parse_datagram(&CHANNELS, datagram(2, None, b"")),
+            Err(ChannelError::UnknownChannel(2))
+        ));
+        assert!(matches!(
+            parse_datagram(&CHANNELS, datagram(1, Some(0), b"too long")),
+            Err(ChannelError::TooLarge(ChannelId(1), 8))
+        ));
+        assert!(matches!(
+            parse_datagram(&CHANNELS, Bytes::from_static(&[0x80])),
+            Err(ChannelError::Malformed)
+        ));
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod quic_tests {
+    use super::*;
+    use crate::quic::test_utils::{connected_apps, poll_until, update_until};
+    use crate::quic::Disconnected;
+    use bevy_ecs::system::RunSystemOnce;
+
+    #[derive(Resource, Default)]
+    struct Received(Vec<(Entity, String)>);
+
+    fn setup(app: &mut App) {
+        app.init_resource::<Received>()
+            .add_message::<String>(ChannelConfig::new(ChannelKind::ReliableOrdered))
+            .add_message::<Vec<u8>>(ChannelConfig::new(ChannelKind::Unreliable))
+            .add_systems(
+                bevy_app::Update,
+                |reader: MessageReader<String>, mut received: ResMut<Received>| {
+                    received
+                        .0
+                        .extend(reader.read().map(|(entity, m)| (entity, m.clone())));
+                },
+            );
+    }
+
+    #[test]
+    fn reliable_ordered_messages_arrive_in_order() {
+        let (mut server, mut client, server_connection, client_connection) = connected_apps(setup);
+
+        client
+            .world_mut()
+            .run_system_once(move |mut writer: MessageWriter<String>| {
+                for i in 0..100 {
+                    writer.send(client_connection, i.to_string());
+                }
+            });
+
+        update_until([&mut server, &mut client], |[server, _]| {
+            server.world().resource::<Received>().0.len() == 100
+        });
+
+        let received = &server.world().resource::<Received>().0;
+        for (i, (connection, message)) in received.iter().enumerate() {
+            assert_eq!(*connection, server_connection);
+            assert_eq!(*message, i.to_string());
+        }
+
+        server
+            .world_mut()
+            .run_system_once(|mut writer: MessageWriter<String>| {
+                writer.broadcast("pong".to_string());
+            });
+        update_until([&mut server, &mut client], |[_, client]| {
+            !client.world().resource::<Received>().0.is_empty()
+        });
+        assert_eq!(
+            client.world().resource::<Received>().0,
+            [(client_connection, "pong".to_string())]
+        );
+    }
+
+    #[test]
+    fn oversized_messages_are_dropped_by_the_sender() {
+        let (mut server, mut client, _, client_connection) = connected_apps(|app| {
+            app.init_resource::<Received>()
+                .add_message::<String>(
+                    ChannelConfig::new(ChannelKind::ReliableOrdered).with_max_message_size(8),
+                )
+                .add_systems(
+                    bevy_app::Update,
+                    |reader: MessageReader<String>, mut received: ResMut<Received>| {
+                        received
+                            .0
+                            .extend(reader.read().map(|(entity, m)| (entity, m.clone())));
+                    },
+                );
+        });
+
+        client
+            .world_mut()
+            .run_system_once(move |mut writer: MessageWriter<String>| {
+                writer.send(client_connection, "far too long".to_string());
+                writer.send(client_connection, "short".to_string());
+            });
+        update_until([&mut server, &mut client], |[server, _]| {
+            !server.world().resource::<Received>().0.is_empty()
+        });
+
+        // The oversized message is not sent, so it does not break the stream for the next one.
+        let received = &server.world().resource::<Received>().0;
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].1, "short");
+    }
+
+    #[test]
+    fn closes_the_connection_when_an_ordered_stream_fails() {
+        #[derive(Resource)]
+        struct ClosedWith(Option<VarInt>);
+
+        // The server accepts smaller messages than the client sends, so it stops reading the
+        // client's stream.
+        let is_server = std::cell::Cell::new(true);
+        let (mut server, mut client, _, client_connection) = connected_apps(|app| {
+            let max_message_size = if is_server.replace(false) { 8 } else { 64 };
+            app.add_message::<String>(
+                ChannelConfig::new(ChannelKind::ReliableOrdered)
+                    .with_max_message_size(max_message_size),
+            )
+            .observe(|trigger: Trigger<Disconnected>, mut commands: Commands| {
+                commands.insert_resource(ClosedWith(trigger.event().error_code));
+            });
+        });
+
+        // The client only notices once it writes to the stream after the server stopped it.
+        let error_code = poll_until(|| {
+            client
+                .world_mut()
+                .run_system_once(move |mut writer: MessageWriter<String>| {
+                    writer.send(client_connection, "far too long".to_string());
+                });
+            client.update();
+            server.update();
+            server
+                .world()
+                .get_resource::<ClosedWith>()
+                .map(|closed| closed.0)
+        });
+        assert_eq!(error_code, Some(MESSAGE_STREAM_FAILED));
+    }
+}
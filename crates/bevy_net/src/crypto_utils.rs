@@ -1,7 +1,183 @@
 //! Extra cryptographic utilities
 
-use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use bevy_utils::HashSet;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
 use std::sync::Arc;
+use thiserror::Error;
+
+/// A self-signed certificate and its private key, generated at runtime.
+///
+/// Clients cannot verify such certificates against a certificate authority, but can pin their
+/// [`fingerprint`](Self::fingerprint) with a [`PinnedCertVerifier`].
+pub struct SelfSignedCert {
+    /// The DER encoded certificate.
+    pub cert: CertificateDer<'static>,
+    /// The DER encoded PKCS #8 private key of the certificate.
+    pub key: PrivatePkcs8KeyDer<'static>,
+}
+
+impl SelfSignedCert {
+    /// Generates a certificate valid for the given subject alternative names, such as
+    /// `"localhost"` or `"192.168.1.10"`, along with a new key pair.
+    pub fn generate(subject_alt_names: impl Into<Vec<String>>) -> Result<Self, rcgen::Error> {
+        let certified = rcgen::generate_simple_self_signed(subject_alt_names)?;
+        Ok(Self {
+            cert: certified.cert.der().clone(),
+            key: PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der()),
+        })
+    }
+
+    /// Gets the SHA-256 fingerprint of the certificate.
+    pub fn fingerprint(&self) -> CertFingerprint {
+        CertFingerprint::of(&self.cert)
+    }
+
+    /// Gets a copy of the private key, as expected by server configurations.
+    pub fn key_der(&self) -> PrivateKeyDer<'static> {
+        PrivateKeyDer::Pkcs8(self.key.clone_key())
+    }
+}
+
+impl Debug for SelfSignedCert {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SelfSignedCert")
+            .field("fingerprint", &self.fingerprint())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The SHA-256 digest of a DER encoded certificate.
+///
+/// Displayed and parsed as hexadecimal, optionally with colons between bytes as printed by
+/// `openssl x509 -fingerprint -sha256`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CertFingerprint(pub [u8; 32]);
+
+impl CertFingerprint {
+    /// Computes the fingerprint of a certificate.
+    pub fn of(cert: &CertificateDer<'_>) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, cert);
+        let mut fingerprint = [0; 32];
+        fingerprint.copy_from_slice(digest.as_ref());
+        Self(fingerprint)
+    }
+}
+
+impl Display for CertFingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An error returned when parsing an invalid [`CertFingerprint`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("a certificate fingerprint must be 32 hexadecimal bytes")]
+pub struct ParseFingerprintError;
+
+impl FromStr for CertFingerprint {
+    type Err = ParseFingerprintError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s
+            .bytes()
+            .filter(|byte| *byte != b':')
+            .map(|byte| (byte as char).to_digit(16).ok_or(ParseFingerprintError))
+            .collect::<Result<Vec<_>, _>>()?;
+        if digits.len() != 64 {
+            return Err(ParseFingerprintError);
+        }
+        let mut fingerprint = [0; 32];
+        for (byte, pair) in fingerprint.iter_mut().zip(digits.chunks(2)) {
+            *byte = (pair[0] << 4 | pair[1]) as u8;
+        }
+        Ok(Self(fingerprint))
+    }
+}
+
+/// Certificate verifier that only accepts server certificates whose [`CertFingerprint`] is
+/// pinned, such as [`SelfSignedCert`]s whose fingerprint is shared with clients beforehand.
+///
+/// The server name and validity period of certificates are not checked, as the fingerprint
+/// identifies the exact certificate the client expects. Handshake signatures are still verified,
+/// so a server must own the private key of a pinned certificate.
+#[derive(Debug)]
+pub struct PinnedCertVerifier {
+    fingerprints: HashSet<CertFingerprint>,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+}
+
+impl PinnedCertVerifier {
+    /// Creates a verifier accepting the certificates with the given fingerprints.
+    pub fn new(fingerprints: impl IntoIterator<Item = CertFingerprint>) -> Arc<Self> {
+        Arc::new(Self {
+            fingerprints: fingerprints.into_iter().collect(),
+            provider: Arc::new(rustls::crypto::ring::default_provider()),
+        })
+    }
+
+    /// Whether certificates with this fingerprint are accepted.
+    pub fn is_pinned(&self, fingerprint: &CertFingerprint) -> bool {
+        self.fingerprints.contains(fingerprint)
+    }
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp: &[u8],
+        _now: UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        if self.is_pinned(&CertFingerprint::of(end_entity)) {
+            Ok(rustls::client::danger::ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
 
 /// Dummy certificate verifier that treats any certificate as valid.
 /// NOTE, such verification is vulnerable to MITM attacks, but convenient for testing.
@@ -59,3 +235,54 @@ impl rustls::client::danger::ServerCertVerifier for SkipServerVerification {
         self.0.signature_verification_algorithms.supported_schemes()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::client::danger::ServerCertVerifier;
+
+    fn verify(verifier: &PinnedCertVerifier, cert: &SelfSignedCert) -> bool {
+        verifier
+            .verify_server_cert(
+                &cert.cert,
+                &[],
+                &ServerName::try_from("localhost").unwrap(),
+                &[],
+                UnixTime::now(),
+            )
+            .is_ok()
+    }
+
+    #[test]
+    fn accepts_pinned_certificates_only() {
+        let pinned = SelfSignedCert::generate(["localhost".to_string()]).unwrap();
+        let other = SelfSignedCert::generate(["localhost".to_string()]).unwrap();
+        assert_ne!(pinned.fingerprint(), other.fingerprint());
+
+        let verifier = PinnedCertVerifier::new([pinned.fingerprint()]);
+        assert!(verify(&verifier, &pinned));
+        assert!(!verify(&verifier, &other));
+    }
+
+    #[test]
+    fn parses_fingerprints() {
+        let fingerprint = CertFingerprint(std::array::from_fn(|i| i as u8 * 7));
+        let hex = fingerprint.to_string();
+        assert_eq!(hex.len(), 64);
+        assert_eq!(hex.parse(), Ok(fingerprint));
+
+        let colons = fingerprint
+            .0
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(colons.parse(), Ok(fingerprint));
+
+        assert_eq!("abc".parse::<CertFingerprint>(), Err(ParseFingerprintError));
+        assert_eq!(
+            hex.replace('0', "g").parse::<CertFingerprint>(),
+            Err(ParseFingerprintError)
+        );
+    }
+}
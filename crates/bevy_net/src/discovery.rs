@@ -0,0 +1,383 @@
+//! Discovery of servers on the local network.
+//!
+//! Servers insert a [`LanAdvertiser`] resource holding their [`ServerBeacon`], which the
+//! [`LanDiscoveryPlugin`] sends to a multicast group, or a broadcast address, every
+//! [`DiscoveryConfig::interval`]. Clients insert a [`LanDiscovery`] resource to listen for
+//! beacons, and find the servers heard from recently in the [`DiscoveredServers`] resource. Servers
+//! that were not heard from for [`DiscoveryConfig::expiry`] are removed from it.
+//!
+//! Beacons stay on the local network, and any number of servers and clients can run on the same
+//! machine. Using [`Ipv4Addr::LOCALHOST`] as the [`DiscoveryConfig::interface`] keeps them on that
+//! machine, which is handy for tests.
+//!
+//! # Wire format
+//!
+//! A beacon is a single UDP datagram holding the magic bytes `BNLD` and a format version byte,
+//! followed by the QUIC port, the player count, the server name and the game version. Integers
+//! are variable length encoded, and strings are UTF-8 prefixed with their length.
+
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+
+use bevy_app::{App, Plugin, PostUpdate, PreUpdate};
+use bevy_ecs::prelude::*;
+use bevy_utils::tracing::warn;
+use bevy_utils::HashMap;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::varint::{read_varint, write_varint};
+
+/// The bytes every beacon starts with.
+const MAGIC: &[u8; 4] = b"BNLD";
+
+/// The version of the beacon format, bumped on incompatible changes.
+const FORMAT_VERSION: u8 = 1;
+
+/// The largest beacon sent or received, small enough to never be fragmented.
+pub const MAX_BEACON_SIZE: usize = 1024;
+
+/// Adds the sending and receiving of [`ServerBeacon`]s to an [`App`].
+///
+/// Beacons are only sent while the [`LanAdvertiser`] resource exists, and received while the
+/// [`LanDiscovery`] resource exists.
+#[derive(Default)]
+pub struct LanDiscoveryPlugin;
+
+impl Plugin for LanDiscoveryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DiscoveredServers>()
+            .add_systems(
+                PreUpdate,
+                receive_beacons.run_if(resource_exists::<LanDiscovery>),
+            )
+            .add_systems(
+                PostUpdate,
+                send_beacons.run_if(resource_exists::<LanAdvertiser>),
+            );
+    }
+}
+
+/// Where beacons are sent, and how often.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiscoveryConfig {
+    /// The multicast group or broadcast address beacons are sent to, and its port.
+    pub addr: SocketAddrV4,
+    /// The address of the local network interface beacons are sent and received on, or
+    /// [`Ipv4Addr::UNSPECIFIED`] to let the OS pick one.
+    pub interface: Ipv4Addr,
+    /// How often servers send their beacon.
+    pub interval: Duration,
+    /// How long a server stays in the [`DiscoveredServers`] after its last beacon.
+    pub expiry: Duration,
+}
+
+impl DiscoveryConfig {
+    /// The default value of [`addr`](Self::addr), an organization-local multicast group.
+    pub const DEFAULT_ADDR: SocketAddrV4 =
+        SocketAddrV4::new(Ipv4Addr::new(239, 255, 66, 78), 15200);
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            addr: Self::DEFAULT_ADDR,
+            interface: Ipv4Addr::UNSPECIFIED,
+            interval: Duration::from_secs(1),
+            expiry: Duration::from_secs(5),
+        }
+    }
+}
+
+/// What a server advertises about itself on the local network.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ServerBeacon {
+    /// The name of the server, shown to players.
+    pub name: String,
+    /// The version of the game the server runs, for clients to hide incompatible servers.
+    pub version: String,
+    /// How many players are connected.
+    pub players: u32,
+    /// The port the server's QUIC [`EndPoint`](crate::quic::EndPoint) listens on.
+    pub port: u16,
+}
+
+impl ServerBeacon {
+    /// Appends the encoded beacon to `buf`.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(MAGIC);
+        buf.push(FORMAT_VERSION);
+        write_varint(buf, u64::from(self.port));
+        write_varint(buf, u64::from(self.players));
+        for string in [&self.name, &self.version] {
+            write_varint(buf, string.len() as u64);
+            buf.extend_from_slice(string.as_bytes());
+        }
+    }
+
+    /// Decodes a beacon previously encoded with [`encode`](Self::encode).
+    ///
+    /// Returns `None` if `bytes` is not a beacon of this format version.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut bytes = bytes.strip_prefix(MAGIC)?.strip_prefix(&[FORMAT_VERSION])?;
+        let port = u16::try_from(read_varint(&mut bytes)?).ok()?;
+        let players = u32::try_from(read_varint(&mut bytes)?).ok()?;
+        let mut read_string = || {
+            let len = usize::try_from(read_varint(&mut bytes)?).ok()?;
+            let string = String::from_utf8(bytes.get(..len)?.to_vec()).ok()?;
+            bytes = &bytes[len..];
+            Some(string)
+        };
+        let name = read_string()?;
+        let version = read_string()?;
+        Some(Self {
+            name,
+            version,
+            players,
+            port,
+        })
+    }
+}
+
+/// Makes a server advertise itself on the local network, sending its [`beacon`](Self::beacon)
+/// every [`DiscoveryConfig::interval`].
+///
+/// Changes to the beacon, such as the player count, are sent right away.
+#[derive(Resource, Debug)]
+pub struct LanAdvertiser {
+    /// The beacon sent.
+    pub beacon: ServerBeacon,
+    config: DiscoveryConfig,
+    socket: UdpSocket,
+    next_beacon: Instant,
+}
+
+impl LanAdvertiser {
+    /// Creates an advertiser sending `beacon` as configured by `config`.
+    pub fn new(beacon: ServerBeacon, config: DiscoveryConfig) -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        if config.addr.ip().is_multicast() {
+            socket.set_multicast_if_v4(&config.interface)?;
+            socket.set_multicast_loop_v4(true)?;
+            socket.set_multicast_ttl_v4(1)?;
+        } else {
+            socket.set_broadcast(true)?;
+        }
+        socket.bind(&SocketAddr::from((config.interface, 0)).into())?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            beacon,
+            config,
+            socket: socket.into(),
+            next_beacon: Instant::now(),
+        })
+    }
+
+    /// Gets the configuration of this advertiser.
+    pub fn config(&self) -> &DiscoveryConfig {
+        &self.config
+    }
+}
+
+/// Makes a client listen for the beacons of servers on the local network, and keep track of them
+/// in the [`DiscoveredServers`].
+#[derive(Resource, Debug)]
+pub struct LanDiscovery {
+    config: DiscoveryConfig,
+    socket: UdpSocket,
+}
+
+impl LanDiscovery {
+    /// Starts listening for beacons sent as configured by `config`.
+    pub fn new(config: DiscoveryConfig) -> io::Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        // Lets every client on this machine listen on the same port.
+        socket.set_reuse_address(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.addr.port())).into())?;
+        if config.addr.ip().is_multicast() {
+            socket.join_multicast_v4(config.addr.ip(), &config.interface)?;
+        }
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            config,
+            socket: socket.into(),
+        })
+    }
+
+    /// Gets the configuration of this listener.
+    pub fn config(&self) -> &DiscoveryConfig {
+        &self.config
+    }
+}
+
+/// A server found on the local network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredServer {
+    /// The address of the server's QUIC endpoint, to connect to.
+    pub addr: SocketAddr,
+    /// The latest beacon of the server.
+    pub beacon: ServerBeacon,
+    /// When the latest beacon was received.
+    pub last_seen: Instant,
+}
+
+/// The servers a [`LanDiscovery`] heard from recently, by the address of their QUIC endpoint.
+#[derive(Resource, Debug, Default)]
+pub struct DiscoveredServers(HashMap<SocketAddr, DiscoveredServer>);
+
+impl DiscoveredServers {
+    /// Gets the server whose QUIC endpoint is at `addr`.
+    pub fn get(&self, addr: SocketAddr) -> Option<&DiscoveredServer> {
+        self.0.get(&addr)
+    }
+
+    /// Iterates over the discovered servers, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &DiscoveredServer> {
+        self.0.values()
+    }
+
+    /// The number of discovered servers.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether no server was discovered.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+fn send_beacons(mut advertiser: ResMut<LanAdvertiser>) {
+    let now = Instant::now();
+    let due = advertiser.next_beacon <= now;
+    if !due && !advertiser.is_changed() {
+        return;
+    }
+
+    let advertiser = advertiser.bypass_change_detection();
+    advertiser.next_beacon = now + advertiser.config.interval;
+    let mut beacon = Vec::new();
+    advertiser.beacon.encode(&mut beacon);
+    if beacon.len() > MAX_BEACON_SIZE {
+        warn!(
+            "not sending a beacon of {} bytes, it is too large",
+            beacon.len()
+        );
+        return;
+    }
+    if let Err(error) = advertiser
+        .socket
+        .send_to(&beacon, SocketAddr::from(advertiser.config.addr))
+    {
+        warn!("failed to send a beacon: {error}");
+    }
+}
+
+fn receive_beacons(discovery: Res<LanDiscovery>, mut servers: ResMut<DiscoveredServers>) {
+    let now = Instant::now();
+    let mut buf = [0; MAX_BEACON_SIZE];
+    loop {
+        let (len, from) = match discovery.socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+            Err(error) => {
+                warn!("failed to receive beacons: {error}");
+                break;
+            }
+        };
+        let Some(beacon) = ServerBeacon::decode(&buf[..len]) else {
+            continue;
+        };
+        let addr = SocketAddr::new(from.ip(), beacon.port);
+        servers.0.insert(
+            addr,
+            DiscoveredServer {
+                addr,
+                beacon,
+                last_seen: now,
+            },
+        );
+    }
+
+    let expiry = discovery.config.expiry;
+    let expired = |server: &DiscoveredServer| now.duration_since(server.last_seen) >= expiry;
+    if servers.iter().any(expired) {
+        servers.0.retain(|_, server| !expired(server));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quic::test_utils::update_until;
+
+    fn beacon() -> ServerBeacon {
+        ServerBeacon {
+            name: "Couch Server".to_string(),
+            version: "1.2.0".to_string(),
+            players: 2,
+            port: 5000,
+        }
+    }
+
+    #[test]
+    fn encodes_beacons() {
+        let mut bytes = Vec::new();
+        beacon().encode(&mut bytes);
+        assert_eq!(ServerBeacon::decode(&bytes), Some(beacon()));
+
+        assert_eq!(ServerBeacon::decode(&bytes[..bytes.len() - 1]), None);
+        bytes[4] = FORMAT_VERSION + 1;
+        assert_eq!(ServerBeacon::decode(&bytes), None);
+        assert_eq!(ServerBeacon::decode(b"hello"), None);
+    }
+
+    #[test]
+    fn discovers_servers_over_loopback_multicast() {
+        let config = DiscoveryConfig {
+            addr: SocketAddrV4::new(Ipv4Addr::new(239, 255, 66, 79), 15201),
+            interface: Ipv4Addr::LOCALHOST,
+            interval: Duration::from_millis(10),
+            expiry: Duration::from_millis(200),
+        };
+        let new_app = || {
+            let mut app = App::new();
+            app.add_plugins(LanDiscoveryPlugin);
+            app
+        };
+        let mut server = new_app();
+        server.insert_resource(LanAdvertiser::new(beacon(), config).unwrap());
+        let mut apps = [server, new_app(), new_app()];
+        for client in &mut apps[1..] {
+            client.insert_resource(LanDiscovery::new(config).unwrap());
+        }
+
+        let server_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 5000));
+        let discovered = |app: &App| {
+            app.world()
+                .resource::<DiscoveredServers>()
+                .get(server_addr)
+                .map(|server| server.beacon.clone())
+        };
+        update_until(apps.each_mut(), |apps| {
+            apps[1..].iter().all(|client| discovered(client).is_some())
+        });
+        assert_eq!(discovered(&apps[1]), Some(beacon()));
+
+        apps[0]
+            .world_mut()
+            .resource_mut::<LanAdvertiser>()
+            .beacon
+            .players = 3;
+        update_until(apps.each_mut(), |apps| {
+            discovered(apps[1]).is_some_and(|beacon| beacon.players == 3)
+        });
+
+        apps[0].world_mut().remove_resource::<LanAdvertiser>();
+        update_until(apps.each_mut(), |apps| {
+            apps[1..]
+                .iter()
+                .all(|client| client.world().resource::<DiscoveredServers>().is_empty())
+        });
+    }
+}
@@ -0,0 +1,452 @@
+//! Bevy [`Event`]s sent between the server and its clients.
+//!
+//! [`NetworkEventApp::add_network_event`] makes an event cross the wire on a channel of its own:
+//!
+//! - Events a client writes to its [`Events<E>`] are sent to its server, which reads them as
+//!   [`FromClient<E>`] events tagged with the connection entity they arrived on.
+//! - Servers send events by writing [`ToClients<E>`] events, which target every client, a single
+//!   client, or the clients in a [`Room`]. Clients read them from their [`Events<E>`], alongside
+//!   the events they write themselves, which are not sent back.
+//!
+//! Events are encoded through reflection, so they only need to derive [`Reflect`] and be
+//! registered on both peers, which [`add_network_event`](NetworkEventApp::add_network_event) does.
+//!
+//! ```
+//! # use bevy_app::App;
+//! # use bevy_ecs::prelude::*;
+//! # use bevy_reflect::Reflect;
+//! # use bevy_net::channel::{ChannelConfig, ChannelKind};
+//! # use bevy_net::event::{FromClient, NetworkEventApp, NetworkEventDirection};
+//! #[derive(Event, Reflect)]
+//! struct Chat(String);
+//!
+//! fn print_chat(mut chat: EventReader<FromClient<Chat>>) {
+//!     for FromClient { connection, event } in chat.read() {
+//!         println!("{connection}: {}", event.0);
+//!     }
+//! }
+//!
+//! let mut app = App::new();
+//! app.add_network_event::<Chat>(
+//!     NetworkEventDirection::Bidirectional,
+//!     ChannelConfig::new(ChannelKind::ReliableOrdered),
+//! )
+//! .add_systems(bevy_app::Update, print_chat);
+//! ```
+
+use std::marker::PhantomData;
+
+use bevy_app::{App, PostUpdate, PreUpdate};
+use bevy_ecs::event::EventId;
+use bevy_ecs::prelude::*;
+use bevy_ecs::reflect::AppTypeRegistry;
+use bevy_reflect::{FromReflect, GetTypeRegistration, TypePath};
+use bevy_utils::tracing::warn;
+use bytes::Bytes;
+
+use crate::channel::{
+    ChannelConfig, DecodeError, MessageApp, MessageReader, MessageWriter, NetworkMessage,
+};
+use crate::quic::{ConnectionDirection, ConnectionState, NetworkSystem};
+use crate::replication::{Room, Rooms};
+use crate::wire::{NetworkDeserializer, NetworkSerializer, NetworkTypeIds};
+
+/// Which way a networked event is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NetworkEventDirection {
+    /// Clients send the event to their server.
+    ClientToServer,
+    /// Servers send the event to their clients.
+    ServerToClient,
+    /// Both of the above.
+    Bidirectional,
+}
+
+impl NetworkEventDirection {
+    fn sent_by_clients(self) -> bool {
+        matches!(self, Self::ClientToServer | Self::Bidirectional)
+    }
+
+    fn sent_by_servers(self) -> bool {
+        matches!(self, Self::ServerToClient | Self::Bidirectional)
+    }
+}
+
+/// An event sent by a client, as received by the server.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct FromClient<E> {
+    /// The connection entity of the client.
+    pub connection: Entity,
+    /// The event.
+    pub event: E,
+}
+
+/// An event for the server to send to some of its clients.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct ToClients<E> {
+    /// The clients to send the event to.
+    pub target: ClientTarget,
+    /// The event.
+    pub event: E,
+}
+
+/// The clients a [`ToClients`] event is sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClientTarget {
+    /// Every client.
+    All,
+    /// The client of a connection entity.
+    Client(Entity),
+    /// Every client but the one of a connection entity, such as the sender of the event being
+    /// forwarded.
+    AllExcept(Entity),
+    /// The clients whose connection entities are in a room.
+    Room(Room),
+}
+
+impl ClientTarget {
+    /// Whether the client of the connection entity `connection`, in `rooms`, is targeted.
+    pub fn includes(&self, connection: Entity, rooms: Option<&Rooms>) -> bool {
+        match self {
+            Self::All => true,
+            Self::Client(client) => *client == connection,
+            Self::AllExcept(client) => *client != connection,
+            Self::Room(room) => rooms.is_some_and(|rooms| rooms.contains(*room)),
+        }
+    }
+}
+
+/// Adds networked events to an [`App`].
+pub trait NetworkEventApp {
+    /// Adds the event `E`, along with [`FromClient<E>`] and [`ToClients<E>`] depending on
+    /// `direction`, and sends it over a channel configured with `channel`.
+    ///
+    /// Requires the [`QuicNetworkPlugin`](crate::quic::QuicNetworkPlugin). Both peers must add the
+    /// same network events, in the same order as their other message channels.
+    fn add_network_event<E>(
+        &mut self,
+        direction: NetworkEventDirection,
+        channel: ChannelConfig,
+    ) -> &mut Self
+    where
+        E: Event + FromReflect + TypePath + GetTypeRegistration;
+}
+
+impl NetworkEventApp for App {
+    fn add_network_event<E>(
+        &mut self,
+        direction: NetworkEventDirection,
+        channel: ChannelConfig,
+    ) -> &mut Self
+    where
+        E: Event + FromReflect + TypePath + GetTypeRegistration,
+    {
+        self.register_type::<E>()
+            .add_event::<E>()
+            .insert_resource(NetworkEvents::<E> {
+                direction,
+                received: Vec::new(),
+            })
+            .add_message::<EventMessage<E>>(channel)
+            .add_systems(PreUpdate, receive_events::<E>.after(NetworkSystem::Receive));
+
+        if direction.sent_by_clients() {
+            self.add_event::<FromClient<E>>().add_systems(
+                PostUpdate,
+                send_client_events::<E>.before(NetworkSystem::Send),
+            );
+        }
+        if direction.sent_by_servers() {
+            self.add_event::<ToClients<E>>().add_systems(
+                PostUpdate,
+                send_server_events::<E>.before(NetworkSystem::Send),
+            );
+        }
+        self
+    }
+}
+
+/// The configuration of the networked event `E`, and the events of type `E` received from the
+/// server this frame, which clients must not send back.
+#[derive(Resource)]
+struct NetworkEvents<E: Event> {
+    direction: NetworkEventDirection,
+    received: Vec<EventId<E>>,
+}
+
+/// An encoded event of type `E`.
+struct EventMessage<E> {
+    bytes: Bytes,
+    _event: PhantomData<fn() -> E>,
+}
+
+impl<E> EventMessage<E> {
+    fn new(bytes: Bytes) -> Self {
+        Self {
+            bytes,
+            _event: PhantomData,
+        }
+    }
+}
+
+impl<E: Event> NetworkMessage for EventMessage<E> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.bytes);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Self::new(Bytes::copy_from_slice(bytes)))
+    }
+}
+
+/// Encodes `event`, without its type as the channel implies it.
+fn encode<E: Event + FromReflect + TypePath>(
+    event: &E,
+    registry: &AppTypeRegistry,
+) -> Option<Bytes> {
+    let registry = registry.read();
+    // Identifiers are only needed to tag values with their type.
+    let ids = NetworkTypeIds::default();
+    let mut buf = Vec::new();
+    match NetworkSerializer::new(&registry, &ids).serialize_value(event, &mut buf) {
+        Ok(()) => Some(buf.into()),
+        Err(error) => {
+            warn!("failed to encode a {} event: {error}", E::type_path());
+            None
+        }
+    }
+}
+
+fn send_client_events<E: Event + FromReflect + TypePath>(
+    mut events: EventReader<E>,
+    mut network_events: ResMut<NetworkEvents<E>>,
+    connections: Query<(Entity, &ConnectionDirection, &ConnectionState)>,
+    mut writer: MessageWriter<EventMessage<E>>,
+    registry: Res<AppTypeRegistry>,
+) {
+    let received = std::mem::take(&mut network_events.received);
+    for (event, id) in events.read_with_id() {
+        if received.contains(&id) {
+            continue;
+        }
+        let Some(bytes) = encode(event, &registry) else {
+            continue;
+        };
+        for (connection, direction, state) in &connections {
+            if *direction == ConnectionDirection::Outgoing && *state == ConnectionState::Connected {
+                writer.send(connection, EventMessage::new(bytes.clone()));
+            }
+        }
+    }
+}
+
+fn send_server_events<E: Event + FromReflect + TypePath>(
+    mut events: EventReader<ToClients<E>>,
+    connections: Query<(
+        Entity,
+        &ConnectionDirection,
+        &ConnectionState,
+        Option<&Rooms>,
+    )>,
+    mut writer: MessageWriter<EventMessage<E>>,
+    registry: Res<AppTypeRegistry>,
+) {
+    for ToClients { target, event } in events.read() {
+        let Some(bytes) = encode(event, &registry) else {
+            continue;
+        };
+        for (connection, direction, state, rooms) in &connections {
+            if *direction == ConnectionDirection::Incoming
+                && *state == ConnectionState::Connected
+                && target.includes(connection, rooms)
+            {
+                writer.send(connection, EventMessage::new(bytes.clone()));
+            }
+        }
+    }
+}
+
+fn receive_events<E: Event + FromReflect + TypePath>(
+    reader: MessageReader<EventMessage<E>>,
+    mut network_events: ResMut<NetworkEvents<E>>,
+    directions: Query<&ConnectionDirection>,
+    registry: Res<AppTypeRegistry>,
+    mut events: EventWriter<E>,
+    mut client_events: Option<ResMut<Events<FromClient<E>>>>,
+) {
+    if reader.is_empty() {
+        return;
+    }
+    let registry = registry.read();
+    let ids = NetworkTypeIds::default();
+    let deserializer = NetworkDeserializer::new(&registry, &ids);
+    let registration = registry.get(std::any::TypeId::of::<E>()).unwrap();
+
+    for (connection, message) in reader.read() {
+        let event = deserializer
+            .deserialize_value(registration, &mut &message.bytes[..])
+            .ok()
+            .and_then(|value| E::take_from_reflect(value).ok());
+        let Some(event) = event else {
+            warn!("received a malformed {} event", E::type_path());
+            continue;
+        };
+
+        match (directions.get(connection), &mut client_events) {
+            (Ok(ConnectionDirection::Incoming), Some(client_events)) => {
+                client_events.send(FromClient { connection, event });
+            }
+            (Ok(ConnectionDirection::Outgoing), _)
+                if network_events.direction.sent_by_servers() =>
+            {
+                let id = events.send(event);
+                // Only drained when sending events back to the server.
+                if network_events.direction.sent_by_clients() {
+                    network_events.received.push(id);
+                }
+            }
+            _ => warn!(
+                "received a {} event sent the wrong way from {connection}",
+                E::type_path()
+            ),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod quic_tests {
+    use super::*;
+    use crate::channel::ChannelKind;
+    use crate::quic::test_utils::{connected_apps, update_until};
+    use bevy_app::Update;
+    use bevy_ecs::system::RunSystemOnce;
+    use bevy_reflect::Reflect;
+
+    #[derive(Event, Reflect, Debug, Clone, PartialEq)]
+    struct Chat(String);
+
+    #[derive(Resource, Default)]
+    struct Received {
+        chat: Vec<Chat>,
+        from_clients: Vec<FromClient<Chat>>,
+    }
+
+    fn setup(app: &mut App) {
+        app.init_resource::<Received>()
+            .add_network_event::<Chat>(
+                NetworkEventDirection::Bidirectional,
+                ChannelConfig::new(ChannelKind::ReliableOrdered),
+            )
+            .add_systems(
+                Update,
+                |mut chat: EventReader<Chat>,
+                 mut from_clients: EventReader<FromClient<Chat>>,
+                 mut received: ResMut<Received>| {
+                    received.chat.extend(chat.read().cloned());
+                    received.from_clients.extend(from_clients.read().cloned());
+                },
+            );
+    }
+
+    fn send_to_clients(app: &mut App, target: ClientTarget, message: &str) {
+        let event = Chat(message.into());
+        app.world_mut()
+            .send_event(ToClients { target, event })
+            .unwrap();
+    }
+
+    #[test]
+    fn mirrors_events_across_peers() {
+        let (mut server, mut client, server_connection, _) = connected_apps(setup);
+
+        client.world_mut().send_event(Chat("hello".into())).unwrap();
+        update_until([&mut server, &mut client], |[server, _]| {
+            !server
+                .world()
+                .resource::<Received>()
+                .from_clients
+                .is_empty()
+        });
+        assert_eq!(
+            server.world().resource::<Received>().from_clients,
+            [FromClient {
+                connection: server_connection,
+                event: Chat("hello".into())
+            }]
+        );
+
+        // Only the targeted clients receive the events from the server.
+        send_to_clients(&mut server, ClientTarget::Room(Room(1)), "lobby");
+        send_to_clients(
+            &mut server,
+            ClientTarget::AllExcept(server_connection),
+            "others",
+        );
+        server.update();
+        server
+            .world_mut()
+            .entity_mut(server_connection)
+            .insert(Rooms::new([Room(1)]));
+        send_to_clients(&mut server, ClientTarget::Room(Room(1)), "game");
+        send_to_clients(
+            &mut server,
+            ClientTarget::Client(server_connection),
+            "whisper",
+        );
+        update_until([&mut server, &mut client], |[_, client]| {
+            client.world().resource::<Received>().chat.len() >= 3
+        });
+        assert_eq!(
+            client.world().resource::<Received>().chat,
+            [
+                Chat("hello".into()),
+                Chat("game".into()),
+                Chat("whisper".into())
+            ]
+        );
+
+        // Clients do not send the events they received back.
+        for _ in 0..10 {
+            server.update();
+            client.update();
+        }
+        client
+            .world_mut()
+            .run_system_once(|mut events: EventWriter<Chat>| {
+                events.send(Chat("bye".into()));
+            });
+        update_until([&mut server, &mut client], |[server, _]| {
+            server.world().resource::<Received>().from_clients.len() == 2
+        });
+        assert_eq!(
+            server.world().resource::<Received>().from_clients[1].event,
+            Chat("bye".into())
+        );
+    }
+
+    #[derive(Event, Reflect, Debug, Clone, PartialEq)]
+    struct Notice(u32);
+
+    #[test]
+    fn receives_server_events_without_keeping_them() {
+        let (mut server, mut client, _, _) = connected_apps(|app| {
+            app.add_network_event::<Notice>(
+                NetworkEventDirection::ServerToClient,
+                ChannelConfig::new(ChannelKind::ReliableOrdered),
+            );
+        });
+
+        for notice in 0..3 {
+            let event = Notice(notice);
+            server.world_mut().send_event(ToClients {
+                target: ClientTarget::All,
+                event,
+            });
+        }
+        update_until([&mut server, &mut client], |[_, client]| {
+            client.world().resource::<Events<Notice>>().len() == 3
+        });
+        let network_events = client.world().resource::<NetworkEvents<Notice>>();
+        assert!(network_events.received.is_empty());
+    }
+}
@@ -0,0 +1,885 @@
+//! Inspecting and editing a running app from other processes.
+//!
+//! An [`InspectListener`] accepts local TCP connections, or bidirectional streams on a QUIC
+//! [`EndPoint`], over which editors and test tools send [JSON-RPC 2.0] requests. The
+//! [`InspectPlugin`] answers them from the [`World`], reading and writing components and resources
+//! through their [`ReflectComponent`] and [`ReflectResource`] registrations, so only registered
+//! types with that type data can be inspected.
+//!
+//! ```no_run
+//! # use bevy_app::App;
+//! # use bevy_net::inspect::{InspectListener, InspectPlugin};
+//! let mut app = App::new();
+//! app.add_plugins(InspectPlugin);
+//! let listener = InspectListener::tcp("127.0.0.1:15702".parse().unwrap()).unwrap();
+//! app.world_mut().spawn(listener);
+//! ```
+//!
+//! Anyone able to connect can change the whole world, so a TCP listener should only be bound to a
+//! loopback address, and a QUIC endpoint should only accept trusted clients.
+//!
+//! # Protocol
+//!
+//! Each request, response and notification is a JSON object on a line of its own, such as:
+//!
+//! ```json
+//! {"jsonrpc": "2.0", "id": 1, "method": "component.get", "params": {"entity": 4294967296, "components": ["my_game::Health"]}}
+//! ```
+//!
+//! Requests without an `id` are not answered. Entities are identified by their
+//! [`Entity::to_bits`], components and resources by their [type path](bevy_reflect::TypePath), and
+//! values are in the format of the [`TypedReflectSerializer`]. The methods are:
+//!
+//! - `entity.list`: returns every entity.
+//! - `entity.query`, with `components`, a list of type paths: returns, for every entity with all of
+//!   them, an object holding the `entity` and its `components`, an object of values by type path.
+//! - `entity.spawn`, with `components`, an object of values by type path: spawns an entity with
+//!   them and returns it.
+//! - `entity.despawn`, with an `entity`.
+//! - `component.get`, with an `entity` and `components`, a list of type paths: returns an object
+//!   of their values by type path.
+//! - `component.insert`, with an `entity` and `components`, an object of values by type path.
+//! - `component.remove`, with an `entity` and `components`, a list of type paths.
+//! - `component.watch`, with an `entity` and a `component` type path: returns a watch identifier.
+//!   A `component.changed` notification, with the `watch` and the `value` of the component, is then
+//!   sent with its current value, and again at the end of every frame it changed in. The value is
+//!   `null` while the component is missing. The watch ends once the entity is despawned.
+//! - `component.unwatch`, with a `watch` identifier.
+//! - `resource.get`, with a `resource` type path: returns its value.
+//! - `resource.insert`, with a `resource` type path and a `value`.
+//! - `resource.remove`, with a `resource` type path.
+//!
+//! Methods without a result return `null`. Failed requests are answered with an error, whose code
+//! is given by [`InspectError::code`].
+//!
+//! [JSON-RPC 2.0]: https://www.jsonrpc.org/specification
+
+use std::any::TypeId;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+
+use async_channel::{Receiver, Sender};
+use async_io::Async;
+use bevy_app::{App, Last, Plugin, PreUpdate};
+use bevy_ecs::component::Tick;
+use bevy_ecs::prelude::*;
+use bevy_ecs::reflect::{AppTypeRegistry, ReflectComponent, ReflectResource};
+use bevy_reflect::serde::{TypedReflectDeserializer, TypedReflectSerializer};
+use bevy_reflect::{Reflect, TypeRegistration, TypeRegistry};
+use bevy_tasks::{IoTaskPool, Task};
+use bevy_utils::tracing::warn;
+use futures_lite::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use futures_lite::{AsyncRead, AsyncWrite};
+use serde::de::{DeserializeOwned, DeserializeSeed};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+
+use crate::quic::EndPoint;
+
+/// The longest request line accepted, in bytes. Connections sending longer ones are closed.
+const MAX_REQUEST_SIZE: u64 = 1 << 20;
+
+/// Answers the requests sent to [`InspectListener`]s.
+///
+/// Requests are run against the [`World`] at the start of every frame, and `component.watch`
+/// notifications are sent at its end.
+#[derive(Default)]
+pub struct InspectPlugin;
+
+impl Plugin for InspectPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Watches>()
+            .add_systems(PreUpdate, handle_inspect_requests)
+            .add_systems(Last, notify_watches);
+    }
+}
+
+/// Accepts connections sending requests to the [`InspectPlugin`], once spawned.
+///
+/// Dropping the listener stops accepting connections, and closes the ones it accepted once they
+/// send another request.
+#[derive(Component)]
+pub struct InspectListener {
+    requests: Receiver<InspectRequest>,
+    local_addr: SocketAddr,
+    _accept_task: Task<()>,
+}
+
+/// A request line, and where to send its response.
+struct InspectRequest {
+    line: String,
+    responses: Sender<String>,
+}
+
+impl InspectListener {
+    /// Listens for TCP connections on `addr`, such as `127.0.0.1:15702`.
+    pub fn tcp(addr: SocketAddr) -> io::Result<Self> {
+        let listener = Async::<TcpListener>::bind(addr)?;
+        let local_addr = listener.get_ref().local_addr()?;
+        let (sender, requests) = async_channel::unbounded();
+        let accept_task = IoTaskPool::get().spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let sender = sender.clone();
+                        IoTaskPool::get()
+                            .spawn(async move { serve(&stream, &stream, sender).await })
+                            .detach();
+                    }
+                    Err(error) => warn!("failed to accept an inspector connection: {error}"),
+                }
+            }
+        });
+        Ok(Self {
+            requests,
+            local_addr,
+            _accept_task: accept_task,
+        })
+    }
+
+    /// Accepts every connection made to `endpoint`, which needs a server configuration and must not
+    /// be used for anything else. Each bidirectional stream opened on them is a session of its own.
+    pub fn quic(endpoint: EndPoint) -> io::Result<Self> {
+        let local_addr = endpoint.local_addr()?;
+        let (sender, requests) = async_channel::unbounded();
+        let accept_task = IoTaskPool::get().spawn(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                let sender = sender.clone();
+                IoTaskPool::get()
+                    .spawn(async move {
+                        let Ok(connection) = incoming.await else {
+                            return;
+                        };
+                        while let Ok((mut send, recv)) = connection.accept_bi().await {
+                            let sender = sender.clone();
+                            IoTaskPool::get()
+                                .spawn(async move {
+                                    serve(recv, &mut send, sender).await;
+                                    let _ = send.finish();
+                                })
+                                .detach();
+                        }
+                    })
+                    .detach();
+            }
+        });
+        Ok(Self {
+            requests,
+            local_addr,
+            _accept_task: accept_task,
+        })
+    }
+
+    /// Gets the address the listener is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+/// Forwards the request lines read from `reader` until it ends, and writes the responses and
+/// notifications sent back to `writer` until none can be sent anymore.
+async fn serve(
+    reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    requests: Sender<InspectRequest>,
+) {
+    let (responses, outgoing) = async_channel::unbounded::<String>();
+    let read = async move {
+        let mut reader = BufReader::new(reader);
+        loop {
+            let mut line = String::new();
+            match (&mut reader)
+                .take(MAX_REQUEST_SIZE)
+                .read_line(&mut line)
+                .await
+            {
+                Ok(0) => break,
+                Ok(len) if len as u64 == MAX_REQUEST_SIZE && !line.ends_with('\n') => {
+                    warn!("closing an inspector connection sending requests over {MAX_REQUEST_SIZE} bytes");
+                    break;
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    warn!("failed to read an inspector request: {error}");
+                    break;
+                }
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let request = InspectRequest {
+                line,
+                responses: responses.clone(),
+            };
+            if requests.send(request).await.is_err() {
+                break;
+            }
+        }
+    };
+    // Ends once the reader is done and the requests it sent are answered, unless watches keep
+    // sending notifications.
+    let write = async move {
+        while let Ok(mut message) = outgoing.recv().await {
+            message.push('\n');
+            if writer.write_all(message.as_bytes()).await.is_err() || writer.flush().await.is_err()
+            {
+                break;
+            }
+        }
+    };
+    futures_lite::future::zip(read, write).await;
+}
+
+/// Why an inspection request failed.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InspectError {
+    /// The request is not valid JSON.
+    #[error("invalid JSON: {0}")]
+    Parse(String),
+    /// The request is not a request object.
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    /// There is no method with this name.
+    #[error("no method named `{0}`")]
+    MethodNotFound(String),
+    /// The parameters do not match the ones of the method.
+    #[error("invalid params: {0}")]
+    InvalidParams(String),
+    /// The entity does not exist.
+    #[error("entity {0} does not exist")]
+    NoSuchEntity(u64),
+    /// No type is registered with this type path.
+    #[error("no type is registered with the path `{0}`")]
+    UnknownType(String),
+    /// The type is not registered with [`ReflectComponent`].
+    #[error("`{0}` is not registered as a reflected component")]
+    NotAComponent(String),
+    /// The type is not registered with [`ReflectResource`].
+    #[error("`{0}` is not registered as a reflected resource")]
+    NotAResource(String),
+    /// The entity does not have this component.
+    #[error("the entity has no `{0}` component")]
+    MissingComponent(String),
+    /// The resource does not exist.
+    #[error("there is no `{0}` resource")]
+    MissingResource(String),
+    /// A value does not match its type.
+    #[error("cannot deserialize `{type_path}`: {message}")]
+    Deserialize {
+        /// The type path of the value.
+        type_path: String,
+        /// Why it cannot be deserialized.
+        message: String,
+    },
+    /// A value cannot be serialized, usually because the type of one of its fields is not
+    /// registered.
+    #[error("cannot serialize `{type_path}`: {message}")]
+    Serialize {
+        /// The type path of the value.
+        type_path: String,
+        /// Why it cannot be serialized.
+        message: String,
+    },
+    /// There is no watch with this identifier.
+    #[error("no watch has the identifier {0}")]
+    NoSuchWatch(u64),
+}
+
+impl InspectError {
+    /// Gets the code of the error, one of the JSON-RPC ones for malformed requests and invalid
+    /// values, or one from -32000 downwards for the others.
+    pub fn code(&self) -> i64 {
+        match self {
+            Self::Parse(_) => -32700,
+            Self::InvalidRequest(_) => -32600,
+            Self::MethodNotFound(_) => -32601,
+            Self::InvalidParams(_) | Self::Deserialize { .. } => -32602,
+            Self::NoSuchEntity(_) => -32000,
+            Self::UnknownType(_) | Self::NotAComponent(_) | Self::NotAResource(_) => -32001,
+            Self::MissingComponent(_) | Self::MissingResource(_) => -32002,
+            Self::Serialize { .. } => -32003,
+            Self::NoSuchWatch(_) => -32004,
+        }
+    }
+}
+
+/// A component watched for changes with `component.watch`.
+struct Watch {
+    id: u64,
+    entity: Entity,
+    type_path: String,
+    type_id: TypeId,
+    reflect_component: ReflectComponent,
+    responses: Sender<String>,
+    /// When the component last changed as of the last notification, `Some(None)` if it was
+    /// missing, or `None` before the first notification.
+    seen: Option<Option<Tick>>,
+}
+
+#[derive(Resource, Default)]
+struct Watches {
+    watches: Vec<Watch>,
+    next_id: u64,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct EntityParams {
+    entity: u64,
+}
+
+#[derive(Deserialize)]
+struct QueryParams {
+    components: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct SpawnParams {
+    components: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct ComponentPathsParams {
+    entity: u64,
+    components: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ComponentValuesParams {
+    entity: u64,
+    components: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct WatchParams {
+    entity: u64,
+    component: String,
+}
+
+#[derive(Deserialize)]
+struct UnwatchParams {
+    watch: u64,
+}
+
+#[derive(Deserialize)]
+struct ResourceParams {
+    resource: String,
+}
+
+#[derive(Deserialize)]
+struct ResourceValueParams {
+    resource: String,
+    value: Value,
+}
+
+/// Answers the requests received by every [`InspectListener`].
+fn handle_inspect_requests(world: &mut World, listeners: &mut QueryState<&InspectListener>) {
+    let requests: Vec<_> = listeners
+        .iter(world)
+        .flat_map(|listener| std::iter::from_fn(|| listener.requests.try_recv().ok()))
+        .collect();
+    for request in requests {
+        if let Some(response) = respond(world, &request.line, &request.responses) {
+            // Fails if the client disconnected.
+            let _ = request.responses.try_send(response);
+        }
+    }
+}
+
+/// Runs the request in `line`, and returns its response unless it has no identifier.
+fn respond(world: &mut World, line: &str, responses: &Sender<String>) -> Option<String> {
+    let (id, result) = match serde_json::from_str::<Request>(line) {
+        Ok(request) => {
+            let result = call(world, &request.method, request.params, responses);
+            (request.id?, result)
+        }
+        Err(error) if error.is_data() => (
+            Value::Null,
+            Err(InspectError::InvalidRequest(error.to_string())),
+        ),
+        Err(error) => (Value::Null, Err(InspectError::Parse(error.to_string()))),
+    };
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": error.code(), "message": error.to_string() },
+        }),
+    };
+    Some(response.to_string())
+}
+
+fn call(
+    world: &mut World,
+    method: &str,
+    params: Value,
+    responses: &Sender<String>,
+) -> Result<Value, InspectError> {
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+    match method {
+        "entity.list" => Ok(world
+            .iter_entities()
+            .map(|entity| entity.id().to_bits())
+            .collect()),
+        "entity.query" => {
+            let QueryParams { components } = parse(params)?;
+            let components = components
+                .iter()
+                .map(|type_path| Ok((type_path, reflect_component(&registry, type_path)?.1)))
+                .collect::<Result<Vec<_>, InspectError>>()?;
+            let mut results = Vec::new();
+            'entities: for entity in world.iter_entities() {
+                let mut values = Map::new();
+                for &(type_path, reflect_component) in &components {
+                    let Some(value) = reflect_component.reflect(entity) else {
+                        continue 'entities;
+                    };
+                    values.insert(type_path.clone(), serialize(value, type_path, &registry)?);
+                }
+                results.push(json!({ "entity": entity.id().to_bits(), "components": values }));
+            }
+            Ok(results.into())
+        }
+        "entity.spawn" => {
+            let SpawnParams { components } = parse(params)?;
+            let components = deserialize_components(&registry, components)?;
+            let mut entity = world.spawn_empty();
+            for (reflect_component, value) in components {
+                reflect_component.insert(&mut entity, &*value, &registry);
+            }
+            Ok(entity.id().to_bits().into())
+        }
+        "entity.despawn" => {
+            let EntityParams { entity } = parse(params)?;
+            world.despawn(live_entity(world, entity)?);
+            Ok(Value::Null)
+        }
+        "component.get" => {
+            let ComponentPathsParams { entity, components } = parse(params)?;
+            let entity = world.entity(live_entity(world, entity)?);
+            let mut values = Map::new();
+            for type_path in components {
+                let (_, reflect_component) = reflect_component(&registry, &type_path)?;
+                let Some(value) = reflect_component.reflect(entity) else {
+                    return Err(InspectError::MissingComponent(type_path));
+                };
+                let value = serialize(value, &type_path, &registry)?;
+                values.insert(type_path, value);
+            }
+            Ok(values.into())
+        }
+        "component.insert" => {
+            let ComponentValuesParams { entity, components } = parse(params)?;
+            let entity = live_entity(world, entity)?;
+            let components = deserialize_components(&registry, components)?;
+            let mut entity = world.entity_mut(entity);
+            for (reflect_component, value) in components {
+                reflect_component.insert(&mut entity, &*value, &registry);
+            }
+            Ok(Value::Null)
+        }
+        "component.remove" => {
+            let ComponentPathsParams { entity, components } = parse(params)?;
+            let entity = live_entity(world, entity)?;
+            let components = components
+                .iter()
+                .map(|type_path| Ok(reflect_component(&registry, type_path)?.1))
+                .collect::<Result<Vec<_>, InspectError>>()?;
+            let mut entity = world.entity_mut(entity);
+            for reflect_component in components {
+                reflect_component.remove(&mut entity);
+            }
+            Ok(Value::Null)
+        }
+        "component.watch" => {
+            let WatchParams { entity, component } = parse(params)?;
+            let entity = live_entity(world, entity)?;
+            let (registration, reflect_component) = reflect_component(&registry, &component)?;
+            let mut watches = world.resource_mut::<Watches>();
+            let id = watches.next_id;
+            watches.next_id += 1;
+            watches.watches.push(Watch {
+                id,
+                entity,
+                type_id: registration.type_id(),
+                type_path: component,
+                reflect_component: reflect_component.clone(),
+                responses: responses.clone(),
+                seen: None,
+            });
+            Ok(id.into())
+        }
+        "component.unwatch" => {
+            let UnwatchParams { watch } = parse(params)?;
+            let mut watches = world.resource_mut::<Watches>();
+            let Some(index) = watches.watches.iter().position(|w| w.id == watch) else {
+                return Err(InspectError::NoSuchWatch(watch));
+            };
+            watches.watches.remove(index);
+            Ok(Value::Null)
+        }
+        "resource.get" => {
+            let ResourceParams { resource } = parse(params)?;
+            let (_, reflect_resource) = reflect_resource(&registry, &resource)?;
+            let Some(value) = reflect_resource.reflect(world) else {
+                return Err(InspectError::MissingResource(resource));
+            };
+            serialize(value, &resource, &registry)
+        }
+        "resource.insert" => {
+            let ResourceValueParams { resource, value } = parse(params)?;
+            let (registration, reflect_resource) = reflect_resource(&registry, &resource)?;
+            let value = deserialize(registration, &registry, value)?;
+            reflect_resource.insert(world, &*value, &registry);
+            Ok(Value::Null)
+        }
+        "resource.remove" => {
+            let ResourceParams { resource } = parse(params)?;
+            let (_, reflect_resource) = reflect_resource(&registry, &resource)?;
+            if reflect_resource.reflect(world).is_none() {
+                return Err(InspectError::MissingResource(resource));
+            }
+            reflect_resource.remove(world);
+            Ok(Value::Null)
+        }
+        _ => Err(InspectError::MethodNotFound(method.to_string())),
+    }
+}
+
+/// Sends the watched components that changed since they were last sent.
+fn notify_watches(world: &mut World) {
+    world.resource_scope(|world, mut watches: Mut<Watches>| {
+        let registry = world.resource::<AppTypeRegistry>().read();
+        watches.watches.retain_mut(|watch| {
+            if watch.responses.is_closed() {
+                return false;
+            }
+            let entity = world.get_entity(watch.entity);
+            let changed = entity
+                .zip(world.components().get_id(watch.type_id))
+                .and_then(|(entity, id)| entity.get_change_ticks_by_id(id))
+                .map(|ticks| ticks.last_changed_tick());
+            if watch.seen == Some(changed) {
+                return true;
+            }
+            watch.seen = Some(changed);
+            let value = match entity.and_then(|entity| watch.reflect_component.reflect(entity)) {
+                Some(value) => match serialize(value, &watch.type_path, &registry) {
+                    Ok(value) => value,
+                    Err(error) => {
+                        warn!("ending the watch of a component: {error}");
+                        return false;
+                    }
+                },
+                None => Value::Null,
+            };
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "component.changed",
+                "params": { "watch": watch.id, "value": value },
+            });
+            watch.responses.try_send(notification.to_string()).is_ok() && entity.is_some()
+        });
+    });
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, InspectError> {
+    serde_json::from_value(params).map_err(|error| InspectError::InvalidParams(error.to_string()))
+}
+
+fn live_entity(world: &World, bits: u64) -> Result<Entity, InspectError> {
+    Entity::try_from_bits(bits)
+        .ok()
+        .filter(|&entity| world.get_entity(entity).is_some())
+        .ok_or(InspectError::NoSuchEntity(bits))
+}
+
+fn registration<'a>(
+    registry: &'a TypeRegistry,
+    type_path: &str,
+) -> Result<&'a TypeRegistration, InspectError> {
+    registry
+        .get_with_type_path(type_path)
+        .ok_or_else(|| InspectError::UnknownType(type_path.to_string()))
+}
+
+fn reflect_component<'a>(
+    registry: &'a TypeRegistry,
+    type_path: &str,
+) -> Result<(&'a TypeRegistration, &'a ReflectComponent), InspectError> {
+    let registration = registration(registry, type_path)?;
+    let reflect_component = registration
+        .data::<ReflectComponent>()
+        .ok_or_else(|| InspectError::NotAComponent(type_path.to_string()))?;
+    Ok((registration, reflect_component))
+}
+
+fn reflect_resource<'a>(
+    registry: &'a TypeRegistry,
+    type_path: &str,
+) -> Result<(&'a TypeRegistration, &'a ReflectResource), InspectError> {
+    let registration = registration(registry, type_path)?;
+    let reflect_resource = registration
+        .data::<ReflectResource>()
+        .ok_or_else(|| InspectError::NotAResource(type_path.to_string()))?;
+    Ok((registration, reflect_resource))
+}
+
+fn serialize(
+    value: &dyn Reflect,
+    type_path: &str,
+    registry: &TypeRegistry,
+) -> Result<Value, InspectError> {
+    serde_json::to_value(TypedReflectSerializer::new(value, registry)).map_err(|error| {
+        InspectError::Serialize {
+            type_path: type_path.to_string(),
+            message: error.to_string(),
+        }
+    })
+}
+
+fn deserialize(
+    registration: &TypeRegistration,
+    registry: &TypeRegistry,
+    value: Value,
+) -> Result<Box<dyn Reflect>, InspectError> {
+    TypedReflectDeserializer::new(registration, registry)
+        .deserialize(value)
+        .map_err(|error| InspectError::Deserialize {
+            type_path: registration.type_info().type_path().to_string(),
+            message: error.to_string(),
+        })
+}
+
+/// Deserializes every value of `components`, keyed by type path, before any is inserted.
+fn deserialize_components(
+    registry: &TypeRegistry,
+    components: Map<String, Value>,
+) -> Result<Vec<(&ReflectComponent, Box<dyn Reflect>)>, InspectError> {
+    components
+        .into_iter()
+        .map(|(type_path, value)| {
+            let (registration, reflect_component) = reflect_component(registry, &type_path)?;
+            Ok((
+                reflect_component,
+                deserialize(registration, registry, value)?,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_tasks::TaskPool;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::time::{Duration, Instant};
+
+    const HEALTH: &str = "bevy_net::inspect::tests::Health";
+    const SCORE: &str = "bevy_net::inspect::tests::Score";
+
+    #[derive(Component, Reflect)]
+    #[reflect(Component)]
+    struct Health {
+        points: u32,
+    }
+
+    #[derive(Resource, Reflect, Default, Debug, PartialEq)]
+    #[reflect(Resource)]
+    struct Score {
+        points: u32,
+    }
+
+    /// A blocking client, keeping the notifications received while waiting for responses.
+    struct Client {
+        stream: BufReader<TcpStream>,
+        next_id: u64,
+        notifications: Vec<Value>,
+    }
+
+    impl Client {
+        fn send(&mut self, line: &str) {
+            let stream = self.stream.get_mut();
+            stream.write_all(line.as_bytes()).unwrap();
+            stream.write_all(b"\n").unwrap();
+        }
+
+        fn receive(&mut self) -> Value {
+            let mut line = String::new();
+            self.stream.read_line(&mut line).unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+
+        fn request(&mut self, method: &str, params: Value) -> Value {
+            let id = self.next_id;
+            self.next_id += 1;
+            let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+            self.send(&request.to_string());
+            loop {
+                let message = self.receive();
+                if message.get("method").is_some() {
+                    self.notifications.push(message);
+                } else {
+                    assert_eq!(message["id"], id);
+                    return message;
+                }
+            }
+        }
+
+        fn call(&mut self, method: &str, params: Value) -> Value {
+            let response = self.request(method, params);
+            assert_eq!(response["error"], Value::Null, "{method} failed");
+            response["result"].clone()
+        }
+
+        fn error_code(&mut self, method: &str, params: Value) -> i64 {
+            self.request(method, params)["error"]["code"]
+                .as_i64()
+                .unwrap()
+        }
+
+        fn changed(&mut self) -> Value {
+            if self.notifications.is_empty() {
+                let notification = self.receive();
+                self.notifications.push(notification);
+            }
+            let notification = self.notifications.remove(0);
+            assert_eq!(notification["method"], "component.changed");
+            notification["params"]["value"].clone()
+        }
+    }
+
+    #[test]
+    fn inspects_and_edits_over_tcp() {
+        IoTaskPool::get_or_init(TaskPool::new);
+        let mut app = App::new();
+        app.add_plugins(InspectPlugin)
+            .register_type::<Health>()
+            .register_type::<Score>()
+            .init_resource::<Score>();
+        let listener = InspectListener::tcp(([127, 0, 0, 1], 0).into()).unwrap();
+        let addr = listener.local_addr();
+        app.world_mut().spawn(listener);
+
+        let client = std::thread::spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+            let mut client = Client {
+                stream: BufReader::new(stream),
+                next_id: 0,
+                notifications: Vec::new(),
+            };
+
+            let entity = client.call(
+                "entity.spawn",
+                json!({ "components": { HEALTH: { "points": 10 } } }),
+            );
+            assert!(client
+                .call("entity.list", Value::Null)
+                .as_array()
+                .unwrap()
+                .contains(&entity));
+            assert_eq!(
+                client.call("entity.query", json!({ "components": [HEALTH] })),
+                json!([{ "entity": entity, "components": { HEALTH: { "points": 10 } } }])
+            );
+
+            let watch = client.call(
+                "component.watch",
+                json!({ "entity": entity, "component": HEALTH }),
+            );
+            assert_eq!(client.changed(), json!({ "points": 10 }));
+            client.call(
+                "component.insert",
+                json!({ "entity": entity, "components": { HEALTH: { "points": 4 } } }),
+            );
+            assert_eq!(client.changed(), json!({ "points": 4 }));
+            assert_eq!(
+                client.call(
+                    "component.get",
+                    json!({ "entity": entity, "components": [HEALTH] })
+                ),
+                json!({ HEALTH: { "points": 4 } })
+            );
+            client.call(
+                "component.remove",
+                json!({ "entity": entity, "components": [HEALTH] }),
+            );
+            assert_eq!(client.changed(), Value::Null);
+            assert_eq!(
+                client.error_code(
+                    "component.get",
+                    json!({ "entity": entity, "components": [HEALTH] })
+                ),
+                -32002
+            );
+            client.call("component.unwatch", json!({ "watch": watch }));
+            assert_eq!(
+                client.error_code("component.unwatch", json!({ "watch": watch })),
+                -32004
+            );
+
+            assert_eq!(
+                client.call("resource.get", json!({ "resource": SCORE })),
+                json!({ "points": 0 })
+            );
+            client.call(
+                "resource.insert",
+                json!({ "resource": SCORE, "value": { "points": 7 } }),
+            );
+            assert_eq!(
+                client.error_code(
+                    "resource.insert",
+                    json!({ "resource": SCORE, "value": { "points": "many" } })
+                ),
+                -32602
+            );
+
+            client.call("entity.despawn", json!({ "entity": entity }));
+            assert_eq!(
+                client.error_code("entity.despawn", json!({ "entity": entity })),
+                -32000
+            );
+            assert_eq!(
+                client.error_code("entity.spawn", json!({ "components": { "Missing": {} } })),
+                -32001
+            );
+            assert_eq!(client.error_code("entity.fly", Value::Null), -32601);
+            assert_eq!(client.error_code("entity.spawn", json!([])), -32602);
+
+            // Requests without an identifier are run without being answered.
+            client.send(
+                r#"{"jsonrpc": "2.0", "method": "entity.spawn", "params": {"components": {}}}"#,
+            );
+            client.send("{");
+            assert_eq!(client.receive()["error"]["code"], -32700);
+            assert!(client.notifications.is_empty());
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !client.is_finished() {
+            assert!(Instant::now() < deadline, "the client timed out");
+            app.update();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        client.join().unwrap();
+        assert_eq!(app.world().resource::<Score>(), &Score { points: 7 });
+        let mut health = app.world_mut().query::<&Health>();
+        assert_eq!(health.iter(app.world()).count(), 0);
+        assert_eq!(app.world().entities().len(), 2);
+    }
+}
@@ -0,0 +1,369 @@
+//! Snapshot interpolation of replicated entities controlled by other peers.
+//!
+//! Replicated values arrive in bursts and at uneven intervals, so applying them as they come makes
+//! remote entities stutter. Instead, a client can add [`Interpolated<T>`] to a replicated entity:
+//! every value of `T` received from the server is then buffered as a snapshot for the server's
+//! [`NetworkTick`] it was sent at, and `T` is rendered [`InterpolationConfig::delay`] behind the
+//! server's current tick, blending the snapshots before and after that point with
+//! [`Animatable::interpolate`]. When no snapshot after that point arrived yet, the last two
+//! snapshots are extrapolated, for at most [`InterpolationConfig::max_extrapolation`].
+//!
+//! Any [replicated](crate::replication::ReplicationApp::replicate) component implementing
+//! [`Animatable`], such as `Transform`, can be interpolated once registered with
+//! [`InterpolationApp::interpolate`].
+
+use std::collections::VecDeque;
+
+use bevy_animation::prelude::Animatable;
+use bevy_app::{App, Plugin, PreUpdate};
+use bevy_ecs::prelude::*;
+use bevy_time::{Fixed, Time};
+use bevy_utils::Duration;
+
+use crate::replication::{ConfirmedTick, ReplicationRegistry, ReplicationSystem};
+use crate::tick::{ClockSync, ClockSyncSystem, NetworkTick};
+
+/// Adds snapshot interpolation to an [`App`].
+///
+/// Requires the [`ReplicationPlugin`](crate::replication::ReplicationPlugin) and the
+/// [`NetworkTickPlugin`](crate::tick::NetworkTickPlugin).
+#[derive(Default)]
+pub struct InterpolationPlugin;
+
+impl Plugin for InterpolationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InterpolationConfig>().configure_sets(
+            PreUpdate,
+            InterpolationSystem
+                .after(ReplicationSystem::Receive)
+                .after(ClockSyncSystem),
+        );
+    }
+}
+
+/// Renders the interpolated components.
+///
+/// Runs in [`PreUpdate`], after [`ReplicationSystem::Receive`] and [`ClockSyncSystem`].
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemSet)]
+pub struct InterpolationSystem;
+
+/// Configures how interpolated components are rendered.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct InterpolationConfig {
+    /// How far behind the server's current tick interpolated components are rendered.
+    ///
+    /// Snapshots arriving later than this after they were sent are extrapolated over, so it
+    /// should cover a few ticks of network jitter on top of the interval between snapshots.
+    pub delay: Duration,
+    /// How far past the latest snapshot interpolated components may be extrapolated, before they
+    /// stop until the next snapshot arrives.
+    pub max_extrapolation: Duration,
+}
+
+impl Default for InterpolationConfig {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(100),
+            max_extrapolation: Duration::from_millis(50),
+        }
+    }
+}
+
+/// How many snapshots an [`Interpolated`] component buffers at most, so they do not pile up while
+/// nothing is rendered, such as before the clock is synchronized.
+const MAX_SNAPSHOTS: usize = 64;
+
+/// Renders the replicated component `T` of an entity from buffered snapshots, instead of applying
+/// every value as it arrives.
+#[derive(Component, Debug, Clone)]
+pub struct Interpolated<T> {
+    snapshots: VecDeque<(NetworkTick, T)>,
+}
+
+impl<T> Default for Interpolated<T> {
+    fn default() -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> Interpolated<T> {
+    /// Gets the latest value received from the server, and the tick it was sent at.
+    pub fn latest(&self) -> Option<(NetworkTick, &T)> {
+        self.snapshots.back().map(|(tick, value)| (*tick, value))
+    }
+
+    fn push(&mut self, tick: NetworkTick, value: T) {
+        while self.snapshots.back().is_some_and(|(last, _)| *last >= tick) {
+            self.snapshots.pop_back();
+        }
+        if self.snapshots.len() == MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back((tick, value));
+    }
+
+    /// Drops the snapshots that are no longer needed to render `tick` or anything after it.
+    fn trim(&mut self, tick: f64) {
+        while self.snapshots.len() > 2 && f64::from(self.snapshots[1].0.get()) <= tick {
+            self.snapshots.pop_front();
+        }
+    }
+}
+
+impl<T: Animatable + Clone> Interpolated<T> {
+    /// Renders the value at `tick`, extrapolating at most `max_extrapolation` ticks past the
+    /// latest snapshot.
+    fn sample(&self, tick: f64, max_extrapolation: f64) -> Option<T> {
+        let len = self.snapshots.len();
+        let next = self
+            .snapshots
+            .partition_point(|(snapshot, _)| f64::from(snapshot.get()) <= tick);
+        let (from, to) = match (len, next) {
+            (0, _) => return None,
+            (1, _) | (_, 0) => return Some(self.snapshots[0].1.clone()),
+            (_, next) if next == len => (len - 2, len - 1),
+            (_, next) => (next - 1, next),
+        };
+
+        let (from_tick, from) = &self.snapshots[from];
+        let (to_tick, to) = &self.snapshots[to];
+        let (from_tick, to_tick) = (f64::from(from_tick.get()), f64::from(to_tick.get()));
+        let tick = tick.min(to_tick + max_extrapolation);
+        let t = (tick - from_tick) / (to_tick - from_tick);
+        Some(T::interpolate(from, to, t as f32))
+    }
+}
+
+/// Registers components for interpolation.
+pub trait InterpolationApp {
+    /// Allows components of type `T` to be [`Interpolated`].
+    ///
+    /// `T` must already be [replicated](crate::replication::ReplicationApp::replicate).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is not replicated.
+    fn interpolate<T: Component + Animatable + Clone>(&mut self) -> &mut Self;
+}
+
+impl InterpolationApp for App {
+    fn interpolate<T: Component + Animatable + Clone>(&mut self) -> &mut Self {
+        let replicated = self
+            .world_mut()
+            .resource_mut::<ReplicationRegistry>()
+            .add_on_write::<T>(receive_snapshot::<T>);
+        assert!(
+            replicated,
+            "component {} must be replicated to be interpolated",
+            std::any::type_name::<T>()
+        );
+
+        self.add_systems(
+            PreUpdate,
+            (hold_snapshots::<T>, render::<T>)
+                .chain()
+                .in_set(InterpolationSystem),
+        )
+    }
+}
+
+fn receive_snapshot<T: Component + Clone>(world: &mut World, entity: Entity) {
+    let Some(tick) = world.resource::<ConfirmedTick>().get() else {
+        return;
+    };
+    let mut entity = world.entity_mut(entity);
+    let Some(value) = entity.get::<T>().cloned() else {
+        return;
+    };
+    if let Some(mut interpolated) = entity.get_mut::<Interpolated<T>>() {
+        interpolated.push(tick, value);
+    }
+}
+
+/// Records that the components which did not change are still valid as of the confirmed tick, so
+/// they are not extrapolated.
+fn hold_snapshots<T: Component + Clone>(
+    confirmed: Res<ConfirmedTick>,
+    mut interpolated: Query<(&T, &mut Interpolated<T>)>,
+) {
+    if !confirmed.is_changed() {
+        return;
+    }
+    let Some(tick) = confirmed.get() else {
+        return;
+    };
+
+    for (value, mut interpolated) in &mut interpolated {
+        match interpolated.latest() {
+            Some((latest, _)) if latest >= tick => {}
+            Some((_, latest)) => {
+                let latest = latest.clone();
+                interpolated.push(tick, latest);
+            }
+            // Entities that just started being interpolated hold the received value.
+            None => interpolated.push(tick, value.clone()),
+        }
+    }
+}
+
+fn render<T: Component + Animatable + Clone>(
+    config: Res<InterpolationConfig>,
+    sync: Res<ClockSync>,
+    fixed: Res<Time<Fixed>>,
+    mut interpolated: Query<(&mut T, &mut Interpolated<T>)>,
+) {
+    let timestep = sync
+        .server_timestep()
+        .unwrap_or_else(|| fixed.timestep())
+        .as_secs_f64();
+    let Some(server_tick) = sync.server_tick() else {
+        // Without a clock, the latest value is the best guess.
+        for (mut value, interpolated) in &mut interpolated {
+            if let Some((_, latest)) = interpolated.latest() {
+                *value = latest.clone();
+            }
+        }
+        return;
+    };
+
+    let tick = server_tick - config.delay.as_secs_f64() / timestep;
+    let max_extrapolation = config.max_extrapolation.as_secs_f64() / timestep;
+    for (mut value, mut interpolated) in &mut interpolated {
+        interpolated.trim(tick);
+        if let Some(rendered) = interpolated.sample(tick, max_extrapolation) {
+            *value = rendered;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshots(values: &[(u32, f32)]) -> Interpolated<f32> {
+        let mut interpolated = Interpolated::default();
+        for (tick, value) in values {
+            interpolated.push(NetworkTick::new(*tick), *value);
+        }
+        interpolated
+    }
+
+    #[test]
+    fn interpolates_between_snapshots() {
+        let interpolated = snapshots(&[(10, 0.0), (20, 10.0), (30, 30.0)]);
+        assert_eq!(interpolated.sample(15.0, 0.0), Some(5.0));
+        assert_eq!(interpolated.sample(20.0, 0.0), Some(10.0));
+        assert_eq!(interpolated.sample(25.0, 0.0), Some(20.0));
+        assert_eq!(interpolated.sample(5.0, 0.0), Some(0.0));
+        assert_eq!(snapshots(&[]).sample(5.0, 0.0), None);
+        assert_eq!(snapshots(&[(10, 3.0)]).sample(50.0, 10.0), Some(3.0));
+    }
+
+    #[test]
+    fn caps_extrapolation() {
+        let interpolated = snapshots(&[(10, 0.0), (20, 10.0)]);
+        assert_eq!(interpolated.sample(22.0, 5.0), Some(12.0));
+        assert_eq!(interpolated.sample(40.0, 5.0), Some(15.0));
+    }
+
+    #[test]
+    fn trims_old_snapshots() {
+        let mut interpolated = snapshots(&[(10, 0.0), (20, 10.0), (30, 30.0), (40, 60.0)]);
+        interpolated.trim(25.0);
+        assert_eq!(interpolated.snapshots.len(), 3);
+        assert_eq!(interpolated.sample(25.0, 0.0), Some(20.0));
+        interpolated.trim(100.0);
+        assert_eq!(interpolated.snapshots.len(), 2);
+
+        // Snapshots for ticks that were received again replace the old ones.
+        interpolated.push(NetworkTick::new(35), 0.0);
+        assert_eq!(interpolated.latest(), Some((NetworkTick::new(35), &0.0)));
+    }
+
+    #[test]
+    fn caps_buffered_snapshots() {
+        let mut interpolated = Interpolated::default();
+        let last = MAX_SNAPSHOTS as u32 * 2;
+        for tick in 0..=last {
+            interpolated.push(NetworkTick::new(tick), tick as f32);
+        }
+        assert_eq!(interpolated.snapshots.len(), MAX_SNAPSHOTS);
+        assert_eq!(
+            interpolated.latest(),
+            Some((NetworkTick::new(last), &(last as f32)))
+        );
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod quic_tests {
+    use super::*;
+    use crate::quic::test_utils::{connected_apps, update_until};
+    use crate::replication::{
+        Replicated, ReplicationApp, ReplicationPlugin, ServerEntities, ServerEntity,
+    };
+    use crate::tick::NetworkTickPlugin;
+    use bevy_app::FixedUpdate;
+    use bevy_time::TimePlugin;
+    use bevy_transform::components::Transform;
+
+    #[test]
+    fn renders_remote_entities_behind_the_server() {
+        let (mut server, mut client, _, _) = connected_apps(|app| {
+            app.add_plugins((
+                TimePlugin,
+                ReplicationPlugin,
+                NetworkTickPlugin,
+                InterpolationPlugin,
+            ))
+            .replicate::<Transform>()
+            .interpolate::<Transform>()
+            .observe(
+                |trigger: Trigger<OnAdd, ServerEntity>, mut commands: Commands| {
+                    commands
+                        .entity(trigger.entity())
+                        .insert(Interpolated::<Transform>::default());
+                },
+            );
+        });
+        server.add_systems(
+            FixedUpdate,
+            |tick: Res<NetworkTick>, mut transforms: Query<&mut Transform>| {
+                for mut transform in &mut transforms {
+                    transform.translation.x = tick.get() as f32;
+                }
+            },
+        );
+        let entity = server
+            .world_mut()
+            .spawn((Replicated, Transform::default()))
+            .id();
+
+        let rendered = |client: &App| {
+            let replica = client.world().resource::<ServerEntities>().get(entity)?;
+            let transform = client.world().get::<Transform>(replica)?;
+            client.world().get::<Interpolated<Transform>>(replica)?;
+            Some(transform.translation.x)
+        };
+        update_until([&mut server, &mut client], |[server, client]| {
+            let start = server.world().resource::<NetworkTick>().get() as f32;
+            client.world().resource::<ClockSync>().is_synced()
+                && rendered(client).is_some_and(|x| x > start.min(10.0) + 10.0)
+        });
+
+        // The default delay is 6.4 ticks at the default timestep of 64 Hz.
+        let server_x = server
+            .world()
+            .get::<Transform>(entity)
+            .unwrap()
+            .translation
+            .x;
+        let behind = server_x - rendered(&client).unwrap();
+        assert!(
+            (2.0..=12.0).contains(&behind),
+            "rendered {behind} ticks behind"
+        );
+    }
+}
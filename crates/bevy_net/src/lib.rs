@@ -1,5 +1,7 @@
 //! The place where netcode and networking utilities for bevy live.
 
+#[cfg(feature = "tls")]
+pub use rcgen;
 #[cfg(feature = "tls")]
 pub use rustls;
 
@@ -7,6 +9,43 @@ pub use rustls;
 #[allow(missing_docs)]
 pub mod quic;
 
+#[cfg(feature = "quic")]
+pub mod channel;
+
+#[cfg(all(feature = "quic", feature = "asset"))]
+pub mod asset;
+
+#[cfg(feature = "quic")]
+pub mod transport;
+
+#[cfg(feature = "quic")]
+pub mod discovery;
+
+#[cfg(feature = "quic")]
+pub mod event;
+
+#[cfg(feature = "quic")]
+pub mod replication;
+
+#[cfg(feature = "quic")]
+pub mod tick;
+
+#[cfg(feature = "quic")]
+pub mod prediction;
+
+#[cfg(feature = "rpc")]
+pub mod rpc;
+
+#[cfg(feature = "inspect")]
+pub mod inspect;
+
+#[cfg(feature = "interpolation")]
+pub mod interpolation;
+
+pub mod wire;
+
+mod varint;
+
 #[cfg(feature = "tls")]
 #[allow(missing_docs)]
 pub mod crypto_utils;
@@ -0,0 +1,304 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use bevy_app::{App, FixedPreUpdate, PreUpdate};
+use bevy_derive::{Deref, DerefMut};
+use bevy_ecs::prelude::*;
+
+use super::PredictionRegistry;
+use crate::channel::{
+    ChannelConfig, ChannelKind, DecodeError, MessageApp, MessageReader, MessageWriter,
+    NetworkMessage,
+};
+use crate::quic::{Connected, ConnectionDirection, ConnectionState, NetworkSystem};
+use crate::tick::NetworkTick;
+use crate::varint::{read_varint, write_varint};
+
+/// How many ticks of inputs are kept on clients, to be replayed during rollbacks.
+const INPUT_HISTORY_LEN: usize = 128;
+
+/// How many of the latest inputs every input message repeats, so lost datagrams are covered by
+/// the next ones.
+const INPUT_REDUNDANCY: usize = 8;
+
+/// How many inputs the server buffers per client, at most.
+const INPUT_BUFFER_LEN: usize = 128;
+
+/// The local player's input on a client, sent to the server every tick.
+///
+/// Set it before [`FixedPreUpdate`], where it is recorded for the current [`NetworkTick`]. During
+/// rollbacks, it holds the input recorded for the tick being re-simulated.
+#[derive(Resource, Debug, Default, Clone, Deref, DerefMut)]
+pub struct LocalInput<I>(pub I);
+
+/// The input of a client for the current [`NetworkTick`], on its connection entity on the server.
+///
+/// Updated in [`FixedPreUpdate`] with the latest input received for a tick no later than the
+/// current one, so a client keeps its last input when the next one is late.
+#[derive(Component, Debug, Default, Clone, Deref)]
+pub struct RemoteInput<I>(I);
+
+impl<I> RemoteInput<I> {
+    /// Gets the client's input.
+    pub fn get(&self) -> &I {
+        &self.0
+    }
+}
+
+/// The local inputs of the latest ticks.
+#[derive(Resource)]
+struct InputHistory<I> {
+    inputs: VecDeque<(NetworkTick, I)>,
+    /// The current input, set aside while past inputs are replayed.
+    stash: Option<I>,
+}
+
+impl<I> Default for InputHistory<I> {
+    fn default() -> Self {
+        Self {
+            inputs: VecDeque::new(),
+            stash: None,
+        }
+    }
+}
+
+impl<I> InputHistory<I> {
+    fn record(&mut self, tick: NetworkTick, input: I) {
+        while self.inputs.back().is_some_and(|(last, _)| *last >= tick) {
+            self.inputs.pop_back();
+        }
+        if self.inputs.len() == INPUT_HISTORY_LEN {
+            self.inputs.pop_front();
+        }
+        self.inputs.push_back((tick, input));
+    }
+
+    fn get(&self, tick: NetworkTick) -> Option<&I> {
+        self.inputs
+            .iter()
+            .find(|(recorded, _)| *recorded == tick)
+            .map(|(_, input)| input)
+    }
+}
+
+/// The inputs received from a client, by tick, on its connection entity on the server.
+#[derive(Component)]
+struct InputBuffer<I> {
+    inputs: BTreeMap<u32, I>,
+    last_applied: Option<u32>,
+}
+
+/// The latest inputs of a client, the last one being the input for `tick`.
+struct InputMessage<I> {
+    tick: u32,
+    inputs: Vec<I>,
+}
+
+impl<I: NetworkMessage> NetworkMessage for InputMessage<I> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write_varint(buf, u64::from(self.tick));
+        let mut input_buf = Vec::new();
+        for input in &self.inputs {
+            input_buf.clear();
+            input.encode(&mut input_buf);
+            write_varint(buf, input_buf.len() as u64);
+            buf.extend_from_slice(&input_buf);
+        }
+    }
+
+    fn decode(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let malformed = || DecodeError("malformed input message".into());
+        let tick = read_varint(&mut bytes)
+            .and_then(|tick| u32::try_from(tick).ok())
+            .ok_or_else(malformed)?;
+        let mut inputs = Vec::new();
+        while !bytes.is_empty() {
+            let len = read_varint(&mut bytes)
+                .and_then(|len| usize::try_from(len).ok())
+                .filter(|len| *len <= bytes.len())
+                .ok_or_else(malformed)?;
+            let (input, rest) = bytes.split_at(len);
+            inputs.push(I::decode(input)?);
+            bytes = rest;
+        }
+        let max_inputs = tick.checked_add(1).ok_or_else(malformed)?;
+        if inputs.len() > INPUT_REDUNDANCY || inputs.len() as u32 > max_inputs {
+            return Err(malformed());
+        }
+        Ok(Self { tick, inputs })
+    }
+}
+
+pub(super) fn add_input<I: NetworkMessage + Clone + Default>(app: &mut App) {
+    app.world_mut()
+        .get_resource_mut::<PredictionRegistry>()
+        .expect("the `PredictionPlugin` must be added before adding inputs")
+        .inputs
+        .push(replay_input::<I>);
+
+    app.init_resource::<LocalInput<I>>()
+        .init_resource::<InputHistory<I>>()
+        .add_message::<InputMessage<I>>(ChannelConfig::new(ChannelKind::UnreliableSequenced))
+        .observe(add_input_buffer::<I>)
+        .add_systems(FixedPreUpdate, (send_input::<I>, apply_remote_input::<I>))
+        .add_systems(
+            PreUpdate,
+            buffer_remote_inputs::<I>.after(NetworkSystem::Receive),
+        );
+}
+
+/// Sets the local input to the one recorded for `tick`, or restores the current one.
+fn replay_input<I: Clone + Send + Sync + 'static>(world: &mut World, tick: Option<NetworkTick>) {
+    world.resource_scope(|world, mut history: Mut<InputHistory<I>>| {
+        let mut input = world.resource_mut::<LocalInput<I>>();
+        match tick {
+            Some(tick) => {
+                if history.stash.is_none() {
+                    history.stash = Some(input.0.clone());
+                }
+                if let Some(recorded) = history.get(tick) {
+                    input.0 = recorded.clone();
+                }
+            }
+            None => {
+                if let Some(stash) = history.stash.take() {
+                    input.0 = stash;
+                }
+            }
+        }
+    });
+}
+
+fn send_input<I: NetworkMessage + Clone>(
+    tick: Res<NetworkTick>,
+    input: Res<LocalInput<I>>,
+    mut history: ResMut<InputHistory<I>>,
+    connections: Query<(Entity, &ConnectionDirection, &ConnectionState)>,
+    mut writer: MessageWriter<InputMessage<I>>,
+) {
+    history.record(*tick, input.0.clone());
+
+    for (connection, direction, state) in &connections {
+        if *direction != ConnectionDirection::Outgoing || *state != ConnectionState::Connected {
+            continue;
+        }
+        let inputs = history
+            .inputs
+            .iter()
+            .rev()
+            .take_while(|(recorded, _)| recorded.get() + INPUT_REDUNDANCY as u32 > tick.get())
+            .map(|(_, input)| input.clone())
+            .collect::<Vec<_>>();
+        writer.send(
+            connection,
+            InputMessage {
+                tick: tick.get(),
+                inputs: inputs.into_iter().rev().collect(),
+            },
+        );
+    }
+}
+
+fn add_input_buffer<I: Send + Sync + 'static>(
+    trigger: Trigger<Connected>,
+    directions: Query<&ConnectionDirection>,
+    mut commands: Commands,
+) {
+    let connection = trigger.event().connection;
+    if directions.get(connection) == Ok(&ConnectionDirection::Incoming) {
+        commands.entity(connection).insert(InputBuffer::<I> {
+            inputs: BTreeMap::new(),
+            last_applied: None,
+        });
+    }
+}
+
+fn buffer_remote_inputs<I: NetworkMessage + Clone>(
+    reader: MessageReader<InputMessage<I>>,
+    mut buffers: Query<&mut InputBuffer<I>>,
+) {
+    for (connection, message) in reader.read() {
+        let Ok(mut buffer) = buffers.get_mut(connection) else {
+            continue;
+        };
+        let first = message
+            .tick
+            .checked_add(1)
+            .and_then(|end| end.checked_sub(message.inputs.len() as u32));
+        let Some(first) = first else {
+            continue;
+        };
+        for (tick, input) in (first..=message.tick).zip(&message.inputs) {
+            if buffer.last_applied.is_some_and(|last| tick <= last) {
+                continue;
+            }
+            if buffer.inputs.len() >= INPUT_BUFFER_LEN && !buffer.inputs.contains_key(&tick) {
+                break;
+            }
+            buffer.inputs.insert(tick, input.clone());
+        }
+    }
+}
+
+fn apply_remote_input<I: Clone + Send + Sync + 'static>(
+    tick: Res<NetworkTick>,
+    mut buffers: Query<(Entity, &mut InputBuffer<I>, Option<&mut RemoteInput<I>>)>,
+    mut commands: Commands,
+) {
+    for (connection, mut buffer, remote) in &mut buffers {
+        let mut latest = None;
+        while let Some(entry) = buffer.inputs.first_entry() {
+            if *entry.key() > tick.get() {
+                break;
+            }
+            latest = Some(entry.remove_entry());
+        }
+        let Some((applied, input)) = latest else {
+            continue;
+        };
+
+        buffer.last_applied = Some(applied);
+        match remote {
+            Some(mut remote) => remote.0 = input,
+            None => {
+                commands.entity(connection).insert(RemoteInput(input));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(message: &InputMessage<Vec<u8>>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        message.encode(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn rejects_inputs_before_the_first_tick() {
+        let message = InputMessage {
+            tick: 1,
+            inputs: vec![vec![1], vec![2]],
+        };
+        let decoded = InputMessage::<Vec<u8>>::decode(&encode(&message)).unwrap();
+        assert_eq!(decoded.tick, 1);
+        assert_eq!(decoded.inputs, message.inputs);
+
+        let message = InputMessage {
+            tick: 1,
+            inputs: vec![vec![1], vec![2], vec![3]],
+        };
+        assert!(InputMessage::<Vec<u8>>::decode(&encode(&message)).is_err());
+    }
+
+    #[test]
+    fn rejects_the_last_tick() {
+        let message = InputMessage {
+            tick: u32::MAX,
+            inputs: vec![vec![1]],
+        };
+        assert!(InputMessage::<Vec<u8>>::decode(&encode(&message)).is_err());
+    }
+}
@@ -0,0 +1,481 @@
+//! Client-side prediction of replicated entities, with rollback to the server's state.
+//!
+//! Replicated entities marked with [`Predicted`] on a client are simulated ahead of the server
+//! with the client's own inputs, instead of waiting for the server's state to arrive. The systems
+//! that simulate them go in the [`PredictedUpdate`] schedule, which runs once per [`NetworkTick`]
+//! during [`FixedUpdate`] on every peer, reading the [`LocalInput`] on clients and the
+//! [`RemoteInput`] of every connection on the server.
+//!
+//! The state of every component registered with [`PredictionApp::predict`] is recorded after each
+//! tick. When the server's state for a tick arrives and differs from the prediction for that tick,
+//! the client rolls the predicted components back to the server's state and re-runs
+//! [`PredictedUpdate`] with the inputs it stored, up to its current tick. The rest of the app,
+//! including the other [`FixedMain`](bevy_app::FixedMain) schedules, does not run again.
+
+mod input;
+
+pub use input::*;
+
+use std::collections::VecDeque;
+
+use bevy_app::{App, FixedUpdate, Plugin, PreUpdate};
+use bevy_derive::Deref;
+use bevy_ecs::prelude::*;
+use bevy_ecs::schedule::ScheduleLabel;
+use bevy_reflect::std_traits::ReflectDefault;
+use bevy_reflect::Reflect;
+use bevy_time::{Fixed, Time};
+
+use crate::channel::NetworkMessage;
+use crate::replication::{ConfirmedTick, ReplicationRegistry, ReplicationSystem};
+use crate::tick::NetworkTick;
+
+/// Adds client-side prediction to an [`App`].
+///
+/// Requires the [`ReplicationPlugin`](crate::replication::ReplicationPlugin) and the
+/// [`NetworkTickPlugin`](crate::tick::NetworkTickPlugin). Both peers must add this plugin.
+#[derive(Default)]
+pub struct PredictionPlugin;
+
+impl Plugin for PredictionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_schedule(PredictedUpdate)
+            .init_schedule(RecordPrediction)
+            .register_type::<Predicted>()
+            .init_resource::<PredictionRegistry>()
+            .init_resource::<Rollback>()
+            .configure_sets(
+                PreUpdate,
+                (PredictionSystem::Check, PredictionSystem::Rollback)
+                    .chain()
+                    .after(ReplicationSystem::Receive),
+            )
+            .add_systems(FixedUpdate, run_predicted_update)
+            .add_systems(PreUpdate, rollback.in_set(PredictionSystem::Rollback));
+    }
+}
+
+/// The schedule simulating predicted entities, run once per [`NetworkTick`] in [`FixedUpdate`],
+/// and again for every tick re-simulated during a rollback.
+#[derive(ScheduleLabel, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PredictedUpdate;
+
+/// Records the state of the predicted components after every run of [`PredictedUpdate`].
+#[derive(ScheduleLabel, Clone, Debug, PartialEq, Eq, Hash)]
+struct RecordPrediction;
+
+/// Labels for the prediction systems.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemSet)]
+pub enum PredictionSystem {
+    /// Compares the state confirmed by the server with the predictions made for its tick.
+    ///
+    /// Runs in [`PreUpdate`], after [`ReplicationSystem::Receive`].
+    Check,
+    /// Rolls the predicted entities back to the server's state and re-simulates them, if any
+    /// prediction was wrong.
+    ///
+    /// Runs in [`PreUpdate`], after [`PredictionSystem::Check`].
+    Rollback,
+}
+
+/// Marks a replicated entity on a client to be predicted.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[reflect(Component, Default)]
+pub struct Predicted;
+
+/// The latest state of a predicted component received from the server, as of the
+/// [`ConfirmedTick`].
+#[derive(Component, Debug, Clone, PartialEq, Deref)]
+pub struct Confirmed<C>(C);
+
+impl<C> Confirmed<C> {
+    /// Gets the state confirmed by the server.
+    pub fn get(&self) -> &C {
+        &self.0
+    }
+}
+
+/// Tracks rollbacks of the predicted entities.
+#[derive(Resource, Debug, Default)]
+pub struct Rollback {
+    from: Option<NetworkTick>,
+    resimulating: bool,
+}
+
+impl Rollback {
+    /// Rolls the predicted entities back to their state at `tick` during the next
+    /// [`PredictionSystem::Rollback`], and re-simulates the ticks after it.
+    pub fn request(&mut self, tick: NetworkTick) {
+        self.from = Some(self.from.map_or(tick, |from| from.min(tick)));
+    }
+
+    /// Whether [`PredictedUpdate`] is re-simulating a past tick, which systems with side effects
+    /// that must only happen once, such as playing sounds, can check.
+    pub fn is_resimulating(&self) -> bool {
+        self.resimulating
+    }
+}
+
+/// Registers components and inputs for prediction.
+pub trait PredictionApp {
+    /// Predicts components of type `C` on [`Predicted`] entities.
+    ///
+    /// `C` must already be [replicated](crate::replication::ReplicationApp::replicate). Its
+    /// predicted state is compared to the server's with [`PartialEq`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the [`PredictionPlugin`] was not added or `C` is not replicated.
+    fn predict<C: Component + Clone + PartialEq>(&mut self) -> &mut Self;
+
+    /// Adds an input type, sent from the [`LocalInput<I>`] of clients to the [`RemoteInput<I>`] of
+    /// their connection on the server every tick.
+    ///
+    /// Inputs are sent through a message channel, so both peers must register the same input
+    /// types in the same order as their messages.
+    fn add_input<I: NetworkMessage + Clone + Default>(&mut self) -> &mut Self;
+}
+
+impl PredictionApp for App {
+    fn predict<C: Component + Clone + PartialEq>(&mut self) -> &mut Self {
+        let replicated = self
+            .world_mut()
+            .resource_mut::<ReplicationRegistry>()
+            .add_on_write::<C>(confirm::<C>);
+        assert!(
+            replicated,
+            "component {} must be replicated to be predicted",
+            std::any::type_name::<C>()
+        );
+
+        self.world_mut()
+            .get_resource_mut::<PredictionRegistry>()
+            .expect("the `PredictionPlugin` must be added before predicting components")
+            .rewinds
+            .push(rewind::<C>);
+        self.add_systems(RecordPrediction, record::<C>)
+            .add_systems(PreUpdate, check::<C>.in_set(PredictionSystem::Check))
+    }
+
+    fn add_input<I: NetworkMessage + Clone + Default>(&mut self) -> &mut Self {
+        input::add_input::<I>(self);
+        self
+    }
+}
+
+/// How many ticks of predictions are kept.
+const HISTORY_LEN: usize = 128;
+
+/// The predicted states of a component after each of the latest ticks.
+#[derive(Component)]
+struct PredictionHistory<C> {
+    states: VecDeque<(NetworkTick, C)>,
+}
+
+impl<C> PredictionHistory<C> {
+    fn record(&mut self, tick: NetworkTick, state: C) {
+        // Re-simulated ticks replace the predictions made for them.
+        while self.states.back().is_some_and(|(last, _)| *last >= tick) {
+            self.states.pop_back();
+        }
+        if self.states.len() == HISTORY_LEN {
+            self.states.pop_front();
+        }
+        self.states.push_back((tick, state));
+    }
+
+    fn get(&self, tick: NetworkTick) -> Option<&C> {
+        self.states
+            .iter()
+            .find(|(recorded, _)| *recorded == tick)
+            .map(|(_, state)| state)
+    }
+
+    fn latest(&self) -> Option<&C> {
+        self.states.back().map(|(_, state)| state)
+    }
+}
+
+/// The prediction functions of every predicted component and input type.
+#[derive(Resource, Default)]
+struct PredictionRegistry {
+    /// Rolls the components back to their state at a tick.
+    rewinds: Vec<fn(&mut World, NetworkTick)>,
+    /// Sets the local input to the one of a tick, or restores the current one.
+    inputs: Vec<fn(&mut World, Option<NetworkTick>)>,
+}
+
+fn run_predicted_update(world: &mut World) {
+    world.run_schedule(PredictedUpdate);
+    world.run_schedule(RecordPrediction);
+}
+
+fn record<C: Component + Clone>(
+    tick: Res<NetworkTick>,
+    mut predicted: Query<(Entity, &C, Option<&mut PredictionHistory<C>>), With<Predicted>>,
+    mut commands: Commands,
+) {
+    for (entity, component, history) in &mut predicted {
+        match history {
+            Some(mut history) => history.record(*tick, component.clone()),
+            None => {
+                let mut history = PredictionHistory {
+                    states: VecDeque::new(),
+                };
+                history.record(*tick, component.clone());
+                commands.entity(entity).insert(history);
+            }
+        }
+    }
+}
+
+/// Keeps the server's state of a predicted component aside, and restores the prediction.
+fn confirm<C: Component + Clone>(world: &mut World, entity: Entity) {
+    let mut entity = world.entity_mut(entity);
+    if !entity.contains::<Predicted>() {
+        return;
+    }
+    let Some(server) = entity.get::<C>().cloned() else {
+        return;
+    };
+    let predicted = entity
+        .get::<PredictionHistory<C>>()
+        .and_then(PredictionHistory::latest)
+        .cloned();
+
+    entity.insert(Confirmed(server));
+    if let Some(predicted) = predicted {
+        *entity.get_mut::<C>().unwrap() = predicted;
+    }
+}
+
+fn check<C: Component + PartialEq>(
+    confirmed_tick: Res<ConfirmedTick>,
+    predicted: Query<(&Confirmed<C>, Option<&PredictionHistory<C>>), With<Predicted>>,
+    mut rollback: ResMut<Rollback>,
+) {
+    if !confirmed_tick.is_changed() {
+        return;
+    }
+    let Some(tick) = confirmed_tick.get() else {
+        return;
+    };
+
+    let mispredicted = predicted.iter().any(|(confirmed, history)| {
+        history.and_then(|history| history.get(tick)) != Some(&confirmed.0)
+    });
+    if mispredicted {
+        rollback.request(tick);
+    }
+}
+
+fn rewind<C: Component + Clone>(world: &mut World, tick: NetworkTick) {
+    let mut predicted = world.query_filtered::<(
+        &mut C,
+        Option<&Confirmed<C>>,
+        Option<&PredictionHistory<C>>,
+    ), With<Predicted>>();
+
+    for (mut component, confirmed, history) in predicted.iter_mut(world) {
+        let state = confirmed
+            .map(Confirmed::get)
+            .or_else(|| history.and_then(|history| history.get(tick)));
+        if let Some(state) = state {
+            *component = state.clone();
+        }
+    }
+}
+
+fn rollback(world: &mut World) {
+    let Some(from) = world.resource_mut::<Rollback>().from.take() else {
+        return;
+    };
+
+    let registry = world.resource::<PredictionRegistry>();
+    let (rewinds, inputs) = (registry.rewinds.clone(), registry.inputs.clone());
+    for rewind in &rewinds {
+        rewind(world, from);
+    }
+
+    let current = world.resource::<NetworkTick>().get();
+    if from.get() >= current {
+        return;
+    }
+
+    // Re-simulated systems see the fixed clock, as they do in `FixedUpdate`.
+    let time = world.get_resource::<Time>().copied();
+    if let Some(fixed) = world.get_resource::<Time<Fixed>>() {
+        let fixed = fixed.as_generic();
+        world.insert_resource(fixed);
+    }
+    world.resource_mut::<Rollback>().resimulating = true;
+
+    for tick in from.get() + 1..=current {
+        world.resource_mut::<NetworkTick>().set(tick);
+        for input in &inputs {
+            input(world, Some(NetworkTick::new(tick)));
+        }
+        run_predicted_update(world);
+    }
+
+    for input in &inputs {
+        input(world, None);
+    }
+    world.resource_mut::<Rollback>().resimulating = false;
+    if let Some(time) = time {
+        world.insert_resource(time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::DecodeError;
+    use crate::quic::QuicNetworkPlugin;
+    use crate::replication::{ReplicationApp, ReplicationPlugin};
+    use crate::tick::NetworkTickPlugin;
+    use bevy_app::FixedMain;
+    use bevy_time::TimePlugin;
+
+    #[derive(Component, Reflect, Debug, Clone, PartialEq)]
+    #[reflect(Component)]
+    pub(super) struct Position(i32);
+
+    #[derive(Debug, Default, Clone, PartialEq)]
+    pub(super) struct Move(pub(super) i32);
+
+    impl NetworkMessage for Move {
+        fn encode(&self, buf: &mut Vec<u8>) {
+            buf.extend_from_slice(&self.0.to_le_bytes());
+        }
+
+        fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+            let bytes = bytes
+                .try_into()
+                .map_err(|_| DecodeError("bad move".into()))?;
+            Ok(Self(i32::from_le_bytes(bytes)))
+        }
+    }
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            QuicNetworkPlugin,
+            TimePlugin,
+            ReplicationPlugin,
+            NetworkTickPlugin,
+            PredictionPlugin,
+        ))
+        .replicate::<Position>()
+        .predict::<Position>()
+        .add_input::<Move>()
+        .add_systems(
+            PredictedUpdate,
+            |input: Res<LocalInput<Move>>, mut positions: Query<&mut Position, With<Predicted>>| {
+                for mut position in &mut positions {
+                    position.0 += input.0 .0;
+                }
+            },
+        );
+        app.finish();
+        app.cleanup();
+        app
+    }
+
+    fn step(app: &mut App, input: i32) {
+        app.world_mut().resource_mut::<LocalInput<Move>>().0 = Move(input);
+        app.world_mut().run_schedule(FixedMain);
+    }
+
+    /// Applies the server's state for `tick` like replication would.
+    fn receive(app: &mut App, entity: Entity, tick: u32, position: i32) {
+        let world = app.world_mut();
+        world.entity_mut(entity).insert(Position(position));
+        confirm::<Position>(world, entity);
+        world
+            .resource_mut::<ConfirmedTick>()
+            .set(NetworkTick::new(tick));
+        world.run_schedule(PreUpdate);
+    }
+
+    #[test]
+    fn rolls_back_mispredictions() {
+        let mut app = app();
+        let entity = app.world_mut().spawn((Predicted, Position(0))).id();
+        for input in 1..=5 {
+            step(&mut app, input);
+        }
+        assert_eq!(app.world().resource::<NetworkTick>().get(), 5);
+        assert_eq!(app.world().get::<Position>(entity), Some(&Position(15)));
+
+        // The server moved the entity somewhere else by tick 2.
+        receive(&mut app, entity, 2, 10);
+        assert_eq!(app.world().get::<Position>(entity), Some(&Position(22)));
+        assert_eq!(
+            app.world()
+                .get::<Confirmed<Position>>(entity)
+                .map(Confirmed::get),
+            Some(&Position(10))
+        );
+        assert_eq!(app.world().resource::<NetworkTick>().get(), 5);
+        assert_eq!(app.world().resource::<LocalInput<Move>>().0, Move(5));
+        assert!(!app.world().resource::<Rollback>().is_resimulating());
+    }
+
+    #[test]
+    fn keeps_correct_predictions() {
+        let mut app = app();
+        let entity = app.world_mut().spawn((Predicted, Position(0))).id();
+        for input in 1..=5 {
+            step(&mut app, input);
+        }
+        app.world_mut().resource_mut::<LocalInput<Move>>().0 = Move(100);
+
+        receive(&mut app, entity, 4, 10);
+        assert_eq!(app.world().get::<Position>(entity), Some(&Position(15)));
+        assert!(app.world().resource::<Rollback>().from.is_none());
+        assert_eq!(app.world().resource::<LocalInput<Move>>().0, Move(100));
+    }
+
+    #[test]
+    fn only_predicts_marked_entities() {
+        let mut app = app();
+        let entity = app.world_mut().spawn(Position(0)).id();
+        step(&mut app, 1);
+
+        receive(&mut app, entity, 1, 7);
+        assert_eq!(app.world().get::<Position>(entity), Some(&Position(7)));
+        assert!(app.world().get::<Confirmed<Position>>(entity).is_none());
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod quic_tests {
+    use super::tests::Move;
+    use super::*;
+    use crate::quic::test_utils::{connected_apps, update_until};
+    use crate::replication::ReplicationPlugin;
+    use crate::tick::NetworkTickPlugin;
+    use bevy_time::TimePlugin;
+
+    #[test]
+    fn sends_inputs_to_the_server() {
+        let (mut server, mut client, server_connection, _) = connected_apps(|app| {
+            app.add_plugins((
+                TimePlugin,
+                ReplicationPlugin,
+                NetworkTickPlugin,
+                PredictionPlugin,
+            ))
+            .add_input::<Move>();
+        });
+        client.world_mut().resource_mut::<LocalInput<Move>>().0 = Move(7);
+
+        update_until([&mut server, &mut client], |[server, _]| {
+            server
+                .world()
+                .get::<RemoteInput<Move>>(server_connection)
+                .is_some_and(|input| input.get() == &Move(7))
+        });
+    }
+}
@@ -0,0 +1,412 @@
+//! Server certificates loaded as assets, reloaded into the [`EndPoint`]s using them.
+//!
+//! Add the [`ServerCertificatePlugin`] and a [`TlsCertificate`] on an [`EndPoint`] entity to serve
+//! a certificate loaded by the [`AssetServer`](bevy_asset::AssetServer). Whenever the certificate
+//! asset changes, for example once renewed on disk while [file watching] is enabled, the
+//! endpoint's [`ServerConfig`] is rebuilt. Connections accepted afterwards use the new
+//! certificate, while established connections are unaffected.
+//!
+//! [file watching]: bevy_asset::AssetPlugin::watch_for_changes_override
+
+use std::sync::Arc;
+
+use bevy_app::{App, Plugin, PreUpdate};
+use bevy_asset::io::Reader;
+use bevy_asset::{
+    Asset, AssetApp, AssetEvent, AssetId, AssetLoader, Assets, Handle, LoadContext,
+    ReadAssetBytesError,
+};
+use bevy_ecs::prelude::*;
+use bevy_reflect::TypePath;
+use bevy_utils::tracing::warn;
+use bevy_utils::HashSet;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls_pemfile::Item;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{EndPoint, NetworkSystem, ServerConfig};
+
+/// Adds the [`ServerCertificate`] asset and its loader, and keeps the server configuration of
+/// [`EndPoint`]s with a [`TlsCertificate`] up to date.
+#[derive(Default)]
+pub struct ServerCertificatePlugin;
+
+impl Plugin for ServerCertificatePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<ServerCertificate>()
+            .init_asset_loader::<ServerCertificateLoader>()
+            .add_systems(
+                PreUpdate,
+                update_server_configs.before(NetworkSystem::Connections),
+            );
+    }
+}
+
+/// A certificate chain and its private key, served by a QUIC server.
+#[derive(Asset, TypePath, Debug)]
+pub struct ServerCertificate {
+    /// The certificate chain, starting with the server's certificate.
+    pub chain: Vec<CertificateDer<'static>>,
+    /// The private key of the server's certificate.
+    pub key: PrivateKeyDer<'static>,
+}
+
+impl ServerCertificate {
+    /// Builds a server configuration using this certificate, and the default settings otherwise.
+    pub fn server_config(&self) -> Result<ServerConfig, rustls::Error> {
+        ServerConfig::with_single_cert(self.chain.clone(), self.key.clone_key())
+    }
+}
+
+/// Serves a [`ServerCertificate`] from an [`EndPoint`], on its entity.
+///
+/// The endpoint's [`ServerConfig`] is replaced once the certificate is loaded, and every time it
+/// is modified afterwards.
+#[derive(Component, Clone)]
+pub struct TlsCertificate {
+    certificate: Handle<ServerCertificate>,
+    configure: Option<Arc<dyn Fn(&mut ServerConfig) + Send + Sync>>,
+}
+
+impl TlsCertificate {
+    /// Serves `certificate` with the default server settings.
+    pub fn new(certificate: Handle<ServerCertificate>) -> Self {
+        Self {
+            certificate,
+            configure: None,
+        }
+    }
+
+    /// Customizes the server configurations built for the certificate, such as their transport
+    /// settings.
+    pub fn with_configure(
+        mut self,
+        configure: impl Fn(&mut ServerConfig) + Send + Sync + 'static,
+    ) -> Self {
+        self.configure = Some(Arc::new(configure));
+        self
+    }
+
+    /// Gets the handle of the served certificate.
+    pub fn certificate(&self) -> &Handle<ServerCertificate> {
+        &self.certificate
+    }
+}
+
+/// Settings for the [`ServerCertificateLoader`].
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ServerCertificateSettings {
+    /// The path of the file holding the private key, relative to the certificate, when the
+    /// certificate file does not contain it.
+    ///
+    /// It is read as PEM if it starts with a PEM header, or as DER otherwise. Changes to it are
+    /// reloaded along with the certificate.
+    pub key_path: Option<String>,
+}
+
+/// Loads [`ServerCertificate`]s from PEM files, holding the certificate chain and possibly the
+/// private key, or from DER files, holding a single certificate.
+///
+/// The private key is read from [`ServerCertificateSettings::key_path`] when set.
+#[derive(Default)]
+pub struct ServerCertificateLoader;
+
+/// Possible errors that can be produced by [`ServerCertificateLoader`]
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum ServerCertificateLoaderError {
+    /// An [IO](std::io) Error
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The file holding the private key could not be read.
+    #[error(transparent)]
+    ReadKey(#[from] ReadAssetBytesError),
+    /// The key path is not a valid asset path.
+    #[error("invalid key path: {0}")]
+    InvalidKeyPath(String),
+    /// The certificate file does not contain any certificate.
+    #[error("no certificate found")]
+    MissingCertificate,
+    /// Neither the certificate file nor the key file contain a private key.
+    #[error("no private key found")]
+    MissingKey,
+    /// The key file contains more than a private key, or an unsupported kind of key.
+    #[error("invalid private key: {0}")]
+    InvalidKey(&'static str),
+}
+
+impl AssetLoader for ServerCertificateLoader {
+    type Asset = ServerCertificate;
+    type Settings = ServerCertificateSettings;
+    type Error = ServerCertificateLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut dyn Reader,
+        settings: &'a ServerCertificateSettings,
+        load_context: &'a mut LoadContext<'_>,
+    ) -> Result<ServerCertificate, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let (chain, mut key) = parse_certificates(&bytes)?;
+
+        if let Some(key_path) = &settings.key_path {
+            let path = load_context
+                .asset_path()
+                .resolve_embed(key_path)
+                .map_err(|_| ServerCertificateLoaderError::InvalidKeyPath(key_path.clone()))?;
+            key = Some(parse_key(&load_context.read_asset_bytes(path).await?)?);
+        }
+
+        if chain.is_empty() {
+            return Err(ServerCertificateLoaderError::MissingCertificate);
+        }
+        Ok(ServerCertificate {
+            chain,
+            key: key.ok_or(ServerCertificateLoaderError::MissingKey)?,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["pem", "crt", "der"]
+    }
+}
+
+fn is_pem(bytes: &[u8]) -> bool {
+    bytes.trim_ascii_start().starts_with(b"-----BEGIN")
+}
+
+/// Reads the certificates and the private key of a PEM file, or the certificate of a DER file.
+fn parse_certificates(
+    bytes: &[u8],
+) -> Result<
+    (Vec<CertificateDer<'static>>, Option<PrivateKeyDer<'static>>),
+    ServerCertificateLoaderError,
+> {
+    if !is_pem(bytes) {
+        return Ok((vec![CertificateDer::from(bytes.to_vec())], None));
+    }
+
+    let (mut chain, mut key) = (Vec::new(), None);
+    for item in rustls_pemfile::read_all(&mut &bytes[..]) {
+        match item? {
+            Item::X509Certificate(cert) => chain.push(cert),
+            Item::Pkcs1Key(pkcs1) => key = Some(pkcs1.into()),
+            Item::Pkcs8Key(pkcs8) => key = Some(pkcs8.into()),
+            Item::Sec1Key(sec1) => key = Some(sec1.into()),
+            _ => {}
+        }
+    }
+    Ok((chain, key))
+}
+
+/// Reads the private key of a PEM or DER file.
+fn parse_key(bytes: &[u8]) -> Result<PrivateKeyDer<'static>, ServerCertificateLoaderError> {
+    if !is_pem(bytes) {
+        return PrivateKeyDer::try_from(bytes.to_vec())
+            .map_err(ServerCertificateLoaderError::InvalidKey);
+    }
+    rustls_pemfile::private_key(&mut &bytes[..])?.ok_or(ServerCertificateLoaderError::MissingKey)
+}
+
+fn update_server_configs(
+    mut events: EventReader<AssetEvent<ServerCertificate>>,
+    certificates: Res<Assets<ServerCertificate>>,
+    endpoints: Query<(&EndPoint, Ref<TlsCertificate>)>,
+) {
+    let updated = events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect::<HashSet<AssetId<ServerCertificate>>>();
+
+    for (endpoint, tls) in &endpoints {
+        if !tls.is_changed() && !updated.contains(&tls.certificate.id()) {
+            continue;
+        }
+        // Certificates that are still loading are applied once added.
+        let Some(certificate) = certificates.get(&tls.certificate) else {
+            continue;
+        };
+
+        match certificate.server_config() {
+            Ok(mut config) => {
+                if let Some(configure) = &tls.configure {
+                    configure(&mut config);
+                }
+                endpoint.set_server_config(Some(config));
+            }
+            Err(error) => warn!(
+                "failed to use certificate {:?}, keeping the previous one: {error}",
+                tls.certificate.path()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto_utils::SelfSignedCert;
+    use crate::quic::test_utils::init_task_pool;
+    use bevy_asset::io::memory::{Dir, MemoryAssetReader};
+    use bevy_asset::io::{AssetSource, AssetSourceId};
+    use bevy_asset::{AssetPlugin, AssetServer, LoadState};
+    use std::path::Path;
+
+    #[test]
+    fn loads_certificates_with_separate_keys() {
+        init_task_pool();
+        let cert = SelfSignedCert::generate(["localhost".to_string()]).unwrap();
+        let pem = rcgen::generate_simple_self_signed(["localhost".to_string()]).unwrap();
+
+        let dir = Dir::default();
+        dir.insert_asset(Path::new("server.der"), cert.cert.to_vec());
+        dir.insert_asset(Path::new("keyless.der"), cert.cert.to_vec());
+        dir.insert_asset(
+            Path::new("keys/server.key"),
+            cert.key.secret_pkcs8_der().to_vec(),
+        );
+        dir.insert_asset_text(
+            Path::new("bundle.pem"),
+            &format!("{}{}", pem.cert.pem(), pem.key_pair.serialize_pem()),
+        );
+
+        let mut app = App::new();
+        app.register_asset_source(
+            AssetSourceId::Default,
+            AssetSource::build()
+                .with_reader(move || Box::new(MemoryAssetReader { root: dir.clone() })),
+        )
+        .add_plugins((AssetPlugin::default(), ServerCertificatePlugin));
+
+        let server = app.world().resource::<AssetServer>();
+        let separate =
+            server.load_with_settings("server.der", |settings: &mut ServerCertificateSettings| {
+                settings.key_path = Some("keys/server.key".into());
+            });
+        let bundle = server.load::<ServerCertificate>("bundle.pem");
+        let missing_key = server.load::<ServerCertificate>("keyless.der");
+
+        for _ in 0..1000 {
+            let server = app.world().resource::<AssetServer>();
+            let states = [&separate, &bundle, &missing_key].map(|handle| server.load_state(handle));
+            if !states
+                .iter()
+                .any(|state| matches!(state, LoadState::Loading | LoadState::NotLoaded))
+            {
+                break;
+            }
+            app.update();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        let server = app.world().resource::<AssetServer>();
+        assert!(matches!(
+            server.load_state(&missing_key),
+            LoadState::Failed(_)
+        ));
+        let certificates = app.world().resource::<Assets<ServerCertificate>>();
+        let separate = certificates.get(&separate).unwrap();
+        assert_eq!(separate.chain, vec![cert.cert]);
+        assert!(separate.server_config().is_ok());
+        let bundle = certificates.get(&bundle).unwrap();
+        assert_eq!(bundle.chain, vec![pem.cert.der().clone()]);
+        assert!(bundle.server_config().is_ok());
+    }
+}
+
+#[cfg(test)]
+mod quic_tests {
+    use super::*;
+    use crate::crypto_utils::{PinnedCertVerifier, SelfSignedCert};
+    use crate::quic::crypto::rustls::QuicClientConfig;
+    use crate::quic::test_utils::{connected_apps, update_until, ConnectedEntities};
+    use crate::quic::{ClientConfig, QuicConnection, QuicEntityCommands};
+    use bevy_asset::AssetPlugin;
+
+    fn pinned_client_config(cert: &SelfSignedCert) -> ClientConfig {
+        ClientConfig::new(Arc::new(
+            QuicClientConfig::try_from(
+                rustls::ClientConfig::builder()
+                    .dangerous()
+                    .with_custom_certificate_verifier(PinnedCertVerifier::new([cert.fingerprint()]))
+                    .with_no_client_auth(),
+            )
+            .unwrap(),
+        ))
+    }
+
+    /// Connects the client to the server, only accepting `cert`, and waits for the connection.
+    fn connect_pinned(server: &mut App, client: &mut App, cert: &SelfSignedCert) {
+        let server_addr = server
+            .world_mut()
+            .query::<&EndPoint>()
+            .single(server.world())
+            .local_addr()
+            .unwrap();
+        let client_endpoint = client
+            .world_mut()
+            .query_filtered::<Entity, With<EndPoint>>()
+            .single(client.world());
+        client
+            .world_mut()
+            .commands()
+            .entity(client_endpoint)
+            .connect_with(pinned_client_config(cert), server_addr, "localhost");
+
+        let connections = client.world().resource::<ConnectedEntities>().0.len();
+        update_until([server, client], |[_, client]| {
+            client.world().resource::<ConnectedEntities>().0.len() > connections
+        });
+    }
+
+    #[test]
+    fn swaps_certificates_of_running_servers() {
+        let (mut server, mut client, _, client_connection) = connected_apps(|app| {
+            app.add_plugins((AssetPlugin::default(), ServerCertificatePlugin));
+        });
+
+        let cert = SelfSignedCert::generate(["localhost".to_string()]).unwrap();
+        let handle = server
+            .world_mut()
+            .resource_mut::<Assets<ServerCertificate>>()
+            .add(ServerCertificate {
+                chain: vec![cert.cert.clone()],
+                key: cert.key_der(),
+            });
+        let server_endpoint = server
+            .world_mut()
+            .query_filtered::<Entity, With<EndPoint>>()
+            .single(server.world());
+        server
+            .world_mut()
+            .entity_mut(server_endpoint)
+            .insert(TlsCertificate::new(handle.clone()));
+        server.update();
+        connect_pinned(&mut server, &mut client, &cert);
+
+        // Renew the certificate in place, like a reload from disk.
+        let renewed = SelfSignedCert::generate(["localhost".to_string()]).unwrap();
+        server
+            .world_mut()
+            .resource_mut::<Assets<ServerCertificate>>()
+            .insert(
+                &handle,
+                ServerCertificate {
+                    chain: vec![renewed.cert.clone()],
+                    key: renewed.key_der(),
+                },
+            );
+        server.update();
+        connect_pinned(&mut server, &mut client, &renewed);
+
+        assert!(client
+            .world()
+            .get::<QuicConnection>(client_connection)
+            .is_some_and(|connection| connection.get().close_reason().is_none()));
+    }
+}
@@ -0,0 +1,643 @@
+use std::collections::BinaryHeap;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::io::{self, IoSliceMut};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use async_io::Timer;
+use bevy_ecs::system::Resource;
+use bevy_tasks::IoTaskPool;
+use futures_lite::future;
+use quinn::udp::{EcnCodepoint, RecvMeta};
+
+use super::{AsyncUdpSocket, BevyQuinnRuntime, Runtime, Transmit, UdpPoller};
+
+/// How a [`LinkConditioner`] impairs the datagrams going through a socket, in each direction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkProfile {
+    /// The delay added to every datagram.
+    pub latency: Duration,
+    /// The maximum random delay added on top of the latency, picked uniformly for every datagram.
+    pub jitter: Duration,
+    /// The probability for a datagram to be dropped, between `0.0` and `1.0`.
+    pub loss: f32,
+    /// The probability for a datagram to be delivered twice.
+    pub duplication: f32,
+    /// The probability for a datagram to be held back for an extra
+    /// [`REORDER_DELAY`](Self::REORDER_DELAY), so the datagrams after it overtake it.
+    pub reordering: f32,
+}
+
+impl LinkProfile {
+    /// A link that delivers every datagram immediately.
+    pub const PERFECT: Self = Self {
+        latency: Duration::ZERO,
+        jitter: Duration::ZERO,
+        loss: 0.0,
+        duplication: 0.0,
+        reordering: 0.0,
+    };
+
+    /// A home wifi network with a little jitter and rare losses.
+    pub const GOOD_WIFI: Self = Self {
+        latency: Duration::from_millis(10),
+        jitter: Duration::from_millis(5),
+        loss: 0.005,
+        duplication: 0.0,
+        reordering: 0.001,
+    };
+
+    /// A congested mobile network.
+    pub const MOBILE_3G: Self = Self {
+        latency: Duration::from_millis(100),
+        jitter: Duration::from_millis(50),
+        loss: 0.03,
+        duplication: 0.005,
+        reordering: 0.01,
+    };
+
+    /// How long reordered datagrams are held back on top of their delay.
+    pub const REORDER_DELAY: Duration = Duration::from_millis(20);
+}
+
+impl Default for LinkProfile {
+    fn default() -> Self {
+        Self::PERFECT
+    }
+}
+
+/// Simulates a bad network on the sockets it wraps, by delaying, dropping, duplicating and
+/// reordering the datagrams they send and receive according to a [`LinkProfile`].
+///
+/// Every socket wrapped by a conditioner, and every clone of it, shares its profile and random
+/// number generator, so the profile can be switched at runtime for all of them, from the resource
+/// holding the conditioner:
+///
+/// ```no_run
+/// # use bevy_ecs::prelude::*;
+/// # use bevy_net::quic::{EndPoint, EndpointConfig, LinkConditioner, LinkProfile};
+/// # use std::net::UdpSocket;
+/// # fn setup(world: &mut World) -> std::io::Result<()> {
+/// let conditioner = LinkConditioner::new(LinkProfile::GOOD_WIFI);
+/// let socket = conditioner.wrap_udp_socket(UdpSocket::bind("0.0.0.0:0")?)?;
+/// let endpoint = EndPoint::new_with_abstract_socket(EndpointConfig::default(), None, socket)?;
+/// world.spawn(endpoint);
+/// world.insert_resource(conditioner);
+/// # Ok(())
+/// # }
+///
+/// fn degrade_network(conditioner: Res<LinkConditioner>) {
+///     conditioner.set_profile(LinkProfile::MOBILE_3G);
+/// }
+/// ```
+///
+/// Datagrams held back by the conditioner are sent once they are due, and dropped if the socket
+/// is not writable at that point.
+#[derive(Resource, Debug, Clone)]
+pub struct LinkConditioner {
+    state: Arc<Mutex<ConditionerState>>,
+}
+
+#[derive(Debug)]
+struct ConditionerState {
+    profile: LinkProfile,
+    rng: fastrand::Rng,
+    /// Orders the datagrams due at the same instant by arrival.
+    sequence: u64,
+}
+
+impl ConditionerState {
+    /// Picks the delays of the copies of a datagram to deliver, if any.
+    fn delays(&mut self) -> [Option<Duration>; 2] {
+        if self.rng.f32() < self.profile.loss {
+            return [None, None];
+        }
+        let duplicated = self.rng.f32() < self.profile.duplication;
+        [Some(self.delay()), duplicated.then(|| self.delay())]
+    }
+
+    fn delay(&mut self) -> Duration {
+        let mut delay = self.profile.latency + self.profile.jitter.mul_f64(self.rng.f64());
+        if self.rng.f32() < self.profile.reordering {
+            delay += LinkProfile::REORDER_DELAY;
+        }
+        delay
+    }
+
+    fn delayed<T>(&mut self, at: Instant, value: T) -> Delayed<T> {
+        self.sequence += 1;
+        Delayed {
+            at,
+            sequence: self.sequence,
+            value,
+        }
+    }
+}
+
+impl LinkConditioner {
+    /// Creates a conditioner with a random seed.
+    pub fn new(profile: LinkProfile) -> Self {
+        Self::with_seed(profile, fastrand::u64(..))
+    }
+
+    /// Creates a conditioner whose random decisions are derived from `seed`.
+    ///
+    /// The impairments are only reproducible as long as the datagrams are sent and received in
+    /// the same order.
+    pub fn with_seed(profile: LinkProfile, seed: u64) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ConditionerState {
+                profile,
+                rng: fastrand::Rng::with_seed(seed),
+                sequence: 0,
+            })),
+        }
+    }
+
+    /// Gets the current profile.
+    pub fn profile(&self) -> LinkProfile {
+        self.state.lock().unwrap().profile
+    }
+
+    /// Switches every socket of this conditioner to another profile.
+    ///
+    /// Datagrams that are already held back keep their delay.
+    pub fn set_profile(&self, profile: LinkProfile) {
+        self.state.lock().unwrap().profile = profile;
+    }
+
+    /// Wraps a UDP socket, driven by the same runtime as every [`EndPoint`](super::EndPoint), to
+    /// be used with [`EndPoint::new_with_abstract_socket`](super::EndPoint::new_with_abstract_socket).
+    pub fn wrap_udp_socket(&self, socket: UdpSocket) -> io::Result<Arc<dyn AsyncUdpSocket>> {
+        Ok(self.wrap(BevyQuinnRuntime.wrap_udp_socket(socket)?))
+    }
+
+    /// Wraps any socket.
+    ///
+    /// Delayed datagrams are sent from a task spawned on the [`IoTaskPool`].
+    pub fn wrap(&self, socket: Arc<dyn AsyncUdpSocket>) -> Arc<dyn AsyncUdpSocket> {
+        let (sender, receiver) = async_channel::unbounded();
+        IoTaskPool::get()
+            .spawn(send_delayed(socket.clone(), receiver))
+            .detach();
+
+        let buf = vec![0; u16::MAX as usize * socket.max_receive_segments()];
+        Arc::new(ConditionedSocket {
+            inner: socket,
+            conditioner: self.clone(),
+            outgoing: sender,
+            incoming: Mutex::new(Incoming {
+                queue: BinaryHeap::new(),
+                timer: None,
+                buf,
+            }),
+        })
+    }
+}
+
+/// A value due at some instant, ordered so the earliest one is at the top of a [`BinaryHeap`].
+struct Delayed<T> {
+    at: Instant,
+    sequence: u64,
+    value: T,
+}
+
+impl<T> PartialEq for Delayed<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.sequence) == (other.at, other.sequence)
+    }
+}
+
+impl<T> Eq for Delayed<T> {}
+
+impl<T> PartialOrd for Delayed<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Delayed<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (other.at, other.sequence).cmp(&(self.at, self.sequence))
+    }
+}
+
+struct OutgoingDatagram {
+    destination: SocketAddr,
+    ecn: Option<EcnCodepoint>,
+    contents: Vec<u8>,
+    src_ip: Option<IpAddr>,
+}
+
+struct IncomingDatagram {
+    meta: RecvMeta,
+    contents: Vec<u8>,
+}
+
+struct Incoming {
+    queue: BinaryHeap<Delayed<IncomingDatagram>>,
+    timer: Option<Timer>,
+    /// Receives datagrams from the wrapped socket.
+    buf: Vec<u8>,
+}
+
+struct ConditionedSocket {
+    inner: Arc<dyn AsyncUdpSocket>,
+    conditioner: LinkConditioner,
+    outgoing: async_channel::Sender<Delayed<OutgoingDatagram>>,
+    incoming: Mutex<Incoming>,
+}
+
+impl Debug for ConditionedSocket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConditionedSocket")
+            .field("inner", &self.inner)
+            .field("conditioner", &self.conditioner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncUdpSocket for ConditionedSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        self.inner.clone().create_io_poller()
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let segment_size = transmit
+            .segment_size
+            .unwrap_or(transmit.contents.len())
+            .max(1);
+        let now = Instant::now();
+        let mut state = self.conditioner.state.lock().unwrap();
+        // Every datagram's fate is decided up front, so a failed send is not impaired twice.
+        let datagrams: Vec<_> = transmit
+            .contents
+            .chunks(segment_size)
+            .flat_map(|contents| {
+                let delays = state.delays();
+                delays
+                    .into_iter()
+                    .flatten()
+                    .map(move |delay| (contents, delay))
+            })
+            .collect();
+
+        // Once part of the transmit went out, quinn must not retry it, so the datagrams the
+        // socket cannot take right away are held back instead.
+        let mut sent = false;
+        let mut blocked = false;
+        for (contents, delay) in datagrams {
+            if delay.is_zero() && !blocked {
+                match self.inner.try_send(&Transmit {
+                    contents,
+                    segment_size: None,
+                    ..*transmit
+                }) {
+                    Ok(()) => {
+                        sent = true;
+                        continue;
+                    }
+                    Err(error) if !sent => return Err(error),
+                    Err(_) => blocked = true,
+                }
+            }
+            let datagram = OutgoingDatagram {
+                destination: transmit.destination,
+                ecn: transmit.ecn,
+                contents: contents.to_vec(),
+                src_ip: transmit.src_ip,
+            };
+            // The task only stops once this socket is dropped.
+            let _ = self.outgoing.try_send(state.delayed(now + delay, datagram));
+            sent = true;
+        }
+        Ok(())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let mut incoming = self.incoming.lock().unwrap();
+        let incoming = &mut *incoming;
+
+        // Queue everything the wrapped socket received, so it is woken again once more arrives.
+        loop {
+            let mut received = [RecvMeta::default()];
+            let mut buf = [IoSliceMut::new(&mut incoming.buf)];
+            match self.inner.poll_recv(cx, &mut buf, &mut received) {
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => break,
+            }
+
+            let [received] = received;
+            let now = Instant::now();
+            let mut state = self.conditioner.state.lock().unwrap();
+            for contents in incoming.buf[..received.len].chunks(received.stride.max(1)) {
+                for delay in state.delays().into_iter().flatten() {
+                    let datagram = IncomingDatagram {
+                        meta: received,
+                        contents: contents.to_vec(),
+                    };
+                    incoming.queue.push(state.delayed(now + delay, datagram));
+                }
+            }
+        }
+
+        loop {
+            let now = Instant::now();
+            let mut count = 0;
+            while count < bufs.len().min(meta.len())
+                && incoming.queue.peek().is_some_and(|next| next.at <= now)
+            {
+                let datagram = incoming.queue.pop().unwrap().value;
+                let len = datagram.contents.len().min(bufs[count].len());
+                bufs[count][..len].copy_from_slice(&datagram.contents[..len]);
+                meta[count] = RecvMeta {
+                    len,
+                    stride: len,
+                    ..datagram.meta
+                };
+                count += 1;
+            }
+            if count > 0 {
+                return Poll::Ready(Ok(count));
+            }
+
+            let Some(next) = incoming.queue.peek().map(|next| next.at) else {
+                return Poll::Pending;
+            };
+            let timer = incoming.timer.get_or_insert_with(|| Timer::at(next));
+            timer.set_at(next);
+            if Pin::new(timer).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    // Batches are split into single datagrams, so each one is impaired on its own.
+    fn max_transmit_segments(&self) -> usize {
+        1
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        1
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.inner.may_fragment()
+    }
+}
+
+enum Wake<T> {
+    Received(T),
+    Due,
+    Closed,
+}
+
+/// Sends the datagrams held back by a [`ConditionedSocket`] once they are due.
+async fn send_delayed(
+    socket: Arc<dyn AsyncUdpSocket>,
+    receiver: async_channel::Receiver<Delayed<OutgoingDatagram>>,
+) {
+    let mut queue = BinaryHeap::new();
+    loop {
+        let receive = async { receiver.recv().await.map_or(Wake::Closed, Wake::Received) };
+        let wake = match queue.peek().map(|next: &Delayed<_>| next.at) {
+            Some(at) => {
+                let due = async {
+                    Timer::at(at).await;
+                    Wake::Due
+                };
+                future::or(receive, due).await
+            }
+            None => receive.await,
+        };
+        match wake {
+            Wake::Received(datagram) => queue.push(datagram),
+            Wake::Due => {}
+            Wake::Closed => return,
+        }
+
+        let now = Instant::now();
+        while queue.peek().is_some_and(|next| next.at <= now) {
+            let datagram = queue.pop().unwrap().value;
+            // A socket that is not writable drops the datagram, like a congested link would.
+            let _ = socket.try_send(&Transmit {
+                destination: datagram.destination,
+                ecn: datagram.ecn,
+                contents: &datagram.contents,
+                segment_size: None,
+                src_ip: datagram.src_ip,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_tasks::{block_on, TaskPool};
+    use std::net::Ipv4Addr;
+
+    fn state(profile: LinkProfile, seed: u64) -> ConditionerState {
+        ConditionerState {
+            profile,
+            rng: fastrand::Rng::with_seed(seed),
+            sequence: 0,
+        }
+    }
+
+    #[test]
+    fn picks_delays_from_the_profile() {
+        let lossy = LinkProfile {
+            loss: 1.0,
+            ..LinkProfile::PERFECT
+        };
+        assert_eq!(state(lossy, 0).delays(), [None, None]);
+
+        let duplicating = LinkProfile {
+            latency: Duration::from_millis(30),
+            duplication: 1.0,
+            ..LinkProfile::PERFECT
+        };
+        let delay = Some(Duration::from_millis(30));
+        assert_eq!(state(duplicating, 0).delays(), [delay, delay]);
+
+        let jittery = LinkProfile {
+            latency: Duration::from_millis(30),
+            jitter: Duration::from_millis(10),
+            reordering: 0.5,
+            ..LinkProfile::PERFECT
+        };
+        let mut first = state(jittery, 42);
+        let mut second = state(jittery, 42);
+        for _ in 0..100 {
+            let delays = first.delays();
+            assert_eq!(delays, second.delays());
+            let delay = delays[0].unwrap();
+            assert!(delay >= Duration::from_millis(30));
+            assert!(delay <= Duration::from_millis(40) + LinkProfile::REORDER_DELAY);
+        }
+    }
+
+    fn socket(conditioner: Option<&LinkConditioner>) -> Arc<dyn AsyncUdpSocket> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        match conditioner {
+            Some(conditioner) => conditioner.wrap_udp_socket(socket).unwrap(),
+            None => BevyQuinnRuntime.wrap_udp_socket(socket).unwrap(),
+        }
+    }
+
+    fn send(from: &Arc<dyn AsyncUdpSocket>, to: &Arc<dyn AsyncUdpSocket>, contents: &[u8]) {
+        from.try_send(&Transmit {
+            destination: to.local_addr().unwrap(),
+            ecn: None,
+            contents,
+            segment_size: None,
+            src_ip: None,
+        })
+        .unwrap();
+    }
+
+    /// Waits up to `timeout` for a datagram, returning it and how long it took to arrive.
+    fn receive(socket: &Arc<dyn AsyncUdpSocket>, timeout: Duration) -> Option<(Vec<u8>, Duration)> {
+        let start = Instant::now();
+        let mut buf = [0; 1500];
+        let received = future::poll_fn(|cx| {
+            let mut meta = [RecvMeta::default()];
+            socket
+                .poll_recv(cx, &mut [IoSliceMut::new(&mut buf)], &mut meta)
+                .map(|result| result.map(|_| meta[0].len))
+        });
+        let timeout = async {
+            Timer::after(timeout).await;
+            Ok(0)
+        };
+        let len = block_on(future::or(received, timeout)).unwrap();
+        (len > 0).then(|| (buf[..len].to_vec(), start.elapsed()))
+    }
+
+    /// A socket that accepts `capacity` datagrams, and then would block.
+    #[derive(Debug)]
+    struct FullSocket {
+        capacity: usize,
+        sent: Mutex<Vec<Vec<u8>>>,
+    }
+
+    /// Always reports the socket as writable, leaving it to `try_send` to refuse datagrams.
+    #[derive(Debug)]
+    struct ReadyPoller;
+
+    impl UdpPoller for ReadyPoller {
+        fn poll_writable(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncUdpSocket for FullSocket {
+        fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+            Box::pin(ReadyPoller)
+        }
+
+        fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+            let mut sent = self.sent.lock().unwrap();
+            if sent.len() == self.capacity {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            sent.push(transmit.contents.to_vec());
+            Ok(())
+        }
+
+        fn poll_recv(
+            &self,
+            _cx: &mut Context,
+            _bufs: &mut [IoSliceMut<'_>],
+            _meta: &mut [RecvMeta],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Ok((Ipv4Addr::LOCALHOST, 0).into())
+        }
+    }
+
+    #[test]
+    fn holds_back_the_rest_of_partially_sent_transmits() {
+        IoTaskPool::get_or_init(TaskPool::new);
+        let conditioner = LinkConditioner::with_seed(LinkProfile::PERFECT, 0);
+        let transmit = Transmit {
+            destination: (Ipv4Addr::LOCALHOST, 1).into(),
+            ecn: None,
+            contents: b"aabbcc",
+            segment_size: Some(2),
+            src_ip: None,
+        };
+
+        // Nothing went out, so quinn may retry the whole transmit.
+        let full = Arc::new(FullSocket {
+            capacity: 0,
+            sent: Mutex::new(Vec::new()),
+        });
+        let socket = conditioner.wrap(full.clone());
+        assert!(socket.try_send(&transmit).is_err());
+        assert!(full.sent.lock().unwrap().is_empty());
+
+        // The first datagram went out, so the transmit must not be sent again.
+        let full = Arc::new(FullSocket {
+            capacity: 1,
+            sent: Mutex::new(Vec::new()),
+        });
+        let socket = conditioner.wrap(full.clone());
+        assert!(socket.try_send(&transmit).is_ok());
+        assert_eq!(*full.sent.lock().unwrap(), [b"aa".to_vec()]);
+    }
+
+    #[test]
+    fn impairs_both_directions() {
+        IoTaskPool::get_or_init(TaskPool::new);
+        let conditioner = LinkConditioner::with_seed(
+            LinkProfile {
+                latency: Duration::from_millis(50),
+                ..LinkProfile::PERFECT
+            },
+            0,
+        );
+        let conditioned = socket(Some(&conditioner));
+        let plain = socket(None);
+
+        send(&conditioned, &plain, b"sent");
+        let (contents, elapsed) = receive(&plain, Duration::from_secs(5)).unwrap();
+        assert_eq!(contents, b"sent");
+        assert!(elapsed >= Duration::from_millis(45), "sent in {elapsed:?}");
+
+        send(&plain, &conditioned, b"received");
+        let (contents, elapsed) = receive(&conditioned, Duration::from_secs(5)).unwrap();
+        assert_eq!(contents, b"received");
+        assert!(
+            elapsed >= Duration::from_millis(45),
+            "received in {elapsed:?}"
+        );
+
+        conditioner.set_profile(LinkProfile {
+            loss: 1.0,
+            ..LinkProfile::PERFECT
+        });
+        send(&conditioned, &plain, b"lost");
+        send(&plain, &conditioned, b"lost");
+        assert_eq!(receive(&plain, Duration::from_millis(100)), None);
+        assert_eq!(receive(&conditioned, Duration::from_millis(100)), None);
+    }
+}
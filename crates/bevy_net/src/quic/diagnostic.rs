@@ -0,0 +1,329 @@
+use bevy_app::{App, Plugin, PostUpdate};
+use bevy_diagnostic::{
+    Diagnostic, DiagnosticPath, Diagnostics, DiagnosticsStore, RegisterDiagnostic,
+};
+use bevy_ecs::prelude::*;
+use bevy_utils::HashMap;
+use quinn::ConnectionStats;
+
+use super::{ConnectionEndPoint, EndPoint, NetworkSystem, QuicConnection};
+
+/// How many diagnostics every connection has.
+const PER_CONNECTION: usize = 7;
+
+/// How many aggregate diagnostics every endpoint has, the number of connections followed by the
+/// diagnostics of every connection.
+const PER_ENDPOINT: usize = PER_CONNECTION + 1;
+
+/// Adds the diagnostics of the QUIC connections to an [`App`].
+///
+/// Every established connection has its own diagnostics under `net/<connection>/`, where
+/// `<connection>` is its entity, such as `net/12v1/rtt`. They are removed once it closes. Every
+/// [`EndPoint`] entity also has diagnostics aggregating those of its connections under
+/// `net/<endpoint>/`, such as `net/3v1/connections`, which are removed along with the endpoint. The
+/// global diagnostics directly under `net/` aggregate the connections of every endpoint.
+///
+/// Requires the [`QuicNetworkPlugin`](super::QuicNetworkPlugin) and the
+/// [`DiagnosticsPlugin`](bevy_diagnostic::DiagnosticsPlugin).
+#[derive(Default)]
+pub struct NetworkDiagnosticsPlugin;
+
+impl Plugin for NetworkDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        for (path, suffix) in Self::per_endpoint() {
+            app.register_diagnostic(Diagnostic::new(path).with_suffix(suffix));
+        }
+        app.observe(register_connection)
+            .observe(unregister_connection)
+            .observe(register_endpoint)
+            .observe(unregister_endpoint)
+            .add_systems(
+                PostUpdate,
+                Self::diagnostic_system.after(NetworkSystem::Send),
+            );
+    }
+}
+
+impl NetworkDiagnosticsPlugin {
+    /// The number of established connections, over every endpoint.
+    pub const CONNECTIONS: DiagnosticPath = DiagnosticPath::const_new("net/connections");
+    /// The round trip time in milliseconds, averaged over the connections.
+    pub const RTT: DiagnosticPath = DiagnosticPath::const_new("net/rtt");
+    /// The congestion window in bytes, summed over the connections.
+    pub const CONGESTION_WINDOW: DiagnosticPath = DiagnosticPath::const_new("net/cwnd");
+    /// The packets detected as lost per frame.
+    pub const LOST_PACKETS: DiagnosticPath = DiagnosticPath::const_new("net/lost_packets");
+    /// The bytes of UDP datagrams sent per frame.
+    pub const BYTES_SENT: DiagnosticPath = DiagnosticPath::const_new("net/bytes_sent");
+    /// The bytes of UDP datagrams received per frame.
+    pub const BYTES_RECEIVED: DiagnosticPath = DiagnosticPath::const_new("net/bytes_received");
+    /// The UDP datagrams sent per frame.
+    pub const DATAGRAMS_SENT: DiagnosticPath = DiagnosticPath::const_new("net/datagrams_sent");
+    /// The UDP datagrams received per frame.
+    pub const DATAGRAMS_RECEIVED: DiagnosticPath =
+        DiagnosticPath::const_new("net/datagrams_received");
+
+    /// The aggregate diagnostics that every connection also has on its own, with their suffix.
+    const PER_CONNECTION: [(DiagnosticPath, &'static str); PER_CONNECTION] = [
+        (Self::RTT, " ms"),
+        (Self::CONGESTION_WINDOW, " B"),
+        (Self::LOST_PACKETS, ""),
+        (Self::BYTES_SENT, " B"),
+        (Self::BYTES_RECEIVED, " B"),
+        (Self::DATAGRAMS_SENT, ""),
+        (Self::DATAGRAMS_RECEIVED, ""),
+    ];
+
+    /// The global diagnostics that every endpoint also has for its own connections, with their
+    /// suffix, in the order of [`Totals::values`].
+    fn per_endpoint() -> impl Iterator<Item = (DiagnosticPath, &'static str)> {
+        std::iter::once((Self::CONNECTIONS, "")).chain(Self::PER_CONNECTION)
+    }
+
+    /// Gets the path of the diagnostic of a single connection matching the global diagnostic
+    /// `diagnostic`, such as `net/12v1/rtt` for [`RTT`](Self::RTT).
+    pub fn connection_path(connection: Entity, diagnostic: &DiagnosticPath) -> DiagnosticPath {
+        let name = diagnostic.components().last().unwrap();
+        DiagnosticPath::from_components(["net", &connection.to_string(), name])
+    }
+
+    /// Gets the path of the diagnostic aggregating the connections of a single endpoint matching
+    /// the global diagnostic `diagnostic`, such as `net/3v1/connections` for
+    /// [`CONNECTIONS`](Self::CONNECTIONS).
+    pub fn endpoint_path(endpoint: Entity, diagnostic: &DiagnosticPath) -> DiagnosticPath {
+        Self::connection_path(endpoint, diagnostic)
+    }
+
+    fn diagnostic_system(
+        mut diagnostics: Diagnostics,
+        mut connections: Query<(
+            &QuicConnection,
+            &ConnectionEndPoint,
+            &mut ConnectionDiagnostics,
+        )>,
+        endpoints: Query<(Entity, &EndPointDiagnostics)>,
+    ) {
+        let mut totals = Totals::default();
+        let mut endpoint_totals = HashMap::<Entity, Totals>::new();
+        for (connection, endpoint, mut connection_diagnostics) in &mut connections {
+            let stats = connection.stats();
+            let values = measure(&connection_diagnostics.last, &stats);
+            connection_diagnostics.last = stats;
+
+            for (path, value) in connection_diagnostics.paths.iter().zip(values) {
+                diagnostics.add_measurement(path, || value);
+            }
+            totals.add(values);
+            endpoint_totals
+                .entry(endpoint.get())
+                .or_default()
+                .add(values);
+        }
+
+        // Measured even without connections, so that the per frame values drop back to zero.
+        for ((path, _), value) in Self::per_endpoint().zip(totals.values()) {
+            diagnostics.add_measurement(&path, || value);
+        }
+        for (endpoint, endpoint_diagnostics) in &endpoints {
+            let values = endpoint_totals
+                .get(&endpoint)
+                .copied()
+                .unwrap_or_default()
+                .values();
+            for (path, value) in endpoint_diagnostics.paths.iter().zip(values) {
+                diagnostics.add_measurement(path, || value);
+            }
+        }
+    }
+}
+
+/// The diagnostics of a set of connections, summed.
+#[derive(Clone, Copy, Default)]
+struct Totals {
+    count: usize,
+    sums: [f64; PER_CONNECTION],
+}
+
+impl Totals {
+    fn add(&mut self, values: [f64; PER_CONNECTION]) {
+        self.count += 1;
+        for (sum, value) in self.sums.iter_mut().zip(values) {
+            *sum += value;
+        }
+    }
+
+    /// Gets the aggregate diagnostics, in the order of [`NetworkDiagnosticsPlugin::per_endpoint`].
+    fn values(self) -> [f64; PER_ENDPOINT] {
+        let mut values = [0.0; PER_ENDPOINT];
+        values[0] = self.count as f64;
+        values[1..].copy_from_slice(&self.sums);
+        // The round trip time is averaged rather than summed.
+        if self.count > 0 {
+            values[1] /= self.count as f64;
+        }
+        values
+    }
+}
+
+/// The diagnostic paths of a connection entity, and its statistics as of the last measurement.
+#[derive(Component)]
+struct ConnectionDiagnostics {
+    paths: [DiagnosticPath; PER_CONNECTION],
+    last: ConnectionStats,
+}
+
+/// The aggregate diagnostic paths of an endpoint entity.
+#[derive(Component)]
+struct EndPointDiagnostics {
+    paths: Vec<DiagnosticPath>,
+}
+
+/// Measures the diagnostics of a connection, in the order of
+/// [`NetworkDiagnosticsPlugin::PER_CONNECTION`].
+fn measure(last: &ConnectionStats, stats: &ConnectionStats) -> [f64; PER_CONNECTION] {
+    let delta = |last: u64, current: u64| current.saturating_sub(last) as f64;
+    [
+        stats.path.rtt.as_secs_f64() * 1000.0,
+        stats.path.cwnd as f64,
+        delta(last.path.lost_packets, stats.path.lost_packets),
+        delta(last.udp_tx.bytes, stats.udp_tx.bytes),
+        delta(last.udp_rx.bytes, stats.udp_rx.bytes),
+        delta(last.udp_tx.datagrams, stats.udp_tx.datagrams),
+        delta(last.udp_rx.datagrams, stats.udp_rx.datagrams),
+    ]
+}
+
+fn register_connection(
+    trigger: Trigger<OnAdd, QuicConnection>,
+    connections: Query<&QuicConnection>,
+    mut store: ResMut<DiagnosticsStore>,
+    mut commands: Commands,
+) {
+    let entity = trigger.entity();
+    let Ok(connection) = connections.get(entity) else {
+        return;
+    };
+    let paths = NetworkDiagnosticsPlugin::PER_CONNECTION.map(|(path, suffix)| {
+        let path = NetworkDiagnosticsPlugin::connection_path(entity, &path);
+        store.add(Diagnostic::new(path.clone()).with_suffix(suffix));
+        path
+    });
+    commands.entity(entity).insert(ConnectionDiagnostics {
+        paths,
+        last: connection.stats(),
+    });
+}
+
+fn unregister_connection(
+    trigger: Trigger<OnRemove, QuicConnection>,
+    connections: Query<&ConnectionDiagnostics>,
+    mut store: ResMut<DiagnosticsStore>,
+) {
+    let Ok(diagnostics) = connections.get(trigger.entity()) else {
+        return;
+    };
+    for path in &diagnostics.paths {
+        store.remove(path);
+    }
+}
+
+fn register_endpoint(
+    trigger: Trigger<OnAdd, EndPoint>,
+    mut store: ResMut<DiagnosticsStore>,
+    mut commands: Commands,
+) {
+    let entity = trigger.entity();
+    let paths = NetworkDiagnosticsPlugin::per_endpoint()
+        .map(|(path, suffix)| {
+            let path = NetworkDiagnosticsPlugin::endpoint_path(entity, &path);
+            store.add(Diagnostic::new(path.clone()).with_suffix(suffix));
+            path
+        })
+        .collect();
+    commands
+        .entity(entity)
+        .insert(EndPointDiagnostics { paths });
+}
+
+fn unregister_endpoint(
+    trigger: Trigger<OnRemove, EndPoint>,
+    endpoints: Query<&EndPointDiagnostics>,
+    mut store: ResMut<DiagnosticsStore>,
+) {
+    let Ok(diagnostics) = endpoints.get(trigger.entity()) else {
+        return;
+    };
+    for path in &diagnostics.paths {
+        store.remove(path);
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod quic_tests {
+    use super::*;
+    use crate::quic::test_utils::{connected_apps, update_until};
+    use crate::quic::QuicEntityCommands;
+    use bevy_diagnostic::DiagnosticsPlugin;
+
+    #[test]
+    fn measures_every_connection() {
+        let (mut server, mut client, _, connection) = connected_apps(|app| {
+            app.add_plugins((DiagnosticsPlugin, NetworkDiagnosticsPlugin));
+        });
+
+        let rtt =
+            NetworkDiagnosticsPlugin::connection_path(connection, &NetworkDiagnosticsPlugin::RTT);
+        assert_eq!(rtt.as_str(), format!("net/{connection}/rtt"));
+        let value = |app: &App, path: &DiagnosticPath| {
+            app.world()
+                .resource::<DiagnosticsStore>()
+                .get(path)
+                .and_then(Diagnostic::value)
+        };
+        update_until([&mut server, &mut client], |[_, client]| {
+            value(client, &rtt).is_some()
+                && value(client, &NetworkDiagnosticsPlugin::BYTES_SENT).is_some()
+        });
+        assert_eq!(
+            value(&client, &NetworkDiagnosticsPlugin::CONNECTIONS),
+            Some(1.0)
+        );
+        assert_eq!(
+            value(&client, &NetworkDiagnosticsPlugin::RTT),
+            value(&client, &rtt)
+        );
+        let endpoint = client
+            .world()
+            .get::<ConnectionEndPoint>(connection)
+            .unwrap()
+            .get();
+        let endpoint_connections = NetworkDiagnosticsPlugin::endpoint_path(
+            endpoint,
+            &NetworkDiagnosticsPlugin::CONNECTIONS,
+        );
+        assert_eq!(value(&client, &endpoint_connections), Some(1.0));
+
+        client
+            .world_mut()
+            .commands()
+            .entity(connection)
+            .disconnect(0u32.into(), "bye");
+        update_until([&mut server, &mut client], |apps| {
+            apps.iter()
+                .all(|app| value(app, &NetworkDiagnosticsPlugin::CONNECTIONS) == Some(0.0))
+        });
+        assert_eq!(value(&client, &endpoint_connections), Some(0.0));
+        client.update();
+        assert_eq!(
+            value(&client, &NetworkDiagnosticsPlugin::BYTES_SENT),
+            Some(0.0)
+        );
+        let store = client.world().resource::<DiagnosticsStore>();
+        assert!(store.get(&rtt).is_none());
+        assert!(store.get(&NetworkDiagnosticsPlugin::RTT).is_some());
+
+        client.world_mut().despawn(endpoint);
+        let store = client.world().resource::<DiagnosticsStore>();
+        assert!(store.get(&endpoint_connections).is_none());
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, IoSliceMut};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+
+use bevy_utils::HashMap;
+use quinn::udp::{EcnCodepoint, RecvMeta};
+
+use super::{AsyncUdpSocket, Transmit, UdpPoller};
+
+/// How many datagrams a [`LoopbackNetwork`] socket holds before dropping the ones it receives,
+/// like the receive buffer of a real socket.
+const INBOX_CAPACITY: usize = 4096;
+
+/// The first port assigned to sockets bound to port `0`.
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+/// An in-memory network, connecting the sockets bound on it without going through the OS.
+///
+/// Sockets are bound to arbitrary addresses, and can be handed to
+/// [`EndPoint::new_with_abstract_socket`](super::EndPoint::new_with_abstract_socket) so that
+/// endpoints in the same process, such as the server and client [`App`]s of a test, talk to each
+/// other over QUIC:
+///
+/// ```
+/// # use bevy_net::quic::{EndPoint, EndpointConfig, LoopbackNetwork};
+/// # bevy_tasks::IoTaskPool::get_or_init(bevy_tasks::TaskPool::new);
+/// let network = LoopbackNetwork::default();
+/// let socket = network.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+/// let endpoint = EndPoint::new_with_abstract_socket(EndpointConfig::default(), None, socket);
+/// ```
+///
+/// Datagrams sent to addresses no socket is bound to are dropped. Clones of a network refer to the
+/// same network.
+///
+/// [`App`]: bevy_app::App
+#[derive(Debug, Clone, Default)]
+pub struct LoopbackNetwork {
+    sockets: Arc<Mutex<Sockets>>,
+}
+
+#[derive(Debug, Default)]
+struct Sockets {
+    inboxes: HashMap<SocketAddr, Weak<Mutex<Inbox>>>,
+    next_port: u16,
+}
+
+impl LoopbackNetwork {
+    /// Binds a socket to `addr`, or to an unused port of its IP if its port is `0`.
+    ///
+    /// The socket is unbound once dropped.
+    pub fn bind(&self, mut addr: SocketAddr) -> io::Result<Arc<dyn AsyncUdpSocket>> {
+        let mut sockets = self.sockets.lock().unwrap();
+        sockets.inboxes.retain(|_, inbox| inbox.strong_count() > 0);
+
+        if addr.port() == 0 {
+            let start = sockets.next_port.max(FIRST_EPHEMERAL_PORT);
+            let port = (start..=u16::MAX)
+                .chain(FIRST_EPHEMERAL_PORT..start)
+                .find(|port| {
+                    !sockets
+                        .inboxes
+                        .contains_key(&SocketAddr::new(addr.ip(), *port))
+                })
+                .ok_or(ErrorKind::AddrInUse)?;
+            sockets.next_port = port.wrapping_add(1);
+            addr.set_port(port);
+        } else if sockets.inboxes.contains_key(&addr) {
+            return Err(ErrorKind::AddrInUse.into());
+        }
+
+        let inbox = Arc::new(Mutex::new(Inbox::default()));
+        sockets.inboxes.insert(addr, Arc::downgrade(&inbox));
+        Ok(Arc::new(LoopbackSocket {
+            addr,
+            network: self.clone(),
+            inbox,
+        }))
+    }
+
+    /// Creates a network with two sockets bound on it, on `127.0.0.1`.
+    pub fn pair() -> (Arc<dyn AsyncUdpSocket>, Arc<dyn AsyncUdpSocket>) {
+        let network = Self::default();
+        let localhost = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+        (
+            network.bind(localhost).unwrap(),
+            network.bind(localhost).unwrap(),
+        )
+    }
+
+    fn inbox(&self, addr: SocketAddr) -> Option<Arc<Mutex<Inbox>>> {
+        self.sockets.lock().unwrap().inboxes.get(&addr)?.upgrade()
+    }
+}
+
+struct Datagram {
+    source: SocketAddr,
+    destination: IpAddr,
+    ecn: Option<EcnCodepoint>,
+    contents: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Inbox {
+    datagrams: VecDeque<Datagram>,
+    waker: Option<Waker>,
+}
+
+impl std::fmt::Debug for Inbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Inbox")
+            .field("datagrams", &self.datagrams.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A socket bound on a [`LoopbackNetwork`].
+#[derive(Debug)]
+struct LoopbackSocket {
+    addr: SocketAddr,
+    network: LoopbackNetwork,
+    inbox: Arc<Mutex<Inbox>>,
+}
+
+/// Loopback sockets are always writable, as they drop what does not fit.
+#[derive(Debug)]
+struct LoopbackPoller;
+
+impl UdpPoller for LoopbackPoller {
+    fn poll_writable(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncUdpSocket for LoopbackSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(LoopbackPoller)
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let Some(inbox) = self.network.inbox(transmit.destination) else {
+            return Ok(());
+        };
+        let mut inbox = inbox.lock().unwrap();
+        let segment_size = transmit
+            .segment_size
+            .unwrap_or(transmit.contents.len())
+            .max(1);
+        for contents in transmit.contents.chunks(segment_size) {
+            if inbox.datagrams.len() < INBOX_CAPACITY {
+                inbox.datagrams.push_back(Datagram {
+                    source: self.addr,
+                    destination: transmit.destination.ip(),
+                    ecn: transmit.ecn,
+                    contents: contents.to_vec(),
+                });
+            }
+        }
+        if let Some(waker) = inbox.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let mut inbox = self.inbox.lock().unwrap();
+        if inbox.datagrams.is_empty() {
+            inbox.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let mut count = 0;
+        while count < bufs.len().min(meta.len()) {
+            let Some(datagram) = inbox.datagrams.pop_front() else {
+                break;
+            };
+            let len = datagram.contents.len().min(bufs[count].len());
+            bufs[count][..len].copy_from_slice(&datagram.contents[..len]);
+            meta[count] = RecvMeta {
+                addr: datagram.source,
+                len,
+                stride: len,
+                ecn: datagram.ecn,
+                dst_ip: Some(datagram.destination),
+            };
+            count += 1;
+        }
+        Poll::Ready(Ok(count))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn may_fragment(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_tasks::block_on;
+    use futures_lite::future;
+
+    fn send(from: &Arc<dyn AsyncUdpSocket>, to: SocketAddr, contents: &[u8]) {
+        from.try_send(&Transmit {
+            destination: to,
+            ecn: None,
+            contents,
+            segment_size: None,
+            src_ip: None,
+        })
+        .unwrap();
+    }
+
+    fn receive(socket: &Arc<dyn AsyncUdpSocket>) -> Option<(SocketAddr, Vec<u8>)> {
+        let mut buf = [0; 64];
+        let mut meta = [RecvMeta::default()];
+        let poll = future::poll_fn(|cx| {
+            Poll::Ready(socket.poll_recv(cx, &mut [IoSliceMut::new(&mut buf)], &mut meta))
+        });
+        match block_on(poll) {
+            Poll::Ready(result) => {
+                result.unwrap();
+                Some((meta[0].addr, buf[..meta[0].len].to_vec()))
+            }
+            Poll::Pending => None,
+        }
+    }
+
+    #[test]
+    fn delivers_datagrams_between_sockets() {
+        let (a, b) = LoopbackNetwork::pair();
+        let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        assert_ne!(a_addr, b_addr);
+        assert_eq!(receive(&b), None);
+
+        send(&a, b_addr, b"hello");
+        send(&a, b_addr, b"world");
+        assert_eq!(receive(&b), Some((a_addr, b"hello".to_vec())));
+        assert_eq!(receive(&b), Some((a_addr, b"world".to_vec())));
+        assert_eq!(receive(&b), None);
+
+        // Datagrams to unbound addresses are lost.
+        drop(b);
+        send(&a, b_addr, b"lost");
+    }
+
+    #[test]
+    fn binds_unique_addresses() {
+        let network = LoopbackNetwork::default();
+        let addr = "10.0.0.1:5000".parse().unwrap();
+        let socket = network.bind(addr).unwrap();
+        assert_eq!(socket.local_addr().unwrap(), addr);
+        assert_eq!(network.bind(addr).unwrap_err().kind(), ErrorKind::AddrInUse);
+
+        drop(socket);
+        assert!(network.bind(addr).is_ok());
+    }
+}
@@ -0,0 +1,574 @@
+//! Reimplementation of [`quinn`] types for use with bevy's runtime.
+//! Only available on platforms that support the standard library.
+
+use async_io::{Async, Timer};
+pub use quinn::udp::Transmit;
+use quinn::udp::{RecvMeta, UdpSockRef, UdpSocketState};
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::io;
+use std::io::{ErrorKind, IoSliceMut};
+use std::net::{SocketAddr, UdpSocket};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Instant;
+
+use bevy_ecs::component::Component;
+use bevy_tasks::IoTaskPool;
+
+pub use quinn::*;
+
+#[cfg(all(feature = "tls", feature = "asset"))]
+mod certificate;
+mod conditioner;
+mod diagnostic;
+mod loopback;
+mod plugin;
+mod reconnect;
+mod token;
+
+#[cfg(all(feature = "tls", feature = "asset"))]
+pub use certificate::*;
+pub use conditioner::*;
+pub use diagnostic::*;
+pub use loopback::*;
+pub use plugin::*;
+pub use reconnect::*;
+pub use token::*;
+
+/// A QUIC endpoint.
+///
+/// An endpoint corresponds to a single UDP socket, may host many connections, and may act as both
+/// client and server for different connections.
+///
+/// May be cloned to obtain another handle to the same endpoint.
+///
+/// When added to an entity, the [`QuicNetworkPlugin`] accepts incoming connections on it.
+#[derive(Component, Debug, Clone)]
+pub struct EndPoint(Endpoint);
+
+// todo A couple of endpoint methods aren't reimplemented due to the relevant types
+// in quinn not being reexported. A pr with a fix (https://github.com/quinn-rs/quinn/pull/1920#event-13538285399)
+// as of the 25th of july 2024, a new update with that fix and other stuff should be out in "about a week"
+impl EndPoint {
+    /// Construct an endpoint with arbitrary configuration and socket
+    pub fn new(
+        config: EndpointConfig,
+        server_config: Option<ServerConfig>,
+        socket: UdpSocket,
+    ) -> io::Result<Self> {
+        Ok(Self(Endpoint::new(
+            config,
+            server_config,
+            socket,
+            Arc::new(BevyQuinnRuntime),
+        )?))
+    }
+
+    /// Construct an endpoint with arbitrary configuration and a socket of any kind, such as one
+    /// wrapped by a [`LinkConditioner`] or bound on a [`LoopbackNetwork`]
+    pub fn new_with_abstract_socket(
+        config: EndpointConfig,
+        server_config: Option<ServerConfig>,
+        socket: Arc<dyn AsyncUdpSocket>,
+    ) -> io::Result<Self> {
+        Ok(Self(Endpoint::new_with_abstract_socket(
+            config,
+            server_config,
+            socket,
+            Arc::new(BevyQuinnRuntime),
+        )?))
+    }
+
+    /// Helper to construct an endpoint for use with both incoming and outgoing connections
+    ///
+    /// Platform defaults for dual-stack sockets vary. For example, any socket bound to a wildcard
+    /// IPv6 address on Windows will not by default be able to communicate with IPv4
+    /// addresses. Portable applications should bind an address that matches the family they wish to
+    /// communicate within.
+    pub fn server(config: ServerConfig, addr: SocketAddr) -> io::Result<Self> {
+        Self::new(
+            EndpointConfig::default(),
+            Some(config),
+            UdpSocket::bind(addr)?,
+        )
+    }
+
+    /// Helper to construct an endpoint for use with outgoing connections only
+    ///
+    /// Note that `addr` is the *local* address to bind to, which should usually be a wildcard
+    /// address like `0.0.0.0:0` or `[::]:0`, which allow communication with any reachable IPv4 or
+    /// IPv6 address respectively from an OS-assigned port.
+    ///
+    /// Platform defaults for dual-stack sockets vary. For example, any socket bound to a wildcard
+    /// IPv6 address on Windows will not by default be able to communicate with IPv4
+    /// addresses. Portable applications should bind an address that matches the family they wish to
+    /// communicate within.
+    pub fn client(addr: SocketAddr) -> io::Result<Self> {
+        Self::new(EndpointConfig::default(), None, UdpSocket::bind(addr)?)
+    }
+
+    /// Get the next incoming connection attempt from a client
+    ///
+    /// Yields [`Incoming`]s, or `None` if the endpoint is [`close`](Self::close)d. [`Incoming`]
+    /// can be `await`ed to obtain the final [`Connection`], or used to e.g.
+    /// filter connection attempts or force address validation, or converted into an intermediate
+    /// `Connecting` future which can be used to e.g. send 0.5-RTT data.
+    pub fn accept(&self) -> Accept<'_> {
+        self.0.accept()
+    }
+
+    /// Set the client configuration used by [`connect`](Self::connect)
+    pub fn set_default_client_config(&mut self, config: ClientConfig) {
+        self.0.set_default_client_config(config);
+    }
+
+    /// Connect to a remote endpoint
+    ///
+    /// `server_name` must be covered by the certificate presented by the server. This prevents a
+    /// connection from being intercepted by an attacker with a valid certificate for some other
+    /// server.
+    ///
+    /// May fail immediately due to configuration errors, or in the future if the connection could
+    /// not be established.
+    pub fn connect(&self, addr: SocketAddr, server_name: &str) -> Result<Connecting, ConnectError> {
+        self.0.connect(addr, server_name)
+    }
+
+    /// Connect to a remote endpoint using a custom configuration.
+    ///
+    /// See [`connect()`] for details.
+    ///
+    /// [`connect()`]: Self::connect
+    pub fn connect_with(
+        &self,
+        config: ClientConfig,
+        addr: SocketAddr,
+        server_name: &str,
+    ) -> Result<Connecting, ConnectError> {
+        self.0.connect_with(config, addr, server_name)
+    }
+
+    /// Switch to a new UDP socket
+    ///
+    /// Allows the endpoint's address to be updated live, affecting all active connections. Incoming
+    /// connections and connections to servers unreachable from the new address will be lost.
+    ///
+    /// On error, the old UDP socket is retained.
+    pub fn rebind(&self, socket: UdpSocket) -> io::Result<()> {
+        self.0.rebind(socket)
+    }
+
+    /// Switch to a new socket of any kind
+    ///
+    /// See [`rebind()`] for details.
+    ///
+    /// [`rebind()`]: Self::rebind
+    pub fn rebind_abstract(&self, socket: Arc<dyn AsyncUdpSocket>) -> io::Result<()> {
+        self.0.rebind_abstract(socket)
+    }
+
+    /// Replace the server configuration, affecting new incoming connections only
+    ///
+    /// Useful for e.g. refreshing TLS certificates without disrupting existing connections.
+    pub fn set_server_config(&self, server_config: Option<ServerConfig>) {
+        self.0.set_server_config(server_config);
+    }
+
+    /// Get the local `SocketAddr` the underlying socket is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+
+    /// Get the number of connections that are currently open
+    pub fn open_connections(&self) -> usize {
+        self.0.open_connections()
+    }
+
+    /// Close all of this endpoint's connections immediately and cease accepting new connections.
+    ///
+    /// See [`Connection::close()`](Connection) for details.
+    ///
+    /// [`Connection::close()`](Connection)
+    pub fn close(&self, error_code: VarInt, reason: &[u8]) {
+        self.0.close(error_code, reason);
+    }
+
+    /// Wait for all connections on the endpoint to be cleanly shut down
+    ///
+    /// Waiting for this condition before exiting ensures that a good-faith effort is made to notify
+    /// peers of recent connection closes, whereas exiting immediately could force them to wait out
+    /// the idle timeout period.
+    ///
+    /// Does not proactively close existing connections or cause incoming connections to be
+    /// rejected. Consider calling [`close()`] if that is desired.
+    ///
+    /// [`close()`]: Self::close
+    pub async fn wait_idle(&self) {
+        self.0.wait_idle().await;
+    }
+}
+
+/// The [`Runtime`] used by every [`EndPoint`].
+///
+/// Tasks are spawned onto the [`IoTaskPool`], while sockets and timers are driven by the
+/// [`async_io`] reactor, so they are only woken when the OS reports readiness or a deadline passes.
+#[derive(Debug)]
+struct BevyQuinnRuntime;
+
+impl Runtime for BevyQuinnRuntime {
+    fn new_timer(&self, i: Instant) -> Pin<Box<dyn AsyncTimer>> {
+        Box::pin(IoTimer(Timer::at(i)))
+    }
+
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        IoTaskPool::get().spawn(future).detach();
+    }
+
+    fn wrap_udp_socket(&self, t: UdpSocket) -> io::Result<Arc<dyn AsyncUdpSocket>> {
+        Ok(Arc::new(QuinnUdp::new(t)?))
+    }
+}
+
+#[derive(Debug)]
+struct QuinnUdp {
+    io: Async<UdpSocket>,
+    state: UdpSocketState,
+}
+
+impl QuinnUdp {
+    fn new(socket: UdpSocket) -> io::Result<Self> {
+        Ok(Self {
+            state: UdpSocketState::new(UdpSockRef::from(&socket))?,
+            io: Async::new_nonblocking(socket)?,
+        })
+    }
+}
+
+/// Waits for the socket to become writable.
+///
+/// [`Async::poll_writable`] only remembers the most recent waker, so each poller keeps its own
+/// [`Async::writable`] future, allowing any number of tasks to wait on the same socket.
+struct QuinnPoller {
+    socket: Arc<QuinnUdp>,
+    writable: Option<Pin<Box<dyn Future<Output = io::Result<()>> + Send + Sync>>>,
+}
+
+impl Debug for QuinnPoller {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QuinnPoller")
+            .field("socket", &self.socket)
+            .finish_non_exhaustive()
+    }
+}
+
+impl UdpPoller for QuinnPoller {
+    fn poll_writable(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let writable = this.writable.get_or_insert_with(|| {
+            let socket = this.socket.clone();
+            Box::pin(async move { socket.io.writable().await })
+        });
+
+        let result = writable.as_mut().poll(cx);
+        if result.is_ready() {
+            // The future has completed and must not be polled again,
+            // the next call will wait for a fresh readiness event.
+            this.writable = None;
+        }
+        result
+    }
+}
+
+impl AsyncUdpSocket for QuinnUdp {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(QuinnPoller {
+            socket: self,
+            writable: None,
+        })
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        self.state.send(UdpSockRef::from(&self.io), transmit)
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            ready!(self.io.poll_readable(cx))?;
+            match self.state.recv(UdpSockRef::from(&self.io), bufs, meta) {
+                Ok(n) => return Poll::Ready(Ok(n)),
+                // The readiness was spurious, or another task drained the socket first,
+                // so register interest again.
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                Err(error) => return Poll::Ready(Err(error)),
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().local_addr()
+    }
+
+    fn max_transmit_segments(&self) -> usize {
+        self.state.max_gso_segments()
+    }
+
+    fn max_receive_segments(&self) -> usize {
+        self.state.gro_segments()
+    }
+
+    fn may_fragment(&self) -> bool {
+        self.state.may_fragment()
+    }
+}
+
+/// A timer registered with the [`async_io`] reactor, which wakes its task once the deadline passes.
+#[derive(Debug)]
+struct IoTimer(Timer);
+
+impl AsyncTimer for IoTimer {
+    fn reset(mut self: Pin<&mut Self>, i: Instant) {
+        self.0.set_at(i);
+    }
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        Pin::new(&mut self.0).poll(cx).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use bevy_app::App;
+    use std::time::{Duration, Instant};
+    #[cfg(feature = "tls")]
+    use {
+        super::crypto::rustls::QuicClientConfig,
+        super::{
+            ClientConfig, Connected, EndPoint, EndpointConfig, LinkConditioner, LinkProfile,
+            LoopbackNetwork, QuicEntityCommands, QuicNetworkPlugin, Reconnect, ReconnectPlugin,
+            ServerConfig, TransportConfig,
+        },
+        crate::crypto_utils::SkipServerVerification,
+        bevy_ecs::prelude::*,
+        bevy_tasks::{IoTaskPool, TaskPool},
+        rustls_pemfile::{read_all, Item},
+        std::net::{Ipv4Addr, SocketAddr},
+        std::sync::Arc,
+    };
+
+    /// Makes sure the [`IoTaskPool`] the runtime spawns onto exists.
+    #[cfg(feature = "tls")]
+    pub(crate) fn init_task_pool() {
+        IoTaskPool::get_or_init(TaskPool::new);
+    }
+
+    /// A server configuration using the certificate of the `ping_pong` example.
+    #[cfg(feature = "tls")]
+    pub(crate) fn server_config() -> ServerConfig {
+        let pem = include_bytes!("../../../../assets/cypto/bevy_ping_pong_example.pem");
+        let (mut cert, mut key) = (None, None);
+        for item in read_all(&mut &pem[..]) {
+            match item.unwrap() {
+                Item::X509Certificate(c) => cert = Some(c),
+                Item::Pkcs1Key(k) => key = Some(k),
+                _ => {}
+            }
+        }
+        ServerConfig::with_single_cert(vec![cert.unwrap()], key.unwrap().into()).unwrap()
+    }
+
+    /// A client configuration that accepts any server certificate.
+    #[cfg(feature = "tls")]
+    pub(crate) fn client_config() -> ClientConfig {
+        ClientConfig::new(Arc::new(
+            QuicClientConfig::try_from(
+                rustls::ClientConfig::builder()
+                    .dangerous()
+                    .with_custom_certificate_verifier(SkipServerVerification::new())
+                    .with_no_client_auth(),
+            )
+            .unwrap(),
+        ))
+    }
+
+    /// Calls `poll` until it returns something, panicking if that takes too long.
+    pub(crate) fn poll_until<T>(mut poll: impl FnMut() -> Option<T>) -> T {
+        let start = Instant::now();
+        loop {
+            if let Some(value) = poll() {
+                return value;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Updates every app in turn until `done` returns `true`, panicking if that takes too long.
+    pub(crate) fn update_until<const N: usize>(
+        mut apps: [&mut App; N],
+        mut done: impl FnMut([&App; N]) -> bool,
+    ) {
+        poll_until(|| {
+            if done(apps.each_ref().map(|app| &**app)) {
+                return Some(());
+            }
+            for app in &mut apps {
+                app.update();
+            }
+            None
+        });
+    }
+
+    /// Records the connection entity of every [`Connected`] event.
+    #[cfg(feature = "tls")]
+    #[derive(Resource, Default)]
+    pub(crate) struct ConnectedEntities(pub(crate) Vec<Entity>);
+
+    /// Creates a server and a client app, both set up by `setup`, and connects them over a
+    /// [`LoopbackNetwork`].
+    ///
+    /// Returns the apps along with the server's and the client's connection entities.
+    #[cfg(feature = "tls")]
+    pub(crate) fn connected_apps(setup: impl Fn(&mut App)) -> (App, App, Entity, Entity) {
+        init_task_pool();
+        let localhost = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+        let new_app = || {
+            let mut app = App::new();
+            app.add_plugins(QuicNetworkPlugin)
+                .init_resource::<ConnectedEntities>()
+                .observe(
+                    |trigger: Trigger<Connected>, mut connected: ResMut<ConnectedEntities>| {
+                        connected.0.push(trigger.event().connection);
+                    },
+                );
+            setup(&mut app);
+            app.finish();
+            app.cleanup();
+            app
+        };
+
+        let network = LoopbackNetwork::default();
+
+        let mut server = new_app();
+        let endpoint = EndPoint::new_with_abstract_socket(
+            EndpointConfig::default(),
+            Some(server_config()),
+            network.bind(localhost).unwrap(),
+        )
+        .unwrap();
+        let server_addr = endpoint.local_addr().unwrap();
+        server.world_mut().spawn(endpoint);
+
+        let mut client = new_app();
+        let mut endpoint = EndPoint::new_with_abstract_socket(
+            EndpointConfig::default(),
+            None,
+            network.bind(localhost).unwrap(),
+        )
+        .unwrap();
+        endpoint.set_default_client_config(client_config());
+        let endpoint = client.world_mut().spawn(endpoint).id();
+        client
+            .world_mut()
+            .commands()
+            .entity(endpoint)
+            .connect(server_addr, "localhost");
+
+        update_until([&mut server, &mut client], |apps| {
+            apps.iter()
+                .all(|app| !app.world().resource::<ConnectedEntities>().0.is_empty())
+        });
+
+        let server_connection = server.world().resource::<ConnectedEntities>().0[0];
+        let client_connection = client.world().resource::<ConnectedEntities>().0[0];
+        (server, client, server_connection, client_connection)
+    }
+
+    /// Creates a server and a client app like [`connected_apps`], but with a [`Reconnect`] policy
+    /// on the client's endpoint and idle timeouts short enough to lose the connection quickly once
+    /// the returned [`LinkConditioner`] cuts the client's link.
+    ///
+    /// Returns the apps along with the conditioner and the client's endpoint entity.
+    #[cfg(feature = "tls")]
+    pub(crate) fn reconnecting_apps(
+        setup: impl Fn(&mut App),
+    ) -> (App, App, LinkConditioner, Entity) {
+        init_task_pool();
+        let localhost = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+        let mut transport = TransportConfig::default();
+        transport
+            .max_idle_timeout(Some(Duration::from_millis(300).try_into().unwrap()))
+            .keep_alive_interval(Some(Duration::from_millis(50)))
+            .initial_rtt(Duration::from_millis(10));
+        let transport = Arc::new(transport);
+        let new_app = || {
+            let mut app = App::new();
+            app.add_plugins((QuicNetworkPlugin, ReconnectPlugin))
+                .init_resource::<ConnectedEntities>()
+                .observe(
+                    |trigger: Trigger<Connected>, mut connected: ResMut<ConnectedEntities>| {
+                        connected.0.push(trigger.event().connection);
+                    },
+                );
+            setup(&mut app);
+            app.finish();
+            app.cleanup();
+            app
+        };
+
+        let network = LoopbackNetwork::default();
+
+        let mut server = new_app();
+        let mut config = server_config();
+        config.transport_config(transport.clone());
+        let endpoint = EndPoint::new_with_abstract_socket(
+            EndpointConfig::default(),
+            Some(config),
+            network.bind(localhost).unwrap(),
+        )
+        .unwrap();
+        let server_addr = endpoint.local_addr().unwrap();
+        server.world_mut().spawn(endpoint);
+
+        let mut client = new_app();
+        let link = LinkConditioner::with_seed(LinkProfile::PERFECT, 7);
+        let endpoint = EndPoint::new_with_abstract_socket(
+            EndpointConfig::default(),
+            None,
+            link.wrap(network.bind(localhost).unwrap()),
+        )
+        .unwrap();
+        let mut config = client_config();
+        config.transport_config(transport);
+        let endpoint = client
+            .world_mut()
+            .spawn((
+                endpoint,
+                Reconnect {
+                    initial_delay: Duration::from_millis(20),
+                    jitter: 0.0,
+                    max_attempts: None,
+                    rebind_on_address_change: false,
+                    ..Default::default()
+                },
+            ))
+            .id();
+        client.world_mut().commands().entity(endpoint).connect_with(
+            config,
+            server_addr,
+            "localhost",
+        );
+        update_until([&mut server, &mut client], |apps| {
+            apps.iter()
+                .all(|app| !app.world().resource::<ConnectedEntities>().0.is_empty())
+        });
+
+        (server, client, link, endpoint)
+    }
+}
@@ -0,0 +1,776 @@
+//! Integration of [`EndPoint`]s and their connections with the ECS.
+//!
+//! Spawn an entity with an [`EndPoint`] component and the [`QuicNetworkPlugin`] starts accepting
+//! incoming connections on it. Outgoing connections are started with
+//! [`QuicEntityCommands::connect`]. Every connection, in either direction, lives on its own entity
+//! and reports its progress through the [`ConnectionAttempt`], [`Connected`], [`ConnectionFailed`]
+//! and [`Disconnected`] events, which are both triggered on the connection entity for observers
+//! and sent as buffered events for [`EventReader`](bevy_ecs::event::EventReader)s.
+
+use std::net::SocketAddr;
+
+use bevy_app::{App, Plugin, PreUpdate};
+use bevy_derive::Deref;
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::EntityCommands;
+use bevy_ecs::world::Command;
+use bevy_tasks::{IoTaskPool, Task};
+use bevy_utils::tracing::{debug, warn};
+use bytes::Bytes;
+use crossbeam_channel::{Receiver, Sender};
+use thiserror::Error;
+
+use crate::transport::{self, TransportEndPoint, TransportError};
+
+use super::{
+    authenticate, ClientConfig, ConnectError, ConnectTokenAuth, ConnectTokenError, ConnectTokenKey,
+    Connecting, Connection, ConnectionError, EndPoint, Incoming, TokenHandshake, VarInt,
+    VerifiedConnectToken,
+};
+
+/// Adds ECS-driven management of [`EndPoint`]s and their connections to an [`App`].
+#[derive(Default)]
+pub struct QuicNetworkPlugin;
+
+impl Plugin for QuicNetworkPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<QuicMessages>()
+            .add_event::<ConnectionAttempt>()
+            .add_event::<Connected>()
+            .add_event::<ConnectionFailed>()
+            .add_event::<Disconnected>()
+            .observe(start_accepting)
+            .observe(close_removed_connection)
+            .configure_sets(
+                PreUpdate,
+                NetworkSystem::Receive.after(NetworkSystem::Connections),
+            )
+            .add_systems(
+                PreUpdate,
+                process_quic_messages.in_set(NetworkSystem::Connections),
+            );
+    }
+}
+
+/// Labels for the networking systems.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemSet)]
+pub enum NetworkSystem {
+    /// Spawns, updates and despawns connection entities, and sends the connection lifecycle events.
+    ///
+    /// Runs in [`PreUpdate`].
+    Connections,
+    /// Drains the data received from every connection.
+    ///
+    /// Runs in [`PreUpdate`], after [`NetworkSystem::Connections`].
+    Receive,
+    /// Flushes the data queued for sending to the connections.
+    ///
+    /// Runs in [`PostUpdate`](bevy_app::PostUpdate).
+    Send,
+}
+
+/// A live QUIC connection, inserted on a connection entity once its handshake completes.
+///
+/// Removing this component, or despawning its entity, closes the connection with error code `0`.
+#[derive(Component, Debug, Clone, Deref)]
+pub struct QuicConnection(Connection);
+
+impl QuicConnection {
+    /// Returns the underlying [`Connection`].
+    pub fn get(&self) -> &Connection {
+        &self.0
+    }
+}
+
+/// The [`EndPoint`] entity a connection entity belongs to.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Deref)]
+pub struct ConnectionEndPoint(Entity);
+
+impl ConnectionEndPoint {
+    pub(crate) fn new(endpoint: Entity) -> Self {
+        Self(endpoint)
+    }
+
+    /// Gets the [`Entity`] holding the [`EndPoint`] of this connection.
+    pub fn get(&self) -> Entity {
+        self.0
+    }
+}
+
+/// Which side initiated a connection.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionDirection {
+    /// The remote peer connected to one of our [`EndPoint`]s.
+    Incoming,
+    /// We connected to a remote peer with [`QuicEntityCommands::connect`].
+    Outgoing,
+}
+
+/// Where an outgoing connection entity connects to, as given to [`QuicEntityCommands::connect`].
+#[derive(Component, Debug, Clone)]
+pub struct ConnectionTarget {
+    /// The address of the server.
+    pub addr: SocketAddr,
+    /// The name the server's certificate must be valid for.
+    pub server_name: String,
+    /// The client configuration used, if not the endpoint's default.
+    pub config: Option<ClientConfig>,
+}
+
+/// How far along a connection entity is.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    /// The handshake is in progress.
+    Connecting,
+    /// The handshake completed and the [`QuicConnection`] component is available.
+    Connected,
+}
+
+/// Triggered when a connection entity is spawned, before its handshake has completed.
+///
+/// On servers requiring [connect tokens](super::ConnectTokenAuth), incoming connection entities
+/// are only spawned once their handshake completed, right before [`Connected`].
+#[derive(Event, Debug, Clone)]
+pub struct ConnectionAttempt {
+    /// The connection entity.
+    pub connection: Entity,
+    /// The [`EndPoint`] entity the connection belongs to.
+    pub endpoint: Entity,
+    /// The address of the remote peer.
+    pub remote_address: SocketAddr,
+    /// Which side initiated the connection.
+    pub direction: ConnectionDirection,
+}
+
+/// Triggered once a connection's handshake completes and its [`QuicConnection`] has been inserted.
+#[derive(Event, Debug, Clone)]
+pub struct Connected {
+    /// The connection entity.
+    pub connection: Entity,
+    /// The [`EndPoint`] entity the connection belongs to.
+    pub endpoint: Entity,
+}
+
+/// Triggered when a connection could not be established.
+///
+/// The connection entity is despawned right after this event.
+#[derive(Event, Debug, Clone)]
+pub struct ConnectionFailed {
+    /// The connection entity.
+    pub connection: Entity,
+    /// The [`EndPoint`] entity the connection belonged to.
+    pub endpoint: Entity,
+    /// Why the connection failed.
+    pub error: QuicError,
+}
+
+/// Triggered when an established connection closes, for any reason.
+///
+/// The connection entity is despawned right after this event.
+#[derive(Event, Debug, Clone)]
+pub struct Disconnected {
+    /// The connection entity.
+    pub connection: Entity,
+    /// The [`EndPoint`] entity the connection belonged to.
+    pub endpoint: Entity,
+    /// The application error code, if either side closed the connection deliberately.
+    pub error_code: Option<VarInt>,
+    /// The reason given alongside [`error_code`](Self::error_code), empty if there wasn't one.
+    pub reason: Bytes,
+    /// The error the connection was closed with.
+    pub error: ConnectionError,
+}
+
+/// An error that prevented a connection from being established.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum QuicError {
+    /// The connection could not be started, usually because of a configuration error.
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+    /// The connection failed during its handshake.
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+    /// The connection was started from an entity without an [`EndPoint`], or with a custom client
+    /// configuration from a [`TransportEndPoint`].
+    #[error("entity {0:?} does not have an `EndPoint` component")]
+    MissingEndPoint(Entity),
+    /// The client's [connect token](super::ConnectToken) was rejected by the server it connected
+    /// to.
+    #[error(transparent)]
+    Token(#[from] ConnectTokenError),
+    /// The connection of a [`TransportEndPoint`] failed.
+    #[error(transparent)]
+    Transport(TransportError),
+}
+
+/// Extension trait adding QUIC methods to [`EntityCommands`].
+pub trait QuicEntityCommands {
+    /// Starts connecting from this [`EndPoint`] entity to the server at `addr`, using the endpoint's
+    /// default client configuration.
+    ///
+    /// Returns the connection entity, which is spawned right away and reports its progress through
+    /// the connection lifecycle events. See [`EndPoint::connect`] for the meaning of `server_name`.
+    ///
+    /// Also connects from [`TransportEndPoint`] entities, through their transport.
+    fn connect(&mut self, addr: SocketAddr, server_name: impl Into<String>) -> Entity;
+
+    /// Like [`connect`](Self::connect), but with a custom client configuration.
+    ///
+    /// Only supported on [`EndPoint`] entities.
+    fn connect_with(
+        &mut self,
+        config: ClientConfig,
+        addr: SocketAddr,
+        server_name: impl Into<String>,
+    ) -> Entity;
+
+    /// Closes the connection on this connection entity with the given error code and reason.
+    ///
+    /// [`Disconnected`] is triggered once the connection has shut down.
+    fn disconnect(&mut self, error_code: VarInt, reason: impl Into<Bytes>) -> &mut Self;
+}
+
+impl QuicEntityCommands for EntityCommands<'_> {
+    fn connect(&mut self, addr: SocketAddr, server_name: impl Into<String>) -> Entity {
+        let endpoint = self.id();
+        let connection = self.commands().spawn(ConnectionEndPoint(endpoint)).id();
+        self.commands().add(Connect {
+            endpoint,
+            connection,
+            addr,
+            server_name: server_name.into(),
+            config: None,
+        });
+        connection
+    }
+
+    fn connect_with(
+        &mut self,
+        config: ClientConfig,
+        addr: SocketAddr,
+        server_name: impl Into<String>,
+    ) -> Entity {
+        let endpoint = self.id();
+        let connection = self.commands().spawn(ConnectionEndPoint(endpoint)).id();
+        self.commands().add(Connect {
+            endpoint,
+            connection,
+            addr,
+            server_name: server_name.into(),
+            config: Some(config),
+        });
+        connection
+    }
+
+    fn disconnect(&mut self, error_code: VarInt, reason: impl Into<Bytes>) -> &mut Self {
+        let connection = self.id();
+        self.commands().add(Disconnect {
+            connection,
+            error_code,
+            reason: reason.into(),
+        });
+        self
+    }
+}
+
+/// Command that starts an outgoing connection from an [`EndPoint`] entity.
+#[derive(Debug)]
+pub struct Connect {
+    /// The entity holding the [`EndPoint`] to connect from.
+    pub endpoint: Entity,
+    /// The entity to manage the connection on.
+    pub connection: Entity,
+    /// The address of the server.
+    pub addr: SocketAddr,
+    /// The name the server's certificate must be valid for.
+    pub server_name: String,
+    /// The client configuration to use, the endpoint's default is used if this is `None`.
+    pub config: Option<ClientConfig>,
+}
+
+impl Command for Connect {
+    fn apply(self, world: &mut World) {
+        let Some(endpoint) = world.get::<EndPoint>(self.endpoint) else {
+            if self.config.is_none() && world.get::<TransportEndPoint>(self.endpoint).is_some() {
+                transport::connect(
+                    world,
+                    self.connection,
+                    self.endpoint,
+                    self.addr,
+                    &self.server_name,
+                );
+                return;
+            }
+            fail(
+                world,
+                self.connection,
+                self.endpoint,
+                QuicError::MissingEndPoint(self.endpoint),
+            );
+            return;
+        };
+
+        let connecting = match &self.config {
+            Some(config) => endpoint.connect_with(config.clone(), self.addr, &self.server_name),
+            None => endpoint.connect(self.addr, &self.server_name),
+        };
+
+        match connecting {
+            Ok(connecting) => {
+                start_connection(
+                    world,
+                    self.connection,
+                    self.endpoint,
+                    self.addr,
+                    ConnectionDirection::Outgoing,
+                    connecting,
+                );
+                if let Some(mut entity) = world.get_entity_mut(self.connection) {
+                    entity.insert(ConnectionTarget {
+                        addr: self.addr,
+                        server_name: self.server_name,
+                        config: self.config,
+                    });
+                }
+            }
+            Err(error) => fail(world, self.connection, self.endpoint, error.into()),
+        }
+    }
+}
+
+/// Command that closes the connection of a connection entity.
+#[derive(Debug)]
+pub struct Disconnect {
+    /// The connection entity.
+    pub connection: Entity,
+    /// The application error code sent to the peer.
+    pub error_code: VarInt,
+    /// The reason sent to the peer.
+    pub reason: Bytes,
+}
+
+impl Command for Disconnect {
+    fn apply(self, world: &mut World) {
+        let Some(mut entity) = world.get_entity_mut(self.connection) else {
+            return;
+        };
+
+        if let Some(connection) = entity.get::<QuicConnection>() {
+            connection.close(self.error_code, &self.reason);
+        }
+        entity.insert(LocalClose {
+            error_code: self.error_code,
+            reason: self.reason.clone(),
+        });
+        transport::disconnect(world, self.connection, self.error_code, &self.reason);
+    }
+}
+
+/// Updates sent from the async tasks driving endpoints and connections back to the [`World`].
+enum QuicMessage {
+    Incoming {
+        endpoint: Entity,
+        incoming: Box<Incoming>,
+    },
+    Established {
+        connection: Entity,
+        result: Result<(Connection, Option<VerifiedConnectToken>), QuicError>,
+    },
+    Authenticated {
+        endpoint: Entity,
+        remote_address: SocketAddr,
+        connection: Connection,
+        token: VerifiedConnectToken,
+    },
+    Closed {
+        connection: Entity,
+        error: ConnectionError,
+    },
+}
+
+#[derive(Resource)]
+struct QuicMessages {
+    sender: Sender<QuicMessage>,
+    receiver: Receiver<QuicMessage>,
+}
+
+impl Default for QuicMessages {
+    fn default() -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        Self { sender, receiver }
+    }
+}
+
+/// The task accepting incoming connections for an [`EndPoint`] entity, cancelled when dropped.
+#[derive(Component)]
+struct AcceptTask(#[allow(dead_code)] Task<()>);
+
+/// The task driving a connection entity, cancelled when dropped.
+#[derive(Component)]
+struct ConnectionTask(#[allow(dead_code)] Task<()>);
+
+/// The code and reason a connection was closed with through a [`Disconnect`] command.
+#[derive(Component)]
+struct LocalClose {
+    error_code: VarInt,
+    reason: Bytes,
+}
+
+fn start_accepting(
+    trigger: Trigger<OnAdd, EndPoint>,
+    endpoints: Query<&EndPoint>,
+    messages: Res<QuicMessages>,
+    mut commands: Commands,
+) {
+    let entity = trigger.entity();
+    let endpoint = endpoints.get(entity).unwrap().clone();
+    let sender = messages.sender.clone();
+
+    let task = IoTaskPool::get().spawn(async move {
+        while let Some(incoming) = endpoint.accept().await {
+            let message = QuicMessage::Incoming {
+                endpoint: entity,
+                incoming: Box::new(incoming),
+            };
+            if sender.send(message).is_err() {
+                return;
+            }
+        }
+    });
+
+    commands.entity(entity).insert(AcceptTask(task));
+}
+
+fn close_removed_connection(
+    trigger: Trigger<OnRemove, QuicConnection>,
+    connections: Query<&QuicConnection>,
+) {
+    if let Ok(connection) = connections.get(trigger.entity()) {
+        connection.close(VarInt::from_u32(0), &[]);
+    }
+}
+
+fn process_quic_messages(world: &mut World) {
+    let receiver = world.resource::<QuicMessages>().receiver.clone();
+
+    for message in receiver.try_iter() {
+        match message {
+            QuicMessage::Incoming { endpoint, incoming } => {
+                if let Some(auth) = world.get::<ConnectTokenAuth>(endpoint) {
+                    authenticate_incoming(world, endpoint, *incoming, auth.0.clone());
+                    continue;
+                }
+                let remote_address = incoming.remote_address();
+                let connection = world.spawn(ConnectionEndPoint(endpoint)).id();
+                match incoming.accept() {
+                    Ok(connecting) => start_connection(
+                        world,
+                        connection,
+                        endpoint,
+                        remote_address,
+                        ConnectionDirection::Incoming,
+                        connecting,
+                    ),
+                    Err(error) => fail(world, connection, endpoint, error.into()),
+                }
+            }
+            QuicMessage::Established { connection, result } => {
+                established(world, connection, result);
+            }
+            QuicMessage::Authenticated {
+                endpoint,
+                remote_address,
+                connection: authenticated,
+                token,
+            } => {
+                if world.get_entity(endpoint).is_none() {
+                    authenticated.close(VarInt::from_u32(0), &[]);
+                    continue;
+                }
+                let connection = world.spawn(ConnectionEndPoint(endpoint)).id();
+                world
+                    .entity_mut(connection)
+                    .insert((ConnectionState::Connecting, ConnectionDirection::Incoming));
+                notify(
+                    world,
+                    connection,
+                    ConnectionAttempt {
+                        connection,
+                        endpoint,
+                        remote_address,
+                        direction: ConnectionDirection::Incoming,
+                    },
+                );
+                established(world, connection, Ok((authenticated, Some(token))));
+            }
+            QuicMessage::Closed { connection, error } => closed(world, connection, error),
+        }
+    }
+}
+
+fn start_connection(
+    world: &mut World,
+    connection: Entity,
+    endpoint: Entity,
+    remote_address: SocketAddr,
+    direction: ConnectionDirection,
+    connecting: Connecting,
+) {
+    let sender = world.resource::<QuicMessages>().sender.clone();
+    let handshake = TokenHandshake::new(world, endpoint, direction);
+    let task = IoTaskPool::get().spawn(async move {
+        let result = handshake
+            .run(connecting)
+            .await
+            .map(|established| (established, None));
+        let _ = sender.send(QuicMessage::Established { connection, result });
+    });
+
+    let Some(mut entity) = world.get_entity_mut(connection) else {
+        // The connection entity was despawned before the command applied,
+        // dropping the task abandons the handshake.
+        return;
+    };
+    entity.insert((ConnectionState::Connecting, direction, ConnectionTask(task)));
+
+    notify(
+        world,
+        connection,
+        ConnectionAttempt {
+            connection,
+            endpoint,
+            remote_address,
+            direction,
+        },
+    );
+}
+
+/// Accepts an incoming connection to an endpoint requiring connect tokens, only spawning its
+/// entity once the token was verified during the handshake and acknowledged.
+fn authenticate_incoming(
+    world: &mut World,
+    endpoint: Entity,
+    incoming: Incoming,
+    key: ConnectTokenKey,
+) {
+    let remote_address = incoming.remote_address();
+    let connecting = match incoming.accept() {
+        Ok(connecting) => connecting,
+        Err(error) => {
+            debug!("failed to accept a connection from {remote_address}: {error}");
+            return;
+        }
+    };
+    let sender = world.resource::<QuicMessages>().sender.clone();
+    IoTaskPool::get()
+        .spawn(async move {
+            match authenticate(connecting, key).await {
+                Ok((connection, token)) => {
+                    let _ = sender.send(QuicMessage::Authenticated {
+                        endpoint,
+                        remote_address,
+                        connection,
+                        token,
+                    });
+                }
+                Err(error) => debug!("refused a connection from {remote_address}: {error}"),
+            }
+        })
+        .detach();
+}
+
+fn established(
+    world: &mut World,
+    connection: Entity,
+    result: Result<(Connection, Option<VerifiedConnectToken>), QuicError>,
+) {
+    let Some(endpoint) = world
+        .get::<ConnectionEndPoint>(connection)
+        .map(ConnectionEndPoint::get)
+    else {
+        if let Ok((established, _)) = result {
+            established.close(VarInt::from_u32(0), &[]);
+        }
+        return;
+    };
+
+    let (established, token) = match result {
+        Ok(established) => established,
+        Err(error) => {
+            fail(world, connection, endpoint, error);
+            return;
+        }
+    };
+
+    // A disconnect was requested while the handshake was still in progress.
+    if let Some(local) = world.get::<LocalClose>(connection) {
+        established.close(local.error_code, &local.reason);
+    }
+
+    let sender = world.resource::<QuicMessages>().sender.clone();
+    let watched = established.clone();
+    let task = IoTaskPool::get().spawn(async move {
+        let error = watched.closed().await;
+        let _ = sender.send(QuicMessage::Closed { connection, error });
+    });
+
+    let mut entity = world.entity_mut(connection);
+    entity.insert((
+        QuicConnection(established),
+        ConnectionState::Connected,
+        ConnectionTask(task),
+    ));
+    if let Some(token) = token {
+        entity.insert((token.client_id, token.user_data));
+    }
+
+    notify(
+        world,
+        connection,
+        Connected {
+            connection,
+            endpoint,
+        },
+    );
+}
+
+pub(crate) fn closed(world: &mut World, connection: Entity, error: ConnectionError) {
+    let Some(mut entity) = world.get_entity_mut(connection) else {
+        return;
+    };
+    let endpoint = entity.get::<ConnectionEndPoint>().unwrap().get();
+
+    let (error_code, reason) = match (&error, entity.take::<LocalClose>()) {
+        (ConnectionError::ApplicationClosed(close), _) => {
+            (Some(close.error_code), close.reason.clone())
+        }
+        (ConnectionError::LocallyClosed, Some(local)) => (Some(local.error_code), local.reason),
+        _ => (None, Bytes::new()),
+    };
+
+    notify(
+        world,
+        connection,
+        Disconnected {
+            connection,
+            endpoint,
+            error_code,
+            reason,
+            error,
+        },
+    );
+    world.despawn(connection);
+}
+
+pub(crate) fn fail(world: &mut World, connection: Entity, endpoint: Entity, error: QuicError) {
+    warn!("connection {connection:?} failed: {error}");
+    notify(
+        world,
+        connection,
+        ConnectionFailed {
+            connection,
+            endpoint,
+            error,
+        },
+    );
+    world.despawn(connection);
+}
+
+/// Triggers `event` on `target` and sends it as a buffered event.
+pub(crate) fn notify<E: Event + Clone>(world: &mut World, target: Entity, event: E) {
+    world.trigger_targets(event.clone(), target);
+    world.send_event(event);
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+    use super::*;
+    use crate::quic::test_utils::{client_config, init_task_pool, server_config, update_until};
+    use std::net::Ipv4Addr;
+
+    #[derive(Resource, Default)]
+    struct Log {
+        attempts: Vec<ConnectionAttempt>,
+        connected: Vec<Connected>,
+        disconnected: Vec<Disconnected>,
+    }
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins(QuicNetworkPlugin)
+            .init_resource::<Log>()
+            .observe(
+                |trigger: Trigger<ConnectionAttempt>, mut log: ResMut<Log>| {
+                    log.attempts.push(trigger.event().clone());
+                },
+            )
+            .observe(|trigger: Trigger<Connected>, mut log: ResMut<Log>| {
+                log.connected.push(trigger.event().clone());
+            })
+            .observe(|trigger: Trigger<Disconnected>, mut log: ResMut<Log>| {
+                log.disconnected.push(trigger.event().clone());
+            });
+        app
+    }
+
+    #[test]
+    fn connection_lifecycle() {
+        init_task_pool();
+        let localhost = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+
+        let mut server = app();
+        let endpoint = EndPoint::server(server_config(), localhost).unwrap();
+        let server_addr = endpoint.local_addr().unwrap();
+        server.world_mut().spawn(endpoint);
+
+        let mut client = app();
+        let mut endpoint = EndPoint::client(localhost).unwrap();
+        endpoint.set_default_client_config(client_config());
+        let client_endpoint = client.world_mut().spawn(endpoint).id();
+        let connection = client
+            .world_mut()
+            .commands()
+            .entity(client_endpoint)
+            .connect(server_addr, "localhost");
+
+        update_until([&mut server, &mut client], |[server, client]| {
+            server.world().resource::<Log>().connected.len() == 1
+                && client.world().resource::<Log>().connected.len() == 1
+        });
+
+        let log = client.world().resource::<Log>();
+        assert_eq!(log.attempts[0].connection, connection);
+        assert_eq!(log.attempts[0].direction, ConnectionDirection::Outgoing);
+        assert_eq!(log.connected[0].endpoint, client_endpoint);
+        assert_eq!(
+            client.world().get::<ConnectionState>(connection),
+            Some(&ConnectionState::Connected)
+        );
+
+        let log = server.world().resource::<Log>();
+        assert_eq!(log.attempts[0].direction, ConnectionDirection::Incoming);
+        let server_connection = log.connected[0].connection;
+        assert!(server
+            .world()
+            .get::<QuicConnection>(server_connection)
+            .is_some());
+
+        client
+            .world_mut()
+            .commands()
+            .entity(connection)
+            .disconnect(VarInt::from_u32(7), "bye");
+
+        update_until([&mut server, &mut client], |[server, client]| {
+            server.world().resource::<Log>().disconnected.len() == 1
+                && client.world().resource::<Log>().disconnected.len() == 1
+        });
+
+        for app in [&server, &client] {
+            let disconnected = &app.world().resource::<Log>().disconnected[0];
+            assert_eq!(disconnected.error_code, Some(VarInt::from_u32(7)));
+            assert_eq!(disconnected.reason, Bytes::from("bye"));
+            assert!(app.world().get_entity(disconnected.connection).is_none());
+        }
+    }
+}
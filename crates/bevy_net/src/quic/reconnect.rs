@@ -0,0 +1,440 @@
+//! Automatic reconnection of clients whose connection to their server drops.
+//!
+//! Add a [`Reconnect`] policy to a client [`EndPoint`] entity, then connect from it as usual.
+//! Once connected, the endpoint keeps track of the server it is connected to. If that connection
+//! is lost, rather than closed on purpose by either side, the endpoint reconnects to the same
+//! server with exponential backoff, triggering a [`ReconnectAttempt`] for every attempt, then
+//! either [`Reconnected`] or, once it runs out of attempts, [`ReconnectFailed`]. The endpoint has
+//! a [`Reconnecting`] component in the meantime.
+//!
+//! Reconnections present the endpoint's [`ConnectToken`](super::ConnectToken), if any, so the
+//! server identifies the client with the same [`ClientId`](super::ClientId) again, and the
+//! entities replicated to the client are kept and resynchronized rather than despawned and
+//! spawned again.
+//!
+//! Endpoints also follow changes of the local address they reach their server from, such as a
+//! phone switching from Wi-Fi to cellular, by [rebinding](EndPoint::rebind) to a new socket. The
+//! QUIC connection then migrates to the new address, which often avoids losing it at all.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use bevy_app::{App, Plugin, PreUpdate};
+use bevy_ecs::prelude::*;
+use bevy_utils::tracing::{info, warn};
+
+use super::{
+    notify, Connected, ConnectionDirection, ConnectionError, ConnectionFailed, ConnectionTarget,
+    Disconnected, EndPoint, NetworkSystem, QuicEntityCommands,
+};
+
+/// How often the local address endpoints reach their server from is checked.
+const ADDRESS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Adds automatic reconnection to the [`EndPoint`]s with a [`Reconnect`] policy.
+///
+/// Requires the [`QuicNetworkPlugin`](super::QuicNetworkPlugin).
+#[derive(Default)]
+pub struct ReconnectPlugin;
+
+impl Plugin for ReconnectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ReconnectAttempt>()
+            .add_event::<Reconnected>()
+            .add_event::<ReconnectFailed>()
+            .observe(track_connection)
+            .observe(start_reconnecting)
+            .observe(retry)
+            .add_systems(
+                PreUpdate,
+                (follow_local_address, reconnect)
+                    .chain()
+                    .after(NetworkSystem::Connections)
+                    .before(NetworkSystem::Receive),
+            );
+    }
+}
+
+/// How a client [`EndPoint`] reconnects to its server, on the endpoint's entity.
+///
+/// The delay before attempt `n`, starting at zero, is `initial_delay * multiplier^n`, capped at
+/// `max_delay`, and shortened by a random fraction of up to `jitter` so that clients dropped at
+/// the same time do not all reconnect at once.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct Reconnect {
+    /// The delay before the first attempt.
+    pub initial_delay: Duration,
+    /// The longest delay between attempts.
+    pub max_delay: Duration,
+    /// How much the delay grows after every failed attempt.
+    pub multiplier: f32,
+    /// The fraction of every delay that is randomized, between `0.0` and `1.0`.
+    pub jitter: f32,
+    /// How many attempts are made before giving up, or `None` to never give up.
+    pub max_attempts: Option<u32>,
+    /// Whether to rebind the endpoint when the local address it reaches the server from changes.
+    ///
+    /// Disable it for endpoints that are not backed by an OS socket, as rebinding replaces their
+    /// socket with one.
+    pub rebind_on_address_change: bool,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_attempts: Some(10),
+            rebind_on_address_change: true,
+        }
+    }
+}
+
+impl Reconnect {
+    /// Picks the delay before the attempt `attempt`, starting at zero.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.min(i32::MAX as u32) as i32;
+        let delay = self
+            .initial_delay
+            .mul_f64(
+                f64::from(self.multiplier)
+                    .powi(exponent)
+                    .min(u32::MAX as f64),
+            )
+            .min(self.max_delay);
+        delay.mul_f64(1.0 - f64::from(self.jitter.clamp(0.0, 1.0)) * fastrand::f64())
+    }
+
+    /// Whether a connection closed with `error` should be reconnected, as it was not closed on
+    /// purpose.
+    pub fn should_reconnect(error: &ConnectionError) -> bool {
+        !matches!(
+            error,
+            ConnectionError::ApplicationClosed(_)
+                | ConnectionError::LocallyClosed
+                | ConnectionError::VersionMismatch
+        )
+    }
+}
+
+/// Marks an [`EndPoint`] entity that lost its connection and is reconnecting.
+#[derive(Component, Debug, Clone)]
+pub struct Reconnecting {
+    attempts: u32,
+    next_attempt: Instant,
+    /// The connection entity of the attempt in progress.
+    connection: Option<Entity>,
+}
+
+impl Reconnecting {
+    /// Gets how many attempts were started so far.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+}
+
+/// Triggered on a client [`EndPoint`] entity when it starts an attempt to reconnect.
+#[derive(Event, Debug, Clone)]
+pub struct ReconnectAttempt {
+    /// The endpoint entity.
+    pub endpoint: Entity,
+    /// The connection entity of the attempt.
+    pub connection: Entity,
+    /// The number of the attempt, starting at one.
+    pub attempt: u32,
+}
+
+/// Triggered on a client [`EndPoint`] entity once it has reconnected.
+#[derive(Event, Debug, Clone)]
+pub struct Reconnected {
+    /// The endpoint entity.
+    pub endpoint: Entity,
+    /// The new connection entity.
+    pub connection: Entity,
+    /// How many attempts it took.
+    pub attempts: u32,
+}
+
+/// Triggered on a client [`EndPoint`] entity when it gives up reconnecting.
+#[derive(Event, Debug, Clone)]
+pub struct ReconnectFailed {
+    /// The endpoint entity.
+    pub endpoint: Entity,
+    /// How many attempts were made.
+    pub attempts: u32,
+}
+
+/// The server a client endpoint is connected or reconnecting to.
+#[derive(Component)]
+struct ReconnectTarget {
+    target: ConnectionTarget,
+    connection: Entity,
+    /// The local IP the server was last reached from.
+    local_ip: Option<IpAddr>,
+    last_check: Instant,
+}
+
+fn track_connection(
+    trigger: Trigger<Connected>,
+    connections: Query<(&ConnectionDirection, &ConnectionTarget)>,
+    mut endpoints: Query<Option<&Reconnecting>, With<Reconnect>>,
+    mut reconnected: EventWriter<Reconnected>,
+    mut commands: Commands,
+) {
+    let Connected {
+        connection,
+        endpoint,
+    } = *trigger.event();
+    let Ok((ConnectionDirection::Outgoing, target)) = connections.get(connection) else {
+        return;
+    };
+    let Ok(reconnecting) = endpoints.get_mut(endpoint) else {
+        return;
+    };
+
+    commands.entity(endpoint).insert(ReconnectTarget {
+        target: target.clone(),
+        connection,
+        local_ip: local_ip_towards(target.addr),
+        last_check: Instant::now(),
+    });
+
+    if let Some(reconnecting) = reconnecting {
+        let event = Reconnected {
+            endpoint,
+            connection,
+            attempts: reconnecting.attempts,
+        };
+        info!(
+            "reconnected to {} after {} attempts",
+            target.addr, event.attempts
+        );
+        commands.entity(endpoint).remove::<Reconnecting>();
+        commands.trigger_targets(event.clone(), endpoint);
+        reconnected.send(event);
+    }
+}
+
+fn start_reconnecting(
+    trigger: Trigger<Disconnected>,
+    endpoints: Query<(&Reconnect, &ReconnectTarget)>,
+    mut commands: Commands,
+) {
+    let event = trigger.event();
+    let Ok((policy, target)) = endpoints.get(event.endpoint) else {
+        return;
+    };
+    if target.connection != event.connection || !Reconnect::should_reconnect(&event.error) {
+        return;
+    }
+
+    warn!(
+        "lost the connection to {}: {}",
+        target.target.addr, event.error
+    );
+    commands.entity(event.endpoint).insert(Reconnecting {
+        attempts: 0,
+        next_attempt: Instant::now() + policy.delay(0),
+        connection: None,
+    });
+}
+
+fn retry(
+    trigger: Trigger<ConnectionFailed>,
+    mut endpoints: Query<(&Reconnect, &mut Reconnecting)>,
+    mut failed: EventWriter<ReconnectFailed>,
+    mut commands: Commands,
+) {
+    let event = trigger.event();
+    let Ok((policy, mut reconnecting)) = endpoints.get_mut(event.endpoint) else {
+        return;
+    };
+    if reconnecting.connection != Some(event.connection) {
+        return;
+    }
+
+    reconnecting.connection = None;
+    if policy
+        .max_attempts
+        .is_some_and(|max| reconnecting.attempts >= max)
+    {
+        let event = ReconnectFailed {
+            endpoint: event.endpoint,
+            attempts: reconnecting.attempts,
+        };
+        commands
+            .entity(event.endpoint)
+            .remove::<(Reconnecting, ReconnectTarget)>();
+        commands.trigger_targets(event.clone(), event.endpoint);
+        failed.send(event);
+    } else {
+        reconnecting.next_attempt = Instant::now() + policy.delay(reconnecting.attempts);
+    }
+}
+
+fn reconnect(world: &mut World) {
+    let now = Instant::now();
+    let mut due = world.query::<(Entity, &Reconnecting, &ReconnectTarget)>();
+    let due: Vec<_> = due
+        .iter(world)
+        .filter(|(_, reconnecting, _)| {
+            reconnecting.connection.is_none() && reconnecting.next_attempt <= now
+        })
+        .map(|(endpoint, _, target)| (endpoint, target.target.clone()))
+        .collect();
+
+    for (endpoint, target) in due {
+        let mut commands = world.commands();
+        let mut entity = commands.entity(endpoint);
+        let connection = match target.config {
+            Some(config) => entity.connect_with(config, target.addr, target.server_name),
+            None => entity.connect(target.addr, target.server_name),
+        };
+
+        let mut reconnecting = world.get_mut::<Reconnecting>(endpoint).unwrap();
+        reconnecting.attempts += 1;
+        reconnecting.connection = Some(connection);
+        let attempt = reconnecting.attempts;
+        notify(
+            world,
+            endpoint,
+            ReconnectAttempt {
+                endpoint,
+                connection,
+                attempt,
+            },
+        );
+        world.flush();
+    }
+}
+
+fn follow_local_address(mut endpoints: Query<(&EndPoint, &Reconnect, &mut ReconnectTarget)>) {
+    let now = Instant::now();
+    for (endpoint, policy, mut target) in &mut endpoints {
+        if !policy.rebind_on_address_change || now - target.last_check < ADDRESS_CHECK_INTERVAL {
+            continue;
+        }
+        target.last_check = now;
+
+        let local_ip = local_ip_towards(target.target.addr);
+        if local_ip.is_none() || local_ip == target.local_ip {
+            continue;
+        }
+        if let Some(previous) = target.local_ip {
+            if let Err(error) = rebind(endpoint, local_ip.unwrap()) {
+                warn!("failed to rebind after the local address changed from {previous}: {error}");
+                continue;
+            }
+        }
+        target.local_ip = local_ip;
+    }
+}
+
+/// Gets the local IP the OS would send packets to `addr` from.
+fn local_ip_towards(addr: SocketAddr) -> Option<IpAddr> {
+    let unspecified: IpAddr = match addr {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    // Connecting a UDP socket only picks a route, nothing is sent.
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0)).ok()?;
+    socket.connect(addr).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+/// Moves an endpoint to a new socket on `local_ip`, or on any address if it was bound to any.
+fn rebind(endpoint: &EndPoint, local_ip: IpAddr) -> io::Result<()> {
+    let current = endpoint.local_addr()?;
+    let ip = if current.ip().is_unspecified() {
+        current.ip()
+    } else {
+        local_ip
+    };
+    endpoint.rebind(UdpSocket::bind(SocketAddr::new(ip, 0))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_exponentially() {
+        let policy = Reconnect {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(400));
+        assert_eq!(policy.delay(4), Duration::from_secs(1));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(1));
+
+        let policy = Reconnect {
+            jitter: 0.5,
+            ..policy
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+            assert!(delay > Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod quic_tests {
+    use super::*;
+    use crate::quic::test_utils::{reconnecting_apps, update_until, ConnectedEntities};
+    use crate::quic::LinkProfile;
+
+    #[derive(Resource, Default)]
+    struct Reconnections(Vec<ReconnectAttempt>, Vec<Reconnected>);
+
+    #[test]
+    fn reconnects_after_losing_the_connection() {
+        let (mut server, mut client, link, endpoint) = reconnecting_apps(|app| {
+            app.init_resource::<Reconnections>()
+                .observe(
+                    |trigger: Trigger<ReconnectAttempt>,
+                     mut reconnections: ResMut<Reconnections>| {
+                        reconnections.0.push(trigger.event().clone());
+                    },
+                )
+                .observe(
+                    |trigger: Trigger<Reconnected>, mut reconnections: ResMut<Reconnections>| {
+                        reconnections.1.push(trigger.event().clone());
+                    },
+                );
+        });
+
+        // Cut the link until the connection times out and a first attempt fails.
+        link.set_profile(LinkProfile {
+            loss: 1.0,
+            ..LinkProfile::PERFECT
+        });
+        update_until([&mut server, &mut client], |[_, client]| {
+            client
+                .world()
+                .get::<Reconnecting>(endpoint)
+                .is_some_and(|reconnecting| reconnecting.attempts() >= 2)
+        });
+        assert!(client.world().resource::<Reconnections>().1.is_empty());
+
+        link.set_profile(LinkProfile::PERFECT);
+        update_until([&mut server, &mut client], |[_, client]| {
+            !client.world().resource::<Reconnections>().1.is_empty()
+        });
+
+        let world = client.world();
+        let reconnections = world.resource::<Reconnections>();
+        let connected = &world.resource::<ConnectedEntities>().0;
+        assert_eq!(connected.len(), 2);
+        assert_eq!(reconnections.1[0].connection, connected[1]);
+        assert_eq!(reconnections.1[0].attempts as usize, reconnections.0.len());
+        assert_eq!(reconnections.0.last().unwrap().connection, connected[1]);
+        assert!(world.get::<Reconnecting>(endpoint).is_none());
+    }
+}
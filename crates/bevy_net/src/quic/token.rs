@@ -0,0 +1,629 @@
+//! Connect tokens, authorizing clients to connect to a server.
+//!
+//! A trusted backend, such as a matchmaking service, shares a secret [`ConnectTokenKey`] with the
+//! game servers and [issues](ConnectTokenKey::issue) signed, expiring tokens to the clients it
+//! authorizes.
+//!
+//! Tokens are checked during the TLS handshake. Clients present theirs as their TLS client
+//! certificate, built with `ConnectToken::client_auth_cert`, and hold the [`ConnectToken`] on
+//! their [`EndPoint`](super::EndPoint) entity. Servers verify it with the `ConnectTokenVerifier`
+//! of their key in their TLS configuration, and hold a [`ConnectTokenAuth`] on their endpoint
+//! entity. A client without a valid token fails the handshake, so the server never spawns a
+//! connection entity for it nor triggers any event.
+//!
+//! In TLS 1.3, clients complete their side of the handshake before the server has checked their
+//! certificate, so servers acknowledge verified tokens by opening a stream and finishing it right
+//! away. Clients stay [`Connecting`](super::ConnectionState::Connecting) until then, and fail the
+//! connection with [`ConnectTokenError::Rejected`] if the server refuses their token.
+//!
+//! Building and verifying token certificates requires the `tls` feature.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_io::Timer;
+use bevy_ecs::prelude::*;
+use futures_lite::future;
+use ring::hmac;
+use thiserror::Error;
+#[cfg(feature = "tls")]
+use {
+    bevy_utils::tracing::debug,
+    rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, UnixTime},
+    rustls::server::danger::{ClientCertVerified, ClientCertVerifier},
+    rustls::DistinguishedName,
+    std::sync::Arc,
+};
+
+use super::{
+    Connecting, Connection, ConnectionDirection, ConnectionError, QuicError, TransportErrorCode,
+    VarInt,
+};
+use crate::varint::{read_varint, write_varint};
+
+/// The error code servers close connections with when a client's certificate carries no valid
+/// token, because their TLS configuration does not verify tokens.
+pub const CONNECT_TOKEN_REJECTED: VarInt = VarInt::from_u32(0x434f_4e4e);
+
+/// The most bytes of user data a connect token can carry.
+pub const MAX_USER_DATA_LEN: usize = 256;
+
+const TOKEN_VERSION: u8 = 1;
+
+/// The length of the HMAC-SHA256 tag ending every token.
+const TAG_LEN: usize = 32;
+
+/// The size of the largest valid token.
+const MAX_TOKEN_LEN: usize = 1 + 8 + 8 + 2 + MAX_USER_DATA_LEN + TAG_LEN;
+
+/// The scheme of the URI subject alternative name carrying a token in a client certificate,
+/// followed by the token in hexadecimal.
+#[cfg(feature = "tls")]
+const TOKEN_URI_SCHEME: &str = "connect-token:";
+
+/// The TLS `access_denied` alert, sent by servers whose verifier rejects a token.
+const ACCESS_DENIED_ALERT: u8 = 49;
+
+/// How long clients wait for the server to acknowledge their token once the handshake completes.
+const ACKNOWLEDGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The secret shared by the backend issuing connect tokens and the servers verifying them.
+#[derive(Debug, Clone)]
+pub struct ConnectTokenKey(hmac::Key);
+
+impl ConnectTokenKey {
+    /// Creates a key from a secret, which should be at least 32 random bytes.
+    pub fn new(secret: &[u8]) -> Self {
+        Self(hmac::Key::new(hmac::HMAC_SHA256, secret))
+    }
+
+    /// Issues a token for `client_id`, carrying `user_data` and valid for `valid_for` from now.
+    ///
+    /// The user data is readable by the client holding the token, but cannot be modified.
+    pub fn issue(
+        &self,
+        client_id: u64,
+        user_data: &[u8],
+        valid_for: Duration,
+    ) -> Result<ConnectToken, ConnectTokenError> {
+        if user_data.len() > MAX_USER_DATA_LEN {
+            return Err(ConnectTokenError::UserDataTooLong);
+        }
+        let expires_at = unix_time().saturating_add(valid_for.as_secs());
+
+        let mut bytes = Vec::with_capacity(MAX_TOKEN_LEN);
+        bytes.push(TOKEN_VERSION);
+        bytes.extend_from_slice(&client_id.to_le_bytes());
+        bytes.extend_from_slice(&expires_at.to_le_bytes());
+        write_varint(&mut bytes, user_data.len() as u64);
+        bytes.extend_from_slice(user_data);
+        let tag = hmac::sign(&self.0, &bytes);
+        bytes.extend_from_slice(tag.as_ref());
+        Ok(ConnectToken(bytes))
+    }
+
+    /// Checks that a token was issued with this key and has not expired.
+    pub fn verify(&self, token: &[u8]) -> Result<VerifiedConnectToken, ConnectTokenError> {
+        let (verified, expires_at) = self.open(token)?;
+        if unix_time() >= expires_at {
+            return Err(ConnectTokenError::Expired);
+        }
+        Ok(verified)
+    }
+
+    /// Creates the verifier to build the TLS configuration of servers accepting tokens issued with
+    /// this key with, through `rustls::ServerConfig::builder().with_client_cert_verifier`.
+    #[cfg(feature = "tls")]
+    pub fn client_cert_verifier(&self) -> Arc<ConnectTokenVerifier> {
+        Arc::new(ConnectTokenVerifier {
+            key: self.clone(),
+            provider: Arc::new(rustls::crypto::ring::default_provider()),
+        })
+    }
+
+    /// Checks that a token was issued with this key, returning its contents and expiry.
+    fn open(&self, token: &[u8]) -> Result<(VerifiedConnectToken, u64), ConnectTokenError> {
+        if token.len() < TAG_LEN || token.len() > MAX_TOKEN_LEN {
+            return Err(ConnectTokenError::Malformed);
+        }
+        let (signed, tag) = token.split_at(token.len() - TAG_LEN);
+        hmac::verify(&self.0, signed, tag).map_err(|_| ConnectTokenError::InvalidSignature)?;
+
+        let (&version, mut rest) = signed.split_first().ok_or(ConnectTokenError::Malformed)?;
+        if version != TOKEN_VERSION {
+            return Err(ConnectTokenError::Malformed);
+        }
+        let client_id = read_u64(&mut rest)?;
+        let expires_at = read_u64(&mut rest)?;
+        let len = read_varint(&mut rest).ok_or(ConnectTokenError::Malformed)?;
+        if len != rest.len() as u64 {
+            return Err(ConnectTokenError::Malformed);
+        }
+
+        let verified = VerifiedConnectToken {
+            client_id: ClientId(client_id),
+            user_data: ConnectUserData(rest.to_vec()),
+        };
+        Ok((verified, expires_at))
+    }
+}
+
+fn read_u64(bytes: &mut &[u8]) -> Result<u64, ConnectTokenError> {
+    let (value, rest) = bytes
+        .split_first_chunk::<8>()
+        .ok_or(ConnectTokenError::Malformed)?;
+    *bytes = rest;
+    Ok(u64::from_le_bytes(*value))
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// A signed token, presented by the connections of the client [`EndPoint`](super::EndPoint)
+/// entity it is on, which then wait for the server to acknowledge it.
+///
+/// Tokens are opaque to clients, which get them from the backend that issued them.
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct ConnectToken(Vec<u8>);
+
+impl ConnectToken {
+    /// Wraps the bytes of a token received from the backend.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    /// Gets the bytes of the token, to be handed to a client.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Generates a self-signed client certificate carrying the token, along with its private key,
+    /// to build the client's TLS configuration with through
+    /// `rustls::ClientConfig::builder().with_client_auth_cert`.
+    #[cfg(feature = "tls")]
+    pub fn client_auth_cert(
+        &self,
+    ) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), rcgen::Error> {
+        let uri = format!("{TOKEN_URI_SCHEME}{}", encode_hex(&self.0));
+        let mut params = rcgen::CertificateParams::default();
+        params.subject_alt_names = vec![rcgen::SanType::URI(uri.try_into()?)];
+        let key_pair = rcgen::KeyPair::generate()?;
+        let cert = params.self_signed(&key_pair)?;
+        let key = PrivatePkcs8KeyDer::from(key_pair.serialize_der());
+        Ok((vec![cert.der().clone()], PrivateKeyDer::Pkcs8(key)))
+    }
+}
+
+#[cfg(feature = "tls")]
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(feature = "tls")]
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let pairs = hex.as_bytes().chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+    pairs
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Extracts the token a client certificate carries, if any.
+#[cfg(feature = "tls")]
+fn token_from_cert(cert: &CertificateDer<'_>) -> Option<Vec<u8>> {
+    let cert = webpki::EndEntityCert::try_from(cert).ok()?;
+    let hex = cert
+        .valid_uri_names()
+        .find_map(|uri| uri.strip_prefix(TOKEN_URI_SCHEME))?;
+    decode_hex(hex)
+}
+
+/// Requires incoming connections of the server [`EndPoint`](super::EndPoint) entity it is on to
+/// present a token issued with this key.
+///
+/// The endpoint's TLS configuration must verify tokens with the key's
+/// `ConnectTokenKey::client_cert_verifier`. Connections are then only spawned once their
+/// handshake completed, with the [`ClientId`] and [`ConnectUserData`] of their token.
+#[derive(Component, Debug, Clone)]
+pub struct ConnectTokenAuth(pub ConnectTokenKey);
+
+/// Client certificate verifier that only accepts certificates carrying a valid, unexpired token
+/// issued with its key, such as those built with [`ConnectToken::client_auth_cert`].
+///
+/// The certificates are self-signed, so no certificate authority is checked. Handshake signatures
+/// are still verified, so a client must own the private key of the certificate it presents.
+#[cfg(feature = "tls")]
+#[derive(Debug)]
+pub struct ConnectTokenVerifier {
+    key: ConnectTokenKey,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+}
+
+#[cfg(feature = "tls")]
+impl ClientCertVerifier for ConnectTokenVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        let verified = token_from_cert(end_entity)
+            .ok_or(ConnectTokenError::Missing)
+            .and_then(|token| self.key.verify(&token));
+        match verified {
+            Ok(_) => Ok(ClientCertVerified::assertion()),
+            Err(error) => {
+                debug!("rejecting a client certificate: {error}");
+                Err(rustls::Error::InvalidCertificate(
+                    rustls::CertificateError::ApplicationVerificationFailure,
+                ))
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// The client ID of a verified token, on the server's connection entity for the client.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ClientId(pub u64);
+
+impl ClientId {
+    /// Gets the client ID.
+    pub fn get(&self) -> u64 {
+        self.0
+    }
+}
+
+/// The user data of a verified token, on the server's connection entity for the client.
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct ConnectUserData(pub Vec<u8>);
+
+/// The contents of a token that passed verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedConnectToken {
+    /// The client the token was issued to.
+    pub client_id: ClientId,
+    /// The data the backend attached to the token.
+    pub user_data: ConnectUserData,
+}
+
+/// Why a connect token was not issued or accepted.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConnectTokenError {
+    /// The token is not one issued by [`ConnectTokenKey::issue`].
+    #[error("malformed connect token")]
+    Malformed,
+    /// The token was not issued with the server's key, or was tampered with.
+    #[error("connect token signature is invalid")]
+    InvalidSignature,
+    /// The token is past its expiry.
+    #[error("connect token expired")]
+    Expired,
+    /// The client did not present a token.
+    #[error("no connect token presented")]
+    Missing,
+    /// The server rejected the client's token, or did not acknowledge it in time.
+    #[error("connect token rejected by the server")]
+    Rejected,
+    /// The user data to issue a token with is longer than [`MAX_USER_DATA_LEN`].
+    #[error("connect token user data is longer than {MAX_USER_DATA_LEN} bytes")]
+    UserDataTooLong,
+}
+
+/// Whether an outgoing connection waits for the server to acknowledge its token once the
+/// handshake completes.
+pub(super) enum TokenHandshake {
+    None,
+    AwaitAcknowledgement,
+}
+
+impl TokenHandshake {
+    pub(super) fn new(world: &World, endpoint: Entity, direction: ConnectionDirection) -> Self {
+        if direction == ConnectionDirection::Outgoing
+            && world.get::<ConnectToken>(endpoint).is_some()
+        {
+            Self::AwaitAcknowledgement
+        } else {
+            Self::None
+        }
+    }
+
+    /// Completes the handshake, then waits for the acknowledgement if needed.
+    pub(super) async fn run(self, connecting: Connecting) -> Result<Connection, QuicError> {
+        let connection = match connecting.await {
+            Ok(connection) => connection,
+            Err(error) => return Err(self.connection_error(error)),
+        };
+        if let Self::None = self {
+            return Ok(connection);
+        }
+
+        let acknowledged = future::or(async { Some(connection.accept_uni().await) }, async {
+            Timer::after(ACKNOWLEDGE_TIMEOUT).await;
+            None
+        })
+        .await;
+        match acknowledged {
+            Some(Ok(_)) => Ok(connection),
+            Some(Err(error)) => Err(self.connection_error(error)),
+            None => {
+                connection.close(CONNECT_TOKEN_REJECTED, b"connect token not acknowledged");
+                Err(ConnectTokenError::Rejected.into())
+            }
+        }
+    }
+
+    /// Reports the server refusing the token as a rejection.
+    fn connection_error(&self, error: ConnectionError) -> QuicError {
+        let rejected = match (self, &error) {
+            (Self::None, _) => false,
+            (_, ConnectionError::ConnectionClosed(close)) => {
+                close.error_code == TransportErrorCode::crypto(ACCESS_DENIED_ALERT)
+            }
+            (_, ConnectionError::ApplicationClosed(close)) => {
+                close.error_code == CONNECT_TOKEN_REJECTED
+            }
+            _ => false,
+        };
+        if rejected {
+            ConnectTokenError::Rejected.into()
+        } else {
+            error.into()
+        }
+    }
+}
+
+/// Completes the handshake of an incoming connection to an endpoint with a [`ConnectTokenAuth`],
+/// and acknowledges the token the client presented.
+///
+/// The endpoint's TLS configuration already verified the token, it is only checked again in case
+/// the configuration lacks a [`ConnectTokenVerifier`].
+pub(super) async fn authenticate(
+    connecting: Connecting,
+    key: ConnectTokenKey,
+) -> Result<(Connection, VerifiedConnectToken), QuicError> {
+    let connection = connecting.await?;
+    // The expiry was checked during the handshake, which may have been right before it passed.
+    let verified = presented_token(&connection)
+        .ok_or(ConnectTokenError::Missing)
+        .and_then(|token| key.open(&token));
+    match verified {
+        Ok((verified, _)) => {
+            let mut stream = connection.open_uni().await?;
+            // Failing to finish means the connection was lost, which `closed` reports.
+            let _ = stream.finish();
+            Ok((connection, verified))
+        }
+        Err(error) => {
+            connection.close(CONNECT_TOKEN_REJECTED, error.to_string().as_bytes());
+            Err(error.into())
+        }
+    }
+}
+
+/// Extracts the token carried by the certificate a client presented during the handshake.
+fn presented_token(connection: &Connection) -> Option<Vec<u8>> {
+    #[cfg(feature = "tls")]
+    {
+        let certs = connection
+            .peer_identity()?
+            .downcast::<Vec<CertificateDer<'static>>>()
+            .ok()?;
+        token_from_cert(certs.first()?)
+    }
+    #[cfg(not(feature = "tls"))]
+    {
+        let _ = connection;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_issued_tokens() {
+        let key = ConnectTokenKey::new(b"a secret shared with the backend");
+        let token = key
+            .issue(42, b"blue team", Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(
+            key.verify(token.as_bytes()),
+            Ok(VerifiedConnectToken {
+                client_id: ClientId(42),
+                user_data: ConnectUserData(b"blue team".to_vec()),
+            })
+        );
+
+        let other = ConnectTokenKey::new(b"some other secret");
+        assert_eq!(
+            other.verify(token.as_bytes()),
+            Err(ConnectTokenError::InvalidSignature)
+        );
+
+        let mut tampered = token.as_bytes().to_vec();
+        tampered[1] ^= 1;
+        assert_eq!(
+            key.verify(&tampered),
+            Err(ConnectTokenError::InvalidSignature)
+        );
+        assert_eq!(key.verify(&[1, 2, 3]), Err(ConnectTokenError::Malformed));
+
+        let expired = key.issue(42, &[], Duration::ZERO).unwrap();
+        assert_eq!(
+            key.verify(expired.as_bytes()),
+            Err(ConnectTokenError::Expired)
+        );
+        assert_eq!(
+            key.issue(42, &[0; MAX_USER_DATA_LEN + 1], Duration::ZERO),
+            Err(ConnectTokenError::UserDataTooLong)
+        );
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod quic_tests {
+    use super::*;
+    use crate::crypto_utils::{SelfSignedCert, SkipServerVerification};
+    use crate::quic::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+    use crate::quic::test_utils::{connected_apps, update_until, ConnectedEntities};
+    use crate::quic::{
+        ClientConfig, ConnectionAttempt, ConnectionFailed, EndPoint, QuicEntityCommands,
+        ServerConfig,
+    };
+    use bevy_app::App;
+
+    #[derive(Resource, Default)]
+    struct Attempts(usize);
+
+    #[derive(Resource, Default)]
+    struct Failures(Vec<ConnectionFailed>);
+
+    /// A server configuration verifying tokens issued with `key`.
+    fn server_config(key: &ConnectTokenKey) -> ServerConfig {
+        let cert = SelfSignedCert::generate(["localhost".to_string()]).unwrap();
+        let crypto = rustls::ServerConfig::builder()
+            .with_client_cert_verifier(key.client_cert_verifier())
+            .with_single_cert(vec![cert.cert.clone()], cert.key_der())
+            .unwrap();
+        ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto).unwrap()))
+    }
+
+    /// A client configuration presenting `token`.
+    fn client_config(token: &ConnectToken) -> ClientConfig {
+        let (chain, key) = token.client_auth_cert().unwrap();
+        let crypto = rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(SkipServerVerification::new())
+            .with_client_auth_cert(chain, key)
+            .unwrap();
+        ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto).unwrap()))
+    }
+
+    #[test]
+    fn authenticates_clients_with_connect_tokens() {
+        let key = ConnectTokenKey::new(b"a secret shared with the backend");
+        let token = key.issue(7, b"red team", Duration::from_secs(60)).unwrap();
+        let (mut server, mut client, server_connection, _) = connected_apps(|app: &mut App| {
+            let (key, token) = (key.clone(), token.clone());
+            app.init_resource::<Attempts>()
+                .init_resource::<Failures>()
+                .observe(
+                    |_: Trigger<ConnectionAttempt>, mut attempts: ResMut<Attempts>| {
+                        attempts.0 += 1;
+                    },
+                )
+                .observe(
+                    |trigger: Trigger<ConnectionFailed>, mut failures: ResMut<Failures>| {
+                        failures.0.push(trigger.event().clone());
+                    },
+                )
+                .observe(
+                    move |trigger: Trigger<OnAdd, EndPoint>,
+                          mut endpoints: Query<&mut EndPoint>,
+                          mut commands: Commands| {
+                        let mut endpoint = endpoints.get_mut(trigger.entity()).unwrap();
+                        endpoint.set_server_config(Some(server_config(&key)));
+                        endpoint.set_default_client_config(client_config(&token));
+                        commands
+                            .entity(trigger.entity())
+                            .insert((token.clone(), ConnectTokenAuth(key.clone())));
+                    },
+                );
+        });
+
+        let server_world = server.world();
+        assert_eq!(
+            server_world.get::<ClientId>(server_connection),
+            Some(&ClientId(7))
+        );
+        assert_eq!(
+            server_world.get::<ConnectUserData>(server_connection),
+            Some(&ConnectUserData(b"red team".to_vec()))
+        );
+
+        // A token issued by someone else is rejected during the handshake.
+        let forged = ConnectTokenKey::new(b"a guess")
+            .issue(8, &[], Duration::from_secs(60))
+            .unwrap();
+        let client_endpoint = client
+            .world_mut()
+            .query_filtered::<Entity, With<EndPoint>>()
+            .single(client.world());
+        let server_addr = server
+            .world_mut()
+            .query::<&EndPoint>()
+            .single(server.world())
+            .local_addr()
+            .unwrap();
+        let connected = client.world().resource::<ConnectedEntities>().0.len();
+        let connection = client
+            .world_mut()
+            .commands()
+            .entity(client_endpoint)
+            .connect_with(client_config(&forged), server_addr, "localhost");
+
+        update_until([&mut server, &mut client], |[_, client]| {
+            !client.world().resource::<Failures>().0.is_empty()
+        });
+        let failure = &client.world().resource::<Failures>().0[0];
+        assert_eq!(failure.connection, connection);
+        assert_eq!(failure.error, QuicError::Token(ConnectTokenError::Rejected));
+        assert_eq!(
+            client.world().resource::<ConnectedEntities>().0.len(),
+            connected
+        );
+
+        // The server never spawned a connection for the client, nor reported one.
+        for _ in 0..10 {
+            server.update();
+        }
+        let server_world = server.world();
+        assert_eq!(server_world.resource::<Attempts>().0, 1);
+        assert!(server_world.resource::<Failures>().0.is_empty());
+        assert_eq!(server_world.resource::<ConnectedEntities>().0.len(), 1);
+    }
+}
@@ -0,0 +1,354 @@
+use bevy_ecs::entity::{Entities, EntityHashMap, EntityHashSet, EntityMapper, MapEntities};
+use bevy_ecs::prelude::*;
+use bevy_ecs::reflect::AppTypeRegistry;
+use bevy_ecs::system::SystemState;
+use bevy_reflect::{FromReflect, Reflect};
+use bevy_utils::tracing::warn;
+use bytes::Bytes;
+
+use super::{
+    ConnectionDirection, ReplicationError, ReplicationMessage, ReplicationRegistry, DESPAWN,
+    SYNCED, UPDATE,
+};
+use crate::channel::MessageReader;
+use crate::quic::{Disconnected, Reconnect, ReconnectFailed};
+use crate::tick::NetworkTick;
+use crate::varint::read_varint;
+use crate::wire::{NetworkDeserializer, NetworkTypeId, NetworkTypeIds};
+
+/// Marks an entity replicated from the server, holding its counterpart on the server.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerEntity(Entity);
+
+impl ServerEntity {
+    /// Gets the entity on the server this entity is a replica of.
+    pub fn get(&self) -> Entity {
+        self.0
+    }
+}
+
+/// The server's [`NetworkTick`] as of the latest replication message applied on this client.
+///
+/// Every replicated entity holds the state it had on the server at this tick.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ConfirmedTick(pub(super) Option<NetworkTick>);
+
+impl ConfirmedTick {
+    /// Gets the confirmed tick, if any replication message was received.
+    pub fn get(&self) -> Option<NetworkTick> {
+        self.0
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set(&mut self, tick: NetworkTick) {
+        self.0 = Some(tick);
+    }
+}
+
+/// The server entities whose replicas were kept while reconnecting, and were not sent again yet.
+#[derive(Resource, Default)]
+pub(super) struct StaleReplicas(pub(super) EntityHashSet);
+
+/// Maps the entities replicated from the server to their replicas.
+#[derive(Resource, Debug, Default)]
+pub struct ServerEntities(pub(super) EntityHashMap<Entity>);
+
+impl ServerEntities {
+    /// Gets the replica of a server entity.
+    ///
+    /// The replica may be reserved but not spawned yet, if it was only referred to by other
+    /// replicated entities.
+    pub fn get(&self, server_entity: Entity) -> Option<Entity> {
+        self.0.get(&server_entity).copied()
+    }
+
+    /// Iterates over every server entity and its replica.
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, Entity)> + '_ {
+        self.0
+            .iter()
+            .map(|(server_entity, replica)| (*server_entity, *replica))
+    }
+
+    /// Gets the replica of a server entity, spawning it if needed.
+    pub(super) fn spawn_or_get(&mut self, world: &mut World, server_entity: Entity) -> Entity {
+        world.flush();
+        let replica = match self.get(server_entity) {
+            Some(replica) if world.get_entity(replica).is_some() => replica,
+            _ => {
+                let replica = world.spawn_empty().id();
+                self.0.insert(server_entity, replica);
+                replica
+            }
+        };
+
+        let mut entity = world.entity_mut(replica);
+        if !entity.contains::<ServerEntity>() {
+            entity.insert(ServerEntity(server_entity));
+        }
+        replica
+    }
+}
+
+/// Maps server entities to their replicas, reserving replicas for entities that were not
+/// replicated yet.
+struct ReplicaMapper<'a> {
+    map: &'a mut EntityHashMap<Entity>,
+    entities: &'a Entities,
+}
+
+impl EntityMapper for ReplicaMapper<'_> {
+    fn map_entity(&mut self, entity: Entity) -> Entity {
+        if entity == Entity::PLACEHOLDER {
+            return entity;
+        }
+        *self
+            .map
+            .entry(entity)
+            .or_insert_with(|| self.entities.reserve_entity())
+    }
+
+    fn mappings(&self) -> impl Iterator<Item = (Entity, Entity)> {
+        self.map.iter().map(|(source, target)| (*source, *target))
+    }
+}
+
+pub(super) fn write_component<C: Component + FromReflect>(
+    world: &mut World,
+    entity: Entity,
+    value: &dyn Reflect,
+    _map: &mut EntityHashMap<Entity>,
+) -> Result<(), ReplicationError> {
+    let component =
+        C::from_reflect(value).ok_or(ReplicationError::InvalidValue(std::any::type_name::<C>()))?;
+    world.entity_mut(entity).insert(component);
+    Ok(())
+}
+
+pub(super) fn write_mapped_component<C: Component + FromReflect + MapEntities>(
+    world: &mut World,
+    entity: Entity,
+    value: &dyn Reflect,
+    map: &mut EntityHashMap<Entity>,
+) -> Result<(), ReplicationError> {
+    let mut component =
+        C::from_reflect(value).ok_or(ReplicationError::InvalidValue(std::any::type_name::<C>()))?;
+    component.map_entities(&mut ReplicaMapper {
+        map,
+        entities: world.entities(),
+    });
+    world.flush();
+    world.entity_mut(entity).insert(component);
+    Ok(())
+}
+
+/// Reserves replicas for the server entities held by a mapped component, without writing it.
+pub(super) fn reserve_mapped_entities<C: FromReflect + MapEntities>(
+    value: &dyn Reflect,
+    map: &mut EntityHashMap<Entity>,
+    entities: &Entities,
+) {
+    if let Some(mut component) = C::from_reflect(value) {
+        component.map_entities(&mut ReplicaMapper { map, entities });
+    }
+}
+
+pub(super) fn apply_replication(
+    world: &mut World,
+    messages: &mut SystemState<(
+        MessageReader<ReplicationMessage>,
+        Query<&ConnectionDirection>,
+    )>,
+) {
+    let (reader, directions) = messages.get(world);
+    let mut received = Vec::new();
+    for (connection, message) in reader.read() {
+        if directions.get(connection) == Ok(&ConnectionDirection::Outgoing) {
+            received.push((connection, message.0.clone()));
+        } else {
+            warn!("ignoring replication message from client {connection:?}");
+        }
+    }
+    #[cfg(feature = "scene")]
+    super::snapshot::defer_until_snapshot(world, &mut received);
+
+    apply_messages(
+        world,
+        received.into_iter().map(|(_, message)| message).collect(),
+    );
+}
+
+/// Applies replication messages received from the server, in order.
+pub(super) fn apply_messages(world: &mut World, received: Vec<Bytes>) {
+    if received.is_empty() {
+        return;
+    }
+
+    let type_registry = world.resource::<AppTypeRegistry>().clone();
+    let type_registry = type_registry.read();
+    world.resource_scope(|world, registry: Mut<ReplicationRegistry>| {
+        world.resource_scope(|world, ids: Mut<NetworkTypeIds>| {
+            world.resource_scope(|world, mut entities: Mut<ServerEntities>| {
+                let deserializer = NetworkDeserializer::new(&type_registry, &ids);
+                for message in received {
+                    let mut bytes = &message[..];
+                    let Some(tick) = read_varint(&mut bytes).and_then(|t| u32::try_from(t).ok())
+                    else {
+                        warn!("dropping a replication message without a tick");
+                        continue;
+                    };
+                    world.resource_mut::<ConfirmedTick>().0 = Some(NetworkTick::new(tick));
+
+                    while !bytes.is_empty() {
+                        let result = apply_update(
+                            world,
+                            &registry,
+                            &ids,
+                            &deserializer,
+                            &mut entities,
+                            &mut bytes,
+                        );
+                        if let Err(error) = result {
+                            warn!("dropping the rest of a replication message: {error}");
+                            break;
+                        }
+                    }
+                }
+                world.flush();
+            });
+        });
+    });
+}
+
+/// Applies the entity update at the front of `bytes`, advancing it past the update.
+fn apply_update(
+    world: &mut World,
+    registry: &ReplicationRegistry,
+    ids: &NetworkTypeIds,
+    deserializer: &NetworkDeserializer,
+    entities: &mut ServerEntities,
+    bytes: &mut &[u8],
+) -> Result<(), ReplicationError> {
+    let server_entity = read_varint(bytes)
+        .and_then(|bits| Entity::try_from_bits(bits).ok())
+        .ok_or(ReplicationError::Malformed)?;
+
+    let tag = read_varint(bytes).ok_or(ReplicationError::Malformed)?;
+    let stale = tag != SYNCED
+        && world
+            .resource_mut::<StaleReplicas>()
+            .0
+            .remove(&server_entity);
+    match tag {
+        DESPAWN => {
+            if let Some(replica) = entities.0.remove(&server_entity) {
+                world.flush();
+                world.despawn(replica);
+            }
+        }
+        SYNCED => {
+            let stale = std::mem::take(&mut world.resource_mut::<StaleReplicas>().0);
+            world.flush();
+            for server_entity in stale {
+                if let Some(replica) = entities.0.remove(&server_entity) {
+                    world.despawn(replica);
+                }
+            }
+        }
+        UPDATE => {
+            let replica = entities.spawn_or_get(world, server_entity);
+
+            let removed = read_varint(bytes).ok_or(ReplicationError::Malformed)?;
+            for _ in 0..removed {
+                let id = read_varint(bytes).ok_or(ReplicationError::Malformed)?;
+                let component = u32::try_from(id)
+                    .ok()
+                    .and_then(|id| ids.type_id(NetworkTypeId::from_raw(id)))
+                    .and_then(|type_id| registry.index_of(type_id))
+                    .ok_or_else(|| ReplicationError::NotReplicated(format!("with id {id}")))?;
+                (registry.components[component].remove)(&mut world.entity_mut(replica));
+            }
+
+            let changed = read_varint(bytes).ok_or(ReplicationError::Malformed)?;
+            let mut written = Vec::new();
+            for _ in 0..changed {
+                let value = deserializer.deserialize(bytes)?;
+                let type_info = value.get_represented_type_info();
+                let component = type_info
+                    .and_then(|info| registry.index_of(info.type_id()))
+                    .ok_or_else(|| {
+                        ReplicationError::NotReplicated(value.reflect_type_path().to_string())
+                    })?;
+                written.push(component);
+                let component = &registry.components[component];
+                (component.write)(world, replica, &*value, &mut entities.0)?;
+                for on_write in &component.on_write {
+                    on_write(world, replica);
+                }
+            }
+            // The first update after reconnecting holds every component the entity still has.
+            if stale {
+                remove_unsent_components(world, registry, replica, &written);
+            }
+        }
+        _ => return Err(ReplicationError::Malformed),
+    }
+    Ok(())
+}
+
+/// Removes the replicated components of a replica kept while reconnecting that the server did not
+/// send again, as they were removed in the meantime.
+pub(super) fn remove_unsent_components(
+    world: &mut World,
+    registry: &ReplicationRegistry,
+    replica: Entity,
+    sent: &[usize],
+) {
+    let mut entity = world.entity_mut(replica);
+    for (index, component) in registry.components.iter().enumerate() {
+        if !sent.contains(&index) {
+            (component.remove)(&mut entity);
+        }
+    }
+}
+
+pub(super) fn despawn_replicas(
+    trigger: Trigger<Disconnected>,
+    directions: Query<&ConnectionDirection>,
+    reconnecting: Query<(), With<Reconnect>>,
+    mut entities: ResMut<ServerEntities>,
+    mut stale: ResMut<StaleReplicas>,
+    mut confirmed: ResMut<ConfirmedTick>,
+    mut commands: Commands,
+) {
+    let event = trigger.event();
+    if directions.get(event.connection) != Ok(&ConnectionDirection::Outgoing) {
+        return;
+    }
+    // The replicas are kept until the server has sent every entity again after reconnecting.
+    if reconnecting.contains(event.endpoint) && Reconnect::should_reconnect(&event.error) {
+        stale.0 = entities.0.keys().copied().collect();
+        return;
+    }
+    confirmed.0 = None;
+    despawn_all(&mut entities, &mut stale, &mut commands);
+}
+
+pub(super) fn despawn_stale_replicas(
+    _trigger: Trigger<ReconnectFailed>,
+    mut entities: ResMut<ServerEntities>,
+    mut stale: ResMut<StaleReplicas>,
+    mut confirmed: ResMut<ConfirmedTick>,
+    mut commands: Commands,
+) {
+    confirmed.0 = None;
+    despawn_all(&mut entities, &mut stale, &mut commands);
+}
+
+fn despawn_all(entities: &mut ServerEntities, stale: &mut StaleReplicas, commands: &mut Commands) {
+    stale.0.clear();
+    for (_, replica) in entities.0.drain() {
+        if let Some(mut replica) = commands.get_entity(replica) {
+            replica.despawn();
+        }
+    }
+}
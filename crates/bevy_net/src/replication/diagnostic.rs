@@ -0,0 +1,86 @@
+use bevy_app::{App, Plugin, PostUpdate};
+use bevy_diagnostic::{
+    Diagnostic, DiagnosticPath, Diagnostics, DiagnosticsStore, RegisterDiagnostic,
+};
+use bevy_ecs::prelude::*;
+
+use super::server::ClientReplication;
+use super::{ReplicationStats, ReplicationSystem};
+
+/// Adds the replication diagnostics of a server to an [`App`].
+///
+/// Every client also has its own diagnostic under `net/replication/<connection>/`, where
+/// `<connection>` is its connection entity, which is removed once it disconnects.
+///
+/// Requires the [`ReplicationPlugin`](super::ReplicationPlugin) and the
+/// [`DiagnosticsPlugin`](bevy_diagnostic::DiagnosticsPlugin).
+#[derive(Default)]
+pub struct ReplicationDiagnosticsPlugin;
+
+impl Plugin for ReplicationDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_diagnostic(Diagnostic::new(Self::BYTES_SENT).with_suffix(" B"))
+            .observe(register_client)
+            .observe(unregister_client)
+            .add_systems(
+                PostUpdate,
+                Self::diagnostic_system.after(ReplicationSystem::Send),
+            );
+    }
+}
+
+impl ReplicationDiagnosticsPlugin {
+    /// The bytes of replication messages sent to every client per frame.
+    pub const BYTES_SENT: DiagnosticPath = DiagnosticPath::const_new("net/replication/bytes_sent");
+
+    /// Gets the path of the bytes of replication messages sent per frame to the client of the
+    /// connection entity `connection`, such as `net/replication/12v1/bytes_sent`.
+    pub fn client_path(connection: Entity) -> DiagnosticPath {
+        DiagnosticPath::from_components([
+            "net",
+            "replication",
+            &connection.to_string(),
+            "bytes_sent",
+        ])
+    }
+
+    fn diagnostic_system(
+        mut diagnostics: Diagnostics,
+        stats: Res<ReplicationStats>,
+        clients: Query<(Entity, &ClientReplicationDiagnostics)>,
+    ) {
+        diagnostics.add_measurement(&Self::BYTES_SENT, || stats.bytes_sent as f64);
+        for (connection, client) in &clients {
+            let bytes_sent = stats.client_bytes_sent.get(&connection).copied();
+            diagnostics.add_measurement(&client.path, || bytes_sent.unwrap_or(0) as f64);
+        }
+    }
+}
+
+/// The diagnostic path of a client, on the server's connection entity.
+#[derive(Component)]
+struct ClientReplicationDiagnostics {
+    path: DiagnosticPath,
+}
+
+fn register_client(
+    trigger: Trigger<OnAdd, ClientReplication>,
+    mut store: ResMut<DiagnosticsStore>,
+    mut commands: Commands,
+) {
+    let path = ReplicationDiagnosticsPlugin::client_path(trigger.entity());
+    store.add(Diagnostic::new(path.clone()).with_suffix(" B"));
+    commands
+        .entity(trigger.entity())
+        .insert(ClientReplicationDiagnostics { path });
+}
+
+fn unregister_client(
+    trigger: Trigger<OnRemove, ClientReplication>,
+    clients: Query<&ClientReplicationDiagnostics>,
+    mut store: ResMut<DiagnosticsStore>,
+) {
+    if let Ok(client) = clients.get(trigger.entity()) {
+        store.remove(&client.path);
+    }
+}
@@ -0,0 +1,715 @@
+//! Replication of entities and their components from a server to its clients.
+//!
+//! Entities with the [`Replicated`] component are mirrored on every client connected to the
+//! server, along with those of their components that were registered with
+//! [`ReplicationApp::replicate`] or [`ReplicationApp::replicate_mapped`]. The server relies on
+//! change detection to only send what changed: spawned and despawned entities, and inserted,
+//! changed and removed components.
+//!
+//! The server is the side that accepted the connection, so replication flows over every
+//! [`Incoming`](ConnectionDirection::Incoming) connection and is only accepted from
+//! [`Outgoing`](ConnectionDirection::Outgoing) ones. Clients are expected to be connected to a
+//! single server at a time, and the entities replicated from it are despawned when it disconnects.
+//! Clients that [reconnect](crate::quic::Reconnect) keep them instead: they are updated in full
+//! once reconnected, losing the components the server no longer sends, and the entities the server
+//! no longer sends are despawned.
+//!
+//! Entities on the client are distinct from the ones on the server. Every replicated entity is
+//! spawned with a [`ServerEntity`] component pointing back to its server counterpart, and the
+//! mapping is kept in the [`ServerEntities`] resource. Components that refer to other entities,
+//! such as `Parent` and `Children`, must be registered with [`ReplicationApp::replicate_mapped`] so
+//! their references are mapped through [`MapEntities`] before they are inserted on the client.
+//!
+//! Every message the server sends is tagged with its current tick, which the client exposes as the
+//! [`ConfirmedTick`] once the message is applied. The server sends a message for every new tick,
+//! even if nothing changed, so the state of a client is always confirmed up to the latest tick.
+//!
+//! Both peers must add the [`ReplicationPlugin`] and register the same components.
+//!
+//! # Visibility
+//!
+//! The server can limit which replicated entities each client sees, with components on the
+//! entities and on the clients' connection entities:
+//!
+//! - Entities and clients can join [`Rooms`], in which case entities are only visible to clients
+//!   sharing a room with them.
+//! - A client's [`ReplicationRange`] hides the entities whose [`GlobalTransform`] is too far from
+//!   the one of the entity the client sees the world from.
+//! - [`VisibilityOverrides`] force entities to be visible or hidden to a client.
+//!
+//! Entities leaving the interest of a client are despawned on the client, and sent again in full
+//! once they are back in it.
+//!
+//! [`GlobalTransform`]: bevy_transform::components::GlobalTransform
+//!
+//! # Snapshots
+//!
+//! With the `scene` feature, the [`ReplicationSnapshotPlugin`] sends clients joining mid-match a
+//! single [`DynamicScene`](bevy_scene::DynamicScene) of the entities visible to them, rather than
+//! the first update of every entity. Incremental replication starts once it is applied.
+//!
+//! # Wire format
+//!
+//! Replication messages are sent on a reliable ordered channel. They start with the server's
+//! [`NetworkTick`](crate::tick::NetworkTick), or zero without the
+//! [`NetworkTickPlugin`](crate::tick::NetworkTickPlugin), followed by a sequence of entity
+//! updates. Every update starts with the server entity's bits and a tag, all integers being
+//! variable length encoded:
+//!
+//! - A despawn only holds the tag.
+//! - A spawn or change holds the [`NetworkTypeId`](crate::wire::NetworkTypeId)s of the components
+//!   removed from the entity, followed by the components inserted or changed, each encoded with a
+//!   [`NetworkSerializer`](crate::wire::NetworkSerializer). The client spawns the entity on its
+//!   first update, which holds every replicated component of the entity.
+//! - A sync marker, sent once per connection for [`Entity::PLACEHOLDER`], tells the client that
+//!   every entity visible to it has been sent.
+
+mod client;
+mod diagnostic;
+mod server;
+#[cfg(feature = "scene")]
+mod snapshot;
+mod visibility;
+
+pub use client::*;
+pub use diagnostic::*;
+#[cfg(feature = "scene")]
+pub use snapshot::*;
+pub use visibility::*;
+
+use std::any::TypeId;
+
+use bevy_app::{App, Plugin, PostUpdate, PreUpdate};
+use bevy_ecs::entity::{Entities, EntityHashMap, MapEntities};
+use bevy_ecs::prelude::*;
+use bevy_ecs::reflect::{AppTypeRegistry, ReflectComponent, ReflectMapEntities};
+use bevy_ecs::world::{EntityRef, EntityWorldMut};
+use bevy_reflect::std_traits::ReflectDefault;
+use bevy_reflect::{FromReflect, GetTypeRegistration, Reflect, TypePath};
+use bevy_transform::TransformSystem;
+use bevy_utils::HashMap;
+use bytes::Bytes;
+use thiserror::Error;
+
+use crate::channel::{ChannelConfig, ChannelKind, DecodeError, MessageApp, NetworkMessage};
+use crate::quic::{ConnectionDirection, NetworkSystem};
+use crate::wire::{NetworkSerializer, NetworkTypeIds, ReflectNetworked, WireError};
+
+/// Adds server to client replication to an [`App`].
+///
+/// Requires the [`QuicNetworkPlugin`](crate::quic::QuicNetworkPlugin).
+#[derive(Default)]
+pub struct ReplicationPlugin;
+
+impl Plugin for ReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Replicated>()
+            .register_type::<ReplicationPriority>()
+            .init_resource::<ReplicationRegistry>()
+            .init_resource::<ReplicationStats>()
+            .init_resource::<ServerEntities>()
+            .init_resource::<client::StaleReplicas>()
+            .init_resource::<ConfirmedTick>()
+            .add_message::<ReplicationMessage>(
+                ChannelConfig::new(ChannelKind::ReliableOrdered)
+                    .with_max_message_size(MAX_MESSAGE_SIZE),
+            )
+            .observe(server::start_replication)
+            .observe(client::despawn_replicas)
+            .observe(client::despawn_stale_replicas)
+            .configure_sets(
+                PreUpdate,
+                ReplicationSystem::Receive.after(NetworkSystem::Receive),
+            )
+            .configure_sets(
+                PostUpdate,
+                (ReplicationSystem::Collect, ReplicationSystem::Send)
+                    .chain()
+                    .before(NetworkSystem::Send),
+            )
+            .add_systems(
+                PreUpdate,
+                client::apply_replication.in_set(ReplicationSystem::Receive),
+            )
+            .add_systems(
+                PostUpdate,
+                (
+                    server::collect_entities
+                        .in_set(ReplicationSystem::Collect)
+                        .before(server::CollectComponents),
+                    server::update_visibility
+                        .in_set(ReplicationSystem::Collect)
+                        .after(server::collect_entities)
+                        .after(server::CollectComponents)
+                        .after(TransformSystem::TransformPropagate),
+                    server::send_replication.in_set(ReplicationSystem::Send),
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let ids = NetworkTypeIds::from_registry(&app.world().resource::<AppTypeRegistry>().read());
+        app.insert_resource(ids);
+    }
+}
+
+/// Labels for the replication systems.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemSet)]
+pub enum ReplicationSystem {
+    /// Applies the updates received from the server.
+    ///
+    /// Runs in [`PreUpdate`], after [`NetworkSystem::Receive`].
+    Receive,
+    /// Records the changes to replicated entities made this frame.
+    ///
+    /// Runs in [`PostUpdate`].
+    Collect,
+    /// Queues the recorded changes for sending to every client.
+    ///
+    /// Runs in [`PostUpdate`], after [`ReplicationSystem::Collect`] and before
+    /// [`NetworkSystem::Send`].
+    Send,
+}
+
+/// Marks an entity to be replicated to clients.
+///
+/// Removing it despawns the entity on the clients, without despawning it on the server.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[reflect(Component, Default)]
+pub struct Replicated;
+
+/// How urgently the changes to a replicated entity should be sent to clients with a
+/// [`ReplicationBudget`], on the server. Defaults to `1.0` for entities without it.
+///
+/// Every tick the changes to an entity are deferred, they gain this much priority, and the
+/// entities with the most priority are sent first. Like the budget, priority does not grow in
+/// frames without a new tick.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Component, Default)]
+pub struct ReplicationPriority(pub f32);
+
+impl ReplicationPriority {
+    /// Gets the priority.
+    pub fn get(&self) -> f32 {
+        self.0
+    }
+}
+
+impl Default for ReplicationPriority {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+/// Limits how many bytes of entity updates are sent to a client per [`NetworkTick`], on the
+/// server's connection entity.
+///
+/// Updates that do not fit in the budget are deferred to the next ticks, highest
+/// [`ReplicationPriority`] first. Despawns are always sent, and so is the first update of every
+/// tick, even if it is larger than the budget. Without the
+/// [`NetworkTickPlugin`](crate::tick::NetworkTickPlugin), the budget applies to every frame.
+///
+/// [`NetworkTick`]: crate::tick::NetworkTick
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplicationBudget {
+    /// The number of bytes available per tick.
+    pub bytes_per_tick: usize,
+}
+
+/// What the server sent during the last [`ReplicationSystem::Send`].
+#[derive(Resource, Default)]
+struct ReplicationStats {
+    bytes_sent: usize,
+    /// The bytes sent to every client that was sent anything, by connection entity.
+    client_bytes_sent: EntityHashMap<usize>,
+}
+
+/// Registers components for replication.
+pub trait ReplicationApp {
+    /// Replicates components of type `C` on [`Replicated`] entities.
+    ///
+    /// The component is registered with the [`AppTypeRegistry`] as [networked](ReflectNetworked).
+    /// Use [`replicate_mapped`](Self::replicate_mapped) instead for components holding entities.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the [`ReplicationPlugin`] was not added, or if `C` was already registered.
+    fn replicate<C>(&mut self) -> &mut Self
+    where
+        C: Component + Reflect + FromReflect + TypePath + GetTypeRegistration;
+
+    /// Like [`replicate`](Self::replicate), but the entities held by the component are mapped to
+    /// their client counterparts before it is inserted on a client.
+    ///
+    /// Entities that were not replicated yet are reserved on the client, and used once their server
+    /// counterpart is replicated.
+    fn replicate_mapped<C>(&mut self) -> &mut Self
+    where
+        C: Component + Reflect + FromReflect + TypePath + GetTypeRegistration + MapEntities;
+}
+
+impl ReplicationApp for App {
+    fn replicate<C>(&mut self) -> &mut Self
+    where
+        C: Component + Reflect + FromReflect + TypePath + GetTypeRegistration,
+    {
+        register_component::<C>(self, client::write_component::<C>, None)
+    }
+
+    fn replicate_mapped<C>(&mut self) -> &mut Self
+    where
+        C: Component + Reflect + FromReflect + TypePath + GetTypeRegistration + MapEntities,
+    {
+        register_component::<C>(
+            self,
+            client::write_mapped_component::<C>,
+            Some(client::reserve_mapped_entities::<C>),
+        )
+        .register_type_data::<C, ReflectMapEntities>()
+    }
+}
+
+fn register_component<C>(app: &mut App, write: WriteFn, reserve: Option<ReserveFn>) -> &mut App
+where
+    C: Component + Reflect + FromReflect + TypePath + GetTypeRegistration,
+{
+    let mut registry = app
+        .world_mut()
+        .get_resource_mut::<ReplicationRegistry>()
+        .expect("the `ReplicationPlugin` must be added before replicating components");
+    assert!(
+        !registry.indices.contains_key(&TypeId::of::<C>()),
+        "component {} is already replicated",
+        std::any::type_name::<C>()
+    );
+
+    let index = registry.components.len();
+    registry.indices.insert(TypeId::of::<C>(), index);
+    registry.components.push(ReplicatedComponent {
+        type_id: TypeId::of::<C>(),
+        serialize: serialize_component::<C>,
+        write,
+        reserve,
+        remove: remove_component::<C>,
+        on_write: Vec::new(),
+    });
+
+    app.register_type::<C>()
+        .register_type_data::<C, ReflectComponent>()
+        .register_type_data::<C, ReflectNetworked>()
+        .add_systems(
+            PostUpdate,
+            server::collect_components::<C>
+                .in_set(ReplicationSystem::Collect)
+                .in_set(server::CollectComponents),
+        )
+}
+
+type SerializeFn = fn(EntityRef, &NetworkSerializer, &mut Vec<u8>) -> Result<(), WireError>;
+type WriteFn = fn(
+    &mut World,
+    Entity,
+    &dyn Reflect,
+    &mut EntityHashMap<Entity>,
+) -> Result<(), ReplicationError>;
+type ReserveFn = fn(&dyn Reflect, &mut EntityHashMap<Entity>, &Entities);
+type RemoveFn = fn(&mut EntityWorldMut);
+/// Called on a client after a replicated component was written to an entity.
+pub(crate) type OnWriteFn = fn(&mut World, Entity);
+
+/// How to replicate a component type.
+struct ReplicatedComponent {
+    type_id: TypeId,
+    serialize: SerializeFn,
+    write: WriteFn,
+    /// Reserves replicas for the entities held by the component, if it is mapped, when loading
+    /// snapshots.
+    #[cfg_attr(not(feature = "scene"), allow(dead_code))]
+    reserve: Option<ReserveFn>,
+    remove: RemoveFn,
+    on_write: Vec<OnWriteFn>,
+}
+
+/// Every replicated component type, indexed in registration order.
+#[derive(Resource, Default)]
+pub(crate) struct ReplicationRegistry {
+    components: Vec<ReplicatedComponent>,
+    indices: HashMap<TypeId, usize>,
+}
+
+impl ReplicationRegistry {
+    fn index_of(&self, type_id: TypeId) -> Option<usize> {
+        self.indices.get(&type_id).copied()
+    }
+
+    /// Adds a function called after components of type `C` are written on a client.
+    ///
+    /// Returns `false` if `C` is not replicated.
+    pub(crate) fn add_on_write<C: Component>(&mut self, on_write: OnWriteFn) -> bool {
+        let Some(index) = self.index_of(TypeId::of::<C>()) else {
+            return false;
+        };
+        self.components[index].on_write.push(on_write);
+        true
+    }
+}
+
+fn serialize_component<C: Component + Reflect>(
+    entity: EntityRef,
+    serializer: &NetworkSerializer,
+    buf: &mut Vec<u8>,
+) -> Result<(), WireError> {
+    // Only called for components the entity holds.
+    serializer.serialize(entity.get::<C>().unwrap(), buf)
+}
+
+fn remove_component<C: Component>(entity: &mut EntityWorldMut) {
+    entity.remove::<C>();
+}
+
+/// A batch of entity updates, see the [module docs](self) for its format.
+struct ReplicationMessage(Bytes);
+
+impl NetworkMessage for ReplicationMessage {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.0);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Self(Bytes::copy_from_slice(bytes)))
+    }
+}
+
+/// The largest [`ReplicationMessage`], entities whose update does not fit in one are not sent.
+const MAX_MESSAGE_SIZE: usize = ChannelConfig::DEFAULT_MAX_MESSAGE_SIZE;
+
+/// Tags an entity update as a despawn.
+const DESPAWN: u64 = 0;
+/// Tags an entity update as a spawn or a change to its components.
+const UPDATE: u64 = 1;
+/// Tags the update, for a placeholder entity, telling a client that every entity visible to it
+/// was sent since it connected.
+const SYNCED: u64 = 2;
+
+/// An error in a replication message received from the server.
+#[derive(Error, Debug)]
+enum ReplicationError {
+    #[error("malformed replication message")]
+    Malformed,
+    #[error("component {0} is not replicated")]
+    NotReplicated(String),
+    #[error("received an invalid value for component {0}")]
+    InvalidValue(&'static str),
+    #[error(transparent)]
+    Wire(#[from] WireError),
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+    use super::*;
+    use crate::quic::test_utils::{connected_apps, reconnecting_apps, update_until};
+    use crate::quic::{LinkProfile, Reconnecting};
+    use bevy_diagnostic::{Diagnostic, DiagnosticsPlugin, DiagnosticsStore};
+    use bevy_ecs::entity::EntityMapper;
+    use bevy_hierarchy::{BuildChildren, ChildBuild, Children, Parent};
+    use bevy_transform::components::GlobalTransform;
+
+    #[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+    #[reflect(Component)]
+    struct Health(u32);
+
+    #[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+    #[reflect(Component)]
+    struct Target(Entity);
+
+    #[derive(Component, Reflect, Debug, Clone, PartialEq, Eq)]
+    #[reflect(Component)]
+    struct Blob(Vec<u8>);
+
+    impl MapEntities for Target {
+        fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+            self.0 = entity_mapper.map_entity(self.0);
+        }
+    }
+
+    fn setup(app: &mut App) {
+        app.add_plugins(ReplicationPlugin)
+            .replicate::<Health>()
+            .replicate_mapped::<Target>()
+            .replicate_mapped::<Parent>()
+            .replicate_mapped::<Children>()
+            .replicate::<Blob>();
+    }
+
+    fn client_entity(client: &App, server_entity: Entity) -> Option<Entity> {
+        client
+            .world()
+            .resource::<ServerEntities>()
+            .get(server_entity)
+    }
+
+    #[test]
+    fn replicates_entity_lifecycle() {
+        let (mut server, mut client, _, _) = connected_apps(setup);
+
+        let unreplicated = server.world_mut().spawn(Health(1)).id();
+        let entity = server.world_mut().spawn((Replicated, Health(10))).id();
+        update_until([&mut server, &mut client], |[_, client]| {
+            client_entity(client, entity).is_some()
+        });
+        let replica = client_entity(&client, entity).unwrap();
+        assert_eq!(client.world().get::<Health>(replica), Some(&Health(10)));
+        assert_eq!(
+            client
+                .world()
+                .get::<ServerEntity>(replica)
+                .map(ServerEntity::get),
+            Some(entity)
+        );
+        assert_eq!(client_entity(&client, unreplicated), None);
+
+        server.world_mut().get_mut::<Health>(entity).unwrap().0 = 5;
+        update_until([&mut server, &mut client], |[_, client]| {
+            client.world().get::<Health>(replica) == Some(&Health(5))
+        });
+
+        server.world_mut().entity_mut(entity).remove::<Health>();
+        update_until([&mut server, &mut client], |[_, client]| {
+            client.world().get::<Health>(replica).is_none()
+        });
+
+        server.world_mut().despawn(entity);
+        update_until([&mut server, &mut client], |[_, client]| {
+            client.world().get_entity(replica).is_none()
+        });
+        assert_eq!(client_entity(&client, entity), None);
+    }
+
+    #[test]
+    fn maps_entity_references() {
+        let (mut server, mut client, _, _) = connected_apps(setup);
+
+        let world = server.world_mut();
+        let target = world.spawn(Replicated).id();
+        let holder = world.spawn((Replicated, Target(target))).id();
+        let mut child = None;
+        let parent = world
+            .spawn(Replicated)
+            .with_children(|parent| child = Some(parent.spawn(Replicated).id()))
+            .id();
+        let child = child.unwrap();
+
+        update_until([&mut server, &mut client], |[_, client]| {
+            [target, holder, parent, child]
+                .iter()
+                .all(|entity| client_entity(client, *entity).is_some())
+        });
+
+        let world = client.world();
+        let [target, holder, parent, child] =
+            [target, holder, parent, child].map(|entity| client_entity(&client, entity).unwrap());
+        assert_eq!(world.get::<Target>(holder), Some(&Target(target)));
+        assert_eq!(world.get::<Parent>(child).map(Parent::get), Some(parent));
+        assert_eq!(&**world.get::<Children>(parent).unwrap(), [child]);
+    }
+
+    #[test]
+    fn resynchronizes_replicas_after_reconnecting() {
+        let (mut server, mut client, link, endpoint) = reconnecting_apps(setup);
+
+        let world = server.world_mut();
+        let kept = world.spawn((Replicated, Health(1), Blob(vec![1]))).id();
+        let despawned = world.spawn((Replicated, Health(2))).id();
+        update_until([&mut server, &mut client], |[_, client]| {
+            client_entity(client, kept).is_some() && client_entity(client, despawned).is_some()
+        });
+        let [kept_replica, despawned_replica] =
+            [kept, despawned].map(|entity| client_entity(&client, entity).unwrap());
+
+        link.set_profile(LinkProfile {
+            loss: 1.0,
+            ..LinkProfile::PERFECT
+        });
+        update_until([&mut server, &mut client], |[_, client]| {
+            client.world().get::<Reconnecting>(endpoint).is_some()
+        });
+        // The server changes while the client is away, without being able to tell it.
+        server.world_mut().entity_mut(kept).remove::<Health>();
+        server.world_mut().despawn(despawned);
+        link.set_profile(LinkProfile::PERFECT);
+
+        // The replicas the server sends again are kept, and the others despawned once synced.
+        update_until([&mut server, &mut client], |[_, client]| {
+            client.world().get_entity(despawned_replica).is_none()
+        });
+        assert_eq!(client_entity(&client, kept), Some(kept_replica));
+        assert_eq!(client_entity(&client, despawned), None);
+        let world = client.world();
+        assert_eq!(world.get::<Blob>(kept_replica), Some(&Blob(vec![1])));
+        assert_eq!(world.get::<Health>(kept_replica), None);
+    }
+
+    #[test]
+    fn stops_replicating_removed_entities() {
+        let (mut server, mut client, _, _) = connected_apps(setup);
+
+        let entity = server.world_mut().spawn((Replicated, Health(3))).id();
+        update_until([&mut server, &mut client], |[_, client]| {
+            client_entity(client, entity).is_some()
+        });
+        let replica = client_entity(&client, entity).unwrap();
+
+        server.world_mut().entity_mut(entity).remove::<Replicated>();
+        update_until([&mut server, &mut client], |[_, client]| {
+            client.world().get_entity(replica).is_none()
+        });
+        assert!(server.world().get::<Health>(entity).is_some());
+    }
+
+    #[test]
+    fn replicates_entities_sharing_a_room() {
+        let (mut server, mut client, connection, _) = connected_apps(setup);
+
+        let world = server.world_mut();
+        world.entity_mut(connection).insert(Rooms::new([Room(1)]));
+        let global = world.spawn((Replicated, Health(1))).id();
+        let red = world
+            .spawn((Replicated, Health(2), Rooms::new([Room(1)])))
+            .id();
+        let blue = world
+            .spawn((Replicated, Health(3), Rooms::new([Room(2)])))
+            .id();
+        update_until([&mut server, &mut client], |[_, client]| {
+            client_entity(client, global).is_some() && client_entity(client, red).is_some()
+        });
+        assert_eq!(client_entity(&client, blue), None);
+
+        // Leaving a room despawns its entities, and joining one sends its entities in full.
+        let mut rooms = server.world_mut().get_mut::<Rooms>(connection).unwrap();
+        rooms.leave(Room(1));
+        rooms.join(Room(2));
+        update_until([&mut server, &mut client], |[_, client]| {
+            client_entity(client, red).is_none() && client_entity(client, blue).is_some()
+        });
+        let replica = client_entity(&client, blue).unwrap();
+        assert_eq!(client.world().get::<Health>(replica), Some(&Health(3)));
+        assert!(client_entity(&client, global).is_some());
+
+        let mut overrides = VisibilityOverrides::default();
+        overrides.show(red);
+        overrides.hide(global);
+        server.world_mut().entity_mut(connection).insert(overrides);
+        update_until([&mut server, &mut client], |[_, client]| {
+            client_entity(client, red).is_some() && client_entity(client, global).is_none()
+        });
+        let replica = client_entity(&client, red).unwrap();
+        assert_eq!(client.world().get::<Health>(replica), Some(&Health(2)));
+    }
+
+    #[test]
+    fn replicates_entities_in_range() {
+        let (mut server, mut client, connection, _) = connected_apps(setup);
+
+        let world = server.world_mut();
+        let center = world.spawn(GlobalTransform::default()).id();
+        world.entity_mut(connection).insert(ReplicationRange {
+            center,
+            radius: 10.0,
+        });
+        let at = |x| GlobalTransform::from_xyz(x, 0.0, 0.0);
+        let near = world.spawn((Replicated, Health(1), at(5.0))).id();
+        let far = world.spawn((Replicated, Health(2), at(50.0))).id();
+        update_until([&mut server, &mut client], |[_, client]| {
+            client_entity(client, near).is_some()
+        });
+        assert_eq!(client_entity(&client, far), None);
+
+        *server
+            .world_mut()
+            .get_mut::<GlobalTransform>(center)
+            .unwrap() = at(45.0);
+        update_until([&mut server, &mut client], |[_, client]| {
+            client_entity(client, near).is_none() && client_entity(client, far).is_some()
+        });
+    }
+
+    #[test]
+    fn skips_entities_too_large_for_a_message() {
+        let (mut server, mut client, _, _) = connected_apps(setup);
+
+        let world = server.world_mut();
+        let large = world
+            .spawn((Replicated, Blob(vec![0; MAX_MESSAGE_SIZE])))
+            .id();
+        let entity = world.spawn((Replicated, Health(1))).id();
+        update_until([&mut server, &mut client], |[_, client]| {
+            client_entity(client, entity).is_some()
+        });
+        assert_eq!(client_entity(&client, large), None);
+
+        // The skipped entity does not hold up the ones sent after it.
+        server.world_mut().get_mut::<Health>(entity).unwrap().0 = 2;
+        let replica = client_entity(&client, entity).unwrap();
+        update_until([&mut server, &mut client], |[_, client]| {
+            client.world().get::<Health>(replica) == Some(&Health(2))
+        });
+    }
+
+    #[test]
+    fn defers_updates_past_the_budget() {
+        let (mut server, mut client, connection, _) = connected_apps(|app| {
+            setup(app);
+            app.add_plugins((DiagnosticsPlugin, ReplicationDiagnosticsPlugin));
+        });
+
+        let world = server.world_mut();
+        world
+            .entity_mut(connection)
+            .insert(ReplicationBudget { bytes_per_tick: 1 });
+        let entities: Vec<_> = (0..10)
+            .map(|health| world.spawn((Replicated, Health(health))).id())
+            .collect();
+        let urgent = world
+            .spawn((Replicated, Health(100), ReplicationPriority(100.0)))
+            .id();
+
+        let replicas = |client: &App| {
+            entities
+                .iter()
+                .filter(|entity| client_entity(client, **entity).is_some())
+                .count()
+        };
+        update_until([&mut server, &mut client], |[_, client]| {
+            client_entity(client, urgent).is_some()
+        });
+        assert!(replicas(&client) < entities.len());
+
+        update_until([&mut server, &mut client], |[_, client]| {
+            replicas(client) == entities.len()
+        });
+        let bytes_sent = server
+            .world()
+            .resource::<DiagnosticsStore>()
+            .get(&ReplicationDiagnosticsPlugin::BYTES_SENT)
+            .and_then(Diagnostic::value);
+        assert!(bytes_sent.is_some());
+        let client_bytes_sent = |server: &App| {
+            server
+                .world()
+                .resource::<DiagnosticsStore>()
+                .get(&ReplicationDiagnosticsPlugin::client_path(connection))
+                .and_then(Diagnostic::value)
+        };
+        assert!(client_bytes_sent(&server).is_some());
+
+        // Nothing is left to send, so both drop back to zero.
+        server.update();
+        let bytes_sent = server
+            .world()
+            .resource::<DiagnosticsStore>()
+            .get(&ReplicationDiagnosticsPlugin::BYTES_SENT)
+            .and_then(Diagnostic::value);
+        assert_eq!(bytes_sent, Some(0.0));
+        assert_eq!(client_bytes_sent(&server), Some(0.0));
+    }
+}
@@ -0,0 +1,422 @@
+use bevy_ecs::entity::{EntityHashMap, EntityHashSet};
+use bevy_ecs::prelude::*;
+use bevy_ecs::reflect::AppTypeRegistry;
+use bevy_ecs::system::SystemState;
+use bevy_ecs::world::EntityRef;
+use bevy_transform::components::GlobalTransform;
+use bevy_utils::tracing::warn;
+
+use super::visibility::Interest;
+use super::{
+    ConnectionDirection, Replicated, ReplicationBudget, ReplicationMessage, ReplicationPriority,
+    ReplicationRange, ReplicationRegistry, ReplicationStats, Rooms, VisibilityOverrides, DESPAWN,
+    MAX_MESSAGE_SIZE, SYNCED, UPDATE,
+};
+use crate::channel::MessageWriter;
+use crate::quic::Connected;
+use crate::tick::NetworkTick;
+use crate::varint::{write_varint, MAX_VARINT_LEN};
+use crate::wire::{NetworkSerializer, NetworkTypeIds};
+
+/// Replication messages are split once they grow past this size.
+const MESSAGE_SPLIT_SIZE: usize = 64 * 1024;
+
+/// Collects the changes to every replicated component type.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemSet)]
+pub(super) struct CollectComponents;
+
+/// The replication state of a client, on the server's connection entity.
+#[derive(Component, Default)]
+pub(super) struct ClientReplication {
+    /// The entities the client was sent and has not been told were despawned.
+    known: EntityHashSet,
+    /// Entities with changes waiting to be sent.
+    pending: EntityHashMap<PendingChanges>,
+    /// Known entities waiting to be despawned.
+    despawned: Vec<Entity>,
+    /// The [`NetworkTick`] of the last message sent.
+    last_tick: Option<u32>,
+    /// Whether every visible entity was sent and the client was told so.
+    synced: bool,
+}
+
+/// The changes to an entity waiting to be sent, as indices into the [`ReplicationRegistry`].
+#[derive(Default)]
+struct PendingChanges {
+    changed: Vec<usize>,
+    removed: Vec<usize>,
+    /// The [`ReplicationPriority`] accumulated while the changes were deferred.
+    priority: f32,
+}
+
+impl ClientReplication {
+    fn spawned(&mut self, entity: Entity) {
+        self.pending.entry(entity).or_default();
+    }
+
+    fn changed(&mut self, entity: Entity, component: usize) {
+        let changes = self.pending.entry(entity).or_default();
+        changes.removed.retain(|removed| *removed != component);
+        if !changes.changed.contains(&component) {
+            changes.changed.push(component);
+        }
+    }
+
+    fn removed(&mut self, entity: Entity, component: usize) {
+        let changes = self.pending.entry(entity).or_default();
+        changes.changed.retain(|changed| *changed != component);
+        if !changes.removed.contains(&component) {
+            changes.removed.push(component);
+        }
+    }
+
+    fn despawned(&mut self, entity: Entity) {
+        self.pending.remove(&entity);
+        if self.known.contains(&entity) {
+            self.despawned.push(entity);
+        }
+    }
+
+    /// Marks the entities waiting to be spawned on the client as sent, returning them, for clients
+    /// that receive them in a snapshot rather than in their first updates.
+    #[cfg(feature = "scene")]
+    pub(super) fn take_spawns(&mut self) -> Vec<Entity> {
+        let spawns: Vec<_> = self
+            .pending
+            .keys()
+            .copied()
+            .filter(|entity| !self.known.contains(entity))
+            .collect();
+        for entity in &spawns {
+            self.pending.remove(entity);
+            self.known.insert(*entity);
+        }
+        spawns
+    }
+
+    /// Undoes [`take_spawns`](Self::take_spawns), for entities the snapshot could not hold.
+    #[cfg(feature = "scene")]
+    pub(super) fn restore_spawns(&mut self, spawns: Vec<Entity>) {
+        for entity in spawns {
+            self.known.remove(&entity);
+            self.spawned(entity);
+        }
+    }
+}
+
+pub(super) fn start_replication(
+    trigger: Trigger<Connected>,
+    connections: Query<&ConnectionDirection>,
+    mut commands: Commands,
+) {
+    let connection = trigger.event().connection;
+    if connections.get(connection) == Ok(&ConnectionDirection::Incoming) {
+        commands
+            .entity(connection)
+            .insert(ClientReplication::default());
+    }
+}
+
+pub(super) fn collect_entities(
+    mut removed: RemovedComponents<Replicated>,
+    mut clients: Query<&mut ClientReplication>,
+) {
+    for entity in removed.read() {
+        for mut client in &mut clients {
+            client.despawned(entity);
+        }
+    }
+}
+
+pub(super) fn collect_components<C: Component>(
+    registry: Res<ReplicationRegistry>,
+    components: Query<(Entity, Ref<C>), With<Replicated>>,
+    mut removed: RemovedComponents<C>,
+    replicated: Query<(), With<Replicated>>,
+    mut clients: Query<&mut ClientReplication>,
+) {
+    let index = registry.index_of(std::any::TypeId::of::<C>()).unwrap();
+
+    let changed: Vec<_> = components
+        .iter()
+        .filter(|(_, component)| component.is_changed())
+        .map(|(entity, _)| entity)
+        .collect();
+    // Despawned entities also report their components as removed.
+    let removed: Vec<_> = removed
+        .read()
+        .filter(|entity| replicated.contains(*entity))
+        .collect();
+
+    // Removals come first, so a component that was removed and inserted again is sent anew.
+    for mut client in &mut clients {
+        for entity in &removed {
+            client.removed(*entity, index);
+        }
+        for entity in &changed {
+            client.changed(*entity, index);
+        }
+    }
+}
+
+/// Spawns the entities that entered the interest of each client, and despawns the ones that left
+/// it.
+///
+/// Every replicated entity is checked against every client on every frame, so new clients and
+/// entities that were not visible are sent in full as soon as they become visible.
+pub(super) fn update_visibility(
+    replicated: Query<(Entity, Option<&Rooms>, Option<&GlobalTransform>), With<Replicated>>,
+    transforms: Query<&GlobalTransform>,
+    mut clients: Query<(
+        &mut ClientReplication,
+        Option<&Rooms>,
+        Option<&ReplicationRange>,
+        Option<&VisibilityOverrides>,
+    )>,
+) {
+    for (mut client, rooms, range, overrides) in &mut clients {
+        let interest = Interest {
+            rooms,
+            range: range.and_then(|range| {
+                let center = transforms.get(range.center).ok()?;
+                Some((*center, range.radius * range.radius))
+            }),
+            overrides,
+        };
+        for (entity, rooms, transform) in &replicated {
+            match (
+                interest.sees(entity, rooms, transform),
+                client.known.contains(&entity),
+            ) {
+                (true, false) => client.spawned(entity),
+                (false, true) => client.despawned(entity),
+                (false, false) => {
+                    client.pending.remove(&entity);
+                }
+                (true, true) => {}
+            }
+        }
+    }
+}
+
+pub(super) fn send_replication(
+    world: &mut World,
+    clients: &mut SystemState<Query<(Entity, &mut ClientReplication, Option<&ReplicationBudget>)>>,
+    writer: &mut SystemState<MessageWriter<ReplicationMessage>>,
+) {
+    let mut stats = world.resource_mut::<ReplicationStats>();
+    stats.bytes_sent = 0;
+    stats.client_bytes_sent.clear();
+
+    let tick = world.get_resource::<NetworkTick>().map(|tick| tick.get());
+    let mut states = Vec::new();
+    let mut client_query = clients.get_mut(world);
+    for (connection, mut client, budget) in &mut client_query {
+        // Clients are told about every new tick, so they know when the state they have is
+        // confirmed even if nothing changed.
+        let new_tick = client.last_tick != tick;
+        if client.pending.is_empty() && client.despawned.is_empty() && !new_tick && client.synced {
+            continue;
+        }
+        // Budgets and priorities are per tick, so frames without a new tick only send despawns.
+        let ticks = match (tick, client.last_tick) {
+            (Some(tick), Some(last)) => tick.saturating_sub(last),
+            _ => 1,
+        };
+        let budget = budget.map(|budget| budget.bytes_per_tick.saturating_mul(ticks as usize));
+        client.last_tick = tick;
+        states.push((
+            connection,
+            budget,
+            ticks,
+            new_tick,
+            std::mem::take(&mut *client),
+        ));
+    }
+    if states.is_empty() {
+        return;
+    }
+
+    let type_registry = world.resource::<AppTypeRegistry>().read();
+    let ids = world.resource::<NetworkTypeIds>();
+    let serializer = NetworkSerializer::new(&type_registry, ids);
+    let registry = world.resource::<ReplicationRegistry>();
+
+    let new_message = || {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, u64::from(tick.unwrap_or_default()));
+        buf
+    };
+    let header_len = new_message().len();
+    // Leaves room for the `SYNCED` marker.
+    let max_len = MAX_MESSAGE_SIZE - 2 * MAX_VARINT_LEN;
+
+    let mut messages = Vec::new();
+    for (connection, budget, ticks, new_tick, client) in &mut states {
+        let mut buf = new_message();
+        let mut sent = false;
+        for entity in client.despawned.drain(..) {
+            if client.known.remove(&entity) {
+                write_varint(&mut buf, entity.to_bits());
+                write_varint(&mut buf, DESPAWN);
+            }
+            if buf.len() >= MESSAGE_SPLIT_SIZE {
+                messages.push((*connection, std::mem::replace(&mut buf, new_message())));
+                sent = true;
+            }
+        }
+
+        // Entities that were deferred have accumulated priority, so they eventually make it into
+        // the budget ahead of the entities that keep changing.
+        let mut pending: Vec<_> = client
+            .pending
+            .drain()
+            .filter_map(|(entity, mut changes)| {
+                let entity_ref = world.get_entity(entity)?;
+                if !entity_ref.contains::<Replicated>() {
+                    return None;
+                }
+                changes.priority += *ticks as f32
+                    * entity_ref
+                        .get::<ReplicationPriority>()
+                        .map_or(1.0, ReplicationPriority::get);
+                Some((entity_ref, changes))
+            })
+            .collect();
+        pending.sort_by(|(_, a), (_, b)| b.priority.total_cmp(&a.priority));
+
+        let mut used = 0;
+        let mut update = Vec::new();
+        let mut pending = pending.into_iter();
+        for (entity_ref, changes) in pending.by_ref() {
+            let entity = entity_ref.id();
+            let known = client.known.contains(&entity);
+            update.clear();
+            write_update(
+                &mut update,
+                entity_ref,
+                &changes,
+                known,
+                registry,
+                ids,
+                &serializer,
+            );
+            // The changes are dropped, as the client could never receive them.
+            if header_len + update.len() > max_len {
+                warn!(
+                    "cannot replicate {entity:?}: its update of {} bytes does not fit in a message",
+                    update.len()
+                );
+                continue;
+            }
+
+            // The first update always fits, so entities larger than the budget are still sent.
+            if budget
+                .is_some_and(|budget| used + update.len() > budget && (used > 0 || budget == 0))
+            {
+                client.pending.insert(entity, changes);
+                break;
+            }
+            used += update.len();
+            client.known.insert(entity);
+            if buf.len() + update.len() > max_len {
+                messages.push((*connection, std::mem::replace(&mut buf, new_message())));
+                sent = true;
+            }
+            buf.extend_from_slice(&update);
+
+            if buf.len() >= MESSAGE_SPLIT_SIZE {
+                messages.push((*connection, std::mem::replace(&mut buf, new_message())));
+                sent = true;
+            }
+        }
+        client
+            .pending
+            .extend(pending.map(|(entity_ref, changes)| (entity_ref.id(), changes)));
+
+        if !client.synced && client.pending.is_empty() {
+            write_varint(&mut buf, Entity::PLACEHOLDER.to_bits());
+            write_varint(&mut buf, SYNCED);
+            client.synced = true;
+        }
+
+        if buf.len() > header_len || (*new_tick && !sent) {
+            messages.push((*connection, buf));
+        }
+    }
+    drop(type_registry);
+
+    let mut client_query = clients.get_mut(world);
+    for (connection, _, _, _, client) in states {
+        if let Ok((_, mut state, _)) = client_query.get_mut(connection) {
+            state.known = client.known;
+            state.pending = client.pending;
+            state.last_tick = client.last_tick;
+            state.synced = client.synced;
+        }
+    }
+
+    let mut bytes_sent = 0;
+    let mut client_bytes_sent = EntityHashMap::default();
+    let mut writer = writer.get_mut(world);
+    for (connection, buf) in messages {
+        bytes_sent += buf.len();
+        *client_bytes_sent.entry(connection).or_default() += buf.len();
+        writer.send(connection, ReplicationMessage(buf.into()));
+    }
+    let mut stats = world.resource_mut::<ReplicationStats>();
+    stats.bytes_sent = bytes_sent;
+    stats.client_bytes_sent = client_bytes_sent;
+}
+
+/// Writes the update of an entity, holding every replicated component if the client does not
+/// know the entity yet.
+fn write_update(
+    buf: &mut Vec<u8>,
+    entity_ref: EntityRef,
+    changes: &PendingChanges,
+    known: bool,
+    registry: &ReplicationRegistry,
+    ids: &NetworkTypeIds,
+    serializer: &NetworkSerializer,
+) {
+    let entity = entity_ref.id();
+    write_varint(buf, entity.to_bits());
+    write_varint(buf, UPDATE);
+
+    let removed: Vec<_> = changes
+        .removed
+        .iter()
+        .map(|index| registry.components[*index].type_id)
+        .filter(|type_id| known && !entity_ref.contains_type_id(*type_id))
+        .filter_map(|type_id| ids.get(type_id))
+        .collect();
+    write_varint(buf, removed.len() as u64);
+    for id in removed {
+        write_varint(buf, u64::from(id.get()));
+    }
+
+    let changed: Vec<_> = if known {
+        changes.changed.clone()
+    } else {
+        (0..registry.components.len()).collect()
+    };
+    let mut values = Vec::new();
+    let mut count = 0u64;
+    for index in changed {
+        let component = &registry.components[index];
+        if !entity_ref.contains_type_id(component.type_id) {
+            continue;
+        }
+        let len = values.len();
+        match (component.serialize)(entity_ref, serializer, &mut values) {
+            Ok(()) => count += 1,
+            Err(error) => {
+                warn!("cannot replicate a component of {entity:?}: {error}");
+                values.truncate(len);
+            }
+        }
+    }
+    write_varint(buf, count);
+    buf.extend_from_slice(&values);
+}
@@ -0,0 +1,438 @@
+use bevy_app::{App, Plugin, PostUpdate, PreUpdate};
+use bevy_ecs::prelude::*;
+use bevy_ecs::reflect::AppTypeRegistry;
+use bevy_ecs::system::SystemState;
+use bevy_scene::serde::{SceneDeserializer, SceneSerializer};
+use bevy_scene::{DynamicScene, DynamicSceneBuilder, SceneFilter, SceneSpawnError};
+use bevy_utils::tracing::warn;
+use bincode::Options;
+use bytes::Bytes;
+use thiserror::Error;
+
+use super::server::ClientReplication;
+use super::{
+    client, server, ConfirmedTick, ConnectionDirection, ReplicationRegistry, ReplicationSystem,
+    ServerEntities, StaleReplicas,
+};
+use crate::channel::{
+    ChannelConfig, ChannelKind, DecodeError, MessageApp, MessageReader, MessageWriter,
+    NetworkMessage,
+};
+use crate::quic::Connected;
+use crate::tick::NetworkTick;
+use crate::varint::{read_varint, write_varint};
+
+/// Sends joining clients a snapshot of the replicated world, rather than the first update of
+/// every entity visible to them.
+///
+/// The server builds a [`DynamicScene`] of the visible [`Replicated`](super::Replicated) entities
+/// with a [`DynamicSceneBuilder`], filtered down to the replicated components, and serializes it
+/// like scenes are, so saves and joins share one serialization path. The snapshot is sent on its
+/// own reliable channel, and clients defer the replication messages of the server until they have
+/// applied it with [`DynamicScene::write_to_world_with`]. Only then is the world incrementally
+/// replicated, as without this plugin.
+///
+/// Entity references are mapped to the client's replicas like the ones of
+/// [mapped](super::ReplicationApp::replicate_mapped) components, so references to entities outside
+/// of the snapshot point to reserved replicas, used once these entities are replicated.
+///
+/// Requires the [`ReplicationPlugin`](super::ReplicationPlugin). Both peers must add it.
+pub struct ReplicationSnapshotPlugin {
+    /// The largest snapshot, in bytes, clients accept.
+    pub max_snapshot_size: usize,
+}
+
+impl ReplicationSnapshotPlugin {
+    /// The default value of [`max_snapshot_size`](Self::max_snapshot_size).
+    pub const DEFAULT_MAX_SNAPSHOT_SIZE: usize = 64 * 1024 * 1024;
+}
+
+impl Default for ReplicationSnapshotPlugin {
+    fn default() -> Self {
+        Self {
+            max_snapshot_size: Self::DEFAULT_MAX_SNAPSHOT_SIZE,
+        }
+    }
+}
+
+impl Plugin for ReplicationSnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SnapshotLimit(self.max_snapshot_size as u64))
+            .add_message::<SnapshotMessage>(
+                ChannelConfig::new(ChannelKind::ReliableOrdered)
+                    .with_max_message_size(self.max_snapshot_size),
+            )
+            .observe(await_snapshot)
+            .add_systems(
+                PreUpdate,
+                apply_snapshots
+                    .in_set(ReplicationSystem::Receive)
+                    .before(client::apply_replication),
+            )
+            .add_systems(
+                PostUpdate,
+                send_snapshots
+                    .in_set(ReplicationSystem::Send)
+                    .before(server::send_replication),
+            );
+    }
+}
+
+/// The largest snapshot the client decodes.
+#[derive(Resource)]
+struct SnapshotLimit(u64);
+
+/// The replicated entities visible to a client when it connected, along with the server's
+/// [`NetworkTick`] or zero without the [`NetworkTickPlugin`](crate::tick::NetworkTickPlugin).
+#[derive(Clone)]
+struct SnapshotMessage {
+    tick: u32,
+    scene: Bytes,
+}
+
+impl NetworkMessage for SnapshotMessage {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write_varint(buf, u64::from(self.tick));
+        buf.extend_from_slice(&self.scene);
+    }
+
+    fn decode(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let tick = read_varint(&mut bytes)
+            .and_then(|tick| u32::try_from(tick).ok())
+            .ok_or_else(|| DecodeError("missing snapshot tick".into()))?;
+        Ok(Self {
+            tick,
+            scene: Bytes::copy_from_slice(bytes),
+        })
+    }
+}
+
+/// An error in a snapshot received from the server.
+#[derive(Error, Debug)]
+enum SnapshotError {
+    #[error(transparent)]
+    Serialization(#[from] bincode::Error),
+    #[error(transparent)]
+    Spawn(#[from] SceneSpawnError),
+}
+
+/// The replication messages received from the server before its snapshot, on the client's
+/// connection entity.
+#[derive(Component, Default)]
+struct AwaitingSnapshot(Vec<Bytes>);
+
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+fn await_snapshot(
+    trigger: Trigger<Connected>,
+    connections: Query<&ConnectionDirection>,
+    mut commands: Commands,
+) {
+    let connection = trigger.event().connection;
+    if connections.get(connection) == Ok(&ConnectionDirection::Outgoing) {
+        commands
+            .entity(connection)
+            .insert(AwaitingSnapshot::default());
+    }
+}
+
+/// Sends a snapshot to every client that connected since the last run, in place of the first
+/// updates of the entities visible to it.
+fn send_snapshots(
+    world: &mut World,
+    clients: &mut SystemState<Query<(Entity, &mut ClientReplication), Added<ClientReplication>>>,
+    writer: &mut SystemState<MessageWriter<SnapshotMessage>>,
+) {
+    let mut joined = Vec::new();
+    for (connection, mut client) in &mut clients.get_mut(world) {
+        joined.push((connection, client.take_spawns()));
+    }
+    if joined.is_empty() {
+        return;
+    }
+
+    let tick = world
+        .get_resource::<NetworkTick>()
+        .map_or(0, |tick| tick.get());
+    let filter = world
+        .resource::<ReplicationRegistry>()
+        .components
+        .iter()
+        .fold(SceneFilter::deny_all(), |filter, component| {
+            filter.allow_by_id(component.type_id)
+        });
+
+    let mut snapshots = Vec::new();
+    for (connection, entities) in joined {
+        let scene = DynamicSceneBuilder::from_world(world)
+            .with_filter(filter.clone())
+            .extract_entities(entities.iter().copied())
+            .build();
+        let type_registry = world.resource::<AppTypeRegistry>().read();
+        let serialized = options().serialize(&SceneSerializer::new(&scene, &type_registry));
+        drop(type_registry);
+
+        let scene = match serialized {
+            Ok(scene) => scene,
+            Err(error) => {
+                // The client still waits for a snapshot, so it is sent an empty one and the
+                // entities are sent one by one instead.
+                warn!("cannot snapshot the replicated world for {connection:?}: {error}");
+                if let Some(mut client) = world.get_mut::<ClientReplication>(connection) {
+                    client.restore_spawns(entities);
+                }
+                let type_registry = world.resource::<AppTypeRegistry>().read();
+                options()
+                    .serialize(&SceneSerializer::new(
+                        &DynamicScene::default(),
+                        &type_registry,
+                    ))
+                    .expect("empty scenes are serializable")
+            }
+        };
+        snapshots.push((connection, scene));
+    }
+
+    let mut writer = writer.get_mut(world);
+    for (connection, scene) in snapshots {
+        writer.send(
+            connection,
+            SnapshotMessage {
+                tick,
+                scene: scene.into(),
+            },
+        );
+    }
+}
+
+/// Moves the replication messages of connections still waiting for their snapshot out of
+/// `received`, to be applied along with the snapshot.
+pub(super) fn defer_until_snapshot(world: &mut World, received: &mut Vec<(Entity, Bytes)>) {
+    received.retain(
+        |(connection, message)| match world.get_mut::<AwaitingSnapshot>(*connection) {
+            Some(mut awaiting) => {
+                awaiting.0.push(message.clone());
+                false
+            }
+            None => true,
+        },
+    );
+}
+
+fn apply_snapshots(
+    world: &mut World,
+    messages: &mut SystemState<(
+        MessageReader<SnapshotMessage>,
+        Query<(), With<AwaitingSnapshot>>,
+    )>,
+) {
+    let (reader, awaiting) = messages.get(world);
+    let mut received = Vec::new();
+    for (connection, message) in reader.read() {
+        if awaiting.contains(connection) {
+            received.push((connection, message.clone()));
+        } else {
+            warn!("ignoring unexpected snapshot from {connection:?}");
+        }
+    }
+
+    for (connection, message) in received {
+        if let Err(error) = apply_snapshot(world, &message) {
+            warn!("dropping an invalid snapshot: {error}");
+        }
+        let deferred = world
+            .entity_mut(connection)
+            .take::<AwaitingSnapshot>()
+            .map(|awaiting| awaiting.0)
+            .unwrap_or_default();
+        client::apply_messages(world, deferred);
+    }
+}
+
+fn apply_snapshot(world: &mut World, message: &SnapshotMessage) -> Result<(), SnapshotError> {
+    let type_registry = world.resource::<AppTypeRegistry>().clone();
+    let limit = world.resource::<SnapshotLimit>().0;
+    let scene = options().with_limit(limit).deserialize_seed(
+        SceneDeserializer {
+            type_registry: &type_registry.read(),
+        },
+        &message.scene,
+    )?;
+    world.resource_mut::<ConfirmedTick>().0 = Some(NetworkTick::new(message.tick));
+
+    let stale = &mut world.resource_mut::<StaleReplicas>().0;
+    let kept: Vec<_> = scene
+        .entities
+        .iter()
+        .filter(|entity| stale.remove(&entity.entity))
+        .collect();
+
+    world.resource_scope(|world, mut entities: Mut<ServerEntities>| {
+        for entity in &scene.entities {
+            entities.spawn_or_get(world, entity.entity);
+        }
+        // Entities outside of the snapshot get reserved replicas, as in replication messages.
+        let registry = world.resource::<ReplicationRegistry>();
+        for component in scene.entities.iter().flat_map(|entity| &entity.components) {
+            let reserve = component
+                .get_represented_type_info()
+                .and_then(|info| registry.index_of(info.type_id()))
+                .and_then(|index| registry.components[index].reserve);
+            if let Some(reserve) = reserve {
+                reserve(component.as_ref(), &mut entities.0, world.entities());
+            }
+        }
+        world.flush();
+        scene.write_to_world_with(world, &mut entities.0, &type_registry)?;
+
+        world.resource_scope(|world, registry: Mut<ReplicationRegistry>| {
+            for entity in kept {
+                let Some(replica) = entities.get(entity.entity) else {
+                    continue;
+                };
+                let sent: Vec<_> = entity
+                    .components
+                    .iter()
+                    .filter_map(|component| component.get_represented_type_info())
+                    .filter_map(|info| registry.index_of(info.type_id()))
+                    .collect();
+                client::remove_unsent_components(world, &registry, replica, &sent);
+            }
+        });
+
+        let registry = world.resource::<ReplicationRegistry>();
+        let on_write: Vec<_> = scene
+            .entities
+            .iter()
+            .filter_map(|entity| Some((entities.get(entity.entity)?, entity)))
+            .flat_map(|(replica, entity)| {
+                entity
+                    .components
+                    .iter()
+                    .filter_map(|component| component.get_represented_type_info())
+                    .filter_map(|info| registry.index_of(info.type_id()))
+                    .flat_map(|index| registry.components[index].on_write.iter())
+                    .map(move |on_write| (*on_write, replica))
+            })
+            .collect();
+        for (on_write, replica) in on_write {
+            on_write(world, replica);
+        }
+        Ok(())
+    })
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+    use super::*;
+    use crate::quic::test_utils::{connected_apps, update_until};
+    use crate::replication::{Replicated, ReplicationApp, ReplicationPlugin, Room, Rooms};
+    use bevy_hierarchy::{BuildChildren, ChildBuild, Children, Parent};
+    use bevy_reflect::Reflect;
+    use std::cell::Cell;
+
+    #[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+    #[reflect(Component)]
+    struct Health(u32);
+
+    #[test]
+    fn sends_joining_clients_a_snapshot() {
+        // The server is set up first, and spawns its entities before the client connects.
+        let spawned = Cell::new(None);
+        let (mut server, mut client, _, client_connection) = connected_apps(|app| {
+            app.add_plugins((ReplicationPlugin, ReplicationSnapshotPlugin::default()))
+                .replicate::<Health>()
+                .replicate_mapped::<Parent>()
+                .replicate_mapped::<Children>();
+            if spawned.get().is_none() {
+                let mut child = None;
+                let parent = app
+                    .world_mut()
+                    .spawn((Replicated, Health(10)))
+                    .with_children(|parent| child = Some(parent.spawn(Replicated).id()))
+                    .id();
+                spawned.set(Some((parent, child.unwrap())));
+            }
+        });
+        let (parent, child) = spawned.get().unwrap();
+
+        let replica =
+            |client: &App, entity| client.world().resource::<ServerEntities>().get(entity);
+        update_until([&mut server, &mut client], |[_, client]| {
+            replica(client, parent).is_some() && replica(client, child).is_some()
+        });
+        assert!(!client
+            .world()
+            .entity(client_connection)
+            .contains::<AwaitingSnapshot>());
+
+        let (parent, child) = (
+            replica(&client, parent).unwrap(),
+            replica(&client, child).unwrap(),
+        );
+        let world = client.world();
+        assert_eq!(world.get::<Health>(parent), Some(&Health(10)));
+        assert_eq!(world.get::<Parent>(child).map(Parent::get), Some(parent));
+        assert_eq!(&**world.get::<Children>(parent).unwrap(), [child]);
+
+        // Later changes are replicated incrementally.
+        let server_parent = spawned.get().unwrap().0;
+        server
+            .world_mut()
+            .get_mut::<Health>(server_parent)
+            .unwrap()
+            .0 = 5;
+        update_until([&mut server, &mut client], |[_, client]| {
+            client.world().get::<Health>(parent) == Some(&Health(5))
+        });
+    }
+
+    #[test]
+    fn reserves_replicas_referred_to_from_snapshots() {
+        // The parent is hidden from the client, so only its child is in the snapshot.
+        let spawned = Cell::new(None);
+        let (mut server, mut client, server_connection, _) = connected_apps(|app| {
+            app.add_plugins((ReplicationPlugin, ReplicationSnapshotPlugin::default()))
+                .replicate_mapped::<Parent>()
+                .replicate_mapped::<Children>();
+            if spawned.get().is_none() {
+                let mut child = None;
+                let parent = app
+                    .world_mut()
+                    .spawn((Replicated, Rooms::new([Room(1)])))
+                    .with_children(|parent| child = Some(parent.spawn(Replicated).id()))
+                    .id();
+                spawned.set(Some((parent, child.unwrap())));
+            }
+        });
+        let (parent, child) = spawned.get().unwrap();
+
+        let replica =
+            |client: &App, entity| client.world().resource::<ServerEntities>().get(entity);
+        update_until([&mut server, &mut client], |[_, client]| {
+            replica(client, child).is_some()
+        });
+        let reserved = replica(&client, parent).unwrap();
+        let child = replica(&client, child).unwrap();
+        assert_eq!(
+            client.world().get::<Parent>(child).map(Parent::get),
+            Some(reserved)
+        );
+
+        // The reserved replica is used once the parent is replicated.
+        server
+            .world_mut()
+            .entity_mut(server_connection)
+            .insert(Rooms::new([Room(1)]));
+        update_until([&mut server, &mut client], |[_, client]| {
+            client.world().get::<Children>(reserved).is_some()
+        });
+        assert_eq!(replica(&client, parent), Some(reserved));
+        assert_eq!(
+            &**client.world().get::<Children>(reserved).unwrap(),
+            [child]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use bevy_ecs::entity::EntityHashMap;
+use bevy_ecs::prelude::*;
+use bevy_transform::components::GlobalTransform;
+use bevy_utils::HashSet;
+
+/// A group of replicated entities and clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Room(pub u64);
+
+/// The [`Room`]s a replicated entity or a client's connection entity belongs to.
+///
+/// Replicated entities with this component are only visible to the clients sharing at least one
+/// room with them. Entities without it are visible to every client, including clients in no room.
+#[derive(Component, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Rooms(HashSet<Room>);
+
+impl Rooms {
+    /// Creates a set of rooms.
+    pub fn new(rooms: impl IntoIterator<Item = Room>) -> Self {
+        Self(rooms.into_iter().collect())
+    }
+
+    /// Joins a room, returning `false` if it was already joined.
+    pub fn join(&mut self, room: Room) -> bool {
+        self.0.insert(room)
+    }
+
+    /// Leaves a room, returning `false` if it was not joined.
+    pub fn leave(&mut self, room: Room) -> bool {
+        self.0.remove(&room)
+    }
+
+    /// Whether the room was joined.
+    pub fn contains(&self, room: Room) -> bool {
+        self.0.contains(&room)
+    }
+
+    /// Iterates over the joined rooms.
+    pub fn iter(&self) -> impl Iterator<Item = Room> + '_ {
+        self.0.iter().copied()
+    }
+
+    fn shares_room_with(&self, other: &Rooms) -> bool {
+        !self.0.is_disjoint(&other.0)
+    }
+}
+
+/// Limits the replicated entities a client can see to those within `radius` of the
+/// [`GlobalTransform`] of the `center` entity, on the client's connection entity.
+///
+/// Entities without a [`GlobalTransform`] are always in range, and so is every entity while
+/// `center` has none.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct ReplicationRange {
+    /// The entity the client sees the world from, usually the one it controls.
+    pub center: Entity,
+    /// The distance past which replicated entities are not visible.
+    pub radius: f32,
+}
+
+/// Forces replicated entities to be visible or hidden to a client, regardless of their [`Rooms`]
+/// and [`ReplicationRange`], on the client's connection entity.
+#[derive(Component, Debug, Default, Clone)]
+pub struct VisibilityOverrides(EntityHashMap<bool>);
+
+impl VisibilityOverrides {
+    /// Makes an entity visible to the client.
+    pub fn show(&mut self, entity: Entity) {
+        self.0.insert(entity, true);
+    }
+
+    /// Hides an entity from the client.
+    pub fn hide(&mut self, entity: Entity) {
+        self.0.insert(entity, false);
+    }
+
+    /// Removes the override of an entity.
+    pub fn reset(&mut self, entity: Entity) {
+        self.0.remove(&entity);
+    }
+
+    /// Gets whether an entity is forced to be visible or hidden.
+    pub fn get(&self, entity: Entity) -> Option<bool> {
+        self.0.get(&entity).copied()
+    }
+}
+
+/// The interest of a client, from the components of its connection entity.
+pub(super) struct Interest<'a> {
+    pub(super) rooms: Option<&'a Rooms>,
+    /// The center and squared radius of the client's [`ReplicationRange`].
+    pub(super) range: Option<(GlobalTransform, f32)>,
+    pub(super) overrides: Option<&'a VisibilityOverrides>,
+}
+
+impl Interest<'_> {
+    pub(super) fn sees(
+        &self,
+        entity: Entity,
+        rooms: Option<&Rooms>,
+        transform: Option<&GlobalTransform>,
+    ) -> bool {
+        if let Some(visible) = self.overrides.and_then(|overrides| overrides.get(entity)) {
+            return visible;
+        }
+
+        let in_room = match (rooms, self.rooms) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(rooms), Some(client_rooms)) => rooms.shares_room_with(client_rooms),
+        };
+        let in_range = match (transform, self.range) {
+            (Some(transform), Some((center, radius_squared))) => {
+                transform
+                    .translation()
+                    .distance_squared(center.translation())
+                    <= radius_squared
+            }
+            _ => true,
+        };
+        in_room && in_range
+    }
+}
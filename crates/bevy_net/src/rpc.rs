@@ -0,0 +1,754 @@
+//! Remote procedure calls to functions registered by name.
+//!
+//! Any function that can be turned into a [`DynamicFunction`] can be registered with
+//! [`RpcApp::register_rpc`], after which peers call it with an [`RpcSender`]. Calls are either
+//! fire-and-forget, with [`RpcSender::notify`] and [`RpcSender::broadcast`], or return an
+//! [`RpcResponse`] that resolves once the remote function has run, with [`RpcSender::call`].
+//! Both the server and its clients register the functions they serve, so calls go either way.
+//!
+//! Arguments and return values are encoded as [networked](crate::wire::ReflectNetworked) values,
+//! so their types must be registered with [`ReflectNetworked`](crate::wire::ReflectNetworked) on
+//! both peers. Functions may take their arguments by value or by reference, changes made through
+//! mutable references are not sent back. Calling a function that does not exist, or with the
+//! wrong arguments, resolves to an [`RpcError`] rather than panicking.
+//!
+//! ```
+//! # use bevy_app::App;
+//! # use bevy_net::rpc::{RpcApp, RpcPlugin};
+//! fn add(a: i32, b: i32) -> i32 {
+//!     a + b
+//! }
+//!
+//! let mut app = App::new();
+//! app.add_plugins(RpcPlugin).register_rpc("add", add);
+//! ```
+//!
+//! # Wire format
+//!
+//! All integers are variable length encoded, and strings are prefixed with their length. Every
+//! message starts with its kind:
+//!
+//! - Calls without a response hold the function name, the number of arguments, and every argument
+//!   encoded with [`NetworkSerializer::serialize`].
+//! - Calls with a response hold a call identifier, followed by the same fields.
+//! - Returns hold the identifier of the call, followed by the return value, or by nothing if the
+//!   function returns `()`.
+//! - Errors hold the identifier of the call, followed by the error.
+
+use std::borrow::Cow;
+
+use async_channel::{Receiver, Sender};
+use bevy_app::{App, Plugin, PreUpdate};
+use bevy_ecs::prelude::*;
+use bevy_ecs::reflect::AppTypeRegistry;
+use bevy_ecs::system::SystemParam;
+use bevy_reflect::func::args::Ownership;
+use bevy_reflect::func::{ArgError, ArgList, DynamicFunction, FunctionError, IntoFunction, Return};
+use bevy_reflect::Reflect;
+use bevy_utils::tracing::warn;
+use bevy_utils::HashMap;
+use bytes::Bytes;
+use thiserror::Error;
+
+use crate::channel::{
+    ChannelConfig, ChannelKind, DecodeError, MessageApp, MessageReader, MessageWriter,
+    NetworkMessage,
+};
+use crate::quic::{Disconnected, NetworkSystem};
+use crate::varint::{read_varint, write_varint};
+use crate::wire::{NetworkDeserializer, NetworkSerializer, NetworkTypeIds, WireError};
+
+/// Adds remote procedure calls to an [`App`].
+///
+/// Requires the [`QuicNetworkPlugin`](crate::quic::QuicNetworkPlugin). Both peers must add this
+/// plugin.
+#[derive(Default)]
+pub struct RpcPlugin;
+
+impl Plugin for RpcPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_non_send_resource::<RpcFunctions>()
+            .init_resource::<PendingCalls>()
+            .add_message::<RpcMessage>(ChannelConfig::new(ChannelKind::ReliableOrdered))
+            .observe(cancel_pending_calls)
+            .add_systems(PreUpdate, handle_rpc_messages.after(NetworkSystem::Receive));
+    }
+
+    fn finish(&self, app: &mut App) {
+        let ids = NetworkTypeIds::from_registry(&app.world().resource::<AppTypeRegistry>().read());
+        app.insert_resource(ids);
+    }
+}
+
+/// Registers functions callable by remote peers on an [`App`].
+pub trait RpcApp {
+    /// Registers `function` to be called by remote peers under `name`.
+    ///
+    /// Requires the [`RpcPlugin`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if a function was already registered under `name`.
+    fn register_rpc<Marker>(
+        &mut self,
+        name: impl Into<Cow<'static, str>>,
+        function: impl IntoFunction<Marker>,
+    ) -> &mut Self;
+}
+
+impl RpcApp for App {
+    fn register_rpc<Marker>(
+        &mut self,
+        name: impl Into<Cow<'static, str>>,
+        function: impl IntoFunction<Marker>,
+    ) -> &mut Self {
+        let name = name.into();
+        let function = function.into_function().with_name(name.clone());
+        let mut functions = self.world_mut().non_send_resource_mut::<RpcFunctions>();
+        assert!(
+            !functions.0.contains_key(&name),
+            "a remote procedure named `{name}` was already registered"
+        );
+        functions.0.insert(name, function);
+        self
+    }
+}
+
+/// The functions registered with [`RpcApp::register_rpc`], by name.
+///
+/// A non-send resource, as [`DynamicFunction`]s are not [`Send`].
+#[derive(Default)]
+pub struct RpcFunctions(HashMap<Cow<'static, str>, DynamicFunction>);
+
+impl RpcFunctions {
+    /// Gets the function registered under `name`.
+    pub fn get(&self, name: &str) -> Option<&DynamicFunction> {
+        self.0.get(name)
+    }
+}
+
+/// An error that made a remote procedure call fail.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+    /// No function was registered under the called name.
+    #[error("no remote procedure is named `{0}`")]
+    UnknownProcedure(String),
+    /// The function was called with the wrong number of arguments.
+    #[error("expected {expected} arguments but received {received}")]
+    ArgCountMismatch {
+        /// The number of arguments the function takes.
+        expected: usize,
+        /// The number of arguments it was called with.
+        received: usize,
+    },
+    /// An argument was not of the type the function takes.
+    #[error("expected `{expected}` but received `{received}` (@ argument index {index})")]
+    ArgTypeMismatch {
+        /// The index of the argument.
+        index: usize,
+        /// The type path of the type the function takes.
+        expected: String,
+        /// The type path of the received argument.
+        received: String,
+    },
+    /// The function could not be called for another reason.
+    #[error("failed to call the remote procedure: {0}")]
+    InvalidCall(String),
+    /// An argument or the return value could not be encoded or decoded.
+    #[error("failed to send a value: {0}")]
+    Wire(String),
+    /// The connection closed before the response arrived.
+    #[error("the connection closed before the remote procedure returned")]
+    Disconnected,
+}
+
+impl From<WireError> for RpcError {
+    fn from(error: WireError) -> Self {
+        Self::Wire(error.to_string())
+    }
+}
+
+impl From<FunctionError> for RpcError {
+    fn from(error: FunctionError) -> Self {
+        match error {
+            FunctionError::ArgCountMismatch { expected, received } => {
+                Self::ArgCountMismatch { expected, received }
+            }
+            FunctionError::ArgError(ArgError::UnexpectedType {
+                index,
+                expected,
+                received,
+            }) => Self::ArgTypeMismatch {
+                index,
+                expected: expected.into_owned(),
+                received: received.into_owned(),
+            },
+            error => Self::InvalidCall(error.to_string()),
+        }
+    }
+}
+
+/// The arguments of a remote procedure call, in order.
+#[derive(Debug, Default)]
+pub struct RpcArgs(Vec<Box<dyn Reflect>>);
+
+impl RpcArgs {
+    /// Creates an empty argument list.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an argument.
+    pub fn with(mut self, arg: impl Reflect) -> Self {
+        self.0.push(Box::new(arg));
+        self
+    }
+
+    /// Appends a boxed argument.
+    pub fn with_boxed(mut self, arg: Box<dyn Reflect>) -> Self {
+        self.0.push(arg);
+        self
+    }
+}
+
+/// The result of a remote procedure call: the value returned by the function, or `()` if it
+/// returns nothing.
+pub type RpcResult = Result<Box<dyn Reflect>, RpcError>;
+
+/// The pending response of a call made with [`RpcSender::call`].
+///
+/// Either poll it from a system with [`try_response`](Self::try_response), or await
+/// [`response`](Self::response) from an async task.
+#[derive(Debug)]
+pub struct RpcResponse {
+    receiver: Receiver<RpcResult>,
+}
+
+impl RpcResponse {
+    /// Takes the result of the call if it has arrived.
+    pub fn try_response(&mut self) -> Option<RpcResult> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Whether the result of the call has yet to arrive.
+    pub fn is_pending(&self) -> bool {
+        self.receiver.is_empty()
+    }
+
+    /// Waits for the result of the call.
+    pub async fn response(self) -> RpcResult {
+        self.receiver
+            .recv()
+            .await
+            .unwrap_or(Err(RpcError::Disconnected))
+    }
+}
+
+/// The calls waiting for a response, by call identifier.
+#[derive(Resource, Default)]
+struct PendingCalls {
+    next_id: u64,
+    calls: HashMap<u64, (Entity, Sender<RpcResult>)>,
+}
+
+/// Calls the functions registered by remote peers.
+#[derive(SystemParam)]
+pub struct RpcSender<'w> {
+    writer: MessageWriter<'w, RpcMessage>,
+    registry: Res<'w, AppTypeRegistry>,
+    ids: Res<'w, NetworkTypeIds>,
+    pending: ResMut<'w, PendingCalls>,
+}
+
+impl RpcSender<'_> {
+    /// Calls the function named `name` of the peer of the connection entity `connection`, and
+    /// returns its pending response.
+    ///
+    /// Fails if an argument cannot be encoded.
+    pub fn call(
+        &mut self,
+        connection: Entity,
+        name: &str,
+        args: RpcArgs,
+    ) -> Result<RpcResponse, RpcError> {
+        let id = self.pending.next_id;
+        let args = self.encode_args(args)?;
+        self.pending.next_id += 1;
+
+        let (sender, receiver) = async_channel::bounded(1);
+        self.pending.calls.insert(id, (connection, sender));
+        self.writer.send(
+            connection,
+            RpcMessage::Call {
+                id: Some(id),
+                name: name.to_string(),
+                args,
+            },
+        );
+        Ok(RpcResponse { receiver })
+    }
+
+    /// Calls the function named `name` of the peer of the connection entity `connection`, without
+    /// waiting for a response.
+    ///
+    /// Fails if an argument cannot be encoded.
+    pub fn notify(
+        &mut self,
+        connection: Entity,
+        name: &str,
+        args: RpcArgs,
+    ) -> Result<(), RpcError> {
+        let args = self.encode_args(args)?;
+        self.writer.send(
+            connection,
+            RpcMessage::Call {
+                id: None,
+                name: name.to_string(),
+                args,
+            },
+        );
+        Ok(())
+    }
+
+    /// Calls the function named `name` of every connected peer, without waiting for responses.
+    ///
+    /// Fails if an argument cannot be encoded.
+    pub fn broadcast(&mut self, name: &str, args: RpcArgs) -> Result<(), RpcError> {
+        let args = self.encode_args(args)?;
+        self.writer.broadcast(RpcMessage::Call {
+            id: None,
+            name: name.to_string(),
+            args,
+        });
+        Ok(())
+    }
+
+    fn encode_args(&self, args: RpcArgs) -> Result<Bytes, RpcError> {
+        let registry = self.registry.read();
+        let serializer = NetworkSerializer::new(&registry, &self.ids);
+        let mut buf = Vec::new();
+        write_varint(&mut buf, args.0.len() as u64);
+        for arg in &args.0 {
+            serializer.serialize(&**arg, &mut buf)?;
+        }
+        Ok(buf.into())
+    }
+}
+
+fn handle_rpc_messages(
+    reader: MessageReader<RpcMessage>,
+    mut writer: MessageWriter<RpcMessage>,
+    functions: NonSend<RpcFunctions>,
+    registry: Res<AppTypeRegistry>,
+    ids: Res<NetworkTypeIds>,
+    mut pending: ResMut<PendingCalls>,
+) {
+    let registry = registry.read();
+    let serializer = NetworkSerializer::new(&registry, &ids);
+    let deserializer = NetworkDeserializer::new(&registry, &ids);
+
+    for (connection, message) in reader.read() {
+        match message {
+            RpcMessage::Call { id, name, args } => {
+                let result = call(&functions, name, args, &serializer, &deserializer);
+                match (id, result) {
+                    (Some(id), result) => writer.send(
+                        connection,
+                        RpcMessage::Response {
+                            id: *id,
+                            result: result.map(Bytes::from),
+                        },
+                    ),
+                    (None, Err(error)) => {
+                        warn!(
+                            "remote procedure call to `{name}` from {connection} failed: {error}"
+                        );
+                    }
+                    (None, Ok(_)) => {}
+                }
+            }
+            RpcMessage::Response { id, result } => {
+                let Some((_, sender)) = pending.calls.remove(id) else {
+                    continue;
+                };
+                let result = result.clone().and_then(|value| {
+                    if value.is_empty() {
+                        return Ok(Box::new(()) as Box<dyn Reflect>);
+                    }
+                    Ok(deserializer.deserialize(&mut &value[..])?)
+                });
+                // The caller may have dropped the response.
+                let _ = sender.try_send(result);
+            }
+        }
+    }
+}
+
+/// Calls the function named `name` with the encoded `args`, and encodes its return value.
+fn call(
+    functions: &RpcFunctions,
+    name: &str,
+    mut args: &[u8],
+    serializer: &NetworkSerializer,
+    deserializer: &NetworkDeserializer,
+) -> Result<Vec<u8>, RpcError> {
+    let function = functions
+        .get(name)
+        .ok_or_else(|| RpcError::UnknownProcedure(name.to_string()))?;
+
+    let count = read_varint(&mut args).ok_or_else(|| RpcError::Wire("missing arguments".into()))?;
+    let mut values = Vec::new();
+    for _ in 0..count {
+        values.push(Some(deserializer.deserialize(&mut args)?));
+    }
+
+    let info = function.info();
+    if info.arg_count() != values.len() {
+        return Err(RpcError::ArgCountMismatch {
+            expected: info.arg_count(),
+            received: values.len(),
+        });
+    }
+    let mut list = ArgList::new();
+    for (value, arg) in values.iter_mut().zip(info.args()) {
+        list = match arg.ownership() {
+            Ownership::Owned => list.push_boxed(value.take().unwrap()),
+            Ownership::Ref => list.push_ref(value.as_deref().unwrap()),
+            Ownership::Mut => list.push_mut(value.as_deref_mut().unwrap()),
+        };
+    }
+
+    let mut buf = Vec::new();
+    match function.call(list)? {
+        Return::Unit => {}
+        Return::Owned(value) => serializer.serialize(&*value, &mut buf)?,
+        Return::Ref(value) => serializer.serialize(value, &mut buf)?,
+        Return::Mut(value) => serializer.serialize(value, &mut buf)?,
+    }
+    Ok(buf)
+}
+
+fn cancel_pending_calls(trigger: Trigger<Disconnected>, mut pending: ResMut<PendingCalls>) {
+    let connection = trigger.event().connection;
+    pending.calls.retain(|_, (call_connection, sender)| {
+        if *call_connection != connection {
+            return true;
+        }
+        let _ = sender.try_send(Err(RpcError::Disconnected));
+        false
+    });
+}
+
+/// A remote procedure call or its response, see the [module docs](self) for its format.
+#[derive(Debug, Clone, PartialEq)]
+enum RpcMessage {
+    Call {
+        id: Option<u64>,
+        name: String,
+        args: Bytes,
+    },
+    Response {
+        id: u64,
+        result: Result<Bytes, RpcError>,
+    },
+}
+
+/// Tags a call without a response.
+const NOTIFY: u64 = 0;
+/// Tags a call with a response.
+const CALL: u64 = 1;
+/// Tags the return value of a call.
+const RETURN: u64 = 2;
+/// Tags the error of a call.
+const ERROR: u64 = 3;
+
+impl NetworkMessage for RpcMessage {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::Call { id, name, args } => {
+                match id {
+                    Some(id) => {
+                        write_varint(buf, CALL);
+                        write_varint(buf, *id);
+                    }
+                    None => write_varint(buf, NOTIFY),
+                }
+                write_string(buf, name);
+                buf.extend_from_slice(args);
+            }
+            Self::Response {
+                id,
+                result: Ok(value),
+            } => {
+                write_varint(buf, RETURN);
+                write_varint(buf, *id);
+                buf.extend_from_slice(value);
+            }
+            Self::Response {
+                id,
+                result: Err(error),
+            } => {
+                write_varint(buf, ERROR);
+                write_varint(buf, *id);
+                encode_error(error, buf);
+            }
+        }
+    }
+
+    fn decode(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let malformed = || DecodeError("malformed remote procedure call".into());
+        let bytes = &mut bytes;
+        let message = match read_varint(bytes).ok_or_else(malformed)? {
+            tag @ (NOTIFY | CALL) => Self::Call {
+                id: match tag {
+                    CALL => Some(read_varint(bytes).ok_or_else(malformed)?),
+                    _ => None,
+                },
+                name: read_string(bytes).ok_or_else(malformed)?,
+                args: Bytes::copy_from_slice(bytes),
+            },
+            RETURN => Self::Response {
+                id: read_varint(bytes).ok_or_else(malformed)?,
+                result: Ok(Bytes::copy_from_slice(bytes)),
+            },
+            ERROR => Self::Response {
+                id: read_varint(bytes).ok_or_else(malformed)?,
+                result: Err(decode_error(bytes).ok_or_else(malformed)?),
+            },
+            _ => return Err(malformed()),
+        };
+        Ok(message)
+    }
+}
+
+fn encode_error(error: &RpcError, buf: &mut Vec<u8>) {
+    match error {
+        RpcError::UnknownProcedure(name) => {
+            write_varint(buf, 0);
+            write_string(buf, name);
+        }
+        RpcError::ArgCountMismatch { expected, received } => {
+            write_varint(buf, 1);
+            write_varint(buf, *expected as u64);
+            write_varint(buf, *received as u64);
+        }
+        RpcError::ArgTypeMismatch {
+            index,
+            expected,
+            received,
+        } => {
+            write_varint(buf, 2);
+            write_varint(buf, *index as u64);
+            write_string(buf, expected);
+            write_string(buf, received);
+        }
+        RpcError::InvalidCall(message) => {
+            write_varint(buf, 3);
+            write_string(buf, message);
+        }
+        RpcError::Wire(message) => {
+            write_varint(buf, 4);
+            write_string(buf, message);
+        }
+        RpcError::Disconnected => write_varint(buf, 5),
+    }
+}
+
+fn decode_error(bytes: &mut &[u8]) -> Option<RpcError> {
+    let error = match read_varint(bytes)? {
+        0 => RpcError::UnknownProcedure(read_string(bytes)?),
+        1 => RpcError::ArgCountMismatch {
+            expected: read_varint(bytes)?.try_into().ok()?,
+            received: read_varint(bytes)?.try_into().ok()?,
+        },
+        2 => RpcError::ArgTypeMismatch {
+            index: read_varint(bytes)?.try_into().ok()?,
+            expected: read_string(bytes)?,
+            received: read_string(bytes)?,
+        },
+        3 => RpcError::InvalidCall(read_string(bytes)?),
+        4 => RpcError::Wire(read_string(bytes)?),
+        5 => RpcError::Disconnected,
+        _ => return None,
+    };
+    Some(error)
+}
+
+fn write_string(buf: &mut Vec<u8>, value: &str) {
+    write_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+fn read_string(bytes: &mut &[u8]) -> Option<String> {
+    let len = usize::try_from(read_varint(bytes)?).ok()?;
+    let value = bytes.get(..len)?;
+    *bytes = &bytes[len..];
+    String::from_utf8(value.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_messages() {
+        let messages = [
+            RpcMessage::Call {
+                id: None,
+                name: "spawn".into(),
+                args: Bytes::from_static(&[1, 2, 3]),
+            },
+            RpcMessage::Call {
+                id: Some(300),
+                name: "add".into(),
+                args: Bytes::new(),
+            },
+            RpcMessage::Response {
+                id: 300,
+                result: Ok(Bytes::from_static(&[4])),
+            },
+            RpcMessage::Response {
+                id: 7,
+                result: Err(RpcError::ArgTypeMismatch {
+                    index: 1,
+                    expected: "i32".into(),
+                    received: "u8".into(),
+                }),
+            },
+        ];
+        for message in messages {
+            let mut buf = Vec::new();
+            message.encode(&mut buf);
+            assert_eq!(RpcMessage::decode(&buf), Ok(message));
+        }
+        assert!(RpcMessage::decode(&[ERROR as u8, 1, 9]).is_err());
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod quic_tests {
+    use super::*;
+    use crate::quic::test_utils::{connected_apps, update_until};
+    use crate::quic::QuicEntityCommands;
+    use crate::wire::ReflectNetworked;
+    use bevy_ecs::system::RunSystemOnce;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Reflect, Debug, PartialEq)]
+    #[reflect(Networked)]
+    struct Greeting(String);
+
+    static PINGS: AtomicU32 = AtomicU32::new(0);
+
+    fn add(a: i32, b: i32) -> i32 {
+        a + b
+    }
+
+    fn greet(name: &Greeting) -> Greeting {
+        Greeting(format!("hello {}", name.0))
+    }
+
+    fn ping() {
+        PINGS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn setup(app: &mut App) {
+        app.register_type::<Greeting>()
+            .register_type_data::<i32, ReflectNetworked>()
+            .add_plugins(RpcPlugin)
+            .register_rpc("add", add)
+            .register_rpc("greet", greet)
+            .register_rpc("ping", ping);
+    }
+
+    #[test]
+    fn calls_remote_functions() {
+        let (mut server, mut client, server_connection, client_connection) = connected_apps(setup);
+
+        let call = |client: &mut App, name: &'static str, args: fn() -> RpcArgs| {
+            client
+                .world_mut()
+                .run_system_once(move |mut rpc: RpcSender| {
+                    rpc.call(client_connection, name, args()).unwrap()
+                })
+        };
+        let mut responses = [
+            call(&mut client, "add", || RpcArgs::new().with(2).with(3)),
+            call(&mut client, "greet", || {
+                RpcArgs::new().with(Greeting("bevy".into()))
+            }),
+            call(&mut client, "ping", RpcArgs::new),
+            call(&mut client, "sub", || RpcArgs::new().with(2).with(3)),
+            call(&mut client, "add", || RpcArgs::new().with(2)),
+            call(&mut client, "add", || {
+                RpcArgs::new().with(2).with(Greeting("three".into()))
+            }),
+        ];
+
+        let mut results: [_; 6] = std::array::from_fn(|_| None);
+        update_until([&mut server, &mut client], |_| {
+            for (response, result) in responses.iter_mut().zip(&mut results) {
+                if result.is_none() {
+                    *result = response.try_response();
+                }
+            }
+            results.iter().all(Option::is_some)
+        });
+        let [add, greet, ping, unknown, missing, mismatch] = results.map(Option::unwrap);
+
+        assert_eq!(add.unwrap().take::<i32>().ok(), Some(5));
+        assert_eq!(
+            greet.unwrap().take::<Greeting>().ok(),
+            Some(Greeting("hello bevy".into()))
+        );
+        assert!(ping.unwrap().is::<()>());
+        assert_eq!(
+            unknown.unwrap_err(),
+            RpcError::UnknownProcedure("sub".into())
+        );
+        assert_eq!(
+            missing.unwrap_err(),
+            RpcError::ArgCountMismatch {
+                expected: 2,
+                received: 1
+            }
+        );
+        assert!(matches!(
+            mismatch.unwrap_err(),
+            RpcError::ArgTypeMismatch { index: 1, .. }
+        ));
+
+        // Arguments must be networked.
+        let error = client
+            .world_mut()
+            .run_system_once(move |mut rpc: RpcSender| {
+                rpc.notify(client_connection, "add", RpcArgs::new().with(1u8).with(2u8))
+            });
+        assert!(matches!(error, Err(RpcError::Wire(_))));
+
+        // Servers call their clients too.
+        let pings = PINGS.load(Ordering::Relaxed);
+        server
+            .world_mut()
+            .run_system_once(|mut rpc: RpcSender| rpc.broadcast("ping", RpcArgs::new()))
+            .unwrap();
+        update_until([&mut server, &mut client], |_| {
+            PINGS.load(Ordering::Relaxed) > pings
+        });
+
+        // Pending calls fail once the connection closes.
+        let response = server.world_mut().run_system_once(
+            move |mut rpc: RpcSender, mut commands: Commands| {
+                commands
+                    .entity(server_connection)
+                    .disconnect(0u32.into(), "bye");
+                rpc.call(server_connection, "ping", RpcArgs::new()).unwrap()
+            },
+        );
+        update_until([&mut server, &mut client], |_| !response.is_pending());
+        assert_eq!(
+            bevy_tasks::block_on(response.response()).unwrap_err(),
+            RpcError::Disconnected
+        );
+    }
+}
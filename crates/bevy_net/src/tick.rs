@@ -0,0 +1,466 @@
+//! A simulation tick shared by the server and its clients.
+//!
+//! The [`NetworkTick`] resource counts the steps of the [`Time<Fixed>`] clock, and is advanced
+//! in [`FixedFirst`] on every peer. The server's tick is the authoritative one: clients
+//! periodically ping the server over their [`Outgoing`](ConnectionDirection::Outgoing)
+//! connections to estimate the round trip time and the server's current tick, and keep their own
+//! tick [`ClockSyncConfig::ticks_ahead`] ticks ahead of the tick the server will have reached by the
+//! time their messages arrive. A client follows the clock of the first server to answer, until
+//! that connection closes.
+//!
+//! Clients stay in sync by nudging the timestep of their [`Time<Fixed>`] clock to run slightly
+//! faster or slower than the server's, and only jump straight to the right tick when they are too
+//! far off. The fixed clock keeps following [`Time<Virtual>`](bevy_time::Virtual), so pausing or
+//! slowing down virtual time on a client makes it fall behind the server.
+
+use bevy_app::{App, FixedFirst, Plugin, PostUpdate, PreUpdate};
+use bevy_ecs::prelude::*;
+use bevy_time::{Fixed, Real, Time};
+use bevy_utils::Duration;
+
+use crate::channel::{
+    ChannelConfig, ChannelKind, DecodeError, MessageApp, MessageReader, MessageWriter,
+    NetworkMessage,
+};
+use crate::quic::{ConnectionDirection, ConnectionState, Disconnected, NetworkSystem};
+use crate::varint::{read_varint, write_varint};
+
+/// Adds the [`NetworkTick`] and its synchronization with the server to an [`App`].
+///
+/// Requires the [`QuicNetworkPlugin`](crate::quic::QuicNetworkPlugin) and the
+/// [`TimePlugin`](bevy_time::TimePlugin). Both peers must add this plugin.
+#[derive(Default)]
+pub struct NetworkTickPlugin;
+
+impl Plugin for NetworkTickPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetworkTick>()
+            .init_resource::<ClockSync>()
+            .init_resource::<ClockSyncConfig>()
+            .add_message::<Ping>(ChannelConfig::new(ChannelKind::Unreliable))
+            .add_message::<Pong>(ChannelConfig::new(ChannelKind::Unreliable))
+            .observe(reset_clock_sync)
+            .add_systems(FixedFirst, advance_tick)
+            .add_systems(
+                PreUpdate,
+                sync_clock
+                    .in_set(ClockSyncSystem)
+                    .after(NetworkSystem::Receive),
+            )
+            .add_systems(
+                PostUpdate,
+                (send_pings, answer_pings).before(NetworkSystem::Send),
+            );
+    }
+}
+
+/// Updates the [`ClockSync`] estimates and the fixed timestep of clients.
+///
+/// Runs in [`PreUpdate`], after [`NetworkSystem::Receive`].
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemSet)]
+pub struct ClockSyncSystem;
+
+/// The number of [`Time<Fixed>`] steps simulated so far, agreed upon by the server and its
+/// clients.
+///
+/// During the [`FixedMain`](bevy_app::FixedMain) schedules, this is the tick being simulated.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NetworkTick(u32);
+
+impl NetworkTick {
+    /// Creates a tick from its number.
+    pub const fn new(tick: u32) -> Self {
+        Self(tick)
+    }
+
+    /// The number of this tick.
+    pub const fn get(self) -> u32 {
+        self.0
+    }
+
+    /// Sets the number of this tick.
+    pub fn set(&mut self, tick: u32) {
+        self.0 = tick;
+    }
+}
+
+/// Configures how clients synchronize their [`NetworkTick`] with the server.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct ClockSyncConfig {
+    /// How often to ping the server.
+    pub ping_interval: Duration,
+    /// How many ticks ahead of the server's tick a client should be once its messages reach the
+    /// server, as a safety margin for jitter.
+    pub ticks_ahead: u32,
+    /// The largest fraction the fixed timestep is shortened or lengthened by to catch up with the
+    /// target tick.
+    ///
+    /// Clamped between zero and [`Self::MAX_ADJUSTMENT`].
+    pub max_adjustment: f64,
+    /// How many ticks away from the target tick a client may be before it jumps straight to it.
+    pub snap_threshold: u32,
+}
+
+impl ClockSyncConfig {
+    /// The largest value [`max_adjustment`](Self::max_adjustment) is clamped to, as lengthening
+    /// the timestep by a fraction of one or more would stop the fixed clock.
+    pub const MAX_ADJUSTMENT: f64 = 0.9;
+}
+
+impl Default for ClockSyncConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_millis(100),
+            ticks_ahead: 2,
+            max_adjustment: 0.05,
+            snap_threshold: 10,
+        }
+    }
+}
+
+/// A client's estimate of the server's clock.
+///
+/// Reset when the connection to the server closes.
+#[derive(Resource, Debug, Default)]
+pub struct ClockSync {
+    /// The connection to the server the estimates are about.
+    connection: Option<Entity>,
+    rtt: Option<Duration>,
+    /// The server's tick, estimated at some [`Time<Real>`] elapsed time.
+    anchor: Option<(Duration, f64)>,
+    server_timestep: Duration,
+    server_tick: Option<f64>,
+    last_ping: Option<Duration>,
+}
+
+/// How much a new measurement weighs against the previous estimates.
+const SMOOTHING: f64 = 0.1;
+/// How much faster the fixed clock runs for every tick it is behind its target.
+const ADJUSTMENT_PER_TICK: f64 = 0.01;
+
+impl ClockSync {
+    /// Whether the server answered at least one ping.
+    pub fn is_synced(&self) -> bool {
+        self.anchor.is_some()
+    }
+
+    /// The connection to the server the clock is synchronized with.
+    pub fn connection(&self) -> Option<Entity> {
+        self.connection
+    }
+
+    /// The smoothed round trip time to the server.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// The fixed timestep of the server.
+    pub fn server_timestep(&self) -> Option<Duration> {
+        self.is_synced().then_some(self.server_timestep)
+    }
+
+    /// The estimated tick of the server as of this frame, including the fraction of the next tick
+    /// that has elapsed.
+    pub fn server_tick(&self) -> Option<f64> {
+        self.server_tick
+    }
+
+    /// Extrapolates the server's tick at `now`, the [`Time<Real>`] elapsed time.
+    fn estimate(&self, now: Duration) -> Option<f64> {
+        let (at, tick) = self.anchor?;
+        let elapsed = now.as_secs_f64() - at.as_secs_f64();
+        Some(tick + elapsed / self.server_timestep.as_secs_f64())
+    }
+}
+
+/// Sent by clients to measure the round trip time, holding the [`Time<Real>`] elapsed time.
+struct Ping {
+    sent: Duration,
+}
+
+/// The server's answer to a [`Ping`].
+struct Pong {
+    sent: Duration,
+    tick: u32,
+    overstep: Duration,
+    timestep: Duration,
+}
+
+impl NetworkMessage for Ping {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write_varint(buf, self.sent.as_nanos() as u64);
+    }
+
+    fn decode(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let sent = read_varint(&mut bytes).ok_or_else(|| DecodeError("malformed ping".into()))?;
+        Ok(Self {
+            sent: Duration::from_nanos(sent),
+        })
+    }
+}
+
+impl NetworkMessage for Pong {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write_varint(buf, self.sent.as_nanos() as u64);
+        write_varint(buf, u64::from(self.tick));
+        write_varint(buf, self.overstep.as_nanos() as u64);
+        write_varint(buf, self.timestep.as_nanos() as u64);
+    }
+
+    fn decode(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut read =
+            || read_varint(&mut bytes).ok_or_else(|| DecodeError("malformed pong".into()));
+        let sent = Duration::from_nanos(read()?);
+        let tick = u32::try_from(read()?).map_err(|_| DecodeError("invalid tick".into()))?;
+        let overstep = Duration::from_nanos(read()?);
+        let timestep = Duration::from_nanos(read()?);
+        if timestep.is_zero() {
+            return Err(DecodeError("zero timestep".into()));
+        }
+        Ok(Self {
+            sent,
+            tick,
+            overstep,
+            timestep,
+        })
+    }
+}
+
+fn advance_tick(mut tick: ResMut<NetworkTick>) {
+    tick.0 = tick.0.wrapping_add(1);
+}
+
+fn send_pings(
+    config: Res<ClockSyncConfig>,
+    real: Res<Time<Real>>,
+    mut sync: ResMut<ClockSync>,
+    connections: Query<(Entity, &ConnectionDirection, &ConnectionState)>,
+    mut writer: MessageWriter<Ping>,
+) {
+    let now = real.elapsed();
+    if sync
+        .last_ping
+        .is_some_and(|last| now < last + config.ping_interval)
+    {
+        return;
+    }
+
+    for (connection, direction, state) in &connections {
+        // Until a server answers, every server is pinged.
+        if sync.connection.is_some_and(|synced| synced != connection) {
+            continue;
+        }
+        if *direction == ConnectionDirection::Outgoing && *state == ConnectionState::Connected {
+            writer.send(connection, Ping { sent: now });
+            sync.last_ping = Some(now);
+        }
+    }
+}
+
+fn answer_pings(
+    tick: Res<NetworkTick>,
+    fixed: Res<Time<Fixed>>,
+    pings: MessageReader<Ping>,
+    directions: Query<&ConnectionDirection>,
+    mut writer: MessageWriter<Pong>,
+) {
+    for (connection, ping) in pings.read() {
+        if directions.get(connection) == Ok(&ConnectionDirection::Incoming) {
+            let pong = Pong {
+                sent: ping.sent,
+                tick: tick.0,
+                overstep: fixed.overstep(),
+                timestep: fixed.timestep(),
+            };
+            writer.send(connection, pong);
+        }
+    }
+}
+
+fn sync_clock(
+    config: Res<ClockSyncConfig>,
+    real: Res<Time<Real>>,
+    pongs: MessageReader<Pong>,
+    directions: Query<&ConnectionDirection>,
+    mut sync: ResMut<ClockSync>,
+    mut fixed: ResMut<Time<Fixed>>,
+    mut tick: ResMut<NetworkTick>,
+) {
+    let now = real.elapsed();
+    for (connection, pong) in pongs.read() {
+        if directions.get(connection) != Ok(&ConnectionDirection::Outgoing)
+            || sync.connection.is_some_and(|synced| synced != connection)
+            || pong.sent > now
+        {
+            continue;
+        }
+        sync.connection = Some(connection);
+
+        let rtt = now - pong.sent;
+        sync.rtt = Some(match sync.rtt {
+            Some(smoothed) => smoothed.mul_f64(1.0 - SMOOTHING) + rtt.mul_f64(SMOOTHING),
+            None => rtt,
+        });
+
+        let measured = f64::from(pong.tick)
+            + (pong.overstep + rtt / 2).as_secs_f64() / pong.timestep.as_secs_f64();
+        let tick = match sync.estimate(now) {
+            Some(estimate) if (measured - estimate).abs() < f64::from(config.snap_threshold) => {
+                estimate + SMOOTHING * (measured - estimate)
+            }
+            _ => measured,
+        };
+        sync.anchor = Some((now, tick));
+        sync.server_timestep = pong.timestep;
+    }
+
+    let Some(server_tick) = sync.estimate(now) else {
+        return;
+    };
+    sync.server_tick = Some(server_tick);
+
+    let timestep = sync.server_timestep;
+    let latency = sync.rtt.unwrap_or_default() / 2;
+    let target = server_tick
+        + latency.as_secs_f64() / timestep.as_secs_f64()
+        + f64::from(config.ticks_ahead);
+    let error = target - (f64::from(tick.0) + fixed.overstep_fraction_f64());
+
+    if error.abs() > f64::from(config.snap_threshold) {
+        tick.0 = target.max(0.0) as u32;
+        fixed.set_timestep(timestep);
+    } else {
+        fixed.set_timestep(adjusted_timestep(timestep, error, config.max_adjustment));
+    }
+}
+
+/// Forgets the estimates of a server once the connection to it closes, so the samples of the old
+/// link do not skew the clock after reconnecting.
+fn reset_clock_sync(trigger: Trigger<Disconnected>, mut sync: ResMut<ClockSync>) {
+    if sync.connection == Some(trigger.event().connection) {
+        *sync = ClockSync::default();
+    }
+}
+
+/// Shortens `timestep` when the client is `error` ticks behind its target, or lengthens it when
+/// it is ahead.
+fn adjusted_timestep(timestep: Duration, error: f64, max_adjustment: f64) -> Duration {
+    let max_adjustment = if max_adjustment.is_nan() {
+        0.0
+    } else {
+        max_adjustment.clamp(0.0, ClockSyncConfig::MAX_ADJUSTMENT)
+    };
+    let adjustment = (error * ADJUSTMENT_PER_TICK).clamp(-max_adjustment, max_adjustment);
+    timestep.div_f64(1.0 + adjustment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nudges_the_timestep_towards_the_target() {
+        let timestep = Duration::from_millis(16);
+        assert!(adjusted_timestep(timestep, 2.0, 0.05) < timestep);
+        assert!(adjusted_timestep(timestep, -2.0, 0.05) > timestep);
+        assert_eq!(adjusted_timestep(timestep, 0.0, 0.05), timestep);
+        assert_eq!(
+            adjusted_timestep(timestep, 1000.0, 0.05),
+            timestep.div_f64(1.05)
+        );
+    }
+
+    #[test]
+    fn clamps_the_maximum_adjustment() {
+        let timestep = Duration::from_millis(16);
+        let max = ClockSyncConfig::MAX_ADJUSTMENT;
+        assert_eq!(
+            adjusted_timestep(timestep, -1000.0, 2.0),
+            timestep.div_f64(1.0 - max)
+        );
+        assert_eq!(
+            adjusted_timestep(timestep, 1000.0, 2.0),
+            timestep.div_f64(1.0 + max)
+        );
+        assert_eq!(adjusted_timestep(timestep, 1000.0, -1.0), timestep);
+        assert_eq!(adjusted_timestep(timestep, 1000.0, f64::NAN), timestep);
+    }
+
+    #[test]
+    fn round_trips_pongs() {
+        let pong = Pong {
+            sent: Duration::from_millis(1500),
+            tick: 42,
+            overstep: Duration::from_micros(300),
+            timestep: Duration::from_micros(15625),
+        };
+        let mut buf = Vec::new();
+        pong.encode(&mut buf);
+        let decoded = Pong::decode(&buf).unwrap();
+        assert_eq!(decoded.sent, pong.sent);
+        assert_eq!(decoded.tick, pong.tick);
+        assert_eq!(decoded.overstep, pong.overstep);
+        assert_eq!(decoded.timestep, pong.timestep);
+
+        assert!(Pong::decode(&buf[..3]).is_err());
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod quic_tests {
+    use super::*;
+    use crate::quic::test_utils::{connected_apps, update_until};
+    use crate::quic::QuicEntityCommands;
+    use bevy_time::TimePlugin;
+
+    #[test]
+    fn client_stays_ahead_of_the_server() {
+        let (mut server, mut client, _, _) = connected_apps(|app| {
+            app.add_plugins((TimePlugin, NetworkTickPlugin));
+        });
+        server.world_mut().resource_mut::<NetworkTick>().set(1000);
+
+        let ahead = |[server, client]: [&App; 2]| {
+            let server = server.world().resource::<NetworkTick>().get();
+            let client = client.world().resource::<NetworkTick>().get();
+            i64::from(client) - i64::from(server)
+        };
+        update_until([&mut server, &mut client], |[server, client]| {
+            client.world().resource::<ClockSync>().is_synced()
+                && (ahead([server, client]) - 2).abs() <= 1
+        });
+
+        let sync = client.world().resource::<ClockSync>();
+        assert!(sync.rtt().unwrap() < Duration::from_secs(1));
+        assert_eq!(
+            sync.server_timestep(),
+            Some(server.world().resource::<Time<Fixed>>().timestep())
+        );
+    }
+
+    #[test]
+    fn resets_the_clock_sync_on_disconnect() {
+        let (mut server, mut client, _, client_connection) = connected_apps(|app| {
+            app.add_plugins((TimePlugin, NetworkTickPlugin));
+        });
+        update_until([&mut server, &mut client], |[_, client]| {
+            client.world().resource::<ClockSync>().is_synced()
+        });
+        assert_eq!(
+            client.world().resource::<ClockSync>().connection(),
+            Some(client_connection)
+        );
+
+        client
+            .world_mut()
+            .commands()
+            .entity(client_connection)
+            .disconnect(0u32.into(), "bye");
+        update_until([&mut server, &mut client], |[_, client]| {
+            !client.world().resource::<ClockSync>().is_synced()
+        });
+        let sync = client.world().resource::<ClockSync>();
+        assert_eq!(sync.connection(), None);
+        assert_eq!(sync.rtt(), None);
+    }
+}
@@ -0,0 +1,460 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use bevy_utils::HashMap;
+use bytes::Bytes;
+use crossbeam_channel::{Receiver, Sender};
+
+use super::{
+    NetTransport, TransportConnectionId, TransportError, TransportEvent, TransportMessage,
+    TransportStats,
+};
+use crate::channel::{ChannelId, ChannelKind};
+
+/// The first port assigned to transports bound to port `0`.
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+/// An in-memory network, connecting the [`MemoryTransport`]s bound on it.
+///
+/// Useful to run a server and its clients in the same process, such as in tests, without any
+/// sockets or encryption:
+///
+/// ```
+/// # use bevy_net::transport::{MemoryNetwork, NetTransport};
+/// let network = MemoryNetwork::default();
+/// let server = network.bind("127.0.0.1:5000".parse().unwrap()).unwrap();
+/// let mut client = network.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+/// let connection = client.connect(server.local_addr(), "").unwrap();
+/// ```
+///
+/// Clones of a network refer to the same network.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryNetwork {
+    transports: Arc<Mutex<Transports>>,
+}
+
+#[derive(Debug, Default)]
+struct Transports {
+    inboxes: HashMap<SocketAddr, Sender<Packet>>,
+    next_port: u16,
+}
+
+impl MemoryNetwork {
+    /// Binds a transport to `addr`, or to an unused port of its IP if its port is `0`.
+    ///
+    /// The transport is unbound once dropped.
+    pub fn bind(&self, mut addr: SocketAddr) -> io::Result<MemoryTransport> {
+        let mut transports = self.transports.lock().unwrap();
+
+        if addr.port() == 0 {
+            let start = transports.next_port.max(FIRST_EPHEMERAL_PORT);
+            let port = (start..=u16::MAX)
+                .chain(FIRST_EPHEMERAL_PORT..start)
+                .find(|port| {
+                    !transports
+                        .inboxes
+                        .contains_key(&SocketAddr::new(addr.ip(), *port))
+                })
+                .ok_or(ErrorKind::AddrInUse)?;
+            transports.next_port = port.wrapping_add(1);
+            addr.set_port(port);
+        } else if transports.inboxes.contains_key(&addr) {
+            return Err(ErrorKind::AddrInUse.into());
+        }
+
+        let (sender, inbox) = crossbeam_channel::unbounded();
+        transports.inboxes.insert(addr, sender);
+        Ok(MemoryTransport {
+            addr,
+            network: self.clone(),
+            inbox,
+            next_id: 0,
+            connections: HashMap::default(),
+            accepted: VecDeque::new(),
+            events: VecDeque::new(),
+            messages: VecDeque::new(),
+        })
+    }
+
+    /// Delivers `packet` to the transport bound to `addr`, returning whether there is one.
+    fn send(&self, addr: SocketAddr, packet: Packet) -> bool {
+        let transports = self.transports.lock().unwrap();
+        transports
+            .inboxes
+            .get(&addr)
+            .is_some_and(|inbox| inbox.send(packet).is_ok())
+    }
+}
+
+/// What [`MemoryTransport`]s send each other, addressed to a connection of the receiver.
+#[derive(Debug)]
+enum Packet {
+    Connect {
+        from: SocketAddr,
+        connection: TransportConnectionId,
+    },
+    Accept {
+        from: SocketAddr,
+        connection: TransportConnectionId,
+        peer: TransportConnectionId,
+    },
+    Message {
+        connection: TransportConnectionId,
+        channel: ChannelId,
+        bytes: Bytes,
+    },
+    Close {
+        connection: TransportConnectionId,
+        error_code: u64,
+        reason: Bytes,
+    },
+}
+
+/// A [`NetTransport`] bound on a [`MemoryNetwork`].
+///
+/// Every channel is delivered reliably and in order, whatever its [`ChannelKind`].
+#[derive(Debug)]
+pub struct MemoryTransport {
+    addr: SocketAddr,
+    network: MemoryNetwork,
+    inbox: Receiver<Packet>,
+    next_id: u64,
+    connections: HashMap<TransportConnectionId, MemoryConnection>,
+    accepted: VecDeque<TransportConnectionId>,
+    events: VecDeque<TransportEvent>,
+    messages: VecDeque<TransportMessage>,
+}
+
+#[derive(Debug)]
+struct MemoryConnection {
+    remote_address: SocketAddr,
+    /// The peer's id for this connection, once it accepted it.
+    peer: Option<TransportConnectionId>,
+    stats: TransportStats,
+}
+
+impl MemoryTransport {
+    /// Gets the address this transport is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn next_id(&mut self) -> TransportConnectionId {
+        self.next_id += 1;
+        TransportConnectionId(self.next_id)
+    }
+
+    /// Handles the packets received since the last call.
+    fn process_inbox(&mut self) {
+        while let Ok(packet) = self.inbox.try_recv() {
+            match packet {
+                Packet::Connect { from, connection } => {
+                    let id = self.next_id();
+                    let accept = Packet::Accept {
+                        from: self.addr,
+                        connection,
+                        peer: id,
+                    };
+                    if self.network.send(from, accept) {
+                        self.connections.insert(
+                            id,
+                            MemoryConnection {
+                                remote_address: from,
+                                peer: Some(connection),
+                                stats: TransportStats::default(),
+                            },
+                        );
+                        self.accepted.push_back(id);
+                    }
+                }
+                Packet::Accept {
+                    from,
+                    connection,
+                    peer,
+                } => {
+                    match self.connections.get_mut(&connection) {
+                        Some(state) => {
+                            state.peer = Some(peer);
+                            self.events
+                                .push_back(TransportEvent::Connected { connection });
+                        }
+                        // The connection was closed before the peer accepted it.
+                        None => {
+                            let close = Packet::Close {
+                                connection: peer,
+                                error_code: 0,
+                                reason: Bytes::new(),
+                            };
+                            self.network.send(from, close);
+                        }
+                    }
+                }
+                Packet::Message {
+                    connection,
+                    channel,
+                    bytes,
+                } => {
+                    if let Some(state) = self.connections.get_mut(&connection) {
+                        state.stats.bytes_received += bytes.len() as u64;
+                        self.messages.push_back(TransportMessage {
+                            connection,
+                            channel,
+                            bytes,
+                        });
+                    }
+                }
+                Packet::Close {
+                    connection,
+                    error_code,
+                    reason,
+                } => {
+                    let Some(state) = self.connections.remove(&connection) else {
+                        continue;
+                    };
+                    let error = TransportError::ClosedByPeer { error_code, reason };
+                    self.events.push_back(match state.peer {
+                        Some(_) => TransportEvent::Disconnected { connection, error },
+                        None => TransportEvent::ConnectionFailed { connection, error },
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl NetTransport for MemoryTransport {
+    fn connect(
+        &mut self,
+        addr: SocketAddr,
+        _server_name: &str,
+    ) -> Result<TransportConnectionId, TransportError> {
+        let connection = self.next_id();
+        let packet = Packet::Connect {
+            from: self.addr,
+            connection,
+        };
+        if !self.network.send(addr, packet) {
+            return Err(TransportError::Unreachable(addr));
+        }
+        self.connections.insert(
+            connection,
+            MemoryConnection {
+                remote_address: addr,
+                peer: None,
+                stats: TransportStats::default(),
+            },
+        );
+        Ok(connection)
+    }
+
+    fn accept(&mut self) -> Option<TransportConnectionId> {
+        self.process_inbox();
+        self.accepted.pop_front()
+    }
+
+    fn poll_event(&mut self) -> Option<TransportEvent> {
+        self.process_inbox();
+        self.events.pop_front()
+    }
+
+    fn send(
+        &mut self,
+        connection: TransportConnectionId,
+        channel: ChannelId,
+        _kind: ChannelKind,
+        payload: &[u8],
+    ) -> Result<(), TransportError> {
+        let state = self
+            .connections
+            .get_mut(&connection)
+            .filter(|state| state.peer.is_some())
+            .ok_or(TransportError::UnknownConnection(connection))?;
+        let packet = Packet::Message {
+            connection: state.peer.unwrap(),
+            channel,
+            bytes: Bytes::copy_from_slice(payload),
+        };
+        if !self.network.send(state.remote_address, packet) {
+            // The peer's transport was dropped without closing the connection.
+            let error = TransportError::Unreachable(state.remote_address);
+            self.connections.remove(&connection);
+            self.events.push_back(TransportEvent::Disconnected {
+                connection,
+                error: error.clone(),
+            });
+            return Err(error);
+        }
+        state.stats.bytes_sent += payload.len() as u64;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Option<TransportMessage> {
+        self.process_inbox();
+        self.messages.pop_front()
+    }
+
+    fn disconnect(&mut self, connection: TransportConnectionId, error_code: u64, reason: &[u8]) {
+        let Some(state) = self.connections.remove(&connection) else {
+            return;
+        };
+        let error = TransportError::LocallyClosed;
+        match state.peer {
+            Some(peer) => {
+                let close = Packet::Close {
+                    connection: peer,
+                    error_code,
+                    reason: Bytes::copy_from_slice(reason),
+                };
+                self.network.send(state.remote_address, close);
+                self.events
+                    .push_back(TransportEvent::Disconnected { connection, error });
+            }
+            // The peer is told once it accepts the connection.
+            None => self
+                .events
+                .push_back(TransportEvent::ConnectionFailed { connection, error }),
+        }
+    }
+
+    fn remote_address(&self, connection: TransportConnectionId) -> Option<SocketAddr> {
+        self.connections
+            .get(&connection)
+            .map(|state| state.remote_address)
+    }
+
+    fn stats(&self, connection: TransportConnectionId) -> Option<TransportStats> {
+        self.connections
+            .get(&connection)
+            .filter(|state| state.peer.is_some())
+            .map(|state| state.stats)
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.network
+            .transports
+            .lock()
+            .unwrap()
+            .inboxes
+            .remove(&self.addr);
+        for (_, state) in self.connections.drain() {
+            if let Some(peer) = state.peer {
+                let close = Packet::Close {
+                    connection: peer,
+                    error_code: 0,
+                    reason: Bytes::new(),
+                };
+                self.network.send(state.remote_address, close);
+            }
+        }
+        // Refuse the connections that were not accepted yet.
+        for packet in self.inbox.try_iter() {
+            if let Packet::Connect { from, connection } = packet {
+                let close = Packet::Close {
+                    connection,
+                    error_code: 0,
+                    reason: Bytes::new(),
+                };
+                self.network.send(from, close);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNEL: ChannelId = ChannelId::new(0);
+
+    fn connected_pair() -> (
+        MemoryTransport,
+        MemoryTransport,
+        TransportConnectionId,
+        TransportConnectionId,
+    ) {
+        let network = MemoryNetwork::default();
+        let mut server = network.bind("127.0.0.1:5000".parse().unwrap()).unwrap();
+        let mut client = network.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let client_connection = client.connect(server.local_addr(), "").unwrap();
+        assert_eq!(client.poll_event(), None);
+        let server_connection = server.accept().unwrap();
+        assert_eq!(
+            server.remote_address(server_connection),
+            Some(client.local_addr())
+        );
+        assert_eq!(
+            client.poll_event(),
+            Some(TransportEvent::Connected {
+                connection: client_connection
+            })
+        );
+        (server, client, server_connection, client_connection)
+    }
+
+    #[test]
+    fn exchanges_messages() {
+        let (mut server, mut client, server_connection, client_connection) = connected_pair();
+
+        for message in ["a", "b", "c"] {
+            client
+                .send(
+                    client_connection,
+                    CHANNEL,
+                    ChannelKind::Unreliable,
+                    message.as_bytes(),
+                )
+                .unwrap();
+        }
+        let received: Vec<_> = std::iter::from_fn(|| server.receive()).collect();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0].connection, server_connection);
+        assert_eq!(received[2].bytes, Bytes::from("c"));
+        assert_eq!(client.stats(client_connection).unwrap().bytes_sent, 3);
+        assert_eq!(server.stats(server_connection).unwrap().bytes_received, 3);
+
+        server.disconnect(server_connection, 4, b"bye");
+        assert_eq!(
+            server.poll_event(),
+            Some(TransportEvent::Disconnected {
+                connection: server_connection,
+                error: TransportError::LocallyClosed,
+            })
+        );
+        assert_eq!(
+            client.poll_event(),
+            Some(TransportEvent::Disconnected {
+                connection: client_connection,
+                error: TransportError::ClosedByPeer {
+                    error_code: 4,
+                    reason: Bytes::from("bye"),
+                },
+            })
+        );
+        assert!(client
+            .send(client_connection, CHANNEL, ChannelKind::Unreliable, b"")
+            .is_err());
+    }
+
+    #[test]
+    fn reports_unreachable_peers() {
+        let network = MemoryNetwork::default();
+        let mut client = network.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = "127.0.0.1:5000".parse().unwrap();
+        assert_eq!(
+            client.connect(addr, ""),
+            Err(TransportError::Unreachable(addr))
+        );
+
+        let (server, mut client, _, client_connection) = connected_pair();
+        drop(server);
+        assert!(matches!(
+            client.poll_event(),
+            Some(TransportEvent::Disconnected { connection, .. }) if connection == client_connection
+        ));
+    }
+}
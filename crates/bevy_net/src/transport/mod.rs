@@ -0,0 +1,197 @@
+//! Transport-agnostic connections, for running the networking plugins on other protocols than
+//! QUIC.
+//!
+//! A [`NetTransport`] connects to and accepts peers, and carries the payloads of the
+//! [message channels](crate::channel) between them. Add one to an entity with a
+//! [`TransportEndPoint`] and the [`TransportPlugin`] manages its connections like the
+//! [`QuicNetworkPlugin`](crate::quic::QuicNetworkPlugin) manages those of an
+//! [`EndPoint`](crate::quic::EndPoint): every connection gets its own entity, is started and closed
+//! with [`QuicEntityCommands`](crate::quic::QuicEntityCommands), and reports its lifecycle through
+//! the same events. Messages, events, replication and everything else built on channels then work
+//! unchanged on top of the transport.
+//!
+//! Two transports are provided:
+//!
+//! - [`QuicTransport`], over an [`EndPoint`](crate::quic::EndPoint).
+//! - [`MemoryTransport`], connecting transports in the same process through a [`MemoryNetwork`].
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bytes::Bytes;
+use thiserror::Error;
+
+use crate::channel::{ChannelConfig, ChannelId, ChannelKind};
+use crate::quic::{ConnectError, ConnectionError, SendDatagramError};
+
+mod memory;
+mod plugin;
+mod quic;
+
+pub use memory::*;
+pub use plugin::*;
+pub use quic::*;
+
+/// A protocol carrying message channels between peers.
+///
+/// Transports are polled: calls return right away, and progress made in the background is picked
+/// up through [`accept`](Self::accept), [`poll_event`](Self::poll_event) and
+/// [`receive`](Self::receive).
+pub trait NetTransport: Send + Sync + 'static {
+    /// Sets the channels the connections carry, in the order of their [`ChannelId`]s.
+    ///
+    /// Called before any connection is made, with the channels of the
+    /// [`MessageRegistry`](crate::channel::MessageRegistry). Transports that don't need to know
+    /// the channels up front can ignore it.
+    fn set_channels(&mut self, _channels: &[ChannelConfig]) {}
+
+    /// Starts connecting to the peer at `addr`.
+    ///
+    /// The returned connection is reported through [`poll_event`](Self::poll_event) once it is
+    /// established or failed. Transports without server authentication ignore `server_name`.
+    fn connect(
+        &mut self,
+        addr: SocketAddr,
+        server_name: &str,
+    ) -> Result<TransportConnectionId, TransportError>;
+
+    /// Takes the next connection a remote peer established with this transport.
+    fn accept(&mut self) -> Option<TransportConnectionId>;
+
+    /// Takes the next change in the state of the connections.
+    fn poll_event(&mut self) -> Option<TransportEvent>;
+
+    /// Sends `payload` on a channel of an established connection.
+    ///
+    /// Transports must provide at least the guarantees of `kind`, and may provide stronger ones.
+    fn send(
+        &mut self,
+        connection: TransportConnectionId,
+        channel: ChannelId,
+        kind: ChannelKind,
+        payload: &[u8],
+    ) -> Result<(), TransportError>;
+
+    /// Takes the next message received on any connection.
+    fn receive(&mut self) -> Option<TransportMessage>;
+
+    /// Closes a connection, sending `error_code` and `reason` to the peer.
+    ///
+    /// The connection is then reported as disconnected with [`TransportError::LocallyClosed`], or
+    /// as failed if it was not established yet. Unknown connections are ignored.
+    fn disconnect(&mut self, connection: TransportConnectionId, error_code: u64, reason: &[u8]);
+
+    /// Gets the address of the peer of a connection.
+    fn remote_address(&self, connection: TransportConnectionId) -> Option<SocketAddr>;
+
+    /// Gets the statistics of an established connection.
+    fn stats(&self, connection: TransportConnectionId) -> Option<TransportStats>;
+}
+
+/// Identifies a connection of a [`NetTransport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TransportConnectionId(u64);
+
+impl TransportConnectionId {
+    /// Creates a connection id from its raw value, which is up to the transport.
+    pub const fn new(id: u64) -> Self {
+        Self(id)
+    }
+
+    /// Gets the raw value of this id.
+    pub fn get(self) -> u64 {
+        self.0
+    }
+}
+
+/// A change in the state of a connection of a [`NetTransport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportEvent {
+    /// A connection started with [`NetTransport::connect`] was established.
+    Connected {
+        /// The connection.
+        connection: TransportConnectionId,
+    },
+    /// A connection started with [`NetTransport::connect`] could not be established.
+    ConnectionFailed {
+        /// The connection.
+        connection: TransportConnectionId,
+        /// Why it failed.
+        error: TransportError,
+    },
+    /// An established connection closed, in either direction.
+    Disconnected {
+        /// The connection.
+        connection: TransportConnectionId,
+        /// Why it closed.
+        error: TransportError,
+    },
+}
+
+/// A message received by a [`NetTransport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportMessage {
+    /// The connection the message arrived on.
+    pub connection: TransportConnectionId,
+    /// The channel the message was sent on.
+    pub channel: ChannelId,
+    /// The encoded message.
+    pub bytes: Bytes,
+}
+
+/// Statistics of a connection of a [`NetTransport`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransportStats {
+    /// The current estimate of the round trip time.
+    pub rtt: Duration,
+    /// The bytes sent over the connection, including the transport's overhead.
+    pub bytes_sent: u64,
+    /// The bytes received over the connection, including the transport's overhead.
+    pub bytes_received: u64,
+    /// The packets detected as lost.
+    pub lost_packets: u64,
+}
+
+/// An error reported by a [`NetTransport`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TransportError {
+    /// Nothing answers at the address connected to.
+    #[error("no peer is reachable at {0}")]
+    Unreachable(SocketAddr),
+    /// The connection is not established, or was closed.
+    #[error("connection {0:?} is not open")]
+    UnknownConnection(TransportConnectionId),
+    /// The connection was closed with [`NetTransport::disconnect`].
+    #[error("the connection was closed locally")]
+    LocallyClosed,
+    /// The peer closed the connection.
+    #[error("the peer closed the connection with code {error_code}")]
+    ClosedByPeer {
+        /// The error code given by the peer.
+        error_code: u64,
+        /// The reason given by the peer.
+        reason: Bytes,
+    },
+    /// The QUIC connection could not be started.
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+    /// The QUIC connection was lost.
+    #[error(transparent)]
+    Connection(ConnectionError),
+    /// An unreliable message could not be sent over QUIC.
+    #[error(transparent)]
+    Datagram(#[from] SendDatagramError),
+}
+
+impl From<ConnectionError> for TransportError {
+    fn from(error: ConnectionError) -> Self {
+        match error {
+            ConnectionError::LocallyClosed => Self::LocallyClosed,
+            ConnectionError::ApplicationClosed(close) => Self::ClosedByPeer {
+                error_code: close.error_code.into_inner(),
+                reason: close.reason,
+            },
+            error => Self::Connection(error),
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use bevy_app::{App, Plugin, PreUpdate};
+use bevy_derive::Deref;
+use bevy_ecs::prelude::*;
+use bevy_utils::HashMap;
+
+use super::{
+    NetTransport, TransportConnectionId, TransportError, TransportEvent, TransportMessage,
+};
+use crate::channel::MessageRegistry;
+use crate::quic::{
+    closed, fail, notify, ApplicationClose, Connected, ConnectionAttempt, ConnectionDirection,
+    ConnectionEndPoint, ConnectionError, ConnectionFailed, ConnectionState, Disconnected,
+    NetworkSystem, QuicError, VarInt,
+};
+
+/// Adds ECS-driven management of [`TransportEndPoint`]s and their connections to an [`App`].
+///
+/// Can be used alongside the [`QuicNetworkPlugin`](crate::quic::QuicNetworkPlugin), and sends the
+/// same connection lifecycle events.
+#[derive(Default)]
+pub struct TransportPlugin;
+
+impl Plugin for TransportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ConnectionAttempt>()
+            .add_event::<Connected>()
+            .add_event::<ConnectionFailed>()
+            .add_event::<Disconnected>()
+            .observe(set_channels)
+            .observe(close_removed_connection)
+            .configure_sets(
+                PreUpdate,
+                NetworkSystem::Receive.after(NetworkSystem::Connections),
+            )
+            .add_systems(
+                PreUpdate,
+                poll_transports.in_set(NetworkSystem::Connections),
+            );
+    }
+}
+
+/// A [`NetTransport`] managed by the [`TransportPlugin`], which accepts incoming connections on it.
+///
+/// Outgoing connections are started with
+/// [`QuicEntityCommands::connect`](crate::quic::QuicEntityCommands::connect) on its entity.
+#[derive(Component)]
+pub struct TransportEndPoint {
+    transport: Box<dyn NetTransport>,
+    connections: HashMap<TransportConnectionId, Entity>,
+}
+
+impl TransportEndPoint {
+    /// Creates an endpoint driving `transport`.
+    pub fn new(transport: impl NetTransport) -> Self {
+        Self {
+            transport: Box::new(transport),
+            connections: HashMap::default(),
+        }
+    }
+
+    /// Gets the transport of this endpoint.
+    pub fn get(&self) -> &dyn NetTransport {
+        &*self.transport
+    }
+
+    /// Gets the transport of this endpoint mutably.
+    ///
+    /// Connections and messages should be left to the [`TransportPlugin`] and the message
+    /// channels, as they are tracked by their entities.
+    pub fn get_mut(&mut self) -> &mut dyn NetTransport {
+        &mut *self.transport
+    }
+
+    /// Gets the connection entity of a connection of the transport.
+    pub fn entity(&self, connection: TransportConnectionId) -> Option<Entity> {
+        self.connections.get(&connection).copied()
+    }
+
+    /// Takes the next message received on a connection that has an entity.
+    pub(crate) fn receive(&mut self) -> Option<(Entity, TransportMessage)> {
+        while let Some(message) = self.transport.receive() {
+            if let Some(entity) = self.entity(message.connection) {
+                return Some((entity, message));
+            }
+        }
+        None
+    }
+}
+
+/// The connection of a [`TransportEndPoint`] a connection entity manages.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Deref)]
+pub struct TransportConnection(TransportConnectionId);
+
+impl TransportConnection {
+    /// Gets the id of the connection in the transport.
+    pub fn get(&self) -> TransportConnectionId {
+        self.0
+    }
+}
+
+/// Starts an outgoing connection from a [`TransportEndPoint`] entity.
+pub(crate) fn connect(
+    world: &mut World,
+    connection: Entity,
+    endpoint: Entity,
+    addr: SocketAddr,
+    server_name: &str,
+) {
+    if world.get_entity(connection).is_none() {
+        return;
+    }
+    let mut transport = world.get_mut::<TransportEndPoint>(endpoint).unwrap();
+    let id = match transport.transport.connect(addr, server_name) {
+        Ok(id) => id,
+        Err(error) => {
+            fail(world, connection, endpoint, error.into());
+            return;
+        }
+    };
+    transport.connections.insert(id, connection);
+
+    world.entity_mut(connection).insert((
+        TransportConnection(id),
+        ConnectionDirection::Outgoing,
+        ConnectionState::Connecting,
+    ));
+    notify(
+        world,
+        connection,
+        ConnectionAttempt {
+            connection,
+            endpoint,
+            remote_address: addr,
+            direction: ConnectionDirection::Outgoing,
+        },
+    );
+}
+
+/// Closes the connection of a connection entity of a [`TransportEndPoint`].
+pub(crate) fn disconnect(world: &mut World, connection: Entity, error_code: VarInt, reason: &[u8]) {
+    let Some(entity) = world.get_entity(connection) else {
+        return;
+    };
+    let (Some(id), Some(endpoint)) = (
+        entity
+            .get::<TransportConnection>()
+            .map(TransportConnection::get),
+        entity
+            .get::<ConnectionEndPoint>()
+            .map(ConnectionEndPoint::get),
+    ) else {
+        return;
+    };
+    if let Some(mut transport) = world.get_mut::<TransportEndPoint>(endpoint) {
+        transport
+            .transport
+            .disconnect(id, error_code.into_inner(), reason);
+    }
+}
+
+fn set_channels(
+    trigger: Trigger<OnAdd, TransportEndPoint>,
+    mut endpoints: Query<&mut TransportEndPoint>,
+    registry: Option<Res<MessageRegistry>>,
+) {
+    let (Ok(mut endpoint), Some(registry)) = (endpoints.get_mut(trigger.entity()), registry) else {
+        return;
+    };
+    let channels: Vec<_> = registry.iter().map(|(_, config)| *config).collect();
+    endpoint.transport.set_channels(&channels);
+}
+
+fn close_removed_connection(
+    trigger: Trigger<OnRemove, TransportConnection>,
+    connections: Query<(&TransportConnection, &ConnectionEndPoint)>,
+    mut endpoints: Query<&mut TransportEndPoint>,
+) {
+    let Ok((connection, endpoint)) = connections.get(trigger.entity()) else {
+        return;
+    };
+    let Ok(mut endpoint) = endpoints.get_mut(endpoint.get()) else {
+        return;
+    };
+    // Connections that closed by themselves were already forgotten.
+    if endpoint.connections.remove(&connection.get()).is_some() {
+        endpoint.transport.disconnect(connection.get(), 0, &[]);
+    }
+}
+
+fn poll_transports(world: &mut World) {
+    let endpoints: Vec<Entity> = world
+        .query_filtered::<Entity, With<TransportEndPoint>>()
+        .iter(world)
+        .collect();
+
+    for endpoint in endpoints {
+        loop {
+            let mut transport = world.get_mut::<TransportEndPoint>(endpoint).unwrap();
+            if let Some(id) = transport.transport.accept() {
+                let remote_address = transport
+                    .transport
+                    .remote_address(id)
+                    .unwrap_or((Ipv4Addr::UNSPECIFIED, 0).into());
+                let connection = world
+                    .spawn((
+                        ConnectionEndPoint::new(endpoint),
+                        TransportConnection(id),
+                        ConnectionDirection::Incoming,
+                        ConnectionState::Connecting,
+                    ))
+                    .id();
+                world
+                    .get_mut::<TransportEndPoint>(endpoint)
+                    .unwrap()
+                    .connections
+                    .insert(id, connection);
+                notify(
+                    world,
+                    connection,
+                    ConnectionAttempt {
+                        connection,
+                        endpoint,
+                        remote_address,
+                        direction: ConnectionDirection::Incoming,
+                    },
+                );
+                established(world, connection, endpoint);
+                continue;
+            }
+
+            let Some(event) = transport.transport.poll_event() else {
+                break;
+            };
+            match event {
+                TransportEvent::Connected { connection } => {
+                    if let Some(connection) = transport.entity(connection) {
+                        established(world, connection, endpoint);
+                    }
+                }
+                TransportEvent::ConnectionFailed { connection, error } => {
+                    if let Some(connection) = transport.connections.remove(&connection) {
+                        fail(world, connection, endpoint, error.into());
+                    }
+                }
+                TransportEvent::Disconnected { connection, error } => {
+                    if let Some(connection) = transport.connections.remove(&connection) {
+                        closed(world, connection, connection_error(error));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn established(world: &mut World, connection: Entity, endpoint: Entity) {
+    world
+        .entity_mut(connection)
+        .insert(ConnectionState::Connected);
+    notify(
+        world,
+        connection,
+        Connected {
+            connection,
+            endpoint,
+        },
+    );
+}
+
+/// Converts the reason a transport connection closed to the error of its [`Disconnected`] event.
+fn connection_error(error: TransportError) -> ConnectionError {
+    match error {
+        TransportError::LocallyClosed => ConnectionError::LocallyClosed,
+        TransportError::ClosedByPeer { error_code, reason } => {
+            ConnectionError::ApplicationClosed(ApplicationClose {
+                error_code: VarInt::from_u64(error_code).unwrap_or(VarInt::MAX),
+                reason,
+            })
+        }
+        TransportError::Connection(error) => error,
+        _ => ConnectionError::Reset,
+    }
+}
+
+impl From<TransportError> for QuicError {
+    fn from(error: TransportError) -> Self {
+        match error {
+            TransportError::Connect(error) => Self::Connect(error),
+            TransportError::Connection(error) => Self::Connection(error),
+            error => Self::Transport(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{ChannelConfig, ChannelKind, MessageApp, MessageReader, MessageWriter};
+    use crate::event::{FromClient, NetworkEventApp, NetworkEventDirection};
+    use crate::quic::QuicEntityCommands;
+    use crate::replication::{Replicated, ReplicationApp, ReplicationPlugin, ServerEntities};
+    use crate::transport::MemoryNetwork;
+    use bevy_ecs::system::RunSystemOnce;
+    use bevy_reflect::Reflect;
+    use bytes::Bytes;
+
+    #[derive(Resource, Default)]
+    struct Log {
+        connected: Vec<Entity>,
+        disconnected: Vec<Disconnected>,
+        received: Vec<(Entity, String)>,
+    }
+
+    fn app(network: &MemoryNetwork, addr: &str, setup: impl Fn(&mut App)) -> (App, Entity) {
+        let mut app = App::new();
+        app.add_plugins(TransportPlugin)
+            .init_resource::<Log>()
+            .add_message::<String>(ChannelConfig::new(ChannelKind::ReliableOrdered))
+            .observe(|trigger: Trigger<Connected>, mut log: ResMut<Log>| {
+                log.connected.push(trigger.event().connection);
+            })
+            .observe(|trigger: Trigger<Disconnected>, mut log: ResMut<Log>| {
+                log.disconnected.push(trigger.event().clone());
+            })
+            .add_systems(
+                bevy_app::Update,
+                |reader: MessageReader<String>, mut log: ResMut<Log>| {
+                    log.received
+                        .extend(reader.read().map(|(entity, m)| (entity, m.clone())));
+                },
+            );
+        setup(&mut app);
+        app.finish();
+        app.cleanup();
+        let transport = network.bind(addr.parse().unwrap()).unwrap();
+        let endpoint = app
+            .world_mut()
+            .spawn(TransportEndPoint::new(transport))
+            .id();
+        (app, endpoint)
+    }
+
+    /// Updates both apps until everything sent over the memory network was handled.
+    fn update([a, b]: [&mut App; 2]) {
+        for _ in 0..2 {
+            a.update();
+            b.update();
+        }
+    }
+
+    #[test]
+    fn runs_channels_on_a_transport() {
+        let network = MemoryNetwork::default();
+        let (mut server, _) = app(&network, "127.0.0.1:5000", |_| {});
+        let (mut client, client_endpoint) = app(&network, "127.0.0.1:0", |_| {});
+
+        let connection = client
+            .world_mut()
+            .commands()
+            .entity(client_endpoint)
+            .connect("127.0.0.1:5000".parse().unwrap(), "");
+        update([&mut server, &mut client]);
+        assert_eq!(client.world().resource::<Log>().connected, [connection]);
+        assert_eq!(server.world().resource::<Log>().connected.len(), 1);
+        let server_connection = server.world().resource::<Log>().connected[0];
+        assert_eq!(
+            server.world().get::<ConnectionDirection>(server_connection),
+            Some(&ConnectionDirection::Incoming)
+        );
+
+        client
+            .world_mut()
+            .run_system_once(move |mut writer: MessageWriter<String>| {
+                writer.send(connection, "ping".to_string());
+            });
+        update([&mut server, &mut client]);
+        server
+            .world_mut()
+            .run_system_once(|mut writer: MessageWriter<String>| {
+                writer.broadcast("pong".to_string());
+            });
+        update([&mut server, &mut client]);
+        assert_eq!(
+            server.world().resource::<Log>().received,
+            [(server_connection, "ping".to_string())]
+        );
+        assert_eq!(
+            client.world().resource::<Log>().received,
+            [(connection, "pong".to_string())]
+        );
+
+        client
+            .world_mut()
+            .commands()
+            .entity(connection)
+            .disconnect(VarInt::from_u32(7), "bye");
+        update([&mut server, &mut client]);
+        for app in [&server, &client] {
+            let disconnected = &app.world().resource::<Log>().disconnected[0];
+            assert_eq!(disconnected.error_code, Some(VarInt::from_u32(7)));
+            assert_eq!(disconnected.reason, Bytes::from("bye"));
+            assert!(app.world().get_entity(disconnected.connection).is_none());
+        }
+    }
+
+    #[derive(Event, Reflect, Debug, Clone, PartialEq)]
+    struct Chat(String);
+
+    #[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+    #[reflect(Component)]
+    struct Health(u32);
+
+    #[derive(Resource, Default)]
+    struct ChatLog(Vec<FromClient<Chat>>);
+
+    fn replicated_app(network: &MemoryNetwork, addr: &str) -> (App, Entity) {
+        app(network, addr, |app| {
+            app.add_plugins(ReplicationPlugin)
+                .replicate::<Health>()
+                .add_network_event::<Chat>(
+                    NetworkEventDirection::ClientToServer,
+                    ChannelConfig::new(ChannelKind::ReliableOrdered),
+                )
+                .init_resource::<ChatLog>()
+                .add_systems(
+                    bevy_app::Update,
+                    |mut chat: EventReader<FromClient<Chat>>, mut log: ResMut<ChatLog>| {
+                        log.0.extend(chat.read().cloned());
+                    },
+                );
+        })
+    }
+
+    #[test]
+    fn runs_events_and_replication_on_a_transport() {
+        let network = MemoryNetwork::default();
+        let (mut server, _) = replicated_app(&network, "127.0.0.1:5000");
+        let (mut client, client_endpoint) = replicated_app(&network, "127.0.0.1:0");
+
+        client
+            .world_mut()
+            .commands()
+            .entity(client_endpoint)
+            .connect("127.0.0.1:5000".parse().unwrap(), "");
+        update([&mut server, &mut client]);
+        let server_connection = server.world().resource::<Log>().connected[0];
+
+        let entity = server.world_mut().spawn((Replicated, Health(3))).id();
+        client.world_mut().send_event(Chat("hello".into()));
+        update([&mut server, &mut client]);
+
+        assert_eq!(
+            server.world().resource::<ChatLog>().0,
+            [FromClient {
+                connection: server_connection,
+                event: Chat("hello".into()),
+            }]
+        );
+        let replica = client
+            .world()
+            .resource::<ServerEntities>()
+            .get(entity)
+            .unwrap();
+        assert_eq!(client.world().get::<Health>(replica), Some(&Health(3)));
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bevy_tasks::{IoTaskPool, Task};
+use bevy_utils::HashMap;
+use crossbeam_channel::{Receiver, Sender};
+
+use super::{
+    NetTransport, TransportConnectionId, TransportError, TransportEvent, TransportMessage,
+    TransportStats,
+};
+use crate::channel::{ChannelConfig, ChannelId, ChannelKind, QuicChannels};
+use crate::quic::{Connection, ConnectionError, EndPoint, VarInt};
+
+/// A [`NetTransport`] over an [`EndPoint`], carrying the channels like the connections of the
+/// [`QuicNetworkPlugin`](crate::quic::QuicNetworkPlugin) do.
+///
+/// Accepts incoming connections if the endpoint has a server configuration, and connects with its
+/// default client configuration.
+pub struct QuicTransport {
+    endpoint: EndPoint,
+    channels: Arc<[ChannelConfig]>,
+    next_id: Arc<AtomicU64>,
+    sender: Sender<QuicUpdate>,
+    updates: Receiver<QuicUpdate>,
+    connecting: HashMap<TransportConnectionId, Task<()>>,
+    connections: HashMap<TransportConnectionId, QuicLink>,
+    accepted: VecDeque<TransportConnectionId>,
+    events: VecDeque<TransportEvent>,
+    messages: VecDeque<TransportMessage>,
+    _accept_task: Task<()>,
+}
+
+/// Updates sent from the tasks driving the endpoint and its connections.
+enum QuicUpdate {
+    Established {
+        id: TransportConnectionId,
+        connection: Connection,
+        incoming: bool,
+    },
+    Failed {
+        id: TransportConnectionId,
+        error: ConnectionError,
+    },
+    Closed {
+        id: TransportConnectionId,
+        error: ConnectionError,
+    },
+}
+
+/// An established connection, with the tasks driving it.
+struct QuicLink {
+    connection: Connection,
+    channels: QuicChannels,
+    _closed_task: Task<()>,
+}
+
+impl QuicTransport {
+    /// Creates a transport over `endpoint`.
+    pub fn new(endpoint: EndPoint) -> Self {
+        let (sender, updates) = crossbeam_channel::unbounded();
+        let next_id = Arc::new(AtomicU64::new(0));
+
+        let accept_task = {
+            let endpoint = endpoint.clone();
+            let sender = sender.clone();
+            let next_id = next_id.clone();
+            IoTaskPool::get().spawn(async move {
+                while let Some(incoming) = endpoint.accept().await {
+                    let id = TransportConnectionId(next_id.fetch_add(1, Ordering::Relaxed));
+                    let sender = sender.clone();
+                    IoTaskPool::get()
+                        .spawn(async move {
+                            if let Ok(connection) = incoming.await {
+                                let _ = sender.send(QuicUpdate::Established {
+                                    id,
+                                    connection,
+                                    incoming: true,
+                                });
+                            }
+                        })
+                        .detach();
+                }
+            })
+        };
+
+        Self {
+            endpoint,
+            channels: Arc::new([]),
+            next_id,
+            sender,
+            updates,
+            connecting: HashMap::default(),
+            connections: HashMap::default(),
+            accepted: VecDeque::new(),
+            events: VecDeque::new(),
+            messages: VecDeque::new(),
+            _accept_task: accept_task,
+        }
+    }
+
+    /// Gets the endpoint of this transport.
+    pub fn endpoint(&self) -> &EndPoint {
+        &self.endpoint
+    }
+
+    /// Handles the updates of the background tasks, and drains the received messages.
+    fn process_updates(&mut self) {
+        while let Ok(update) = self.updates.try_recv() {
+            match update {
+                QuicUpdate::Established {
+                    id,
+                    connection,
+                    incoming,
+                } => {
+                    // Outgoing connections closed while connecting are no longer tracked.
+                    if !incoming && self.connecting.remove(&id).is_none() {
+                        connection.close(VarInt::from_u32(0), &[]);
+                        continue;
+                    }
+                    let sender = self.sender.clone();
+                    let watched = connection.clone();
+                    let closed_task = IoTaskPool::get().spawn(async move {
+                        let error = watched.closed().await;
+                        let _ = sender.send(QuicUpdate::Closed { id, error });
+                    });
+                    let channels = QuicChannels::new(connection.clone(), self.channels.clone());
+                    self.connections.insert(
+                        id,
+                        QuicLink {
+                            connection,
+                            channels,
+                            _closed_task: closed_task,
+                        },
+                    );
+                    if incoming {
+                        self.accepted.push_back(id);
+                    } else {
+                        self.events
+                            .push_back(TransportEvent::Connected { connection: id });
+                    }
+                }
+                QuicUpdate::Failed { id, error } => {
+                    if self.connecting.remove(&id).is_some() {
+                        self.events.push_back(TransportEvent::ConnectionFailed {
+                            connection: id,
+                            error: error.into(),
+                        });
+                    }
+                }
+                QuicUpdate::Closed { id, error } => {
+                    if self.connections.remove(&id).is_some() {
+                        self.events.push_back(TransportEvent::Disconnected {
+                            connection: id,
+                            error: error.into(),
+                        });
+                    }
+                }
+            }
+        }
+
+        for (id, link) in &mut self.connections {
+            for (channel, bytes) in link.channels.receive() {
+                self.messages.push_back(TransportMessage {
+                    connection: *id,
+                    channel,
+                    bytes,
+                });
+            }
+        }
+    }
+}
+
+impl NetTransport for QuicTransport {
+    fn set_channels(&mut self, channels: &[ChannelConfig]) {
+        self.channels = channels.into();
+    }
+
+    fn connect(
+        &mut self,
+        addr: SocketAddr,
+        server_name: &str,
+    ) -> Result<TransportConnectionId, TransportError> {
+        let connecting = self.endpoint.connect(addr, server_name)?;
+        let id = TransportConnectionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let sender = self.sender.clone();
+        let task = IoTaskPool::get().spawn(async move {
+            let update = match connecting.await {
+                Ok(connection) => QuicUpdate::Established {
+                    id,
+                    connection,
+                    incoming: false,
+                },
+                Err(error) => QuicUpdate::Failed { id, error },
+            };
+            let _ = sender.send(update);
+        });
+        self.connecting.insert(id, task);
+        Ok(id)
+    }
+
+    fn accept(&mut self) -> Option<TransportConnectionId> {
+        self.process_updates();
+        self.accepted.pop_front()
+    }
+
+    fn poll_event(&mut self) -> Option<TransportEvent> {
+        self.process_updates();
+        self.events.pop_front()
+    }
+
+    fn send(
+        &mut self,
+        connection: TransportConnectionId,
+        channel: ChannelId,
+        kind: ChannelKind,
+        payload: &[u8],
+    ) -> Result<(), TransportError> {
+        let link = self
+            .connections
+            .get_mut(&connection)
+            .ok_or(TransportError::UnknownConnection(connection))?;
+        Ok(link.channels.send(channel, kind, payload)?)
+    }
+
+    fn receive(&mut self) -> Option<TransportMessage> {
+        if self.messages.is_empty() {
+            self.process_updates();
+        }
+        self.messages.pop_front()
+    }
+
+    fn disconnect(&mut self, connection: TransportConnectionId, error_code: u64, reason: &[u8]) {
+        if self.connecting.remove(&connection).is_some() {
+            // Dropping the task abandons the handshake.
+            self.events.push_back(TransportEvent::ConnectionFailed {
+                connection,
+                error: TransportError::LocallyClosed,
+            });
+        } else if let Some(link) = self.connections.get(&connection) {
+            let error_code = VarInt::from_u64(error_code).unwrap_or(VarInt::MAX);
+            link.connection.close(error_code, reason);
+        }
+    }
+
+    fn remote_address(&self, connection: TransportConnectionId) -> Option<SocketAddr> {
+        let link = self.connections.get(&connection)?;
+        Some(link.connection.remote_address())
+    }
+
+    fn stats(&self, connection: TransportConnectionId) -> Option<TransportStats> {
+        let stats = self.connections.get(&connection)?.connection.stats();
+        Some(TransportStats {
+            rtt: stats.path.rtt,
+            bytes_sent: stats.udp_tx.bytes,
+            bytes_received: stats.udp_rx.bytes,
+            lost_packets: stats.path.lost_packets,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod quic_tests {
+    use super::*;
+    use crate::quic::test_utils::{client_config, init_task_pool, poll_until, server_config};
+    use crate::quic::{EndpointConfig, LoopbackNetwork};
+    use bytes::Bytes;
+
+    #[test]
+    fn carries_channels_over_quic() {
+        init_task_pool();
+        let (server_socket, client_socket) = LoopbackNetwork::pair();
+        let server_addr = server_socket.local_addr().unwrap();
+        let server_endpoint = EndPoint::new_with_abstract_socket(
+            EndpointConfig::default(),
+            Some(server_config()),
+            server_socket,
+        )
+        .unwrap();
+        let mut client_endpoint =
+            EndPoint::new_with_abstract_socket(EndpointConfig::default(), None, client_socket)
+                .unwrap();
+        client_endpoint.set_default_client_config(client_config());
+
+        let channels = [
+            ChannelConfig::new(ChannelKind::ReliableOrdered),
+            ChannelConfig::new(ChannelKind::Unreliable),
+        ];
+        let mut server = QuicTransport::new(server_endpoint);
+        let mut client = QuicTransport::new(client_endpoint);
+        server.set_channels(&channels);
+        client.set_channels(&channels);
+
+        let client_connection = client.connect(server_addr, "localhost").unwrap();
+        let server_connection = poll_until(|| server.accept());
+        assert_eq!(
+            poll_until(|| client.poll_event()),
+            TransportEvent::Connected {
+                connection: client_connection
+            }
+        );
+
+        let channel = ChannelId::new(0);
+        client
+            .send(
+                client_connection,
+                channel,
+                ChannelKind::ReliableOrdered,
+                b"hi",
+            )
+            .unwrap();
+        let message = poll_until(|| server.receive());
+        assert_eq!(message.connection, server_connection);
+        assert_eq!(message.channel, channel);
+        assert_eq!(message.bytes, Bytes::from("hi"));
+        assert!(server.stats(server_connection).unwrap().bytes_received > 0);
+
+        client.disconnect(client_connection, 3, b"bye");
+        assert_eq!(
+            poll_until(|| server.poll_event()),
+            TransportEvent::Disconnected {
+                connection: server_connection,
+                error: TransportError::ClosedByPeer {
+                    error_code: 3,
+                    reason: Bytes::from("bye"),
+                },
+            }
+        );
+        assert_eq!(
+            poll_until(|| client.poll_event()),
+            TransportEvent::Disconnected {
+                connection: client_connection,
+                error: TransportError::LocallyClosed,
+            }
+        );
+    }
+}
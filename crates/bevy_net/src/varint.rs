@@ -0,0 +1,72 @@
+//! Variable length integer encoding shared by the wire formats of this crate.
+//!
+//! Integers are written as little endian base 128 groups, where the top bit of each byte marks
+//! whether another byte follows. Small values, such as lengths and identifiers, take a single byte.
+
+/// The most bytes a `u64` can take once encoded.
+pub(crate) const MAX_VARINT_LEN: usize = 10;
+
+/// Appends `value` to `buf`.
+pub(crate) fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Reads a value from the front of `bytes`, advancing it past the value.
+///
+/// Returns `None` if `bytes` ends early or the value does not fit in a `u64`.
+pub(crate) fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (index, &byte) in bytes.iter().enumerate().take(MAX_VARINT_LEN) {
+        let bits = u64::from(byte & 0x7f);
+        let shift = 7 * index as u32;
+        if bits.checked_shl(shift)? >> shift != bits {
+            return None;
+        }
+        value |= bits << shift;
+
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[index + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value);
+            assert!(buf.len() <= MAX_VARINT_LEN);
+
+            let mut bytes = &buf[..];
+            assert_eq!(read_varint(&mut bytes), Some(value));
+            assert!(bytes.is_empty());
+        }
+    }
+
+    #[test]
+    fn small_values_take_one_byte() {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, 127);
+        assert_eq!(buf, [127]);
+    }
+
+    #[test]
+    fn rejects_truncated_and_overflowing_input() {
+        assert_eq!(read_varint(&mut &[0x80][..]), None);
+        assert_eq!(read_varint(&mut &[0xff; MAX_VARINT_LEN][..]), None);
+        assert_eq!(
+            read_varint(&mut &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02][..]),
+            None
+        );
+    }
+}
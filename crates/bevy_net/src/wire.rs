@@ -0,0 +1,423 @@
+//! Compact binary serialization of reflected values for the network.
+//!
+//! Values are encoded through the same [`TypedReflectSerializer`] and [`TypedReflectDeserializer`]
+//! that scenes use, so any type that is registered for scenes can be sent without deriving anything
+//! else. Unlike scenes, the encoding is a compact binary one: struct field names are omitted,
+//! integers and lengths are variable length encoded, and values are tagged with a small numeric
+//! [`NetworkTypeId`] rather than their type path.
+//!
+//! Only types registered with the [`ReflectNetworked`] type data, usually through
+//! `#[reflect(Networked)]`, receive a [`NetworkTypeId`]. Their fields can be of any registered type.
+//!
+//! ```
+//! # use bevy_reflect::{Reflect, TypeRegistry};
+//! # use bevy_net::wire::{NetworkDeserializer, NetworkSerializer, NetworkTypeIds, ReflectNetworked};
+//! #[derive(Reflect, Debug, PartialEq)]
+//! #[reflect(Networked)]
+//! struct Score {
+//!     player: String,
+//!     points: u32,
+//! }
+//!
+//! let mut registry = TypeRegistry::new();
+//! registry.register::<Score>();
+//! let ids = NetworkTypeIds::from_registry(&registry);
+//!
+//! let score = Score { player: "bevy".into(), points: 300 };
+//! let mut bytes = Vec::new();
+//! NetworkSerializer::new(&registry, &ids).serialize(&score, &mut bytes).unwrap();
+//!
+//! let value = NetworkDeserializer::new(&registry, &ids).deserialize(&mut &bytes[..]).unwrap();
+//! assert_eq!(value.downcast_ref::<Score>(), Some(&score));
+//! ```
+
+use std::any::TypeId;
+
+use bevy_ecs::system::Resource;
+use bevy_reflect::serde::{TypedReflectDeserializer, TypedReflectSerializer};
+use bevy_reflect::{FromType, Reflect, ReflectFromReflect, TypeRegistration, TypeRegistry};
+use bevy_utils::HashMap;
+use bincode::Options;
+use thiserror::Error;
+
+use crate::varint::{read_varint, write_varint};
+
+/// Type data marking a type as sendable over the network as a top level value.
+///
+/// Registering it, with `#[reflect(Networked)]` or [`TypeRegistry::register_type_data`], assigns
+/// the type a [`NetworkTypeId`].
+#[derive(Clone, Debug)]
+pub struct ReflectNetworked;
+
+impl<T: Reflect> FromType<T> for ReflectNetworked {
+    fn from_type() -> Self {
+        Self
+    }
+}
+
+/// The numeric identifier of a [networked](ReflectNetworked) type on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NetworkTypeId(u32);
+
+impl NetworkTypeId {
+    /// The raw value of this identifier.
+    pub fn get(self) -> u32 {
+        self.0
+    }
+
+    /// Creates an identifier from its raw value, as returned by [`get`](Self::get).
+    pub fn from_raw(id: u32) -> Self {
+        Self(id)
+    }
+}
+
+/// Assigns a [`NetworkTypeId`] to every [networked](ReflectNetworked) type of a [`TypeRegistry`].
+///
+/// Identifiers are assigned in the order of the types' paths, so peers that registered the same
+/// networked types agree on them regardless of registration order.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct NetworkTypeIds {
+    ids: HashMap<TypeId, NetworkTypeId>,
+    types: Vec<TypeId>,
+}
+
+impl NetworkTypeIds {
+    /// Assigns identifiers to the networked types of `registry`.
+    pub fn from_registry(registry: &TypeRegistry) -> Self {
+        let mut networked: Vec<_> = registry
+            .iter_with_data::<ReflectNetworked>()
+            .map(|(registration, _)| (registration.type_info().type_path(), registration.type_id()))
+            .collect();
+        networked.sort_unstable();
+
+        let types: Vec<_> = networked.into_iter().map(|(_, type_id)| type_id).collect();
+        let ids = types
+            .iter()
+            .enumerate()
+            .map(|(index, type_id)| (*type_id, NetworkTypeId(index as u32)))
+            .collect();
+        Self { ids, types }
+    }
+
+    /// Gets the identifier of a type.
+    pub fn get(&self, type_id: TypeId) -> Option<NetworkTypeId> {
+        self.ids.get(&type_id).copied()
+    }
+
+    /// Gets the type an identifier was assigned to.
+    pub fn type_id(&self, id: NetworkTypeId) -> Option<TypeId> {
+        self.types.get(id.0 as usize).copied()
+    }
+
+    /// The number of networked types.
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    /// Whether there are no networked types.
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+}
+
+/// An error that occurred while encoding or decoding a value.
+#[derive(Error, Debug)]
+pub enum WireError {
+    /// The type of the value was not registered with [`ReflectNetworked`].
+    #[error("type `{0}` is not registered as networked")]
+    NotNetworked(String),
+    /// The received type identifier does not belong to any networked type.
+    #[error("unknown network type id {0}")]
+    UnknownTypeId(u64),
+    /// The received data ended before the type identifier.
+    #[error("missing network type id")]
+    MissingTypeId,
+    /// The value could not be encoded or decoded.
+    #[error(transparent)]
+    Serialization(#[from] bincode::Error),
+}
+
+/// The options shared by the serializer and the deserializer.
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_varint_encoding()
+        .with_little_endian()
+        .allow_trailing_bytes()
+}
+
+/// Encodes reflected values in the compact network format.
+pub struct NetworkSerializer<'a> {
+    registry: &'a TypeRegistry,
+    ids: &'a NetworkTypeIds,
+}
+
+impl<'a> NetworkSerializer<'a> {
+    /// Creates a serializer for the types of `registry`.
+    pub fn new(registry: &'a TypeRegistry, ids: &'a NetworkTypeIds) -> Self {
+        Self { registry, ids }
+    }
+
+    /// Appends `value`, prefixed with its [`NetworkTypeId`], to `buf`.
+    ///
+    /// Dynamic values are encoded as the type they represent.
+    pub fn serialize(&self, value: &dyn Reflect, buf: &mut Vec<u8>) -> Result<(), WireError> {
+        let type_info = value.get_represented_type_info();
+        let id = type_info
+            .and_then(|info| self.ids.get(info.type_id()))
+            .ok_or_else(|| {
+                WireError::NotNetworked(
+                    type_info
+                        .map_or(value.reflect_type_path(), |info| info.type_path())
+                        .into(),
+                )
+            })?;
+
+        write_varint(buf, u64::from(id.0));
+        self.serialize_value(value, buf)
+    }
+
+    /// Appends `value` to `buf` without its type, for when the receiver knows what to expect.
+    ///
+    /// Unlike [`serialize`](Self::serialize), `value` does not need to be networked.
+    pub fn serialize_value(&self, value: &dyn Reflect, buf: &mut Vec<u8>) -> Result<(), WireError> {
+        options().serialize_into(buf, &TypedReflectSerializer::new(value, self.registry))?;
+        Ok(())
+    }
+}
+
+/// Decodes reflected values from the compact network format.
+pub struct NetworkDeserializer<'a> {
+    registry: &'a TypeRegistry,
+    ids: &'a NetworkTypeIds,
+    limit: u64,
+}
+
+impl<'a> NetworkDeserializer<'a> {
+    /// The default value of [`with_limit`](Self::with_limit).
+    pub const DEFAULT_LIMIT: u64 = 1024 * 1024;
+
+    /// Creates a deserializer for the types of `registry`.
+    pub fn new(registry: &'a TypeRegistry, ids: &'a NetworkTypeIds) -> Self {
+        Self {
+            registry,
+            ids,
+            limit: Self::DEFAULT_LIMIT,
+        }
+    }
+
+    /// Limits how many bytes a single value may be decoded from, which also bounds the memory
+    /// untrusted input can make the deserializer allocate.
+    pub fn with_limit(mut self, limit: u64) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Reads a value written by [`NetworkSerializer::serialize`] from the front of `bytes`,
+    /// advancing it past the value.
+    ///
+    /// The value is converted to its concrete type if it registered [`ReflectFromReflect`], and is
+    /// returned as a dynamic value otherwise.
+    pub fn deserialize(&self, bytes: &mut &[u8]) -> Result<Box<dyn Reflect>, WireError> {
+        let id = read_varint(bytes).ok_or(WireError::MissingTypeId)?;
+        let registration = u32::try_from(id)
+            .ok()
+            .and_then(|id| self.ids.type_id(NetworkTypeId::from_raw(id)))
+            .and_then(|type_id| self.registry.get(type_id))
+            .ok_or(WireError::UnknownTypeId(id))?;
+
+        self.deserialize_value(registration, bytes)
+    }
+
+    /// Reads a value written by [`NetworkSerializer::serialize_value`] from the front of `bytes`,
+    /// advancing it past the value.
+    ///
+    /// See [`deserialize`](Self::deserialize) for the type of the returned value.
+    pub fn deserialize_value(
+        &self,
+        registration: &TypeRegistration,
+        bytes: &mut &[u8],
+    ) -> Result<Box<dyn Reflect>, WireError> {
+        let value = options().with_limit(self.limit).deserialize_from_seed(
+            TypedReflectDeserializer::new(registration, self.registry),
+            bytes,
+        )?;
+
+        Ok(registration
+            .data::<ReflectFromReflect>()
+            .and_then(|from_reflect| from_reflect.from_reflect(&*value))
+            .unwrap_or(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_reflect::serde::ReflectSerializer;
+    use bevy_reflect::{DynamicStruct, FromReflect, Struct};
+
+    #[derive(Reflect, Debug, PartialEq)]
+    #[reflect(Networked)]
+    struct Player {
+        name: String,
+        health: u32,
+        position: (f32, f32),
+        state: State,
+        inventory: Vec<Item>,
+        target: Option<u64>,
+        stats: HashMap<String, i16>,
+    }
+
+    #[derive(Reflect, Debug, PartialEq)]
+    enum State {
+        Idle,
+        Moving { speed: f32 },
+    }
+
+    #[derive(Reflect, Debug, PartialEq)]
+    struct Item(u8, bool);
+
+    #[derive(Reflect, Debug, PartialEq)]
+    #[reflect(Networked)]
+    struct Ping(u16);
+
+    #[derive(Reflect)]
+    struct Secret;
+
+    fn registry() -> TypeRegistry {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Player>();
+        registry.register::<Ping>();
+        registry.register::<Secret>();
+        registry
+    }
+
+    fn player() -> Player {
+        Player {
+            name: "bevy".to_string(),
+            health: 100,
+            position: (1.5, -2.0),
+            state: State::Moving { speed: 3.0 },
+            inventory: vec![Item(1, true), Item(200, false)],
+            target: Some(42),
+            stats: [("kills".to_string(), -3)].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn assigns_ids_in_type_path_order() {
+        let ids = NetworkTypeIds::from_registry(&registry());
+        assert_eq!(ids.len(), 2);
+        assert!(ids.get(TypeId::of::<Ping>()) < ids.get(TypeId::of::<Player>()));
+        assert_eq!(ids.get(TypeId::of::<Secret>()), None);
+
+        let mut reversed = TypeRegistry::new();
+        reversed.register::<Ping>();
+        reversed.register::<Player>();
+        let reversed = NetworkTypeIds::from_registry(&reversed);
+        assert_eq!(
+            ids.get(TypeId::of::<Ping>()),
+            reversed.get(TypeId::of::<Ping>())
+        );
+    }
+
+    #[test]
+    fn round_trips_values() {
+        let registry = registry();
+        let ids = NetworkTypeIds::from_registry(&registry);
+        let serializer = NetworkSerializer::new(&registry, &ids);
+
+        let mut bytes = Vec::new();
+        serializer.serialize(&player(), &mut bytes).unwrap();
+        serializer.serialize(&Ping(7), &mut bytes).unwrap();
+
+        let deserializer = NetworkDeserializer::new(&registry, &ids);
+        let mut input = &bytes[..];
+        let value = deserializer.deserialize(&mut input).unwrap();
+        assert_eq!(value.downcast_ref::<Player>(), Some(&player()));
+        let value = deserializer.deserialize(&mut input).unwrap();
+        assert_eq!(value.downcast_ref::<Ping>(), Some(&Ping(7)));
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn is_more_compact_than_scenes() {
+        let registry = registry();
+        let ids = NetworkTypeIds::from_registry(&registry);
+
+        let mut bytes = Vec::new();
+        NetworkSerializer::new(&registry, &ids)
+            .serialize(&player(), &mut bytes)
+            .unwrap();
+        let scene = bincode::serialize(&ReflectSerializer::new(&player(), &registry)).unwrap();
+        assert!(bytes.len() * 2 < scene.len());
+
+        // Small integers take a single byte.
+        let mut bytes = Vec::new();
+        NetworkSerializer::new(&registry, &ids)
+            .serialize(&Ping(7), &mut bytes)
+            .unwrap();
+        assert_eq!(bytes.len(), 2);
+    }
+
+    #[test]
+    fn serializes_dynamic_values_as_their_represented_type() {
+        let registry = registry();
+        let ids = NetworkTypeIds::from_registry(&registry);
+
+        let mut dynamic = player().clone_dynamic();
+        dynamic.insert("health", 5u32);
+        let mut bytes = Vec::new();
+        NetworkSerializer::new(&registry, &ids)
+            .serialize(&dynamic, &mut bytes)
+            .unwrap();
+
+        let value = NetworkDeserializer::new(&registry, &ids)
+            .deserialize(&mut &bytes[..])
+            .unwrap();
+        assert_eq!(Player::from_reflect(&*value).unwrap().health, 5);
+    }
+
+    #[test]
+    fn rejects_unnetworked_and_unknown_types() {
+        let registry = registry();
+        let ids = NetworkTypeIds::from_registry(&registry);
+
+        let mut bytes = Vec::new();
+        let serializer = NetworkSerializer::new(&registry, &ids);
+        assert!(matches!(
+            serializer.serialize(&Secret, &mut bytes),
+            Err(WireError::NotNetworked(_))
+        ));
+        assert!(matches!(
+            serializer.serialize(&DynamicStruct::default(), &mut bytes),
+            Err(WireError::NotNetworked(_))
+        ));
+
+        let deserializer = NetworkDeserializer::new(&registry, &ids);
+        assert!(matches!(
+            deserializer.deserialize(&mut &[9][..]),
+            Err(WireError::UnknownTypeId(9))
+        ));
+        assert!(matches!(
+            deserializer.deserialize(&mut &[][..]),
+            Err(WireError::MissingTypeId)
+        ));
+    }
+
+    #[test]
+    fn limits_allocations() {
+        let registry = registry();
+        let ids = NetworkTypeIds::from_registry(&registry);
+
+        let mut bytes = Vec::new();
+        NetworkSerializer::new(&registry, &ids)
+            .serialize(&player(), &mut bytes)
+            .unwrap();
+
+        let deserializer = NetworkDeserializer::new(&registry, &ids).with_limit(8);
+        assert!(matches!(
+            deserializer.deserialize(&mut &bytes[..]),
+            Err(WireError::Serialization(_))
+        ));
+    }
+}
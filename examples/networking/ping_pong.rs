@@ -1,67 +1,25 @@
 //! This example shows how you can use 2 async tasks (one representing the client, and the other the server)
-//! to ping each other back and forth. The client, establishes a connection (with reduced security for this example) with the
-//! server, then the client sends a single datagram to the server, a 'ping'.
+//! to ping each other back and forth. The client, establishes a connection with the server, pinning the self-signed
+//! certificate the server generated, then the client sends a single datagram to the server, a 'ping'.
 //! The server then sends a 'pong' back to the client, and they repeat.
 //! Both the client and serer have been rate limited to keep the console outputs readable in real time.
 use async_std::task::sleep;
-use bevy::net::crypto_utils::SkipServerVerification;
+use bevy::net::crypto_utils::{PinnedCertVerifier, SelfSignedCert};
 use bevy::net::quic::crypto::rustls::QuicClientConfig;
 use bevy::net::quic::{ClientConfig, EndPoint, ServerConfig};
 use bevy::net::rustls;
-use bevy::net::rustls::pki_types::{CertificateDer, PrivatePkcs1KeyDer};
-use bevy::net::rustls::RootCertStore;
-use rustls_pemfile::{read_all, Item};
-use std::collections::VecDeque;
-use std::fs::File;
-use std::io::Read;
 use std::net::{Ipv4Addr, SocketAddr};
-use std::path::Path;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use bevy::prelude::*;
 use bevy::tasks::IoTaskPool;
 
-/// Load self-sighed certificate and private key using the [`rustls_pemfile`]
-fn load_cert() -> (CertificateDer<'static>, PrivatePkcs1KeyDer<'static>) {
-    #[cfg(not(target_os = "windows"))]
-    let mut file = File::open(Path::new(
-        r"../../../assets/cypto/bevy_ping_pong_example.pem",
-    ))
-    .unwrap();
-
-    #[cfg(target_os = "windows")]
-    let mut file = File::open(Path::new(r"./././assets/cypto/bevy_ping_pong_example.pem")).unwrap();
-
-    let mut bytes = Vec::new();
-
-    file.read_to_end(&mut bytes).unwrap();
-
-    let mut bytes = VecDeque::from(bytes);
-
-    let mut cert = None;
-    let mut key = None;
-
-    for item in read_all(&mut bytes) {
-        match item.unwrap() {
-            Item::X509Certificate(c) => {
-                cert = Some(c);
-            }
-            Item::Pkcs1Key(k) => {
-                key = Some(k);
-            }
-            _ => {
-                unimplemented!()
-            }
-        }
-    }
-
-    (cert.unwrap(), key.unwrap())
-}
-
 // Set up endpoints for the client and server
 fn end_points() -> (EndPoint, EndPoint) {
-    let (cert, key) = load_cert();
+    // Generate a self-signed certificate for the server. A real server would generate it once,
+    // save it, and share its fingerprint with its clients.
+    let cert = SelfSignedCert::generate(["localhost".to_string()]).unwrap();
 
     // Set up the client to use the loopback address, this means any data sent through this endpoint will
     // never leave the local machine, and will only be available to other endpoints assigned
@@ -69,9 +27,6 @@ fn end_points() -> (EndPoint, EndPoint) {
     let mut client_endpoint =
         EndPoint::client(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).unwrap();
 
-    let mut root_store = RootCertStore::empty();
-    root_store.add(cert.clone()).unwrap();
-
     // Here we are setting up the client to use a given default config
     // for all outgoing connections. If you don't do this and use the [`Endpoint::connect`] method
     // it will throw an error.
@@ -81,26 +36,23 @@ fn end_points() -> (EndPoint, EndPoint) {
         QuicClientConfig::try_from(
             // Create a new tls config
             rustls::ClientConfig::builder()
-                // Use the dangerous (possible insecure) settings to bypass server authentication
+                // Use the dangerous settings to replace the verification against certificate
+                // authorities, which can't vouch for a self-signed certificate.
                 .dangerous()
-                // Use a dummy certificate verifier.
-                // This is quite dangerous and should not be used in production
-                // unless your application doesn't need security (most will).
-                .with_custom_certificate_verifier(SkipServerVerification::new())
-                // Indicate that we are only going to verify the public key sent by the server.
-                // Because the verifier we use for that purpose is a dummy
-                // and doesn't actually do that there is in fact no verification
-                // going here at all.
+                // Only accept the exact certificate generated above, identified by its fingerprint.
+                // An attacker can't impersonate the server without its private key.
+                .with_custom_certificate_verifier(PinnedCertVerifier::new([cert.fingerprint()]))
+                // The server doesn't authenticate clients.
                 .with_no_client_auth(),
         )
         .unwrap(),
     )));
 
     // Set up the server endpoint will the certificate we
-    // loaded earlier, also using the loopback address.
+    // generated earlier, also using the loopback address.
 
     let server_endpoint = EndPoint::server(
-        ServerConfig::with_single_cert(vec![cert], key.into()).unwrap(),
+        ServerConfig::with_single_cert(vec![cert.cert.clone()], cert.key_der()).unwrap(),
         SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0),
     )
     .unwrap();
@@ -119,7 +71,11 @@ fn start_ping_pong() {
             // Start attempting to connect to the server from the client
             let connecting = IoTaskPool::get().spawn(async move {
                 (
-                    client.connect(server_addr, "dummy").unwrap().await.unwrap(),
+                    client
+                        .connect(server_addr, "localhost")
+                        .unwrap()
+                        .await
+                        .unwrap(),
                     client,
                 )
             });